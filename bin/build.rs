@@ -0,0 +1,17 @@
+use std::process::Command;
+
+// Records the git commit the daemon was built from, for /api/version. Builds
+// outside of a git checkout just go without.
+fn main() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    let maybe_commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = maybe_commit {
+        println!("cargo:rustc-env=RAYHUNTER_GIT_COMMIT={}", commit.trim());
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::server::{ApiError, ApiErrorCode};
+
+pub struct ApiAuth {
+    pub token: String,
+    // whether read-only API endpoints also require the token
+    pub protect_reads: bool,
+}
+
+impl ApiAuth {
+    fn is_protected(&self, request: &Request) -> bool {
+        let is_read = matches!(*request.method(), Method::GET | Method::HEAD);
+        !is_read || self.protect_reads
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        request.headers().get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+}
+
+// compares every byte regardless of where the first mismatch is, so response
+// times don't leak how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Middleware which rejects requests to protected routes unless they carry an
+// "Authorization: Bearer <api_token>" header. It's only layered onto the
+// API's routes, so the static web UI files are always served, since browsers
+// can't attach a token to them.
+pub async fn require_api_token(State(auth): State<Arc<ApiAuth>>, request: Request, next: Next) -> Result<Response, ApiError> {
+    if auth.is_protected(&request) && !auth.is_authorized(&request) {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, ApiErrorCode::Unauthorized, "missing or invalid API token"));
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::middleware;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn router(protect_reads: bool) -> Router {
+        let auth = Arc::new(ApiAuth {
+            token: "hunter2".to_string(),
+            protect_reads,
+        });
+        Router::new()
+            .route("/api/start-recording", post(|| async { "started" }))
+            .route("/api/qmdl-manifest", get(|| async { "manifest" }))
+            .route_layer(middleware::from_fn_with_state(auth, require_api_token))
+            .route("/index.html", get(|| async { "ui" }))
+    }
+
+    async fn status(router: Router, method: Method, uri: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_protected_route() {
+        assert_eq!(status(router(false), Method::POST, "/api/start-recording", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(router(false), Method::POST, "/api/start-recording", Some("hunter3")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(router(false), Method::POST, "/api/start-recording", Some("hunter2")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_unprotected_route() {
+        assert_eq!(status(router(false), Method::GET, "/api/qmdl-manifest", None).await, StatusCode::OK);
+        assert_eq!(status(router(true), Method::GET, "/api/qmdl-manifest", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(router(true), Method::GET, "/api/qmdl-manifest", Some("hunter2")).await, StatusCode::OK);
+        assert_eq!(status(router(true), Method::GET, "/index.html", None).await, StatusCode::OK);
+    }
+}
@@ -1,14 +1,375 @@
-use std::{future, path::PathBuf, pin::pin};
-use rayhunter::{analysis::analyzer::Harness, diag::DataType, qmdl::QmdlReader};
+use std::{collections::{BTreeMap, VecDeque}, future, path::{Path, PathBuf}, pin::pin};
+use rayhunter::{analysis::{analyzer::{Event, Harness}, information_element::{InformationElement, LteInformationElement}}, cellular_data::CellularData, diag::{describe_log_masks, DataType, LogBody, Message, Timestamp}, diag_device::LOG_CODES_FOR_RAW_PACKET_LOGGING, gsmtap::GsmtapMessage, gsmtap_parser, ip::{self, IpPacket}, pcap::GsmtapPcapWriter, qmdl::QmdlReader};
+use chrono::{DateTime, FixedOffset, Local};
 use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
 use clap::Parser;
 use futures::TryStreamExt;
+use log::warn;
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
-    #[arg(short, long)]
-    qmdl_path: PathBuf,
+    #[arg(short, long, required_unless_present = "show_log_mask")]
+    qmdl_path: Option<PathBuf>,
+
+    /// Print the log codes rayhunter enables in the diag log mask, then exit
+    #[arg(long)]
+    show_log_mask: bool,
+
+    /// Also convert the QMDL file to a pcapng file alongside it, with
+    /// user-plane IP traffic on its own interface
+    #[arg(long)]
+    pcapify: bool,
+
+    /// With --pcapify, only write packets near an analyzer warning, and list
+    /// the warnings in a .warnings.txt file next to the pcap
+    #[arg(long, requires = "pcapify")]
+    only_warnings: bool,
+
+    /// With --only-warnings, how many packets to keep on either side of each
+    /// warning
+    #[arg(long, default_value_t = 50, requires = "only_warnings")]
+    context: usize,
+
+    /// Write the cell parameters seen in the QMDL file as SCAT-style key/value
+    /// logs to a file in this directory, for cross-checking against SCAT
+    #[arg(long)]
+    scat_out: Option<PathBuf>,
+
+    /// Count the messages in the QMDL file by type instead of analyzing it
+    #[arg(long)]
+    stats: bool,
+
+    /// Print --stats output as JSON
+    #[arg(long, requires = "stats")]
+    json: bool,
+
+    /// Print each decoded LTE RRC message's fields, one
+    /// "<timestamp> <channel> <field> = <value>" line per field, instead of
+    /// analyzing the QMDL file
+    #[arg(long)]
+    dump_rrc: bool,
+}
+
+#[derive(Serialize, Default)]
+struct QmdlStats {
+    total_bytes: u64,
+    containers: usize,
+    messages: usize,
+    parse_errors: usize,
+    parse_error_rate: f64,
+    message_types: BTreeMap<&'static str, usize>,
+}
+
+impl std::fmt::Display for QmdlStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "total bytes: {}", self.total_bytes)?;
+        writeln!(f, "containers: {}", self.containers)?;
+        writeln!(f, "messages: {}", self.messages)?;
+        writeln!(f, "parse errors: {} ({:.2}%)", self.parse_errors, self.parse_error_rate * 100.0)?;
+        writeln!(f, "message types:")?;
+        for (name, count) in &self.message_types {
+            writeln!(f, "  {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+async fn open_qmdl(qmdl_path: &Path) -> (QmdlReader<File>, u64) {
+    let qmdl_file = File::open(qmdl_path).await.expect("failed to open QMDL file");
+    let file_size = qmdl_file.metadata().await.expect("failed to get QMDL file metadata").len();
+    (QmdlReader::new(qmdl_file, Some(file_size as usize)), file_size)
+}
+
+async fn count_messages(qmdl_path: &Path) -> QmdlStats {
+    let (mut qmdl_reader, file_size) = open_qmdl(qmdl_path).await;
+    let mut stats = QmdlStats {
+        total_bytes: file_size,
+        ..Default::default()
+    };
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = qmdl_stream.try_next().await.expect("failed getting QMDL container") {
+        stats.containers += 1;
+        for maybe_msg in container.into_messages() {
+            stats.messages += 1;
+            let name = match maybe_msg {
+                Ok(Message::Log { body, .. }) => body.name(),
+                Ok(Message::Response { .. }) => "Response",
+                Err(_) => {
+                    stats.parse_errors += 1;
+                    continue;
+                },
+            };
+            *stats.message_types.entry(name).or_default() += 1;
+        }
+    }
+    if stats.messages > 0 {
+        stats.parse_error_rate = stats.parse_errors as f64 / stats.messages as f64;
+    }
+    stats
+}
+
+enum Packet {
+    Gsmtap(GsmtapMessage, Timestamp),
+    Ip(IpPacket, Timestamp),
+}
+
+// Converts a message into the packet we'd write to the pcap for it, if any
+fn message_to_packet(msg: Message) -> Option<Packet> {
+    match msg {
+        Message::Log { timestamp, body: LogBody::IpTraffic { msg, .. }, .. } => match ip::decode_ip_packet(msg) {
+            Ok(packet) => Some(Packet::Ip(packet, timestamp)),
+            Err(e) => {
+                warn!("dropping IP packet: {}", e);
+                None
+            },
+        },
+        msg => match gsmtap_parser::parse(msg) {
+            Ok(Some((timestamp, gsmtap_msg))) => Some(Packet::Gsmtap(gsmtap_msg, timestamp)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("error converting message to gsmtap: {}", e);
+                None
+            },
+        },
+    }
+}
+
+// Streams packets through while only letting those within `context` packets
+// of a warning out. Up to `context` packets are buffered in case a warning
+// comes along, and the `context` packets after a warning pass straight
+// through.
+struct ContextWindow<T> {
+    context: usize,
+    before: VecDeque<(usize, T)>,
+    remaining_after: usize,
+}
+
+impl<T> ContextWindow<T> {
+    fn new(context: usize) -> Self {
+        ContextWindow {
+            context,
+            before: VecDeque::with_capacity(context),
+            remaining_after: 0,
+        }
+    }
+
+    // Returns the (index, packet) pairs which should be written, in order
+    fn push(&mut self, index: usize, packet: T, is_warning: bool) -> Vec<(usize, T)> {
+        if is_warning {
+            self.remaining_after = self.context;
+            let mut flushed: Vec<(usize, T)> = self.before.drain(..).collect();
+            flushed.push((index, packet));
+            flushed
+        } else if self.remaining_after > 0 {
+            self.remaining_after -= 1;
+            vec![(index, packet)]
+        } else {
+            if self.context > 0 {
+                if self.before.len() == self.context {
+                    self.before.pop_front();
+                }
+                self.before.push_back((index, packet));
+            }
+            Vec::new()
+        }
+    }
+}
+
+async fn write_packet(pcap_writer: &mut GsmtapPcapWriter<File>, packet: Packet) {
+    match packet {
+        Packet::Gsmtap(gsmtap_msg, timestamp) => pcap_writer.write_gsmtap_message(gsmtap_msg, timestamp).await,
+        Packet::Ip(packet, timestamp) => pcap_writer.write_ip_packet(packet, timestamp).await,
+    }.expect("error writing pcap packet");
+}
+
+// Converts a diag timestamp, falling back to the wall clock if it's garbage
+fn datetime_or_now(timestamp: &Timestamp) -> DateTime<FixedOffset> {
+    timestamp.try_to_datetime().unwrap_or_else(|| Local::now().fixed_offset())
+}
+
+// Runs a packet through the harness, returning any events it produced along
+// with the name of the analyzer that produced them, from names (the
+// harness's get_names)
+fn analyze_packet(harness: &mut Harness, names: &[String], packet: &Packet) -> Vec<(String, Event)> {
+    let Packet::Gsmtap(gsmtap_msg, timestamp) = packet else {
+        return Vec::new();
+    };
+    let Ok(element) = InformationElement::try_from(gsmtap_msg) else {
+        return Vec::new();
+    };
+    harness.analyze_information_element(&element, datetime_or_now(timestamp)).into_iter()
+        .zip(names)
+        .filter_map(|(maybe_event, name)| maybe_event.map(|event| (name.clone(), event)))
+        .collect()
+}
+
+async fn pcapify(qmdl_path: &Path, only_warnings_context: Option<usize>) {
+    let pcap_path = qmdl_path.with_extension("pcapng");
+    let pcap_file = File::create(&pcap_path).await.expect("failed to create pcap file");
+    let mut pcap_writer = GsmtapPcapWriter::new(pcap_file).await.expect("failed to create pcap writer");
+    pcap_writer.write_iface_header().await.expect("failed to write pcap interface header");
+    pcap_writer.write_ip_iface_header().await.expect("failed to write pcap interface header");
+
+    let mut harness = Harness::new_with_all_analyzers();
+    let names = harness.get_names().into_iter().map(|name| name.to_string()).collect::<Vec<_>>();
+    let mut window = only_warnings_context.map(ContextWindow::new);
+    let mut warnings = String::new();
+    let mut packet_index = 0;
+    let mut packets_written = 0;
+
+    let (mut qmdl_reader, _) = open_qmdl(qmdl_path).await;
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = qmdl_stream.try_next().await.expect("failed getting QMDL container") {
+        for maybe_msg in container.into_messages() {
+            let msg = match maybe_msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    warn!("error parsing message: {:?}", e);
+                    continue;
+                },
+            };
+            // the analyzers take the cell from the message, before it's
+            // converted to GSMTAP, like the daemon's harness does
+            if let Some((pci, earfcn)) = msg.lte_rrc_cell() {
+                harness.set_lte_rrc_cell(pci, earfcn);
+            }
+            let Some(packet) = message_to_packet(msg) else {
+                continue;
+            };
+            packet_index += 1;
+            let Some(window) = window.as_mut() else {
+                write_packet(&mut pcap_writer, packet).await;
+                continue;
+            };
+
+            let events = analyze_packet(&mut harness, &names, &packet);
+            for (index, packet) in window.push(packet_index, packet, !events.is_empty()) {
+                write_packet(&mut pcap_writer, packet).await;
+                packets_written += 1;
+                // the warning packet is always the last one flushed, so this
+                // is its frame number in the new pcap
+                if index == packet_index {
+                    for (name, event) in &events {
+                        warnings.push_str(&format!("packet {} (frame {} in pcap): {}: {:?}: {}\n",
+                            index, packets_written, name, event.event_type, event.message));
+                    }
+                }
+            }
+        }
+    }
+    eprintln!("wrote {}", pcap_path.display());
+
+    if window.is_some() {
+        let warnings_path = qmdl_path.with_extension("warnings.txt");
+        let mut warnings_file = File::create(&warnings_path).await.expect("failed to create warnings file");
+        warnings_file.write_all(warnings.as_bytes()).await.expect("failed to write warnings file");
+        eprintln!("wrote {} ({} of {} packets kept)", warnings_path.display(), packets_written, packet_index);
+    }
+}
+
+// Flattens a decoded message into "<path> = <value>" lines, leaving out
+// optional fields that weren't present
+fn flatten_fields(path: &str, value: &serde_json::Value, lines: &mut Vec<String>) {
+    let join = |key: &dyn std::fmt::Display| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    match value {
+        serde_json::Value::Null => {},
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten_fields(&join(key), value, lines);
+            }
+        },
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                flatten_fields(&join(&i), item, lines);
+            }
+        },
+        value => lines.push(format!("{} = {}", path, value)),
+    }
+}
+
+// Returns the RRC channel a message was sent on, and its fields as
+// "<path> = <value>" lines
+fn dump_rrc(element: &LteInformationElement) -> (&'static str, Vec<String>) {
+    use LteInformationElement as L;
+    let (channel, value) = match element {
+        L::DlCcch(msg) => ("DlCcch", serde_json::to_value(msg)),
+        L::DlDcch(msg) => ("DlDcch", serde_json::to_value(msg)),
+        L::UlCcch(msg) => ("UlCcch", serde_json::to_value(msg)),
+        L::UlDcch(msg) => ("UlDcch", serde_json::to_value(msg)),
+        L::BcchBch(msg) => ("BcchBch", serde_json::to_value(msg)),
+        L::BcchDlSch(msg) => ("BcchDlSch", serde_json::to_value(msg)),
+        L::PCCH(msg) => ("PCCH", serde_json::to_value(msg)),
+        L::MCCH(msg) => ("MCCH", serde_json::to_value(msg)),
+        L::ScMcch(msg) => ("ScMcch", serde_json::to_value(msg)),
+        L::BcchBchMbms(msg) => ("BcchBchMbms", serde_json::to_value(msg)),
+        L::BcchDlSchBr(msg) => ("BcchDlSchBr", serde_json::to_value(msg)),
+        L::BcchDlSchMbms(msg) => ("BcchDlSchMbms", serde_json::to_value(msg)),
+        L::SbcchSlBch(msg) => ("SbcchSlBch", serde_json::to_value(msg)),
+        L::SbcchSlBchV2x(msg) => ("SbcchSlBchV2x", serde_json::to_value(msg)),
+    };
+    let mut lines = Vec::new();
+    flatten_fields("", &value.expect("failed to serialize RRC message"), &mut lines);
+    (channel, lines)
+}
+
+async fn print_rrc_dump(qmdl_path: &Path) {
+    let (mut qmdl_reader, _) = open_qmdl(qmdl_path).await;
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = qmdl_stream.try_next().await.expect("failed getting QMDL container") {
+        for msg in container.into_messages().into_iter().flatten() {
+            let Ok(Some((timestamp, gsmtap_msg))) = gsmtap_parser::parse(msg) else {
+                continue;
+            };
+            let Ok(InformationElement::LTE(element)) = InformationElement::try_from(&gsmtap_msg) else {
+                continue;
+            };
+            let timestamp = datetime_or_now(&timestamp).to_rfc3339();
+            let (channel, lines) = dump_rrc(&element);
+            for line in lines {
+                println!("{} {} {}", timestamp, channel, line);
+            }
+        }
+    }
+}
+
+// Writes a block of key/value lines each time the cell parameters change
+async fn write_scat_log(qmdl_path: &Path, out_dir: &Path) {
+    let file_name = qmdl_path.file_stem().expect("QMDL path has no file name");
+    let log_path = out_dir.join(file_name).with_extension("scat.log");
+    let mut log_file = File::create(&log_path).await.expect("failed to create SCAT log file");
+    let mut cellular_data = CellularData::default();
+
+    let (mut qmdl_reader, _) = open_qmdl(qmdl_path).await;
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = qmdl_stream.try_next().await.expect("failed getting QMDL container") {
+        for msg in container.into_messages().into_iter().flatten() {
+            let Ok(Some((timestamp, gsmtap_msg))) = gsmtap_parser::parse(msg) else {
+                continue;
+            };
+            let Ok(element) = InformationElement::try_from(&gsmtap_msg) else {
+                continue;
+            };
+            let previous = cellular_data.clone();
+            cellular_data.update(&element);
+            if cellular_data == previous {
+                continue;
+            }
+            let mut block = format!("{}\n", datetime_or_now(&timestamp).to_rfc3339());
+            for (key, value) in cellular_data.to_scat_format() {
+                block.push_str(&format!("{} = {}\n", key, value));
+            }
+            block.push('\n');
+            log_file.write_all(block.as_bytes()).await.expect("failed to write SCAT log");
+        }
+    }
+    eprintln!("wrote {}", log_path.display());
 }
 
 #[tokio::main]
@@ -16,11 +377,30 @@ async fn main() {
     env_logger::init();
     let args = Args::parse();
 
+    if args.show_log_mask {
+        print!("{}", describe_log_masks(&LOG_CODES_FOR_RAW_PACKET_LOGGING));
+        return;
+    }
+    let qmdl_path = args.qmdl_path.expect("no QMDL path given");
+
+    if args.stats {
+        let stats = count_messages(&qmdl_path).await;
+        if args.json {
+            println!("{}", serde_json::to_string(&stats).expect("failed to serialize stats"));
+        } else {
+            print!("{}", stats);
+        }
+        return;
+    }
+
+    if args.dump_rrc {
+        print_rrc_dump(&qmdl_path).await;
+        return;
+    }
+
     let mut harness = Harness::new_with_all_analyzers();
 
-    let qmdl_file = File::open(args.qmdl_path).await.expect("failed to open QMDL file");
-    let file_size = qmdl_file.metadata().await.expect("failed to get QMDL file metadata").len();
-    let mut qmdl_reader = QmdlReader::new(qmdl_file, Some(file_size as usize));
+    let (mut qmdl_reader, _) = open_qmdl(&qmdl_path).await;
     let mut qmdl_stream = pin!(qmdl_reader.as_stream()
         .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
     println!("{}\n", serde_json::to_string(&harness.get_metadata()).expect("failed to serialize report metadata"));
@@ -28,4 +408,143 @@ async fn main() {
         let row = harness.analyze_qmdl_messages(container);
         println!("{}\n", serde_json::to_string(&row).expect("failed to serialize row"));
     }
+
+    if let Some(scat_out) = &args.scat_out {
+        write_scat_log(&qmdl_path, scat_out).await;
+    }
+
+    if args.pcapify {
+        pcapify(&qmdl_path, args.only_warnings.then_some(args.context)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(window: &mut ContextWindow<usize>, warnings: &[usize], count: usize) -> Vec<usize> {
+        let mut written = Vec::new();
+        for i in 0..count {
+            let flushed = window.push(i, i, warnings.contains(&i));
+            written.extend(flushed.into_iter().map(|(index, _)| index));
+        }
+        written
+    }
+
+    #[test]
+    fn test_context_around_warning() {
+        let mut window = ContextWindow::new(3);
+        assert_eq!(push_all(&mut window, &[10], 20), vec![7, 8, 9, 10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn test_overlapping_contexts() {
+        let mut window = ContextWindow::new(2);
+        assert_eq!(push_all(&mut window, &[1, 4, 12], 20), vec![0, 1, 2, 3, 4, 5, 6, 10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn test_no_context() {
+        let mut window = ContextWindow::new(0);
+        assert_eq!(push_all(&mut window, &[3, 5], 10), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_dump_rrc() {
+        use rayhunter::gsmtap::{GsmtapHeader, GsmtapType, LteRrcSubtype};
+        // an RRCConnectionRelease redirecting to EARFCN 850
+        let gsmtap_msg = GsmtapMessage {
+            header: GsmtapHeader::new(GsmtapType::LteRrc(LteRrcSubtype::DlDcch)),
+            payload: vec![0x28, 0x22, 0x00, 0x6a, 0x40],
+        };
+        let Ok(InformationElement::LTE(release)) = InformationElement::try_from(&gsmtap_msg) else {
+            panic!("failed to decode RRCConnectionRelease");
+        };
+        let (channel, lines) = dump_rrc(&release);
+        assert_eq!(channel, "DlDcch");
+        assert_eq!(lines, vec![
+            "message.C1.RrcConnectionRelease.critical_extensions.C1.RrcConnectionRelease_r8.redirected_carrier_info.Eutra = 850",
+            "message.C1.RrcConnectionRelease.critical_extensions.C1.RrcConnectionRelease_r8.release_cause = 1",
+            "message.C1.RrcConnectionRelease.rrc_transaction_identifier = 0",
+        ]);
+    }
+
+    // an HDLC-encapsulated log message with the given type and body
+    fn log_message(log_type: u16, body: &[u8]) -> Vec<u8> {
+        let length = 12 + body.len() as u16;
+        let mut data = vec![16, 0];
+        data.extend(length.to_le_bytes()); // outer_length
+        data.extend(length.to_le_bytes()); // inner_length
+        data.extend(log_type.to_le_bytes());
+        data.extend(0u64.to_le_bytes()); // timestamp
+        data.extend(body);
+        rayhunter::hdlc::hdlc_encapsulate(&data, &rayhunter::diag::CRC_CCITT)
+    }
+
+    #[tokio::test]
+    async fn test_count_messages() {
+        let dir = tempdir::TempDir::new("check_test").unwrap();
+        let qmdl_path = dir.path().join("test.qmdl");
+        let gsm_rr = log_message(0x512f, &[0, 0x3f, 2, 0x06, 0x3f]);
+        // not a log type rayhunter can parse
+        let unknown = log_message(0x1234, &[0, 0, 0, 0]);
+        let qmdl = [gsm_rr.clone(), gsm_rr, unknown].concat();
+        tokio::fs::write(&qmdl_path, &qmdl).await.unwrap();
+
+        let stats = count_messages(&qmdl_path).await;
+        assert_eq!(stats.total_bytes, qmdl.len() as u64);
+        assert_eq!(stats.containers, 3);
+        assert_eq!(stats.messages, 3);
+        assert_eq!(stats.parse_errors, 1);
+        assert!((stats.parse_error_rate - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.message_types, BTreeMap::from([("GsmRrSignallingMessage", 2)]));
+        assert!(stats.to_string().contains("parse errors: 1 (33.33%)"));
+    }
+
+    // an HDLC-encapsulated LTE RRC OTA log with the given PDU number, at the
+    // given number of seconds after 2024-01-01T00:00:00Z
+    fn lte_rrc_message(seconds: u64, pdu_num: u8, payload: &[u8]) -> Vec<u8> {
+        let length = 31 + payload.len() as u16;
+        let mut data = vec![16, 0];
+        data.extend(length.to_le_bytes()); // outer_length
+        data.extend(length.to_le_bytes()); // inner_length
+        data.extend(0xb0c0u16.to_le_bytes());
+        data.extend((((1_388_102_400 + seconds) * 800) << 16).to_le_bytes());
+        data.extend([20, 14, 48, 0]); // ext header version, rrc rel, bearer id
+        data.extend(160u16.to_le_bytes()); // pci
+        data.extend(5230u32.to_le_bytes()); // earfcn
+        data.extend(4057u16.to_le_bytes()); // sfn_subfn
+        data.push(pdu_num);
+        data.extend(0u32.to_le_bytes()); // sib_mask
+        data.extend((payload.len() as u16).to_le_bytes());
+        data.extend(payload);
+        rayhunter::hdlc::hdlc_encapsulate(&data, &rayhunter::diag::CRC_CCITT)
+    }
+
+    #[tokio::test]
+    async fn test_scat_log() {
+        // SIB1s for MCC 310 MNC 26, cell identity 0x1234567, with TACs 1 and 2
+        let sib1_tac_1 = [0x40, 0x4c, 0x40, 0x4d, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, 0x18, 0xc0, 0x10, 0x50, 0x00];
+        let sib1_tac_2 = [0x40, 0x4c, 0x40, 0x4d, 0x00, 0x02, 0x12, 0x34, 0x56, 0x78, 0x18, 0xc0, 0x10, 0x50, 0x00];
+        // a SIB3, as in cellular_data's tests
+        let sib3 = [0x00, 0x04, 0x4a, 0x0d, 0x70, 0x6d, 0x7f, 0x48];
+        // an RRCConnectionRelease, which doesn't carry system information
+        let release = [0x28, 0x22, 0x00, 0x6a, 0x40];
+
+        let dir = tempdir::TempDir::new("check_test").unwrap();
+        let qmdl_path = dir.path().join("sib_changes.qmdl");
+        let qmdl = [
+            lte_rrc_message(0, 2, &sib1_tac_1),
+            // unchanged, so no new block
+            lte_rrc_message(1, 2, &sib1_tac_1),
+            lte_rrc_message(1, 7, &release),
+            lte_rrc_message(2, 2, &sib3),
+            lte_rrc_message(3, 2, &sib1_tac_2),
+        ].concat();
+        tokio::fs::write(&qmdl_path, &qmdl).await.unwrap();
+
+        write_scat_log(&qmdl_path, dir.path()).await;
+        let log = tokio::fs::read_to_string(dir.path().join("sib_changes.scat.log")).await.unwrap();
+        assert_eq!(log, include_str!("../test_data/sib_changes.scat.log"));
+    }
 }
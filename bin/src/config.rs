@@ -1,21 +1,115 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+
 use crate::error::RayhunterError;
 
+use rayhunter::analysis::analyzer::AnalyzerConfig;
+use rayhunter::pcap::GSMTAP_PORT;
+use rayhunter::diag_device::{CaptureProfile, DEFAULT_DIAG_DEVICE_PATH, LOG_CODES_FOR_RAW_PACKET_LOGGING};
+use rayhunter::qmdl::QmdlCompression;
 use serde::Deserialize;
 
+/// A diag device to monitor alongside the main one, e.g. a second modem.
+/// Its recordings go in their own store under the main store's directory, and
+/// its API is served under /devices/<label>/api.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ExtraDiagDevice {
+    pub label: String,
+    pub path: String,
+}
+
 #[derive(Deserialize)]
 struct ConfigFile {
     qmdl_store_path: Option<String>,
     port: Option<u16>,
+    bind_address: Option<String>,
     readonly_mode: Option<bool>,
     ui_level: Option<u8>,
+    headless: Option<bool>,
+    display_brightness: Option<u8>,
+    redact_identities: Option<bool>,
+    auto_resume_after_secs: Option<u64>,
+    analyzers: Option<AnalyzerConfig>,
+    allowed_earfcns: Option<Vec<[u32; 2]>>,
+    api_token: Option<String>,
+    api_token_protects_reads: Option<bool>,
+    stats_stream_interval_secs: Option<u64>,
+    diag_device_path: Option<String>,
+    framebuffer_path: Option<String>,
+    framebuffer_width: Option<u32>,
+    framebuffer_height: Option<u32>,
+    allow_debug_endpoints: Option<bool>,
+    store_compression: Option<QmdlCompression>,
+    rotate_after_bytes: Option<usize>,
+    rotate_after_secs: Option<u64>,
+    capture_profile: Option<CaptureProfile>,
+    custom_log_codes: Option<Vec<u32>>,
+    extra_diag_devices: Option<Vec<ExtraDiagDevice>>,
+    enable_metrics: Option<bool>,
+    display_qr: Option<bool>,
+    autostart: Option<bool>,
+    autostart_delay_secs: Option<u64>,
+    heartbeat_path: Option<String>,
+    heartbeat_interval_secs: Option<u64>,
+    gsmtap_udp_target: Option<String>,
+    cors_allowed_origins: Option<Vec<String>>,
+    discard_qmdl_after_analysis: Option<bool>,
+    stall_timeout_secs: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct Config {
     pub qmdl_store_path: String,
     pub port: u16,
+    pub bind_address: IpAddr,
     pub readonly_mode: bool,
     pub ui_level: u8,
+    pub headless: bool,
+    pub display_brightness: u8,
+    pub redact_identities: bool,
+    pub auto_resume_after_secs: u64,
+    pub analyzers: AnalyzerConfig,
+    // if non-empty, LTE RRC messages on any other EARFCN aren't recorded
+    pub allowed_earfcns: Vec<RangeInclusive<u32>>,
+    // if set, mutating API requests must send "Authorization: Bearer <token>"
+    pub api_token: Option<String>,
+    pub api_token_protects_reads: bool,
+    pub stats_stream_interval_secs: u64,
+    // device paths and geometry, which only need changing when bringing
+    // rayhunter up on hardware other than the Orbic
+    pub diag_device_path: String,
+    pub framebuffer_path: String,
+    pub framebuffer_width: u32,
+    pub framebuffer_height: u32,
+    pub allow_debug_endpoints: bool,
+    pub store_compression: QmdlCompression,
+    pub rotate_after_bytes: usize,
+    pub rotate_after_secs: u64,
+    // the diag log codes to capture, from capture_profile
+    pub capture_log_codes: Vec<u32>,
+    pub extra_diag_devices: Vec<ExtraDiagDevice>,
+    // serves prometheus metrics at /metrics
+    pub enable_metrics: bool,
+    // shows a QR code of the web UI's URL on the display at boot
+    pub display_qr: bool,
+    // whether to start recording at boot, and how long to wait first
+    pub autostart: bool,
+    pub autostart_delay_secs: u64,
+    // if set, this file's rewritten with the current time as diag messages
+    // are read, for an external watchdog to check
+    pub heartbeat_path: Option<String>,
+    pub heartbeat_interval_secs: u64,
+    // if set, GSMTAP packets are streamed here as they're read, for watching
+    // live in Wireshark
+    pub gsmtap_udp_target: Option<SocketAddr>,
+    // origins (e.g. "http://192.168.1.2:3000") allowed to call the API from
+    // a browser. Empty means same-origin only.
+    pub cors_allowed_origins: Vec<String>,
+    // deletes each recording's QMDL once it's closed, keeping the analysis
+    pub discard_qmdl_after_analysis: bool,
+    // if no diag messages arrive for this long, the diag device is
+    // re-opened. 0 disables this.
+    pub stall_timeout_secs: u64,
 }
 
 impl Default for Config {
@@ -23,36 +117,361 @@ impl Default for Config {
         Config {
             qmdl_store_path: "/data/rayhunter/qmdl".to_string(),
             port: 8080,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             readonly_mode: false,
             ui_level: 1,
+            headless: false,
+            display_brightness: 100,
+            redact_identities: false,
+            auto_resume_after_secs: 0,
+            analyzers: AnalyzerConfig::default(),
+            allowed_earfcns: Vec::new(),
+            api_token: None,
+            api_token_protects_reads: false,
+            stats_stream_interval_secs: 1,
+            diag_device_path: DEFAULT_DIAG_DEVICE_PATH.to_string(),
+            framebuffer_path: "/dev/fb0".to_string(),
+            framebuffer_width: 128,
+            framebuffer_height: 128,
+            allow_debug_endpoints: false,
+            store_compression: QmdlCompression::None,
+            rotate_after_bytes: 0,
+            rotate_after_secs: 0,
+            capture_log_codes: LOG_CODES_FOR_RAW_PACKET_LOGGING.to_vec(),
+            extra_diag_devices: Vec::new(),
+            enable_metrics: false,
+            display_qr: false,
+            autostart: true,
+            autostart_delay_secs: 0,
+            heartbeat_path: None,
+            heartbeat_interval_secs: 10,
+            gsmtap_udp_target: None,
+            cors_allowed_origins: Vec::new(),
+            discard_qmdl_after_analysis: false,
+            stall_timeout_secs: 0,
         }
     }
 }
 
 pub fn parse_config<P>(path: P) -> Result<Config, RayhunterError> where P: AsRef<std::path::Path> {
+    let Ok(config_file) = std::fs::read_to_string(&path) else {
+        return Ok(Config::default());
+    };
+    parse_config_str(&config_file).map_err(|mut errors| errors.remove(0))
+}
+
+// Parses a config file's contents, returning every problem with it rather
+// than stopping at the first
+fn parse_config_str(config_file: &str) -> Result<Config, Vec<RayhunterError>> {
+    let parsed_config: ConfigFile = toml::from_str(config_file)
+        .map_err(|err| vec![RayhunterError::ConfigFileParsingError(err)])?;
     let mut config = Config::default();
-    if let Ok(config_file) = std::fs::read_to_string(&path) {
-        let parsed_config: ConfigFile = toml::from_str(&config_file)
-            .map_err(RayhunterError::ConfigFileParsingError)?;
-        if let Some(path) = parsed_config.qmdl_store_path { config.qmdl_store_path = path }
-        if let Some(port) = parsed_config.port { config.port = port }
-        if let Some(readonly_mode) = parsed_config.readonly_mode { config.readonly_mode = readonly_mode }
-        if let Some(ui_level) = parsed_config.ui_level { config.ui_level = ui_level }
+    let mut errors = Vec::new();
+    if let Some(path) = parsed_config.qmdl_store_path { config.qmdl_store_path = path }
+    if let Some(port) = parsed_config.port { config.port = port }
+    if let Some(bind_address) = parsed_config.bind_address {
+        match bind_address.parse() {
+            Ok(bind_address) => config.bind_address = bind_address,
+            Err(_) => errors.push(RayhunterError::InvalidBindAddress(bind_address)),
+        }
+    }
+    if let Some(readonly_mode) = parsed_config.readonly_mode { config.readonly_mode = readonly_mode }
+    if let Some(ui_level) = parsed_config.ui_level { config.ui_level = ui_level }
+    if let Some(headless) = parsed_config.headless { config.headless = headless }
+    if let Some(brightness) = parsed_config.display_brightness { config.display_brightness = brightness.min(100) }
+    if let Some(redact_identities) = parsed_config.redact_identities { config.redact_identities = redact_identities }
+    if let Some(auto_resume_after_secs) = parsed_config.auto_resume_after_secs { config.auto_resume_after_secs = auto_resume_after_secs }
+    if let Some(analyzers) = parsed_config.analyzers { config.analyzers = analyzers }
+    if let Some(allowed_earfcns) = parsed_config.allowed_earfcns {
+        config.allowed_earfcns = allowed_earfcns.into_iter()
+            .map(|[start, end]| start..=end)
+            .collect();
+    }
+    if let Some(api_token) = parsed_config.api_token {
+        // an empty token would let anyone in, so treat it as unset
+        config.api_token = Some(api_token).filter(|token| !token.is_empty());
+    }
+    if let Some(protects_reads) = parsed_config.api_token_protects_reads { config.api_token_protects_reads = protects_reads }
+    if let Some(interval) = parsed_config.stats_stream_interval_secs { config.stats_stream_interval_secs = interval.max(1) }
+    if let Some(diag_device_path) = parsed_config.diag_device_path { config.diag_device_path = diag_device_path }
+    if let Some(framebuffer_path) = parsed_config.framebuffer_path { config.framebuffer_path = framebuffer_path }
+    if let Some(width) = parsed_config.framebuffer_width { config.framebuffer_width = width }
+    if let Some(height) = parsed_config.framebuffer_height { config.framebuffer_height = height }
+    if let Some(allow_debug_endpoints) = parsed_config.allow_debug_endpoints { config.allow_debug_endpoints = allow_debug_endpoints }
+    if let Some(store_compression) = parsed_config.store_compression { config.store_compression = store_compression }
+    if let Some(rotate_after_bytes) = parsed_config.rotate_after_bytes { config.rotate_after_bytes = rotate_after_bytes }
+    if let Some(rotate_after_secs) = parsed_config.rotate_after_secs { config.rotate_after_secs = rotate_after_secs }
+    let capture_profile = parsed_config.capture_profile.unwrap_or_default();
+    match capture_profile.log_codes(&parsed_config.custom_log_codes.unwrap_or_default()) {
+        Ok(log_codes) => config.capture_log_codes = log_codes,
+        Err(err) => errors.push(RayhunterError::InvalidCaptureProfile(err)),
+    }
+    if let Some(extra_diag_devices) = parsed_config.extra_diag_devices {
+        if let Err(err) = validate_device_labels(&extra_diag_devices) {
+            errors.push(err);
+        }
+        config.extra_diag_devices = extra_diag_devices;
+    }
+    if let Some(enable_metrics) = parsed_config.enable_metrics { config.enable_metrics = enable_metrics }
+    if let Some(display_qr) = parsed_config.display_qr { config.display_qr = display_qr }
+    if let Some(autostart) = parsed_config.autostart { config.autostart = autostart }
+    if let Some(delay_secs) = parsed_config.autostart_delay_secs { config.autostart_delay_secs = delay_secs }
+    if let Some(heartbeat_path) = parsed_config.heartbeat_path {
+        config.heartbeat_path = Some(heartbeat_path).filter(|path| !path.is_empty());
+    }
+    if let Some(interval_secs) = parsed_config.heartbeat_interval_secs { config.heartbeat_interval_secs = interval_secs }
+    if let Some(target) = parsed_config.gsmtap_udp_target.filter(|target| !target.is_empty()) {
+        match parse_gsmtap_udp_target(&target) {
+            Some(target) => config.gsmtap_udp_target = Some(target),
+            None => errors.push(RayhunterError::InvalidGsmtapUdpTarget(target)),
+        }
+    }
+    if let Some(origins) = parsed_config.cors_allowed_origins {
+        for origin in origins.iter().filter(|origin| !is_valid_origin(origin)) {
+            errors.push(RayhunterError::InvalidCorsOrigin(origin.clone()));
+        }
+        config.cors_allowed_origins = origins;
+    }
+    if let Some(discard) = parsed_config.discard_qmdl_after_analysis { config.discard_qmdl_after_analysis = discard }
+    if let Some(stall_timeout_secs) = parsed_config.stall_timeout_secs { config.stall_timeout_secs = stall_timeout_secs }
+    if let Err(err) = config.analyzers.validate() {
+        errors.push(RayhunterError::InvalidAnalyzerConfig(err));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
     }
     Ok(config)
 }
 
+/// Checks the config file at the given path, returning every problem found.
+/// Besides what [parse_config] checks, this makes sure the devices and store
+/// it points at exist, which otherwise only comes up once they're used.
+pub fn validate_config<P>(path: P) -> Vec<RayhunterError> where P: AsRef<std::path::Path> {
+    let config_file = match std::fs::read_to_string(&path) {
+        Ok(config_file) => config_file,
+        Err(err) => return vec![RayhunterError::TokioError(err)],
+    };
+    let config = match parse_config_str(&config_file) {
+        Ok(config) => config,
+        Err(errors) => return errors,
+    };
+    let mut errors = Vec::new();
+    if config.readonly_mode {
+        // the diag device isn't opened in readonly mode, but the store has to
+        // be there already
+        if !std::path::Path::new(&config.qmdl_store_path).join("manifest.toml").exists() {
+            errors.push(RayhunterError::NoStoreReadonlyMode(config.qmdl_store_path.clone()));
+        }
+    } else {
+        let device_paths = std::iter::once(&config.diag_device_path)
+            .chain(config.extra_diag_devices.iter().map(|device| &device.path));
+        for device_path in device_paths {
+            if !std::path::Path::new(device_path).exists() {
+                errors.push(RayhunterError::NoSuchDiagDevice(device_path.clone()));
+            }
+        }
+    }
+    errors
+}
+
+impl Config {
+    /// Where recordings from the given extra diag device are stored
+    pub fn extra_store_path(&self, device: &ExtraDiagDevice) -> String {
+        format!("{}/{}", self.qmdl_store_path, device.label)
+    }
+}
+
+// Takes "host:port", or just a host to use the usual GSMTAP port. IPv6
+// addresses with a port need brackets, e.g. "[::1]:4729".
+fn parse_gsmtap_udp_target(target: &str) -> Option<SocketAddr> {
+    target.parse().ok()
+        .or_else(|| target.parse().ok().map(|ip| SocketAddr::new(ip, GSMTAP_PORT)))
+}
+
+// Browsers send the Origin header as just the scheme, host and port, so
+// anything with a path (even a trailing "/") would never match
+fn is_valid_origin(origin: &str) -> bool {
+    let Some(host) = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://")) else {
+        return false;
+    };
+    !host.is_empty() && !host.contains('/')
+}
+
+// Labels end up in store paths and URLs, so keep them to simple, unique names
+fn validate_device_labels(devices: &[ExtraDiagDevice]) -> Result<(), RayhunterError> {
+    for (i, device) in devices.iter().enumerate() {
+        let valid_chars = device.label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if device.label.is_empty() || !valid_chars || devices[..i].iter().any(|other| other.label == device.label) {
+            return Err(RayhunterError::InvalidDiagDeviceLabel(device.label.clone()));
+        }
+    }
+    Ok(())
+}
+
 pub struct Args {
     pub config_path: String,
+    // a QMDL file to feed through the daemon instead of the diag device
+    pub replay_path: Option<String>,
+    // whether to replay at the pace the file was recorded at
+    pub replay_realtime: bool,
+    // check the config file and exit instead of starting the daemon
+    pub validate_config: bool,
+    // move the recordings from the store at this path into qmdl_store_path,
+    // then exit
+    pub migrate_store_from: Option<String>,
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    println!("Usage: {} [--replay /path/to/qmdl [--realtime]] /path/to/config/file", program);
+    println!("       {} --validate-config /path/to/config/file", program);
+    println!("       {} --migrate-store-from /old/qmdl/store/path /path/to/config/file", program);
+    std::process::exit(1);
 }
 
 pub fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} /path/to/config/file", args[0]);
-        std::process::exit(1);
+    let mut config_path = None;
+    let mut replay_path = None;
+    let mut replay_realtime = false;
+    let mut validate_config = false;
+    let mut migrate_store_from = None;
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--replay" => match rest.next() {
+                Some(path) => replay_path = Some(path.clone()),
+                None => exit_with_usage(&args[0]),
+            },
+            "--realtime" => replay_realtime = true,
+            "--validate-config" => validate_config = true,
+            "--migrate-store-from" => match rest.next() {
+                Some(path) => migrate_store_from = Some(path.clone()),
+                None => exit_with_usage(&args[0]),
+            },
+            _ if config_path.is_none() => config_path = Some(arg.clone()),
+            _ => exit_with_usage(&args[0]),
+        }
+    }
+    let Some(config_path) = config_path else {
+        exit_with_usage(&args[0]);
+    };
+    if replay_realtime && replay_path.is_none() {
+        exit_with_usage(&args[0]);
     }
     Args {
-        config_path: args[1].clone(),
+        config_path,
+        replay_path,
+        replay_realtime,
+        validate_config,
+        migrate_store_from,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_config(dir: &TempDir, contents: &str) -> std::path::PathBuf {
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_collects_every_error() {
+        let errors = parse_config_str(r#"
+            bind_address = "localhost"
+            capture_profile = "custom"
+            extra_diag_devices = [
+                { label = "modem", path = "/dev/diag1" },
+                { label = "modem", path = "/dev/diag2" },
+            ]
+        "#).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(&errors[0], RayhunterError::InvalidBindAddress(address) if address == "localhost"));
+        assert!(matches!(&errors[1], RayhunterError::InvalidCaptureProfile(_)));
+        assert!(matches!(&errors[2], RayhunterError::InvalidDiagDeviceLabel(label) if label == "modem"));
+    }
+
+    #[test]
+    fn test_invalid_analyzers() {
+        let errors = parse_config_str("[analyzers]\nredirect_loop_count = 1\n").unwrap_err();
+        assert!(matches!(&errors[..], [RayhunterError::InvalidAnalyzerConfig(_)]));
+        // misspelled analyzer settings don't get silently ignored
+        let errors = parse_config_str("[analyzers]\nredirect_loop_cuont = 3\n").unwrap_err();
+        assert!(matches!(&errors[..], [RayhunterError::ConfigFileParsingError(_)]));
+    }
+
+    #[test]
+    fn test_bind_address() {
+        // listening on every interface is the default
+        assert_eq!(parse_config_str("").unwrap().bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let config = parse_config_str("bind_address = \"127.0.0.1\"\n").unwrap();
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let config = parse_config_str("bind_address = \"::1\"\n").unwrap();
+        assert_eq!(config.bind_address, IpAddr::V6(std::net::Ipv6Addr::LOCALHOST));
+        // a host name isn't an address
+        let errors = parse_config_str("bind_address = \"localhost\"\n").unwrap_err();
+        assert!(matches!(&errors[..], [RayhunterError::InvalidBindAddress(address)] if address == "localhost"));
+    }
+
+    #[test]
+    fn test_gsmtap_udp_target() {
+        let config = parse_config_str("gsmtap_udp_target = \"192.168.1.50\"\n").unwrap();
+        assert_eq!(config.gsmtap_udp_target, Some("192.168.1.50:4729".parse().unwrap()));
+        let config = parse_config_str("gsmtap_udp_target = \"[::1]:5000\"\n").unwrap();
+        assert_eq!(config.gsmtap_udp_target, Some("[::1]:5000".parse().unwrap()));
+        assert_eq!(parse_config_str("gsmtap_udp_target = \"\"\n").unwrap().gsmtap_udp_target, None);
+        let errors = parse_config_str("gsmtap_udp_target = \"laptop:4729\"\n").unwrap_err();
+        assert!(matches!(&errors[..], [RayhunterError::InvalidGsmtapUdpTarget(_)]));
+    }
+
+    #[test]
+    fn test_cors_allowed_origins() {
+        let config = parse_config_str("cors_allowed_origins = [\"http://192.168.1.2:3000\", \"https://example.com\"]\n").unwrap();
+        assert_eq!(config.cors_allowed_origins, vec!["http://192.168.1.2:3000", "https://example.com"]);
+        let errors = parse_config_str("cors_allowed_origins = [\"http://example.com/\", \"example.com\"]\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], RayhunterError::InvalidCorsOrigin(origin) if origin == "http://example.com/"));
+    }
+
+    #[test]
+    fn test_parse_config_returns_first_error() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = write_config(&dir, "bind_address = \"nope\"\ncapture_profile = \"custom\"\n");
+        assert!(matches!(parse_config(&path), Err(RayhunterError::InvalidBindAddress(_))));
+    }
+
+    #[test]
+    fn test_validate_missing_devices() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = write_config(&dir, r#"
+            diag_device_path = "/dev/does-not-exist"
+            extra_diag_devices = [{ label = "modem1", path = "/dev/also-does-not-exist" }]
+        "#);
+        let errors = validate_config(&path);
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], RayhunterError::NoSuchDiagDevice(path) if path == "/dev/does-not-exist"));
+        assert!(matches!(&errors[1], RayhunterError::NoSuchDiagDevice(path) if path == "/dev/also-does-not-exist"));
+    }
+
+    #[test]
+    fn test_validate_readonly_without_store() {
+        let dir = TempDir::new("config_test").unwrap();
+        let store_path = dir.path().join("qmdl");
+        let path = write_config(&dir, &format!("readonly_mode = true\nqmdl_store_path = {:?}\n", store_path));
+        assert!(matches!(&validate_config(&path)[..], [RayhunterError::NoStoreReadonlyMode(_)]));
+    }
+
+    #[test]
+    fn test_validate_valid_config() {
+        let dir = TempDir::new("config_test").unwrap();
+        // any file will do for a diag device here
+        let device_path = dir.path().join("diag");
+        std::fs::write(&device_path, "").unwrap();
+        let path = write_config(&dir, &format!("diag_device_path = {:?}\nport = 8081\n", device_path));
+        assert!(validate_config(&path).is_empty());
+        assert!(!validate_config(dir.path().join("missing.toml")).is_empty());
     }
 }
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::header::{ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY};
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+pub struct CorsPolicy {
+    // origins like "http://192.168.1.2:3000" whose pages may call the API.
+    // Anything else is left to the browser's same-origin policy.
+    pub allowed_origins: Vec<String>,
+}
+
+impl CorsPolicy {
+    fn allowed_origin(&self, request: &Request) -> Option<HeaderValue> {
+        let origin = request.headers().get(ORIGIN)?;
+        let origin_str = origin.to_str().ok()?;
+        self.allowed_origins.iter()
+            .any(|allowed| allowed == origin_str)
+            .then(|| origin.clone())
+    }
+}
+
+// Middleware which adds CORS headers for allowed origins, and answers their
+// preflight requests itself. It has to sit outside require_api_token, since
+// browsers never send the token with a preflight.
+pub async fn apply_cors(State(cors): State<Arc<CorsPolicy>>, request: Request, next: Next) -> Response {
+    let Some(origin) = cors.allowed_origin(&request) else {
+        return next.run(request).await;
+    };
+    let is_preflight = request.method() == Method::OPTIONS &&
+        request.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = if is_preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, HEAD, POST"));
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("authorization, content-type"));
+        headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("600"));
+        response
+    } else {
+        next.run(request).await
+    };
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.append(VARY, HeaderValue::from_static("origin"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{require_api_token, ApiAuth};
+    use axum::body::Body;
+    use axum::http::HeaderMap;
+    use axum::middleware;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        let auth = Arc::new(ApiAuth {
+            token: "hunter2".to_string(),
+            protect_reads: false,
+        });
+        let cors = Arc::new(CorsPolicy {
+            allowed_origins: vec!["http://192.168.1.2:3000".to_string()],
+        });
+        Router::new()
+            .route("/api/qmdl-manifest", get(|| async { "manifest" }))
+            .route("/api/start-recording", post(|| async { "started" }))
+            .layer(middleware::from_fn_with_state(auth, require_api_token))
+            .layer(middleware::from_fn_with_state(cors, apply_cors))
+    }
+
+    async fn send(method: Method, uri: &str, origin: &str) -> (StatusCode, HeaderMap) {
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .header(ORIGIN, origin);
+        if method == Method::OPTIONS {
+            request = request.header(ACCESS_CONTROL_REQUEST_METHOD, "POST");
+        }
+        let response = router().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        (response.status(), response.headers().clone())
+    }
+
+    #[tokio::test]
+    async fn test_allowed_origin() {
+        let (status, headers) = send(Method::GET, "/api/qmdl-manifest", "http://192.168.1.2:3000").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "http://192.168.1.2:3000");
+
+        // preflights get through even though they don't carry the token
+        let (status, headers) = send(Method::OPTIONS, "/api/start-recording", "http://192.168.1.2:3000").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "http://192.168.1.2:3000");
+        assert!(headers[ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("authorization"));
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_origin() {
+        let (status, headers) = send(Method::GET, "/api/qmdl-manifest", "http://evil.example").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let (status, headers) = send(Method::OPTIONS, "/api/start-recording", "http://evil.example").await;
+        assert_ne!(status, StatusCode::NO_CONTENT);
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
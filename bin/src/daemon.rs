@@ -1,24 +1,42 @@
+mod auth;
 mod config;
+mod cors;
 mod error;
+mod evidence;
 mod pcap;
 mod server;
 mod stats;
 mod qmdl_store;
 mod diag;
 mod framebuffer;
+mod gsmtap_udp;
+mod heartbeat;
+mod qr;
+mod replay;
+mod zip;
 
-use crate::config::{parse_config, parse_args};
+use crate::auth::{require_api_token, ApiAuth};
+use crate::cors::{apply_cors, CorsPolicy};
+use crate::config::{parse_config, parse_args, validate_config};
 use crate::diag::run_diag_read_thread;
 use crate::qmdl_store::RecordingStore;
-use crate::server::{ServerState, get_qmdl, serve_static};
+use crate::server::{ServerState, get_config_validation, get_qmdl, get_recording_notes, get_version, protect_recording, serve_static, set_recording_notes};
 use crate::pcap::get_pcap;
-use crate::stats::get_system_stats;
+use crate::stats::{get_analyzers, get_cell_summary, get_current_cell_sibs, get_recording_diff, get_metrics, get_signal_series, get_stats_stream, get_system_stats, DiagCounters, EventCounts};
 use crate::error::RayhunterError;
+use crate::evidence::get_evidence_bundle;
 use crate::framebuffer::Framebuffer;
+use crate::gsmtap_udp::GsmtapUdpSender;
+use crate::heartbeat::Heartbeat;
+use crate::qr::QrCode;
+use crate::replay::replay_stream;
 
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
 use axum::response::Redirect;
-use diag::{get_analysis_report, start_recording, stop_recording, DiagDeviceCtrlMessage};
+use diag::{DiagSource, DiagThreadOptions, InitialRecordingState, RotationPolicy, get_analysis_ndjson, get_analysis_report, import_recording, reanalyze_recording, schedule_autostart, start_recording, stop_recording, trigger_warning, DiagDeviceCtrlMessage, MAX_IMPORT_SIZE_BYTES};
 use log::{info, error};
+use rayhunter::cellular_data::CellularData;
 use rayhunter::diag_device::DiagDevice;
 use axum::routing::{get, post};
 use axum::Router;
@@ -27,42 +45,190 @@ use tokio::sync::mpsc::{self, Sender};
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::task::JoinHandle;
 use tokio_util::task::TaskTracker;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::io::Write;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
-use tokio::sync::{RwLock, oneshot};
+use tokio::sync::{Mutex, RwLock, oneshot};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use include_dir::{include_dir, Dir};
 
-// Runs the axum server, taking all the elements needed to build up our
-// ServerState and a oneshot Receiver that'll fire when it's time to shutdown
-// (i.e. user hit ctrl+c)
-async fn run_server(
+// Everything the daemon keeps for one diag device: its recording store, the
+// channel controlling its diag read thread, and the live state the API reports
+#[derive(Clone)]
+struct Monitor {
+    qmdl_store_lock: Arc<RwLock<RecordingStore>>,
+    diag_device_sender: Sender<DiagDeviceCtrlMessage>,
+    analyzer_event_counts: Arc<RwLock<Vec<EventCounts>>>,
+    current_cell: Arc<RwLock<CellularData>>,
+    diag_counters: Arc<DiagCounters>,
+}
+
+// Loads or creates the store at `store_path`, and if there's a diag source
+// (i.e. we're not in readonly mode), starts recording from it
+async fn start_monitor(
     task_tracker: &TaskTracker,
     config: &config::Config,
-    qmdl_store_lock: Arc<RwLock<RecordingStore>>,
-    server_shutdown_rx: oneshot::Receiver<()>,
-    diag_device_sender: Sender<DiagDeviceCtrlMessage>
-) -> JoinHandle<()> {
+    store_path: &str,
+    source: Option<DiagSource>,
+    heartbeat: Option<Heartbeat>,
+    gsmtap_udp: Option<GsmtapUdpSender>,
+) -> Result<Monitor, RayhunterError> {
+    let mut qmdl_store = init_qmdl_store(store_path, config.readonly_mode).await?;
+    qmdl_store.discard_qmdl_after_analysis = config.discard_qmdl_after_analysis;
+    let monitor = Monitor {
+        qmdl_store_lock: Arc::new(RwLock::new(qmdl_store)),
+        diag_device_sender: mpsc::channel(1).0,
+        analyzer_event_counts: Arc::new(RwLock::new(Vec::new())),
+        current_cell: Arc::new(RwLock::new(CellularData::default())),
+        diag_counters: Arc::new(DiagCounters::default()),
+    };
+    let Some(source) = source else {
+        return Ok(monitor);
+    };
+    let (tx, rx) = mpsc::channel::<DiagDeviceCtrlMessage>(1);
+    run_diag_read_thread(task_tracker, source, rx, monitor.qmdl_store_lock.clone(), DiagThreadOptions {
+        analyzer_event_counts: monitor.analyzer_event_counts.clone(),
+        current_cell: monitor.current_cell.clone(),
+        diag_counters: monitor.diag_counters.clone(),
+        analyzer_config: config.analyzers.clone(),
+        allowed_earfcns: config.allowed_earfcns.clone(),
+        store_compression: config.store_compression,
+        rotation: RotationPolicy {
+            after_bytes: config.rotate_after_bytes,
+            after_secs: config.rotate_after_secs,
+        },
+        initial_state: InitialRecordingState::new(config.autostart, config.autostart_delay_secs),
+        heartbeat,
+        gsmtap_udp,
+        stall_timeout: stall_timeout(config),
+    });
+    Ok(Monitor { diag_device_sender: tx, ..monitor })
+}
+
+fn stall_timeout(config: &config::Config) -> Option<Duration> {
+    (config.stall_timeout_secs > 0).then_some(Duration::from_secs(config.stall_timeout_secs))
+}
+
+async fn open_diag_device(config: &config::Config, path: &str) -> Result<DiagSource, RayhunterError> {
+    let mut dev = DiagDevice::new(path).await
+        .map_err(RayhunterError::DiagInitError)?;
+    dev.set_log_codes(config.capture_log_codes.clone());
+    dev.config_logs().await
+        .map_err(RayhunterError::DiagInitError)?;
+    Ok(DiagSource::Device(dev))
+}
+
+// The API for a single diag device, which is served at /api for the main
+// device and under /devices/<label> for any extras
+fn api_router(task_tracker: &TaskTracker, config: &config::Config, config_path: &str, monitor: Monitor) -> Router {
+    let initial_state = InitialRecordingState::new(config.autostart, config.autostart_delay_secs);
     let state = Arc::new(ServerState {
-        qmdl_store_lock,
-        diag_device_ctrl_sender: diag_device_sender,
-        readonly_mode: config.readonly_mode
+        qmdl_store_lock: monitor.qmdl_store_lock,
+        diag_device_ctrl_sender: monitor.diag_device_sender,
+        readonly_mode: config.readonly_mode,
+        redact_identities: config.redact_identities,
+        auto_resume_after_secs: config.auto_resume_after_secs,
+        auto_resume_task: Mutex::new(None),
+        armed: AtomicBool::new(!config.readonly_mode && initial_state == InitialRecordingState::Armed),
+        analyzer_event_counts: monitor.analyzer_event_counts,
+        analyzer_config: config.analyzers.clone(),
+        stats_stream_interval_secs: config.stats_stream_interval_secs,
+        allow_debug_endpoints: config.allow_debug_endpoints,
+        store_compression: config.store_compression,
+        current_cell: monitor.current_cell,
+        diag_counters: monitor.diag_counters,
+        config_path: config_path.to_string(),
+        task_tracker: task_tracker.clone(),
+        reanalyzing: Mutex::new(HashSet::new()),
     });
+    if let (InitialRecordingState::Delayed(delay), false) = (initial_state, config.readonly_mode) {
+        info!("recording will start in {} seconds", delay.as_secs());
+        schedule_autostart(state.clone(), delay);
+    }
 
-    let app = Router::new()
+    let mut router = Router::new()
         .route("/api/pcap/*name", get(get_pcap))
+        .route("/api/evidence/:name", get(get_evidence_bundle))
         .route("/api/qmdl/*name", get(get_qmdl))
         .route("/api/system-stats", get(get_system_stats))
+        .route("/api/version", get(get_version))
+        .route("/api/config/validate", get(get_config_validation))
+        .route("/api/ws/stats", get(get_stats_stream))
         .route("/api/qmdl-manifest", get(get_qmdl_manifest))
         .route("/api/start-recording", post(start_recording))
         .route("/api/stop-recording", post(stop_recording))
+        .route("/api/debug/trigger-warning", post(trigger_warning))
+        .route("/api/import-recording", post(import_recording)
+            .layer(DefaultBodyLimit::max(MAX_IMPORT_SIZE_BYTES)))
         .route("/api/analysis-report", get(get_analysis_report))
+        .route("/api/analysis-ndjson/*name", get(get_analysis_ndjson))
+        .route("/api/analysis/:name", post(reanalyze_recording))
+        .route("/api/analyzers", get(get_analyzers))
+        .route("/api/current-cell/sibs", get(get_current_cell_sibs))
+        .route("/api/recording/:name/cell-summary", get(get_cell_summary))
+        .route("/api/recording/:name/signal-series", get(get_signal_series))
+        .route("/api/diff", get(get_recording_diff))
+        .route("/api/recording/:name/notes", get(get_recording_notes).post(set_recording_notes))
+        .route("/api/recording/:name/protect", post(protect_recording));
+    if config.enable_metrics {
+        router = router.route("/metrics", get(get_metrics));
+    }
+    let router = router.with_state(state);
+    // layered here rather than on the whole app, so every device's copy of
+    // the API is covered wherever it's nested
+    match &config.api_token {
+        Some(token) => {
+            let auth = Arc::new(ApiAuth {
+                token: token.clone(),
+                protect_reads: config.api_token_protects_reads,
+            });
+            router.route_layer(middleware::from_fn_with_state(auth, require_api_token))
+        },
+        None => router,
+    }
+}
+
+// The whole web app: every monitor's API, plus the static web UI
+fn app_router(
+    task_tracker: &TaskTracker,
+    config: &config::Config,
+    config_path: &str,
+    monitor: Monitor,
+    extra_monitors: Vec<(String, Monitor)>,
+) -> Router {
+    let mut app = api_router(task_tracker, config, config_path, monitor)
         .route("/", get(|| async { Redirect::permanent("/index.html") }))
-        .route("/*path", get(serve_static))
-        .with_state(state);
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+        .route("/*path", get(serve_static));
+    for (label, extra_monitor) in extra_monitors {
+        app = app.nest(&format!("/devices/{}", label), api_router(task_tracker, config, config_path, extra_monitor));
+    }
+    // added last so it runs first, before preflights hit the token check
+    if !config.cors_allowed_origins.is_empty() {
+        let cors = Arc::new(CorsPolicy {
+            allowed_origins: config.cors_allowed_origins.clone(),
+        });
+        app = app.layer(middleware::from_fn_with_state(cors, apply_cors));
+    }
+    app
+}
+
+// Runs the axum server, taking the monitors whose APIs it serves and a
+// oneshot Receiver that'll fire when it's time to shutdown (i.e. user hit
+// ctrl+c)
+async fn run_server(
+    task_tracker: &TaskTracker,
+    config: &config::Config,
+    config_path: &str,
+    monitor: Monitor,
+    extra_monitors: Vec<(String, Monitor)>,
+    server_shutdown_rx: oneshot::Receiver<()>,
+) -> JoinHandle<()> {
+    let app = app_router(task_tracker, config, config_path, monitor, extra_monitors);
+    let addr = SocketAddr::new(config.bind_address, config.port);
     let listener = TcpListener::bind(&addr).await.unwrap();
     task_tracker.spawn(async move {
         info!("The orca is hunting for stingrays...");
@@ -79,11 +245,11 @@ async fn server_shutdown_signal(server_shutdown_rx: oneshot::Receiver<()>) {
 
 // Loads a QmdlStore if one exists, and if not, only create one if we're not in
 // readonly mode.
-async fn init_qmdl_store(config: &config::Config) -> Result<RecordingStore, RayhunterError> {
-    match (RecordingStore::exists(&config.qmdl_store_path).await?, config.readonly_mode) {
-        (true, _) => Ok(RecordingStore::load(&config.qmdl_store_path).await?),
-        (false, false) => Ok(RecordingStore::create(&config.qmdl_store_path).await?),
-        (false, true) => Err(RayhunterError::NoStoreReadonlyMode(config.qmdl_store_path.clone())),
+async fn init_qmdl_store(store_path: &str, readonly_mode: bool) -> Result<RecordingStore, RayhunterError> {
+    match (RecordingStore::exists(store_path).await?, readonly_mode) {
+        (true, _) => Ok(RecordingStore::load(store_path).await?),
+        (false, false) => Ok(RecordingStore::create(store_path).await?),
+        (false, true) => Err(RayhunterError::NoStoreReadonlyMode(store_path.to_string())),
     }
 }
 
@@ -92,28 +258,36 @@ async fn init_qmdl_store(config: &config::Config) -> Result<RecordingStore, Rayh
 // shutdown
 fn run_ctrl_c_thread(
     task_tracker: &TaskTracker,
-    diag_device_sender: Sender<DiagDeviceCtrlMessage>,
+    monitors: Vec<Monitor>,
     server_shutdown_tx: oneshot::Sender<()>,
-    ui_shutdown_tx: oneshot::Sender<()>,
-    qmdl_store_lock: Arc<RwLock<RecordingStore>>
+    maybe_ui_shutdown_tx: Option<oneshot::Sender<()>>,
 ) -> JoinHandle<Result<(), RayhunterError>> {
     task_tracker.spawn(async move {
         match tokio::signal::ctrl_c().await {
             Ok(()) => {
-                let mut qmdl_store = qmdl_store_lock.write().await;
-                if qmdl_store.current_entry.is_some() {
-                    info!("Closing current QMDL entry...");
-                    qmdl_store.close_current_entry().await?;
-                    info!("Done!");
+                for monitor in &monitors {
+                    let mut qmdl_store = monitor.qmdl_store_lock.write().await;
+                    if qmdl_store.current_entry.is_some() {
+                        info!("Closing current QMDL entry...");
+                        // the diag thread's writers are still open, so this
+                        // never discards the entry's QMDL
+                        qmdl_store.close_current_entry().await?;
+                        info!("Done!");
+                    }
                 }
 
                 server_shutdown_tx.send(())
                     .expect("couldn't send server shutdown signal");
-                info!("sending UI shutdown");
-                ui_shutdown_tx.send(())
-                    .expect("couldn't send ui shutdown signal");
-                diag_device_sender.send(DiagDeviceCtrlMessage::Exit).await
-                    .expect("couldn't send Exit message to diag thread");
+                if let Some(ui_shutdown_tx) = maybe_ui_shutdown_tx {
+                    info!("sending UI shutdown");
+                    ui_shutdown_tx.send(())
+                        .expect("couldn't send ui shutdown signal");
+                }
+                for monitor in &monitors {
+                    // in readonly mode there's no diag thread listening, so
+                    // this only fails when there's nothing to tell
+                    let _ = monitor.diag_device_sender.send(DiagDeviceCtrlMessage::Exit).await;
+                }
             },
             Err(err) => {
                 error!("Unable to listen for shutdown signal: {}", err);
@@ -123,15 +297,47 @@ fn run_ctrl_c_thread(
     })
 }
 
-async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, mut ui_shutdown_rx: oneshot::Receiver<()>){
+// how long the QR code stays up at boot before the normal display takes over
+const QR_DISPLAY_DURATION: Duration = Duration::from_secs(30);
+
+// The address to reach the web UI at from a device on the hotspot. The API
+// token goes in the fragment, which browsers don't send to the server, and
+// the web UI saves it from there.
+fn web_ui_url(config: &config::Config) -> String {
+    let ip = if config.bind_address.is_unspecified() {
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))
+    } else {
+        config.bind_address
+    };
+    let mut url = format!("http://{}/", SocketAddr::new(ip, config.port));
+    if let Some(token) = &config.api_token {
+        url.push_str(&format!("#token={token}"));
+    }
+    url
+}
+
+async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, qmdl_store_lock: Arc<RwLock<RecordingStore>>, mut ui_shutdown_rx: oneshot::Receiver<()>){
     static IMAGE_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/static/images/");
     let display_level = config.ui_level;
+    let display_brightness = config.display_brightness;
+    let framebuffer_path = config.framebuffer_path.clone();
+    let (framebuffer_width, framebuffer_height) = (config.framebuffer_width, config.framebuffer_height);
     if display_level == 0 {
         info!("Invisible mode, not spawning UI.");
     }
+    let qr = if config.display_qr && display_level != 0 {
+        let qr = QrCode::encode(web_ui_url(config).as_bytes());
+        if qr.is_none() {
+            error!("web UI URL is too long to show as a QR code");
+        }
+        qr
+    } else {
+        None
+    };
 
     task_tracker.spawn_blocking(move || {
-        let mut fb: Framebuffer = Framebuffer::new();
+        let mut fb: Framebuffer = Framebuffer::new(framebuffer_path, framebuffer_width, framebuffer_height);
+        fb.set_brightness(display_brightness);
         // this feels wrong, is there a more rusty way to do this?
         let mut img: Option<&[u8]> = None;
         if display_level == 2 {
@@ -139,6 +345,10 @@ async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, mut ui_
         } else if display_level == 3 {
             img = Some(IMAGE_DIR.get_file("eff.png").expect("failed to read eff.png").contents());
         }
+        let qr_until = qr.map(|qr| {
+            fb.draw_qr(&qr);
+            Instant::now() + QR_DISPLAY_DURATION
+        });
         loop {
             match ui_shutdown_rx.try_recv() {
                 Ok(_) => {
@@ -149,6 +359,10 @@ async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, mut ui_
                 Err(e) => panic!("error receiving shutdown message: {e}")
             
             }
+            if qr_until.is_some_and(|until| Instant::now() < until) {
+                sleep(Duration::from_millis(100));
+                continue;
+            }
             match display_level  {
                 2 => {
                     fb.draw_gif(img.unwrap());
@@ -164,7 +378,13 @@ async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, mut ui_
                     fb.draw_line(framebuffer::Color565::Cyan, 25);
                 },
                 1 | _ => {
-                    fb.draw_line(framebuffer::Color565::Green, 2);
+                    // yellow while not recording, e.g. armed and waiting for
+                    // a recording to be started
+                    let color = match qmdl_store_lock.blocking_read().current_entry {
+                        Some(_) => framebuffer::Color565::Green,
+                        None => framebuffer::Color565::Yellow,
+                    };
+                    fb.draw_line(color, 2);
                 },
             };
             sleep(Duration::from_millis(100));
@@ -173,35 +393,211 @@ async fn update_ui(task_tracker: &TaskTracker,  config: &config::Config, mut ui_
 
 }
 
+// Logs are human-readable by default, but setting RAYHUNTER_LOG_FORMAT=json
+// emits one JSON object per line for log collectors. RUST_LOG filtering works
+// the same either way.
+fn init_logging() {
+    let mut builder = env_logger::Builder::from_default_env();
+    if std::env::var("RAYHUNTER_LOG_FORMAT").is_ok_and(|format| format == "json") {
+        builder.format(|buf, record| {
+            writeln!(buf, "{}", json_log_line(record, chrono::Local::now()))
+        });
+    }
+    builder.init();
+}
+
+fn json_log_line(record: &log::Record, timestamp: chrono::DateTime<chrono::Local>) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": timestamp.to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "module": record.module_path(),
+        "message": record.args().to_string(),
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), RayhunterError> {
-    env_logger::init();
+    init_logging();
 
     let args = parse_args();
+    if args.validate_config {
+        let errors = validate_config(&args.config_path);
+        for error in &errors {
+            println!("{}", error);
+        }
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{} is valid", args.config_path);
+        return Ok(());
+    }
     let config = parse_config(&args.config_path)?;
+    if let Some(old_store_path) = &args.migrate_store_from {
+        let store = RwLock::new(init_qmdl_store(&config.qmdl_store_path, false).await?);
+        let migrated = RecordingStore::migrate_from(&store, old_store_path).await?;
+        println!("moved {} recordings from {} to {}", migrated, old_store_path, config.qmdl_store_path);
+        return Ok(());
+    }
 
     // TaskTrackers give us an interface to spawn tokio threads, and then
     // eventually await all of them ending
     let task_tracker = TaskTracker::new();
 
-    let qmdl_store_lock = Arc::new(RwLock::new(init_qmdl_store(&config).await?));
-    let (tx, rx) = mpsc::channel::<DiagDeviceCtrlMessage>(1);
-    if !config.readonly_mode {
-        let mut dev = DiagDevice::new().await
-            .map_err(RayhunterError::DiagInitError)?;
-        dev.config_logs().await
-            .map_err(RayhunterError::DiagInitError)?;
-
-        run_diag_read_thread(&task_tracker, dev, rx, qmdl_store_lock.clone());
+    let source = match (&args.replay_path, config.readonly_mode) {
+        (_, true) => None,
+        (Some(replay_path), false) => {
+            info!("replaying {} instead of reading the diag device", replay_path);
+            Some(DiagSource::Replay(replay_stream(replay_path, args.replay_realtime).await?))
+        },
+        (None, false) => Some(open_diag_device(&config, &config.diag_device_path).await?),
+    };
+    // the heartbeat and GSMTAP stream follow the main diag device only
+    let heartbeat = config.heartbeat_path.as_ref()
+        .map(|path| Heartbeat::new(path, Duration::from_secs(config.heartbeat_interval_secs)));
+    let gsmtap_udp = match config.gsmtap_udp_target {
+        Some(target) => {
+            info!("streaming GSMTAP to {}", target);
+            Some(GsmtapUdpSender::new(target).await?)
+        },
+        None => None,
+    };
+    let monitor = start_monitor(&task_tracker, &config, &config.qmdl_store_path, source, heartbeat, gsmtap_udp).await?;
+    let mut extra_monitors = Vec::new();
+    for device in &config.extra_diag_devices {
+        info!("also monitoring diag device {} ({})", device.label, device.path);
+        let source = match config.readonly_mode {
+            true => None,
+            false => Some(open_diag_device(&config, &device.path).await?),
+        };
+        let extra_monitor = start_monitor(&task_tracker, &config, &config.extra_store_path(device), source, None, None).await?;
+        extra_monitors.push((device.label.clone(), extra_monitor));
     }
-    let (ui_shutdown_tx, ui_shutdown_rx) = oneshot::channel();
+    // headless devices have no display to draw to, so skip the UI entirely
+    let (maybe_ui_shutdown_tx, maybe_ui_shutdown_rx) = if config.headless {
+        info!("Headless mode, not spawning UI.");
+        (None, None)
+    } else {
+        let (ui_shutdown_tx, ui_shutdown_rx) = oneshot::channel();
+        (Some(ui_shutdown_tx), Some(ui_shutdown_rx))
+    };
     let (server_shutdown_tx, server_shutdown_rx) = oneshot::channel::<()>();
-    run_ctrl_c_thread(&task_tracker, tx.clone(), server_shutdown_tx, ui_shutdown_tx, qmdl_store_lock.clone());
-    run_server(&task_tracker, &config, qmdl_store_lock.clone(), server_shutdown_rx, tx).await;
-    update_ui(&task_tracker, &config, ui_shutdown_rx).await;
+    let all_monitors = std::iter::once(monitor.clone())
+        .chain(extra_monitors.iter().map(|(_, extra_monitor)| extra_monitor.clone()))
+        .collect();
+    run_ctrl_c_thread(&task_tracker, all_monitors, server_shutdown_tx, maybe_ui_shutdown_tx);
+    let qmdl_store_lock = monitor.qmdl_store_lock.clone();
+    run_server(&task_tracker, &config, &args.config_path, monitor, extra_monitors, server_shutdown_rx).await;
+    if let Some(ui_shutdown_rx) = maybe_ui_shutdown_rx {
+        update_ui(&task_tracker, &config, qmdl_store_lock, ui_shutdown_rx).await;
+    }
 
     task_tracker.close();
     task_tracker.wait().await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExtraDiagDevice;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_extra_devices_get_their_own_stores() {
+        let dir = TempDir::new("daemon_test").unwrap();
+        let config = config::Config {
+            qmdl_store_path: dir.path().to_str().unwrap().to_string(),
+            extra_diag_devices: vec![
+                ExtraDiagDevice { label: "modem1".to_string(), path: "/dev/null".to_string() },
+                ExtraDiagDevice { label: "modem2".to_string(), path: "/dev/null".to_string() },
+            ],
+            ..Default::default()
+        };
+        let task_tracker = TaskTracker::new();
+        let mut monitors = Vec::new();
+        for device in &config.extra_diag_devices {
+            monitors.push(start_monitor(&task_tracker, &config, &config.extra_store_path(device), None, None, None).await.unwrap());
+        }
+        assert!(RecordingStore::exists(dir.path().join("modem1")).await.unwrap());
+        assert!(RecordingStore::exists(dir.path().join("modem2")).await.unwrap());
+        // recording from one device doesn't touch the other's store
+        monitors[0].qmdl_store_lock.write().await.new_entry().await.unwrap();
+        assert_eq!(monitors[0].qmdl_store_lock.read().await.manifest.entries.len(), 1);
+        assert!(monitors[1].qmdl_store_lock.read().await.manifest.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api_token_covers_every_api() {
+        use axum::body::Body;
+        use axum::http::{header::AUTHORIZATION, Method, Request, StatusCode};
+        use tower::ServiceExt;
+
+        let dir = TempDir::new("daemon_test").unwrap();
+        let config = config::Config {
+            qmdl_store_path: dir.path().to_str().unwrap().to_string(),
+            extra_diag_devices: vec![
+                ExtraDiagDevice { label: "modem1".to_string(), path: "/dev/null".to_string() },
+            ],
+            api_token: Some("hunter2".to_string()),
+            api_token_protects_reads: true,
+            enable_metrics: true,
+            ..Default::default()
+        };
+        let task_tracker = TaskTracker::new();
+        let monitor = start_monitor(&task_tracker, &config, &config.qmdl_store_path, None, None, None).await.unwrap();
+        let extra_monitor = start_monitor(&task_tracker, &config, &config.extra_store_path(&config.extra_diag_devices[0]), None, None, None).await.unwrap();
+        let app = app_router(&task_tracker, &config, "config.toml", monitor, vec![("modem1".to_string(), extra_monitor)]);
+        let status = |method: Method, uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let app = app.clone();
+            let request = request.body(Body::empty()).unwrap();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status(Method::POST, "/api/start-recording", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::POST, "/devices/modem1/api/start-recording", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::GET, "/devices/modem1/api/qmdl-manifest", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::GET, "/metrics", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::GET, "/metrics", Some("hunter2")).await, StatusCode::OK);
+        assert_eq!(status(Method::GET, "/devices/modem1/api/qmdl-manifest", Some("hunter2")).await, StatusCode::OK);
+        // the web UI itself is always served
+        assert_eq!(status(Method::GET, "/index.html", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_web_ui_url() {
+        let config = config::Config::default();
+        assert_eq!(web_ui_url(&config), "http://192.168.1.1:8080/");
+        let config = config::Config {
+            bind_address: "10.0.0.2".parse().unwrap(),
+            port: 80,
+            api_token: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(web_ui_url(&config), "http://10.0.0.2:80/#token=hunter2");
+    }
+
+    #[test]
+    fn test_json_log_line() {
+        let timestamp = chrono::DateTime::parse_from_rfc3339("2024-01-01T12:00:00+00:00").unwrap()
+            .with_timezone(&chrono::Local);
+        let line = json_log_line(&log::Record::builder()
+            .args(format_args!("analyzer=\"{}\" {}", "IMSI Paging", "paged by IMSI"))
+            .level(log::Level::Warn)
+            .target("rayhunter::event")
+            .module_path(Some("rayhunter_daemon::diag"))
+            .build(), timestamp);
+        assert_eq!(line, serde_json::json!({
+            "timestamp": timestamp.to_rfc3339(),
+            "level": "WARN",
+            "target": "rayhunter::event",
+            "module": "rayhunter_daemon::diag",
+            "message": "analyzer=\"IMSI Paging\" paged by IMSI",
+        }));
+    }
+}
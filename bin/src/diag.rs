@@ -1,31 +1,172 @@
+use std::borrow::Cow;
+use std::io::SeekFrom;
+use std::ops::RangeInclusive;
 use std::pin::pin;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
-use axum::body::Body;
-use axum::extract::State;
+use axum::body::{Body, Bytes};
+use axum::extract::{Multipart, Path, State};
 use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use rayhunter::analysis::analyzer::Harness;
-use rayhunter::diag::{DataType, MessagesContainer};
-use rayhunter::diag_device::DiagDevice;
+use rayhunter::analysis::analyzer::{AnalysisRow, AnalyzerConfig, Event, EventType, Harness, PacketAnalysis, Severity};
+use chrono::{DateTime, Local};
+use rayhunter::cellular_data::CellularData;
+use rayhunter::diag::{DataType, Message, MessagesContainer};
+use rayhunter::diag_device::{DiagDevice, DiagDeviceError};
 use serde::Serialize;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::Receiver;
-use rayhunter::qmdl::QmdlWriter;
-use log::{debug, error, info};
+use tokio::task::JoinHandle;
+use rayhunter::gsmtap_parser;
+use rayhunter::qmdl::{QmdlCompression, QmdlReader, QmdlWriter};
+use log::{debug, error, info, warn};
 use tokio::fs::File;
-use tokio::io::{BufWriter, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufWriter, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 use tokio_util::task::TaskTracker;
+use futures::stream::BoxStream;
 use futures::{StreamExt, TryStreamExt};
 
-use crate::qmdl_store::RecordingStore;
-use crate::server::ServerState;
+use crate::gsmtap_udp::GsmtapUdpSender;
+use crate::heartbeat::Heartbeat;
+use crate::qmdl_store::{RecordingStore, RecordingStoreError};
+use crate::replay::ReplayStream;
+use crate::server::{ApiError, ApiErrorCode, ServerState};
+use crate::stats::{record_event_counts, DiagCounters, EventCounts};
+
+// imported QMDL files are held in memory while they're validated and written
+// out, so cap their size well below the device's available RAM
+pub const MAX_IMPORT_SIZE_BYTES: usize = 64 * 1024 * 1024;
+
+// how many times, and how often, to try re-opening the diag device after it
+// disappears (e.g. when the modem resets)
+const DIAG_REOPEN_ATTEMPTS: usize = 10;
+const DIAG_REOPEN_DELAY: Duration = Duration::from_secs(3);
+
+// Notices the diag device going quiet without an error, which happens when
+// the modem hangs. A watchdog without a timeout never fires.
+struct StallWatchdog {
+    timeout: Option<Duration>,
+    deadline: tokio::time::Instant,
+}
+
+impl StallWatchdog {
+    fn new(timeout: Option<Duration>) -> Self {
+        let mut watchdog = StallWatchdog { timeout, deadline: tokio::time::Instant::now() };
+        watchdog.reset();
+        watchdog
+    }
+
+    // pushes the deadline back, whenever a container arrives
+    fn reset(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.deadline = tokio::time::Instant::now() + timeout;
+        }
+    }
+
+    // resolves once nothing's arrived for the whole timeout
+    async fn stalled(&self) {
+        match self.timeout {
+            Some(_) => tokio::time::sleep_until(self.deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+// When to close the current recording and carry on in a new entry, so long
+// monitoring sessions are split into manageable files. 0 disables either
+// threshold.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RotationPolicy {
+    pub after_bytes: usize,
+    pub after_secs: u64,
+}
+
+impl RotationPolicy {
+    fn should_rotate(&self, qmdl_bytes_written: usize, recording_age: Duration) -> bool {
+        (self.after_bytes > 0 && qmdl_bytes_written >= self.after_bytes) ||
+            (self.after_secs > 0 && recording_age >= Duration::from_secs(self.after_secs))
+    }
+}
+
+// Whether to start recording as soon as the diag read thread starts, per
+// the autostart and autostart_delay_secs config options
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InitialRecordingState {
+    #[default]
+    Recording,
+    // recording starts after the delay, unless it's started or stopped
+    // manually first
+    Delayed(Duration),
+    // armed: nothing's recorded until a recording is started through the API
+    Armed,
+}
+
+impl InitialRecordingState {
+    pub fn new(autostart: bool, autostart_delay_secs: u64) -> Self {
+        match (autostart, autostart_delay_secs) {
+            (false, _) => InitialRecordingState::Armed,
+            (true, 0) => InitialRecordingState::Recording,
+            (true, delay_secs) => InitialRecordingState::Delayed(Duration::from_secs(delay_secs)),
+        }
+    }
+}
+
+// Where the diag read thread gets its messages from: either the real diag
+// device, or a recording being replayed
+pub enum DiagSource {
+    Device(DiagDevice),
+    Replay(ReplayStream),
+    // stands in for the diag device in tests, playing each stream in turn
+    // and moving on to the next one when it's re-opened
+    #[cfg(test)]
+    Scripted(std::collections::VecDeque<ReplayStream>),
+}
+
+impl DiagSource {
+    fn stream(&mut self) -> BoxStream<'_, Result<MessagesContainer, DiagDeviceError>> {
+        match self {
+            DiagSource::Device(dev) => dev.as_stream().into_stream().boxed(),
+            DiagSource::Replay(replay) => replay.by_ref().boxed(),
+            #[cfg(test)]
+            DiagSource::Scripted(streams) => streams.front_mut().expect("no scripted streams left").by_ref().boxed(),
+        }
+    }
+
+    // replays can't be re-opened, and going quiet is expected of them, since
+    // they just end
+    fn is_device(&self) -> bool {
+        !matches!(self, DiagSource::Replay(_))
+    }
+
+    async fn reopen(&mut self) -> Result<(), DiagDeviceError> {
+        match self {
+            DiagSource::Device(dev) => dev.reopen(DIAG_REOPEN_ATTEMPTS, DIAG_REOPEN_DELAY).await,
+            DiagSource::Replay(_) => unreachable!("replays can't be re-opened"),
+            #[cfg(test)]
+            DiagSource::Scripted(streams) => {
+                // like the real device, re-opening takes a moment
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                streams.pop_front();
+                if streams.is_empty() {
+                    return Err(DiagDeviceError::DeviceReadFailed(std::io::Error::other("out of scripted streams")));
+                }
+                Ok(())
+            },
+        }
+    }
+}
 
 pub enum DiagDeviceCtrlMessage {
     StopRecording,
     StartRecording((QmdlWriter<File>, File)),
+    // sent after the message that closed the given entry's writers, so the
+    // store can discard its QMDL if configured to
+    FinishEntry(usize),
+    TriggerWarning,
     Exit,
 }
 
@@ -42,10 +183,10 @@ struct AnalysisWriter {
 // lets us simply append new rows to the end without parsing the entire JSON
 // object beforehand.
 impl AnalysisWriter {
-    pub async fn new(file: File) -> Result<Self, std::io::Error> {
+    pub async fn new(file: File, analyzer_config: &AnalyzerConfig) -> Result<Self, std::io::Error> {
         let mut result = Self {
             writer: BufWriter::new(file),
-            harness: Harness::new_with_all_analyzers(),
+            harness: Harness::new_with_config(analyzer_config),
             bytes_written: 0,
         };
         let metadata = result.harness.get_metadata();
@@ -54,15 +195,66 @@ impl AnalysisWriter {
     }
 
     // Runs the analysis harness on the given container, serializing the results
-    // to the analysis file and returning the file's new length.
-    pub async fn analyze(&mut self, container: MessagesContainer) -> Result<usize, std::io::Error> {
+    // to the analysis file and returning the file's new length along with the
+    // analysis results.
+    pub async fn analyze(&mut self, container: MessagesContainer) -> Result<(usize, AnalysisRow), std::io::Error> {
         let row = self.harness.analyze_qmdl_messages(container);
         if !row.is_empty() {
             self.write(&row).await?;
         }
-        Ok(self.bytes_written)
+        self.log_events(&row);
+        Ok((self.bytes_written, row))
+    }
+
+    // Writes a made-up warning to the analysis file as if the first analyzer
+    // had raised it, for testing everything downstream of the analyzers
+    // without a real IMSI catcher around
+    pub async fn write_synthetic_warning(&mut self) -> Result<(usize, AnalysisRow), std::io::Error> {
+        let timestamp = Local::now().fixed_offset();
+        let mut events = vec![None; self.harness.get_names().len()];
+        if let Some(first) = events.first_mut() {
+            *first = Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::High },
+                message: "Synthetic warning triggered through /api/debug/trigger-warning".to_string(),
+            });
+        }
+        let row = AnalysisRow {
+            timestamp,
+            skipped_message_reasons: Vec::new(),
+            analysis: vec![PacketAnalysis {
+                timestamp,
+                message_type: None,
+                events,
+            }],
+        };
+        self.write(&row).await?;
+        self.log_events(&row);
+        Ok((self.bytes_written, row))
+    }
+
+    // Logs each event under its own target, so they can be picked out of the
+    // daemon's logs (e.g. by a log collector when using JSON logging)
+    fn log_events(&self, row: &AnalysisRow) {
+        if row.analysis.is_empty() {
+            return;
+        }
+        let names = self.harness.get_names();
+        for packet_analysis in &row.analysis {
+            for (name, event) in names.iter().zip(&packet_analysis.events) {
+                let Some(event) = event else { continue };
+                match &event.event_type {
+                    EventType::Informational => info!(target: "rayhunter::event",
+                        "analyzer=\"{}\" timestamp={} {}", name, packet_analysis.timestamp, event.message),
+                    EventType::QualitativeWarning { severity } => warn!(target: "rayhunter::event",
+                        "analyzer=\"{}\" severity={:?} timestamp={} {}", name, severity, packet_analysis.timestamp, event.message),
+                }
+            }
+        }
     }
 
+    // Each row goes out in a single write and is flushed straight away, so a
+    // daemon killed mid-row leaves at most one partial line at the end of the
+    // file, which readers skip with complete_lines_len
     async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), std::io::Error> {
         let mut value_str = serde_json::to_string(value).unwrap();
         value_str.push('\n');
@@ -79,127 +271,1216 @@ impl AnalysisWriter {
     }
 }
 
+// Keeps the live view of the serving cell up to date with any system
+// information in the container
+async fn update_current_cell(current_cell_lock: &RwLock<CellularData>, container: &MessagesContainer) {
+    let gsmtap_msgs: Vec<_> = container.clone().into_messages().into_iter()
+        .flatten()
+        .filter_map(|msg| gsmtap_parser::parse(msg).ok().flatten())
+        .collect();
+    if gsmtap_msgs.is_empty() {
+        return;
+    }
+    let mut current_cell = current_cell_lock.write().await;
+    for (_, gsmtap_msg) in &gsmtap_msgs {
+        current_cell.update_from_gsmtap(gsmtap_msg);
+    }
+}
+
+// Whether any of the container's messages parse as a log message, which is
+// what the heartbeat shows is still flowing, as opposed to e.g. responses to
+// our own requests
+fn has_log_message(container: &MessagesContainer) -> bool {
+    container.clone().into_messages().into_iter()
+        .flatten()
+        .any(|msg| matches!(msg, Message::Log { .. }))
+}
+
+// Drops any LTE RRC messages on EARFCNs outside allowed_earfcns from what's
+// written to the QMDL file. Only storage is filtered: the analyzers still see
+// every message, since a rogue cell is just as likely to be on an unexpected
+// EARFCN.
+fn messages_to_record<'a>(container: &'a MessagesContainer, allowed_earfcns: &[RangeInclusive<u32>]) -> Cow<'a, MessagesContainer> {
+    if allowed_earfcns.is_empty() {
+        return Cow::Borrowed(container);
+    }
+    let mut recorded = container.clone();
+    recorded.retain_messages(|msg| match msg.lte_rrc_earfcn() {
+        Some(earfcn) => allowed_earfcns.iter().any(|range| range.contains(&earfcn)),
+        None => true,
+    });
+    Cow::Owned(recorded)
+}
+
+// Finishes an entry whose writers the diag thread has closed. Failing to
+// discard its QMDL isn't worth stopping the thread over.
+async fn finish_entry(qmdl_store_lock: &RwLock<RecordingStore>, entry_index: usize) {
+    if let Err(err) = qmdl_store_lock.write().await.finish_entry(entry_index).await {
+        error!("failed to finish recording entry: {}", err);
+    }
+}
+
+// Everything the diag read thread needs besides its source, control channel
+// and recording store. The defaults record straight away with nothing extra
+// enabled, and give the thread its own counters and cell view.
+#[derive(Default)]
+pub struct DiagThreadOptions {
+    pub analyzer_event_counts: Arc<RwLock<Vec<EventCounts>>>,
+    pub current_cell: Arc<RwLock<CellularData>>,
+    pub diag_counters: Arc<DiagCounters>,
+    pub analyzer_config: AnalyzerConfig,
+    pub allowed_earfcns: Vec<RangeInclusive<u32>>,
+    pub store_compression: QmdlCompression,
+    pub rotation: RotationPolicy,
+    pub initial_state: InitialRecordingState,
+    pub heartbeat: Option<Heartbeat>,
+    pub gsmtap_udp: Option<GsmtapUdpSender>,
+    pub stall_timeout: Option<Duration>,
+}
+
 pub fn run_diag_read_thread(
     task_tracker: &TaskTracker,
-    mut dev: DiagDevice,
+    mut source: DiagSource,
     mut qmdl_file_rx: Receiver<DiagDeviceCtrlMessage>,
-    qmdl_store_lock: Arc<RwLock<RecordingStore>>
+    qmdl_store_lock: Arc<RwLock<RecordingStore>>,
+    options: DiagThreadOptions,
 ) {
+    let DiagThreadOptions {
+        analyzer_event_counts,
+        current_cell,
+        diag_counters,
+        analyzer_config,
+        allowed_earfcns,
+        store_compression,
+        rotation,
+        initial_state,
+        mut heartbeat,
+        gsmtap_udp,
+        stall_timeout,
+    } = options;
+    let stall_timeout = stall_timeout.filter(|_| source.is_device());
     task_tracker.spawn(async move {
-        let (initial_qmdl_file, initial_analysis_file) = qmdl_store_lock.write().await.new_entry().await.expect("failed creating QMDL file entry");
-        let mut maybe_qmdl_writer: Option<QmdlWriter<File>> = Some(QmdlWriter::new(initial_qmdl_file));
-        let mut diag_stream = pin!(dev.as_stream().into_stream());
-        let mut maybe_analysis_writer = Some(AnalysisWriter::new(initial_analysis_file).await
-            .expect("failed to create analysis writer"));
+        let mut maybe_qmdl_writer: Option<QmdlWriter<File>> = None;
+        let mut maybe_analysis_writer = None;
+        if initial_state == InitialRecordingState::Recording {
+            let (initial_qmdl_file, initial_analysis_file) = qmdl_store_lock.write().await.new_entry().await.expect("failed creating QMDL file entry");
+            maybe_qmdl_writer = Some(QmdlWriter::new_with_compression(initial_qmdl_file, store_compression));
+            maybe_analysis_writer = Some(AnalysisWriter::new(initial_analysis_file, &analyzer_config).await
+                .expect("failed to create analysis writer"));
+        }
+        let mut recording_started = Instant::now();
+        // replays come to an end, after which we only handle control messages
+        let mut replay_finished = false;
         loop {
-            tokio::select! {
-                msg = qmdl_file_rx.recv() => {
-                    match msg {
-                        Some(DiagDeviceCtrlMessage::StartRecording((new_writer, new_analysis_file))) => {
-                            maybe_qmdl_writer = Some(new_writer);
-                            if let Some(analysis_writer) = maybe_analysis_writer {
-                                analysis_writer.close().await.expect("failed to close analysis writer");
-                            }
-                            maybe_analysis_writer = Some(AnalysisWriter::new(new_analysis_file).await
-                                .expect("failed to write to analysis file"));
-                        },
-                        Some(DiagDeviceCtrlMessage::StopRecording) => {
-                            maybe_qmdl_writer = None;
-                            if let Some(analysis_writer) = maybe_analysis_writer {
-                                analysis_writer.close().await.expect("failed to close analysis writer");
+            let read_err = {
+                let mut diag_stream = source.stream();
+                let mut watchdog = StallWatchdog::new(stall_timeout);
+                loop {
+                    tokio::select! {
+                        msg = qmdl_file_rx.recv() => {
+                            match msg {
+                                Some(DiagDeviceCtrlMessage::StartRecording((new_writer, new_analysis_file))) => {
+                                    maybe_qmdl_writer = Some(new_writer);
+                                    recording_started = Instant::now();
+                                    if let Some(analysis_writer) = maybe_analysis_writer {
+                                        analysis_writer.close().await.expect("failed to close analysis writer");
+                                    }
+                                    maybe_analysis_writer = Some(AnalysisWriter::new(new_analysis_file, &analyzer_config).await
+                                        .expect("failed to write to analysis file"));
+                                },
+                                Some(DiagDeviceCtrlMessage::TriggerWarning) => {
+                                    let Some(analysis_writer) = maybe_analysis_writer.as_mut() else {
+                                        warn!("not recording, ignoring synthetic warning");
+                                        continue;
+                                    };
+                                    let (analysis_file_len, row) = analysis_writer.write_synthetic_warning().await
+                                        .expect("failed to write synthetic warning");
+                                    record_event_counts(&analyzer_event_counts, &row).await;
+                                    let mut qmdl_store = qmdl_store_lock.write().await;
+                                    let index = qmdl_store.current_entry.expect("DiagDevice had analysis_writer, but QmdlStore didn't have current entry???");
+                                    qmdl_store.update_entry_analysis_size(index, analysis_file_len).await
+                                        .expect("failed to update analysis file size");
+                                },
+                                Some(DiagDeviceCtrlMessage::StopRecording) => {
+                                    maybe_qmdl_writer = None;
+                                    if let Some(analysis_writer) = maybe_analysis_writer {
+                                        analysis_writer.close().await.expect("failed to close analysis writer");
+                                    }
+                                    maybe_analysis_writer = None;
+                                },
+                                Some(DiagDeviceCtrlMessage::FinishEntry(entry_index)) => {
+                                    finish_entry(&qmdl_store_lock, entry_index).await;
+                                },
+                                // None means all the Senders have been dropped, so it's
+                                // time to go
+                                Some(DiagDeviceCtrlMessage::Exit) | None => {
+                                    info!("Diag reader thread exiting...");
+                                    if let Some(analysis_writer) = maybe_analysis_writer {
+                                        analysis_writer.close().await.expect("failed to close analysis writer");
+                                    }
+                                    return Ok(())
+                                },
                             }
-                            maybe_analysis_writer = None;
-                        },
-                        // None means all the Senders have been dropped, so it's
-                        // time to go
-                        Some(DiagDeviceCtrlMessage::Exit) | None => {
-                            info!("Diag reader thread exiting...");
-                            if let Some(analysis_writer) = maybe_analysis_writer {
-                                analysis_writer.close().await.expect("failed to close analysis writer");
+                        }
+                        maybe_container = diag_stream.next(), if !replay_finished => {
+                            match maybe_container {
+                                Some(Ok(container)) => {
+                                    watchdog.reset();
+                                    if container.data_type != DataType::UserSpace {
+                                        debug!("skipping non-userspace diag messages...");
+                                        continue;
+                                    }
+                                    if let Some(heartbeat) = heartbeat.as_mut() {
+                                        if has_log_message(&container) {
+                                            heartbeat.beat().await;
+                                        }
+                                    }
+                                    diag_counters.messages.fetch_add(container.messages.len() as u64, Ordering::Relaxed);
+                                    update_current_cell(&current_cell, &container).await;
+                                    // streamed whether or not we're recording, it's a live view
+                                    if let Some(gsmtap_udp) = gsmtap_udp.as_ref() {
+                                        gsmtap_udp.send_container(&container).await;
+                                    }
+                                    // keep track of how many bytes were written to the QMDL file so we can read
+                                    // a valid block of data from it in the HTTP server
+                                    let recorded = messages_to_record(&container, &allowed_earfcns);
+                                    if recorded.messages.is_empty() {
+                                        debug!("no messages on allowed EARFCNs to record");
+                                    } else if let Some(qmdl_writer) = maybe_qmdl_writer.as_mut() {
+                                        qmdl_writer.write_container(&recorded).await.expect("failed to write to QMDL writer");
+                                        debug!("total QMDL bytes written: {}, updating manifest...", qmdl_writer.total_written);
+                                        let mut qmdl_store = qmdl_store_lock.write().await;
+                                        let index = qmdl_store.current_entry.expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
+                                        qmdl_store.update_entry_qmdl_size(index, qmdl_writer.total_written).await
+                                            .expect("failed to update qmdl file size");
+                                        if qmdl_store.manifest.entries[index].first_message_time.is_none() {
+                                            if let Some(timestamp) = container.first_log_timestamp() {
+                                                qmdl_store.set_entry_first_message_time(index, timestamp.with_timezone(&Local)).await
+                                                    .expect("failed to update first message time");
+                                            }
+                                        }
+                                        debug!("done!");
+                                    } else {
+                                        debug!("no qmdl_writer set, continuing...");
+                                    }
+
+                                    if let Some(analysis_writer) = maybe_analysis_writer.as_mut() {
+                                        let (analysis_file_len, row) = analysis_writer.analyze(container).await
+                                            .expect("failed to analyze container");
+                                        diag_counters.parse_errors.fetch_add(row.skipped_message_reasons.len() as u64, Ordering::Relaxed);
+                                        record_event_counts(&analyzer_event_counts, &row).await;
+                                        let mut qmdl_store = qmdl_store_lock.write().await;
+                                        let index = qmdl_store.current_entry.expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
+                                        qmdl_store.update_entry_analysis_size(index, analysis_file_len as usize).await
+                                            .expect("failed to update analysis file size");
+                                    }
+
+                                    let qmdl_bytes_written = maybe_qmdl_writer.as_ref().map_or(0, |writer| writer.total_written);
+                                    if maybe_qmdl_writer.is_some() && rotation.should_rotate(qmdl_bytes_written, recording_started.elapsed()) {
+                                        info!("rotating to a new recording after {} bytes", qmdl_bytes_written);
+                                        // like StartRecording, but the new entry's made here
+                                        maybe_qmdl_writer = None;
+                                        if let Some(analysis_writer) = maybe_analysis_writer.take() {
+                                            analysis_writer.close().await.expect("failed to close analysis writer");
+                                        }
+                                        let mut qmdl_store = qmdl_store_lock.write().await;
+                                        let finished_entry = qmdl_store.current_entry.expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
+                                        let (qmdl_file, analysis_file) = qmdl_store.new_entry().await
+                                            .expect("failed creating QMDL file entry");
+                                        drop(qmdl_store);
+                                        finish_entry(&qmdl_store_lock, finished_entry).await;
+                                        maybe_qmdl_writer = Some(QmdlWriter::new_with_compression(qmdl_file, store_compression));
+                                        maybe_analysis_writer = Some(AnalysisWriter::new(analysis_file, &analyzer_config).await
+                                            .expect("failed to create analysis writer"));
+                                        recording_started = Instant::now();
+                                    }
+                                },
+                                Some(Err(err)) => break err,
+                                // only replays ever end
+                                None => {
+                                    info!("finished replaying, stopping recording");
+                                    replay_finished = true;
+                                    if let Some(analysis_writer) = maybe_analysis_writer.take() {
+                                        analysis_writer.close().await.expect("failed to close analysis writer");
+                                    }
+                                    if maybe_qmdl_writer.take().is_some() {
+                                        let finished_entry = qmdl_store_lock.write().await.close_current_entry().await
+                                            .expect("failed to close current entry");
+                                        finish_entry(&qmdl_store_lock, finished_entry).await;
+                                    }
+                                },
                             }
-                            return Ok(())
-                        },
+                        }
+                        _ = watchdog.stalled() => {
+                            let timeout = stall_timeout.expect("watchdog fired without a timeout");
+                            warn!("no diag messages for {} seconds, the modem may be stuck", timeout.as_secs());
+                            diag_counters.stalls.fetch_add(1, Ordering::Relaxed);
+                            break DiagDeviceError::Stalled(timeout);
+                        }
                     }
                 }
-                maybe_container = diag_stream.next() => {
-                    match maybe_container.unwrap() {
-                        Ok(container) => {
-                            if container.data_type != DataType::UserSpace {
-                                debug!("skipping non-userspace diag messages...");
-                                continue;
-                            }
-                            // keep track of how many bytes were written to the QMDL file so we can read
-                            // a valid block of data from it in the HTTP server
-                            if let Some(qmdl_writer) = maybe_qmdl_writer.as_mut() {
-                                qmdl_writer.write_container(&container).await.expect("failed to write to QMDL writer");
-                                debug!("total QMDL bytes written: {}, updating manifest...", qmdl_writer.total_written);
-                                let mut qmdl_store = qmdl_store_lock.write().await;
-                                let index = qmdl_store.current_entry.expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
-                                qmdl_store.update_entry_qmdl_size(index, qmdl_writer.total_written).await
-                                    .expect("failed to update qmdl file size");
-                                debug!("done!");
-                            } else {
-                                debug!("no qmdl_writer set, continuing...");
-                            }
+            };
 
-                            if let Some(analysis_writer) = maybe_analysis_writer.as_mut() {
-                                let analysis_file_len = analysis_writer.analyze(container).await
-                                    .expect("failed to analyze container");
-                                let mut qmdl_store = qmdl_store_lock.write().await;
-                                let index = qmdl_store.current_entry.expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
-                                qmdl_store.update_entry_analysis_size(index, analysis_file_len as usize).await
-                                    .expect("failed to update analysis file size");
-                            }
+            // reads failing usually means the modem reset and took the diag
+            // device with it, and a stall that it's hung. Either way it's
+            // worth re-opening, but anything else isn't worth retrying.
+            if !source.is_device() {
+                error!("error reading replay: {}", read_err);
+                return Err(read_err);
+            }
+            if !matches!(read_err, DiagDeviceError::DeviceReadFailed(_) | DiagDeviceError::Stalled(_)) {
+                error!("error reading diag device: {}", read_err);
+                return Err(read_err);
+            }
+            error!("lost the diag device ({}), trying to re-open it...", read_err);
+            // close out the current recording first, so everything captured
+            // before the reset is kept
+            let was_recording = maybe_qmdl_writer.take().is_some();
+            if let Some(analysis_writer) = maybe_analysis_writer.take() {
+                analysis_writer.close().await.expect("failed to close analysis writer");
+            }
+            if was_recording {
+                let finished_entry = qmdl_store_lock.write().await.close_current_entry().await
+                    .expect("failed to close current entry");
+                finish_entry(&qmdl_store_lock, finished_entry).await;
+            }
+            // re-opening can take a while, so keep handling control messages
+            // meanwhile. A recording started or stopped in the meantime takes
+            // precedence over resuming the one that was interrupted.
+            let mut resume_recording = was_recording;
+            let mut started_writers = None;
+            let reopened = {
+                let mut reopen = pin!(source.reopen());
+                loop {
+                    tokio::select! {
+                        result = &mut reopen => break result,
+                        msg = qmdl_file_rx.recv() => match msg {
+                            Some(DiagDeviceCtrlMessage::StartRecording(writers)) => {
+                                resume_recording = false;
+                                started_writers = Some(writers);
+                            },
+                            Some(DiagDeviceCtrlMessage::StopRecording) => {
+                                resume_recording = false;
+                                started_writers = None;
+                            },
+                            Some(DiagDeviceCtrlMessage::FinishEntry(entry_index)) => {
+                                finish_entry(&qmdl_store_lock, entry_index).await;
+                            },
+                            Some(DiagDeviceCtrlMessage::TriggerWarning) => {
+                                warn!("not recording, ignoring synthetic warning");
+                            },
+                            Some(DiagDeviceCtrlMessage::Exit) | None => {
+                                info!("Diag reader thread exiting...");
+                                return Ok(())
+                            },
                         },
-                        Err(err) => {
-                            error!("error reading diag device: {}", err);
-                            return Err(err);
-                        }
                     }
                 }
+            };
+            if let Err(err) = reopened {
+                error!("giving up on the diag device: {}", err);
+                // the recording started during the re-open won't get any data
+                if started_writers.is_some() {
+                    qmdl_store_lock.write().await.close_current_entry().await
+                        .expect("failed to close current entry");
+                }
+                return Err(err);
+            }
+            info!("re-opened the diag device");
+            diag_counters.reopens.fetch_add(1, Ordering::Relaxed);
+            if let Some((qmdl_writer, analysis_file)) = started_writers {
+                maybe_qmdl_writer = Some(qmdl_writer);
+                maybe_analysis_writer = Some(AnalysisWriter::new(analysis_file, &analyzer_config).await
+                    .expect("failed to create analysis writer"));
+                recording_started = Instant::now();
+            } else if resume_recording {
+                let (qmdl_file, analysis_file) = qmdl_store_lock.write().await.new_entry().await
+                    .expect("failed creating QMDL file entry");
+                maybe_qmdl_writer = Some(QmdlWriter::new_with_compression(qmdl_file, store_compression));
+                maybe_analysis_writer = Some(AnalysisWriter::new(analysis_file, &analyzer_config).await
+                    .expect("failed to create analysis writer"));
+                recording_started = Instant::now();
             }
         }
     });
 }
 
-pub async fn start_recording(State(state): State<Arc<ServerState>>) -> Result<(StatusCode, String), (StatusCode, String)> {
+pub async fn start_recording(State(state): State<Arc<ServerState>>) -> Result<(StatusCode, String), ApiError> {
     if state.readonly_mode {
-        return Err((StatusCode::FORBIDDEN, "server is in readonly mode".to_string()));
+        return Err(ApiError::readonly_mode());
     }
+    // starting a recording manually cancels any pending auto-resume. We hold
+    // the lock until the recording's started so the two can't race.
+    let mut auto_resume_task = state.auto_resume_task.lock().await;
+    if let Some(task) = auto_resume_task.take() {
+        task.abort();
+    }
+    begin_recording(&state).await?;
+    Ok((StatusCode::ACCEPTED, "ok".to_string()))
+}
+
+// Injects a synthetic warning into the current recording's analysis, only
+// available when allow_debug_endpoints is set
+pub async fn trigger_warning(State(state): State<Arc<ServerState>>) -> Result<(StatusCode, String), ApiError> {
+    if !state.allow_debug_endpoints {
+        return Err((StatusCode::NOT_FOUND, "debug endpoints are disabled".to_string()).into());
+    }
+    if state.readonly_mode {
+        return Err(ApiError::readonly_mode());
+    }
+    state.diag_device_ctrl_sender.send(DiagDeviceCtrlMessage::TriggerWarning).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't send trigger warning message: {}", e)))?;
+    Ok((StatusCode::ACCEPTED, "ok".to_string()))
+}
+
+async fn begin_recording(state: &ServerState) -> Result<(), ApiError> {
+    state.armed.store(false, Ordering::Relaxed);
+    // the store's released before sending, since the diag thread may need it
+    // to handle the messages queued ahead of ours
     let mut qmdl_store = state.qmdl_store_lock.write().await;
+    let previous_entry = qmdl_store.current_entry;
     let (qmdl_file, analysis_file) = qmdl_store.new_entry().await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't create new qmdl entry: {}", e)))?;
-    let qmdl_writer = QmdlWriter::new(qmdl_file);
+    drop(qmdl_store);
+    let qmdl_writer = QmdlWriter::new_with_compression(qmdl_file, state.store_compression);
     state.diag_device_ctrl_sender.send(DiagDeviceCtrlMessage::StartRecording((qmdl_writer, analysis_file))).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't send start recording message: {}", e)))?;
+    if let Some(entry_index) = previous_entry {
+        finish_entry_after_close(state, entry_index).await?;
+    }
+    Ok(())
+}
+
+// The diag thread closes an entry's writers when it gets the message that
+// closed it, so only after that can its QMDL be discarded
+async fn finish_entry_after_close(state: &ServerState, entry_index: usize) -> Result<(), ApiError> {
+    state.diag_device_ctrl_sender.send(DiagDeviceCtrlMessage::FinishEntry(entry_index)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't send finish entry message: {}", e)).into())
+}
+
+// Starts a new recording after the delay, unless the returned task is
+// aborted first (e.g. by the user starting one manually)
+fn schedule_auto_resume(state: Arc<ServerState>, delay: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let mut auto_resume_task = state.auto_resume_task.lock().await;
+        // drop our own handle so that the next stop can schedule a new task
+        auto_resume_task.take();
+        info!("auto-resuming recording after {} seconds", delay.as_secs());
+        if let Err(err) = begin_recording(&state).await {
+            error!("failed to auto-resume recording: {}", err.message);
+        }
+    })
+}
+
+pub async fn stop_recording(State(state): State<Arc<ServerState>>) -> Result<(StatusCode, String), ApiError> {
+    if state.readonly_mode {
+        return Err(ApiError::readonly_mode());
+    }
+    // stopping cancels a pending auto-resume or delayed autostart, even if
+    // there's no recording to stop yet. Like start_recording, we hold the
+    // lock throughout so the two can't race.
+    let mut auto_resume_task = state.auto_resume_task.lock().await;
+    if let Some(task) = auto_resume_task.take() {
+        task.abort();
+    }
+    let closed = state.qmdl_store_lock.write().await.close_current_entry().await;
+    // sent even when there's no current entry, since the diag thread may be
+    // re-opening the device and would otherwise resume recording afterwards
+    state.diag_device_ctrl_sender.send(DiagDeviceCtrlMessage::StopRecording).await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't send stop recording message: {}", e)))?;
+    let entry_index = closed.map_err(|e| match e {
+        RecordingStoreError::NoCurrentEntry => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiErrorCode::NotRecording,
+            format!("couldn't close current qmdl entry: {}", e),
+        ),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't close current qmdl entry: {}", e)).into(),
+    })?;
+    finish_entry_after_close(&state, entry_index).await?;
+    state.armed.store(false, Ordering::Relaxed);
+    if state.auto_resume_after_secs > 0 {
+        *auto_resume_task = Some(schedule_auto_resume(state.clone(), Duration::from_secs(state.auto_resume_after_secs)));
+    }
     Ok((StatusCode::ACCEPTED, "ok".to_string()))
 }
 
-pub async fn stop_recording(State(state): State<Arc<ServerState>>) -> Result<(StatusCode, String), (StatusCode, String)> {
+// Schedules the first recording for autostart_delay_secs after boot. It's
+// tracked like an auto-resume, so starting or stopping a recording in the
+// meantime cancels it.
+pub fn schedule_autostart(state: Arc<ServerState>, delay: Duration) {
+    let mut auto_resume_task = state.auto_resume_task.try_lock()
+        .expect("nothing else should have the auto-resume lock yet");
+    *auto_resume_task = Some(schedule_auto_resume(state.clone(), delay));
+}
+
+// Imports a QMDL file captured elsewhere, uploaded as the "file" field of a
+// multipart form, into a new store entry and analyzes it in the background.
+// Responds with the new entry's name.
+pub async fn import_recording(State(state): State<Arc<ServerState>>, mut multipart: Multipart) -> Result<(StatusCode, String), ApiError> {
     if state.readonly_mode {
-        return Err((StatusCode::FORBIDDEN, "server is in readonly mode".to_string()));
+        return Err(ApiError::readonly_mode());
+    }
+    let mut maybe_qmdl_data = None;
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("couldn't read upload: {}", e)))? {
+        if field.name() == Some("file") {
+            maybe_qmdl_data = Some(field.bytes().await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("couldn't read upload: {}", e)))?);
+            break;
+        }
     }
+    let qmdl_data = maybe_qmdl_data
+        .ok_or((StatusCode::BAD_REQUEST, "upload is missing a \"file\" field".to_string()))?;
+    let start_time = read_first_message_time(&qmdl_data).await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+        .unwrap_or_else(Local::now);
+
     let mut qmdl_store = state.qmdl_store_lock.write().await;
-    qmdl_store.close_current_entry().await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't close current qmdl entry: {}", e)))?;
-    state.diag_device_ctrl_sender.send(DiagDeviceCtrlMessage::StopRecording).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't send stop recording message: {}", e)))?;
+    let (entry_index, analysis_file) = qmdl_store.import_entry(start_time, &qmdl_data).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't create new qmdl entry: {}", e)))?;
+    let entry_name = qmdl_store.manifest.entries[entry_index].name.clone();
+    drop(qmdl_store);
+
+    let state = state.clone();
+    state.task_tracker.clone().spawn(async move {
+        if let Err(e) = analyze_imported_recording(&state, entry_index, analysis_file, &qmdl_data).await {
+            error!("failed to analyze imported recording: {}", e);
+        }
+    });
+    Ok((StatusCode::CREATED, entry_name))
+}
+
+// Reads the first container of the given QMDL data, returning the timestamp of
+// its first log message if it has one. Errors if none of the container's
+// messages can be parsed, since that's a good sign it isn't QMDL at all.
+async fn read_first_message_time(qmdl_data: &[u8]) -> Result<Option<DateTime<Local>>, String> {
+    let mut qmdl_reader = QmdlReader::new(qmdl_data, Some(qmdl_data.len()));
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream());
+    let container = qmdl_stream.try_next().await
+        .map_err(|e| format!("couldn't read QMDL data: {}", e))?
+        .ok_or("QMDL file is empty".to_string())?;
+    let messages: Vec<Message> = container.into_messages().into_iter()
+        .filter_map(|maybe_msg| maybe_msg.ok())
+        .collect();
+    if messages.is_empty() {
+        return Err("file doesn't look like QMDL, couldn't parse its first message".to_string());
+    }
+    Ok(messages.iter().find_map(|msg| match msg {
+        Message::Log { timestamp, .. } => Some(timestamp.to_datetime().with_timezone(&Local)),
+        _ => None,
+    }))
+}
+
+// Runs the analyzers over a whole QMDL file, writing the results to the given
+// analysis file. Returns the analysis file's length.
+async fn analyze_qmdl<R: AsyncRead + Unpin>(mut qmdl_reader: QmdlReader<R>, analysis_file: File, analyzer_config: &AnalyzerConfig) -> Result<usize, std::io::Error> {
+    let mut analysis_writer = AnalysisWriter::new(analysis_file, analyzer_config).await?;
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| futures::future::ready(container.data_type == DataType::UserSpace)));
+    let mut analysis_file_len = analysis_writer.bytes_written;
+    while let Some(container) = qmdl_stream.try_next().await? {
+        (analysis_file_len, _) = analysis_writer.analyze(container).await?;
+    }
+    analysis_writer.close().await?;
+    Ok(analysis_file_len)
+}
+
+async fn analyze_imported_recording(state: &ServerState, entry_index: usize, analysis_file: File, qmdl_data: &[u8]) -> Result<(), std::io::Error> {
+    let qmdl_reader = QmdlReader::new(qmdl_data, Some(qmdl_data.len()));
+    let analysis_file_len = analyze_qmdl(qmdl_reader, analysis_file, &state.analyzer_config).await?;
+    let mut qmdl_store = state.qmdl_store_lock.write().await;
+    qmdl_store.update_entry_analysis_size(entry_index, analysis_file_len).await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    info!("finished analyzing imported recording {}", qmdl_store.manifest.entries[entry_index].name);
+    Ok(())
+}
+
+// Re-runs analysis over a stored recording in the background, replacing its
+// analysis file. The request body can hold an AnalyzerConfig to use instead of
+// the daemon's, for experimenting with analyzer settings without a restart.
+pub async fn reanalyze_recording(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    body: Bytes,
+) -> Result<(StatusCode, String), ApiError> {
+    if state.readonly_mode {
+        return Err(ApiError::readonly_mode());
+    }
+    let analyzer_config = if body.is_empty() {
+        state.analyzer_config.clone()
+    } else {
+        serde_json::from_slice::<AnalyzerConfig>(&body)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid analyzer config: {}", e)))?
+    };
+    analyzer_config.validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid analyzer config: {}", e)))?;
+
+    let mut qmdl_store = state.qmdl_store_lock.write().await;
+    if qmdl_store.get_current_entry().is_some_and(|entry| entry.name == qmdl_name) {
+        return Err(ApiError::new(StatusCode::CONFLICT, ApiErrorCode::RecordingActive, "can't re-analyze the recording in progress"));
+    }
+    // checked up front, since clearing the analysis of a recording with no
+    // QMDL left would lose it for good
+    if qmdl_store.entry_for_name(&qmdl_name).is_some_and(|entry| entry.analysis_only) {
+        return Err(ApiError::qmdl_discarded(RecordingStoreError::QmdlDiscarded(qmdl_name)));
+    }
+    // a second run would truncate the analysis file out from under the first
+    let mut reanalyzing = state.reanalyzing.lock().await;
+    if reanalyzing.contains(&qmdl_name) {
+        return Err(ApiError::new(StatusCode::CONFLICT, ApiErrorCode::Conflict, format!("{} is already being re-analyzed", qmdl_name)));
+    }
+    let (entry_index, analysis_file) = qmdl_store.clear_and_open_entry_analysis(&qmdl_name).await
+        .map_err(|e| match e {
+            RecordingStoreError::NoSuchEntry(_) => ApiError::no_such_entry(&qmdl_name),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't clear analysis file: {}", e)).into(),
+        })?;
+    let entry = qmdl_store.manifest.entries[entry_index].clone();
+    let qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error opening QMDL file: {}", e)))?;
+    reanalyzing.insert(qmdl_name);
+    drop(reanalyzing);
+    drop(qmdl_store);
+
+    let state = state.clone();
+    state.task_tracker.clone().spawn(async move {
+        let qmdl_reader = QmdlReader::new(qmdl_file, Some(entry.qmdl_size_bytes));
+        match analyze_qmdl(qmdl_reader, analysis_file, &analyzer_config).await {
+            Ok(analysis_file_len) => {
+                let mut qmdl_store = state.qmdl_store_lock.write().await;
+                match qmdl_store.update_entry_analysis_size(entry_index, analysis_file_len).await {
+                    Ok(()) => info!("finished re-analyzing {}", entry.name),
+                    Err(e) => error!("failed to update analysis file size: {}", e),
+                }
+            },
+            Err(e) => error!("failed to re-analyze {}: {}", entry.name, e),
+        }
+        state.reanalyzing.lock().await.remove(&entry.name);
+    });
     Ok((StatusCode::ACCEPTED, "ok".to_string()))
 }
 
-pub async fn get_analysis_report(State(state): State<Arc<ServerState>>) -> Result<Response, (StatusCode, String)> {
+// Returns the length of the analysis file up to the end of its last complete
+// line, and rewinds it. If the daemon was killed partway through writing a
+// row, the file ends in a truncated JSON object which would break anything
+// parsing it.
+pub async fn complete_lines_len<R: AsyncRead + AsyncSeek + Unpin>(reader: &mut R) -> Result<u64, std::io::Error> {
+    let mut end = reader.seek(SeekFrom::End(0)).await?;
+    let mut buf = vec![0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        reader.seek(SeekFrom::Start(start)).await?;
+        reader.read_exact(chunk).await?;
+        if let Some(newline) = chunk.iter().rposition(|&b| b == b'\n') {
+            reader.rewind().await?;
+            return Ok(start + newline as u64 + 1);
+        }
+        end = start;
+    }
+    reader.rewind().await?;
+    Ok(0)
+}
+
+pub async fn get_analysis_report(State(state): State<Arc<ServerState>>) -> Result<Response, ApiError> {
     let qmdl_store = state.qmdl_store_lock.read().await;
     let Some(entry) = qmdl_store.get_current_entry() else {
-        return Err((
+        return Err(ApiError::new(
             StatusCode::SERVICE_UNAVAILABLE,
-            "No QMDL data's being recorded to analyze, try starting a new recording!".to_string()
+            ApiErrorCode::NotRecording,
+            "No QMDL data's being recorded to analyze, try starting a new recording!",
         ));
     };
-    let analysis_file = qmdl_store.open_entry_analysis(entry).await
+    let mut analysis_file = qmdl_store.open_entry_analysis(entry).await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)))?;
-    let analysis_stream = ReaderStream::new(analysis_file);
+    let analysis_len = complete_lines_len(&mut analysis_file).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error reading analysis file: {}", e)))?;
+    let analysis_stream = ReaderStream::new(analysis_file.take(analysis_len));
 
     let headers = [(CONTENT_TYPE, "application/x-ndjson")];
     let body = Body::from_stream(analysis_stream);
     Ok((headers, body).into_response())
 }
+
+// Streams the stored analysis file for any recording, so tools can consume
+// rayhunter's own analysis without re-running it
+pub async fn get_analysis_ndjson(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Response, ApiError> {
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let entry = qmdl_store.entry_for_name(&qmdl_name)
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
+    let mut analysis_file = qmdl_store.open_entry_analysis(&entry).await
+        .map_err(|e| match e {
+            RecordingStoreError::ReadFileError(err) if err.kind() == std::io::ErrorKind::NotFound =>
+                (StatusCode::NOT_FOUND, format!("no analysis file for {}", qmdl_name)),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("error opening analysis file: {}", e)),
+        })?;
+    let analysis_len = complete_lines_len(&mut analysis_file).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error reading analysis file: {}", e)))?;
+    let limited_analysis_file = analysis_file.take(analysis_len.min(entry.analysis_size_bytes as u64));
+    let analysis_stream = ReaderStream::new(limited_analysis_file);
+
+    let headers = [(CONTENT_TYPE, "application/x-ndjson")];
+    let body = Body::from_stream(analysis_stream);
+    Ok((headers, body).into_response())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use crate::server::tests::test_state;
+
+    #[test]
+    fn test_rotation_policy() {
+        let by_size = RotationPolicy { after_bytes: 1000, after_secs: 0 };
+        assert!(!by_size.should_rotate(999, Duration::from_secs(100_000)));
+        assert!(by_size.should_rotate(1000, Duration::ZERO));
+
+        let by_time = RotationPolicy { after_bytes: 0, after_secs: 60 };
+        assert!(!by_time.should_rotate(usize::MAX, Duration::from_secs(59)));
+        assert!(by_time.should_rotate(0, Duration::from_secs(60)));
+
+        assert!(!RotationPolicy::default().should_rotate(usize::MAX, Duration::MAX));
+    }
+
+    #[test]
+    fn test_initial_recording_state() {
+        assert_eq!(InitialRecordingState::new(true, 0), InitialRecordingState::Recording);
+        assert_eq!(InitialRecordingState::new(true, 30), InitialRecordingState::Delayed(Duration::from_secs(30)));
+        // arming takes precedence over the delay
+        assert_eq!(InitialRecordingState::new(false, 0), InitialRecordingState::Armed);
+        assert_eq!(InitialRecordingState::new(false, 30), InitialRecordingState::Armed);
+    }
+
+    #[tokio::test]
+    async fn test_synthetic_warning_reaches_analysis_file() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let path = dir.path().join("analysis.ndjson");
+        let file = File::create(&path).await.unwrap();
+        let mut analysis_writer = AnalysisWriter::new(file, &AnalyzerConfig::default()).await.unwrap();
+        let (analysis_file_len, row) = analysis_writer.write_synthetic_warning().await.unwrap();
+        analysis_writer.close().await.unwrap();
+
+        assert!(matches!(row.analysis[0].events[0], Some(Event { event_type: EventType::QualitativeWarning { .. }, .. })));
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents.len(), analysis_file_len);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("Synthetic warning"));
+    }
+
+    #[tokio::test]
+    async fn test_complete_lines_len_skips_partial_row() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let path = dir.path().join("analysis.ndjson");
+        let complete = "{\"analyzers\":[]}\n{\"timestamp\":\"2024-01-01T00:00:00Z\",\"analysis\":[]}\n";
+        let mut contents = complete.to_string();
+        contents.push_str("{\"timestamp\":\"2024-01-01T00:00:01Z\",\"anal");
+        tokio::fs::write(&path, &contents).await.unwrap();
+
+        let mut file = File::open(&path).await.unwrap();
+        let len = complete_lines_len(&mut file).await.unwrap();
+        assert_eq!(len, complete.len() as u64);
+        let mut read_back = String::new();
+        file.take(len).read_to_string(&mut read_back).await.unwrap();
+        assert_eq!(read_back, complete);
+        for line in read_back.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+
+        // a file that's nothing but a partial row has no complete lines, and
+        // one longer than a single read chunk is still searched all the way
+        let long_partial = format!("{}{}", complete, "x".repeat(10_000));
+        tokio::fs::write(&path, &long_partial).await.unwrap();
+        let mut file = File::open(&path).await.unwrap();
+        assert_eq!(complete_lines_len(&mut file).await.unwrap(), complete.len() as u64);
+        tokio::fs::write(&path, "{\"analyzers\"").await.unwrap();
+        let mut file = File::open(&path).await.unwrap();
+        assert_eq!(complete_lines_len(&mut file).await.unwrap(), 0);
+    }
+
+    // Builds a serialized LTE RRC OTA log message (ext header version 20,
+    // DL-DCCH) carrying the given UPER payload
+    fn lte_rrc_dl_dcch_log(payload: &[u8]) -> Vec<u8> {
+        lte_rrc_log(160, 2050, 72659535985485082, payload)
+    }
+
+    /// Like [lte_rrc_dl_dcch_log], but from the given cell and at the given
+    /// diag timestamp
+    pub fn lte_rrc_log(pci: u16, earfcn: u32, timestamp: u64, payload: &[u8]) -> Vec<u8> {
+        let length = 31 + payload.len() as u16;
+        let mut data = vec![16, 0];
+        data.extend(length.to_le_bytes()); // outer_length
+        data.extend(length.to_le_bytes()); // inner_length
+        data.extend(0xb0c0u16.to_le_bytes());
+        data.extend(timestamp.to_le_bytes());
+        data.extend([20, 14, 48, 0]); // ext header version, rrc rel, bearer id
+        data.extend(pci.to_le_bytes());
+        data.extend(earfcn.to_le_bytes());
+        data.extend(4057u16.to_le_bytes()); // sfn_subfn
+        data.push(7); // pdu_num
+        data.extend(0u32.to_le_bytes()); // sib_mask
+        data.extend((payload.len() as u16).to_le_bytes());
+        data.extend(payload);
+        data
+    }
+
+    fn lte_rrc_dl_dcch_container(payload: &[u8]) -> MessagesContainer {
+        let data = rayhunter::hdlc::hdlc_encapsulate(&lte_rrc_dl_dcch_log(payload), &rayhunter::diag::CRC_CCITT);
+        MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![rayhunter::diag::HdlcEncapsulatedMessage { len: data.len() as u32, data }],
+        }
+    }
+
+    // waits up to a second for a file to appear
+    async fn wait_for_file(path: &std::path::Path) -> bool {
+        for _ in 0..100 {
+            if path.exists() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_follows_message_flow() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let heartbeat_path = dir.path().join("heartbeat");
+        let store = RecordingStore::create(dir.path().join("store")).await.unwrap();
+        let (container_tx, container_rx) = futures::channel::mpsc::unbounded();
+        // kept alive so the read loop doesn't exit
+        let (_ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(1);
+        run_diag_read_thread(
+            &TaskTracker::new(),
+            DiagSource::Replay(container_rx.boxed()),
+            ctrl_rx,
+            Arc::new(RwLock::new(store)),
+            DiagThreadOptions {
+                initial_state: InitialRecordingState::Armed,
+                heartbeat: Some(Heartbeat::new(&heartbeat_path, Duration::ZERO)),
+                ..Default::default()
+            },
+        );
+        let send_message = || {
+            container_tx.unbounded_send(Ok(lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]))).unwrap();
+        };
+
+        // the process being up isn't enough, messages have to be arriving
+        assert!(!wait_for_file(&heartbeat_path).await);
+        // and they have to be log messages, not other kinds of diag data or
+        // anything that doesn't parse
+        let mut not_userspace = lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]);
+        not_userspace.data_type = DataType::Other(0);
+        container_tx.unbounded_send(Ok(not_userspace)).unwrap();
+        let garbage = rayhunter::hdlc::hdlc_encapsulate(&[0xff, 0xff, 0xff], &rayhunter::diag::CRC_CCITT);
+        container_tx.unbounded_send(Ok(MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![rayhunter::diag::HdlcEncapsulatedMessage { len: garbage.len() as u32, data: garbage }],
+        })).unwrap();
+        assert!(!wait_for_file(&heartbeat_path).await);
+        send_message();
+        assert!(wait_for_file(&heartbeat_path).await);
+
+        // with messages paused, the heartbeat goes stale
+        tokio::fs::remove_file(&heartbeat_path).await.unwrap();
+        assert!(!wait_for_file(&heartbeat_path).await);
+        send_message();
+        assert!(wait_for_file(&heartbeat_path).await);
+    }
+
+    #[tokio::test]
+    async fn test_reanalyze_with_different_configs() {
+        // RRCConnectionReleases redirecting to EARFCN 850 and 5230
+        let redirect_850 = [0x28, 0x22, 0x00, 0x6a, 0x40];
+        let redirect_5230 = [0x28, 0x22, 0x02, 0x8d, 0xc0];
+        let dir = TempDir::new("diag_test").unwrap();
+        let qmdl_path = dir.path().join("test.qmdl");
+        let mut qmdl_writer = QmdlWriter::new(File::create(&qmdl_path).await.unwrap());
+        for payload in [&redirect_850, &redirect_5230, &redirect_850] {
+            let data = rayhunter::hdlc::hdlc_encapsulate(&lte_rrc_dl_dcch_log(payload), &rayhunter::diag::CRC_CCITT);
+            let container = MessagesContainer {
+                data_type: DataType::UserSpace,
+                num_messages: 1,
+                messages: vec![rayhunter::diag::HdlcEncapsulatedMessage { len: data.len() as u32, data }],
+            };
+            qmdl_writer.write_container(&container).await.unwrap();
+        }
+        let qmdl_len = qmdl_writer.total_written;
+
+        let analyze = |redirect_loop_count: usize| {
+            let qmdl_path = qmdl_path.clone();
+            let analysis_path = dir.path().join(format!("analysis-{}.ndjson", redirect_loop_count));
+            async move {
+                let config = AnalyzerConfig { redirect_loop_count, redirect_loop_window_secs: 60, ..Default::default() };
+                let qmdl_reader = QmdlReader::new(File::open(&qmdl_path).await.unwrap(), Some(qmdl_len));
+                let analysis_file = File::create(&analysis_path).await.unwrap();
+                let analysis_file_len = analyze_qmdl(qmdl_reader, analysis_file, &config).await.unwrap();
+                let contents = tokio::fs::read_to_string(&analysis_path).await.unwrap();
+                assert_eq!(contents.len(), analysis_file_len);
+                contents
+            }
+        };
+
+        let contents = analyze(3).await;
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("redirect"));
+
+        // with a higher threshold, the same recording has nothing to report
+        assert_eq!(analyze(5).await.lines().count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stall_watchdog() {
+        // a device which sends a few containers and then goes quiet
+        let mut stream = futures::stream::iter([1, 2, 3]).chain(futures::stream::pending());
+        let mut watchdog = StallWatchdog::new(Some(Duration::from_millis(50)));
+        let started = tokio::time::Instant::now();
+        let mut received = 0;
+        loop {
+            tokio::select! {
+                Some(_) = stream.next() => {
+                    received += 1;
+                    watchdog.reset();
+                }
+                _ = watchdog.stalled() => break,
+            }
+        }
+        assert_eq!(received, 3);
+        // the clock's paused, so it fires exactly on time
+        assert_eq!(started.elapsed(), Duration::from_millis(50));
+
+        // without a timeout, it never fires
+        let watchdog = StallWatchdog::new(None);
+        assert!(tokio::time::timeout(Duration::from_millis(50), watchdog.stalled()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_device_is_reopened() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let store_lock = Arc::new(RwLock::new(RecordingStore::create(dir.path()).await.unwrap()));
+        let diag_counters = Arc::new(DiagCounters::default());
+        // each time it's opened, the device sends a container and then goes
+        // quiet
+        let quiet_after_one = || -> ReplayStream {
+            futures::stream::iter([Ok(lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]))])
+                .chain(futures::stream::pending())
+                .boxed()
+        };
+        let (_ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(1);
+        let task_tracker = TaskTracker::new();
+        run_diag_read_thread(
+            &task_tracker,
+            DiagSource::Scripted(std::collections::VecDeque::from([quiet_after_one(), quiet_after_one()])),
+            ctrl_rx,
+            store_lock.clone(),
+            DiagThreadOptions {
+                diag_counters: diag_counters.clone(),
+                stall_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+        // the second stall leaves nothing to re-open, so the thread gives up
+        task_tracker.close();
+        tokio::time::timeout(Duration::from_secs(5), task_tracker.wait()).await
+            .expect("diag read thread didn't give up");
+
+        assert_eq!(diag_counters.stalls.load(Ordering::Relaxed), 2);
+        assert_eq!(diag_counters.reopens.load(Ordering::Relaxed), 1);
+        // the recording was closed at each stall, and resumed in a new entry
+        // after the re-open
+        let store = store_lock.read().await;
+        assert_eq!(store.manifest.entries.len(), 2);
+        assert!(store.manifest.entries.iter().all(|entry| entry.qmdl_size_bytes > 0));
+        assert_eq!(store.current_entry, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_during_reopen_isnt_overridden() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let store_lock = Arc::new(RwLock::new(RecordingStore::create(dir.path()).await.unwrap()));
+        let diag_counters = Arc::new(DiagCounters::default());
+        let quiet_after_one = || -> ReplayStream {
+            futures::stream::iter([Ok(lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]))])
+                .chain(futures::stream::pending())
+                .boxed()
+        };
+        let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(1);
+        let task_tracker = TaskTracker::new();
+        run_diag_read_thread(
+            &task_tracker,
+            DiagSource::Scripted(std::collections::VecDeque::from([quiet_after_one(), quiet_after_one()])),
+            ctrl_rx,
+            store_lock.clone(),
+            DiagThreadOptions {
+                diag_counters: diag_counters.clone(),
+                stall_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+        // wait for the first stall to close the recording, then stop it while
+        // the device is still being re-opened
+        for _ in 0..100 {
+            let store = store_lock.read().await;
+            if store.manifest.entries.len() == 1 && store.current_entry.is_none() {
+                break;
+            }
+            drop(store);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(diag_counters.reopens.load(Ordering::Relaxed), 0);
+        ctrl_tx.send(DiagDeviceCtrlMessage::StopRecording).await.unwrap();
+        task_tracker.close();
+        tokio::time::timeout(Duration::from_secs(5), task_tracker.wait()).await
+            .expect("diag read thread didn't give up");
+
+        assert_eq!(diag_counters.reopens.load(Ordering::Relaxed), 1);
+        // no new entry was made after the re-open
+        let store = store_lock.read().await;
+        assert_eq!(store.manifest.entries.len(), 1);
+        assert_eq!(store.current_entry, None);
+    }
+
+    #[tokio::test]
+    async fn test_filtered_earfcns_are_still_analyzed() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let store_lock = Arc::new(RwLock::new(RecordingStore::create(dir.path()).await.unwrap()));
+        // three redirects, enough for the redirect loop analyzer, all on
+        // EARFCN 2050
+        let redirect = lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]);
+        let replay: ReplayStream = futures::stream::iter([Ok(redirect.clone()), Ok(redirect.clone()), Ok(redirect)]).boxed();
+        let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(1);
+        let task_tracker = TaskTracker::new();
+        run_diag_read_thread(
+            &task_tracker,
+            DiagSource::Replay(replay),
+            ctrl_rx,
+            store_lock.clone(),
+            DiagThreadOptions {
+                allowed_earfcns: vec![0..=1000],
+                ..Default::default()
+            },
+        );
+        // wait for the replay to finish and close the recording
+        for _ in 0..100 {
+            let store = store_lock.read().await;
+            if !store.manifest.entries.is_empty() && store.current_entry.is_none() {
+                break;
+            }
+            drop(store);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        ctrl_tx.send(DiagDeviceCtrlMessage::Exit).await.unwrap();
+        task_tracker.close();
+        task_tracker.wait().await;
+
+        let store = store_lock.read().await;
+        let entry = &store.manifest.entries[0];
+        // none of the messages were on an allowed EARFCN, so none were recorded...
+        assert_eq!(entry.qmdl_size_bytes, 0);
+        // ...but they were all analyzed
+        let analysis = tokio::fs::read_to_string(entry.get_analysis_filepath(dir.path())).await.unwrap();
+        assert!(analysis.lines().any(|line| line.contains("redirect")));
+    }
+
+    #[tokio::test]
+    async fn test_replay_rotates_recordings() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let store_lock = Arc::new(RwLock::new(RecordingStore::create(dir.path()).await.unwrap()));
+        let container = lte_rrc_dl_dcch_container(&[0x28, 0x22, 0x00, 0x6a, 0x40]);
+        let replay: ReplayStream = futures::stream::iter([Ok(container.clone()), Ok(container.clone()), Ok(container)]).boxed();
+        let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(1);
+        let task_tracker = TaskTracker::new();
+        run_diag_read_thread(
+            &task_tracker,
+            DiagSource::Replay(replay),
+            ctrl_rx,
+            store_lock.clone(),
+            DiagThreadOptions {
+                // every container is enough to fill a recording
+                rotation: RotationPolicy { after_bytes: 1, after_secs: 0 },
+                ..Default::default()
+            },
+        );
+        // wait for the replay to finish and close the recording
+        for _ in 0..100 {
+            let store = store_lock.read().await;
+            if !store.manifest.entries.is_empty() && store.current_entry.is_none() {
+                break;
+            }
+            drop(store);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        ctrl_tx.send(DiagDeviceCtrlMessage::Exit).await.unwrap();
+        task_tracker.close();
+        task_tracker.wait().await;
+
+        let store = store_lock.read().await;
+        assert!(store.manifest.entries.len() > 1);
+        assert_eq!(store.current_entry, None);
+        // the first recording was closed when it rotated, with its QMDL and
+        // analysis intact, and its successor didn't write over it
+        let first = &store.manifest.entries[0];
+        assert!(first.qmdl_size_bytes > 0);
+        let qmdl = tokio::fs::read(first.get_qmdl_filepath(dir.path())).await.unwrap();
+        assert_eq!(qmdl.len(), first.qmdl_size_bytes);
+        let analysis = tokio::fs::read_to_string(first.get_analysis_filepath(dir.path())).await.unwrap();
+        assert_eq!(analysis.len(), first.analysis_size_bytes);
+        assert!(analysis.ends_with('\n'));
+        let names: std::collections::HashSet<&str> = store.manifest.entries.iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names.len(), store.manifest.entries.len());
+    }
+
+    fn drain_ctrl_messages(ctrl_rx: &mut Receiver<DiagDeviceCtrlMessage>) -> Vec<DiagDeviceCtrlMessage> {
+        let mut messages = Vec::new();
+        while let Ok(msg) = ctrl_rx.try_recv() {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_auto_resume_after_stop() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (mut state, mut ctrl_rx) = test_state(dir.path()).await;
+        state.auto_resume_after_secs = 1;
+        let state = Arc::new(state);
+        start_recording(State(state.clone())).await.unwrap();
+        stop_recording(State(state.clone())).await.unwrap();
+        assert!(state.auto_resume_task.lock().await.is_some());
+
+        // nothing happens before the delay's up
+        tokio::time::advance(Duration::from_millis(900)).await;
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StartRecording(_),
+            DiagDeviceCtrlMessage::StopRecording,
+            DiagDeviceCtrlMessage::FinishEntry(0),
+        ]));
+        // sleeping on the paused clock lets the auto-resume run once it's
+        // due, without any real waiting
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StartRecording(_),
+        ]));
+        // the task cleared its own handle once it ran
+        assert!(state.auto_resume_task.lock().await.is_none());
+        let store = state.qmdl_store_lock.read().await;
+        assert_eq!(store.manifest.entries.len(), 2);
+        assert_eq!(store.current_entry, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_manual_start_aborts_auto_resume() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (mut state, mut ctrl_rx) = test_state(dir.path()).await;
+        state.auto_resume_after_secs = 1;
+        let state = Arc::new(state);
+        start_recording(State(state.clone())).await.unwrap();
+        stop_recording(State(state.clone())).await.unwrap();
+        start_recording(State(state.clone())).await.unwrap();
+        assert!(state.auto_resume_task.lock().await.is_none());
+
+        // long enough for the auto-resume to have fired and finished, if it
+        // hadn't been aborted. The clock's paused, so this is instant.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StartRecording(_),
+            DiagDeviceCtrlMessage::StopRecording,
+            DiagDeviceCtrlMessage::FinishEntry(0),
+            DiagDeviceCtrlMessage::StartRecording(_),
+        ]));
+        assert_eq!(state.qmdl_store_lock.read().await.manifest.entries.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delayed_autostart() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (state, mut ctrl_rx) = test_state(dir.path()).await;
+        let state = Arc::new(state);
+        schedule_autostart(state.clone(), Duration::from_secs(30));
+
+        tokio::time::sleep(Duration::from_secs(29)).await;
+        assert!(drain_ctrl_messages(&mut ctrl_rx).is_empty());
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StartRecording(_),
+        ]));
+        assert_eq!(state.qmdl_store_lock.read().await.current_entry, Some(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_cancels_delayed_autostart() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (state, mut ctrl_rx) = test_state(dir.path()).await;
+        let state = Arc::new(state);
+        schedule_autostart(state.clone(), Duration::from_secs(30));
+
+        // there's nothing recording to stop, but the autostart's still
+        // called off
+        let err = stop_recording(State(state.clone())).await.unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::NotRecording);
+        assert!(state.auto_resume_task.lock().await.is_none());
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StopRecording,
+        ]));
+        assert!(state.qmdl_store_lock.read().await.manifest.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stop_leaves_discarding_to_diag_thread() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (state, mut ctrl_rx) = test_state(dir.path()).await;
+        state.qmdl_store_lock.write().await.discard_qmdl_after_analysis = true;
+        let state = Arc::new(state);
+        start_recording(State(state.clone())).await.unwrap();
+        stop_recording(State(state.clone())).await.unwrap();
+
+        // the diag thread hasn't closed its writers yet, so the QMDL's still
+        // there, and it's told to finish the entry only after stopping
+        let entry = state.qmdl_store_lock.read().await.manifest.entries[0].clone();
+        assert!(!entry.analysis_only);
+        assert!(tokio::fs::try_exists(entry.get_qmdl_filepath(dir.path())).await.unwrap());
+        assert!(matches!(drain_ctrl_messages(&mut ctrl_rx).as_slice(), [
+            DiagDeviceCtrlMessage::StartRecording(_),
+            DiagDeviceCtrlMessage::StopRecording,
+            DiagDeviceCtrlMessage::FinishEntry(0),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_get_analysis_ndjson() {
+        let dir = TempDir::new("diag_test").unwrap();
+        let (state, _ctrl_rx) = test_state(dir.path()).await;
+        let state = Arc::new(state);
+        let mut qmdl_store = state.qmdl_store_lock.write().await;
+        let (_, mut analysis_file) = qmdl_store.new_entry().await.unwrap();
+        // the last row was cut off partway through being written
+        let analysis = b"{\"metadata\":1}\n{\"row\":1}\n{\"ro";
+        analysis_file.write_all(analysis).await.unwrap();
+        analysis_file.flush().await.unwrap();
+        qmdl_store.update_entry_analysis_size(0, analysis.len()).await.unwrap();
+        qmdl_store.close_current_entry().await.unwrap();
+        let entry = qmdl_store.manifest.entries[0].clone();
+        drop(qmdl_store);
+
+        let response = get_analysis_ndjson(State(state.clone()), Path(entry.name.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"{\"metadata\":1}\n{\"row\":1}\n");
+
+        let err = get_analysis_ndjson(State(state.clone()), Path("nope".to_string())).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+
+        tokio::fs::remove_file(entry.get_analysis_filepath(dir.path())).await.unwrap();
+        let err = get_analysis_ndjson(State(state), Path(entry.name)).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -13,6 +13,20 @@ pub enum RayhunterError{
     TokioError(#[from] tokio::io::Error),
     #[error("QmdlStore error: {0}")]
     QmdlStoreError(#[from] RecordingStoreError),
+    #[error("Invalid bind_address {0:?}, expected an IPv4 or IPv6 address")]
+    InvalidBindAddress(String),
+    #[error("Invalid gsmtap_udp_target {0:?}, expected an IP address, optionally with a port")]
+    InvalidGsmtapUdpTarget(String),
+    #[error("Invalid CORS origin {0:?}, expected a scheme and host like \"http://192.168.1.2:3000\", with no path")]
+    InvalidCorsOrigin(String),
+    #[error("Invalid capture profile: {0}")]
+    InvalidCaptureProfile(String),
+    #[error("Invalid extra diag device label {0:?}, labels must be unique and only use letters, numbers, - and _")]
+    InvalidDiagDeviceLabel(String),
+    #[error("Invalid analyzers config: {0}")]
+    InvalidAnalyzerConfig(String),
+    #[error("Diag device {0} doesn't exist")]
+    NoSuchDiagDevice(String),
     #[error("No QMDL store found at path {0}, but can't create a new one due to readonly mode")]
     NoStoreReadonlyMode(String),
 }
@@ -0,0 +1,404 @@
+use std::fmt::Write;
+use std::{future, pin::pin};
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, FixedOffset};
+use futures::TryStreamExt;
+use log::error;
+use rayhunter::diag::{DataType, Message};
+use rayhunter::gsmtap_parser;
+use rayhunter::pcap::{GsmtapPcapError, GsmtapPcapWriter};
+use rayhunter::qmdl::QmdlReader;
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::{duplex, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Take};
+use tokio_util::io::ReaderStream;
+
+use crate::diag::complete_lines_len;
+use crate::pcap::redact_for_export;
+use crate::qmdl_store::{ManifestEntry, RecordingStore, RecordingStoreError};
+use crate::server::{ApiError, ServerState};
+use crate::zip::ZipWriter;
+
+// The time, PCI and EARFCN of each LTE RRC message that came from a
+// different cell than the one before it, in the order they were recorded
+#[derive(Default)]
+struct CellTimeline {
+    changes: Vec<(DateTime<FixedOffset>, u16, u32)>,
+}
+
+impl CellTimeline {
+    fn add_message(&mut self, msg: &Message) {
+        let (Some((pci, earfcn)), Message::Log { timestamp, .. }) = (msg.lte_rrc_cell(), msg) else {
+            return;
+        };
+        let Some(time) = timestamp.try_to_datetime() else {
+            return;
+        };
+        if self.changes.last().is_some_and(|&(_, last_pci, last_earfcn)| (last_pci, last_earfcn) == (pci, earfcn)) {
+            return;
+        }
+        self.changes.push((time, pci, earfcn));
+    }
+
+    // the PCI and EARFCN of the cell last heard from at the given time
+    fn cell_at(&self, time: DateTime<FixedOffset>) -> Option<(u16, u32)> {
+        self.changes.iter().rev()
+            .find(|(cell_time, _, _)| *cell_time <= time)
+            .map(|&(_, pci, earfcn)| (pci, earfcn))
+    }
+}
+
+// Just enough of the analysis file's rows to list the warnings in them
+#[derive(Deserialize)]
+struct AnalysisHeader {
+    analyzers: Vec<AnalyzerHeader>,
+}
+
+#[derive(Deserialize)]
+struct AnalyzerHeader {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct AnalysisRow {
+    analysis: Vec<PacketAnalysis>,
+}
+
+#[derive(Deserialize)]
+struct PacketAnalysis {
+    timestamp: DateTime<FixedOffset>,
+    events: Vec<Option<Event>>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    event_type: EventType,
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum EventType {
+    Informational,
+    QualitativeWarning { severity: String },
+}
+
+struct Warning {
+    timestamp: DateTime<FixedOffset>,
+    severity: String,
+    analyzer: String,
+    message: String,
+}
+
+// Collects the warnings from an analysis file one line at a time
+#[derive(Default)]
+struct Warnings {
+    // the analyzer names from the file's first line, once it's been seen
+    analyzers: Option<Vec<String>>,
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    fn add_line(&mut self, line: &[u8]) {
+        let Some(analyzers) = &self.analyzers else {
+            self.analyzers = Some(serde_json::from_slice::<AnalysisHeader>(line)
+                .map(|header| header.analyzers.into_iter().map(|analyzer| analyzer.name).collect())
+                .unwrap_or_default());
+            return;
+        };
+        let Ok(row) = serde_json::from_slice::<AnalysisRow>(line) else {
+            return;
+        };
+        for packet in row.analysis {
+            for (i, event) in packet.events.into_iter().enumerate() {
+                let Some(Event { event_type: EventType::QualitativeWarning { severity }, message }) = event else {
+                    continue;
+                };
+                self.warnings.push(Warning {
+                    timestamp: packet.timestamp,
+                    severity,
+                    analyzer: analyzers.get(i).cloned().unwrap_or_else(|| format!("analyzer {}", i)),
+                    message,
+                });
+            }
+        }
+    }
+}
+
+// keeps free text from breaking out of a markdown table cell
+fn table_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+// A human-readable markdown report on the recording and its warnings
+fn summary_report(entry: &ManifestEntry, warnings: &[Warning], cells: Option<&CellTimeline>) -> String {
+    let mut report = String::new();
+    writeln!(report, "# Rayhunter recording {}", entry.name).unwrap();
+    writeln!(report).unwrap();
+    writeln!(report, "- Started: {}", entry.start_time.to_rfc3339()).unwrap();
+    match entry.last_message_time {
+        Some(time) => writeln!(report, "- Last message: {}", time.to_rfc3339()).unwrap(),
+        None => writeln!(report, "- Last message: none").unwrap(),
+    }
+    if entry.analysis_only {
+        writeln!(report, "- QMDL: discarded after analysis, so there's no pcap").unwrap();
+    } else {
+        writeln!(report, "- QMDL size: {} bytes", entry.qmdl_size_bytes).unwrap();
+    }
+    if let Some(notes) = &entry.notes {
+        writeln!(report, "- Notes: {}", notes.replace('\n', " ")).unwrap();
+    }
+    writeln!(report).unwrap();
+    writeln!(report, "## Warnings").unwrap();
+    writeln!(report).unwrap();
+    if warnings.is_empty() {
+        writeln!(report, "No warnings were recorded.").unwrap();
+        return report;
+    }
+    writeln!(report, "| Time | Severity | Analyzer | Cell | Message |").unwrap();
+    writeln!(report, "| --- | --- | --- | --- | --- |").unwrap();
+    for warning in warnings {
+        let cell = match cells.and_then(|cells| cells.cell_at(warning.timestamp)) {
+            Some((pci, earfcn)) => format!("PCI {} EARFCN {}", pci, earfcn),
+            None => "unknown".to_string(),
+        };
+        writeln!(
+            report,
+            "| {} | {} | {} | {} | {} |",
+            warning.timestamp.to_rfc3339(),
+            warning.severity,
+            table_cell(&warning.analyzer),
+            cell,
+            table_cell(&warning.message),
+        ).unwrap();
+    }
+    report
+}
+
+// Converts a recording's QMDL data to pcapng as it's read, returning the
+// cells its messages came from
+async fn write_pcap<W, Q>(writer: W, qmdl_reader: &mut QmdlReader<Q>, redact_identities: bool) -> Result<CellTimeline, GsmtapPcapError>
+    where W: AsyncWrite + Unpin + Send, Q: AsyncRead + Unpin
+{
+    let mut cells = CellTimeline::default();
+    let mut pcap_writer = GsmtapPcapWriter::new(writer).await?;
+    pcap_writer.write_iface_header().await?;
+    let mut containers = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = containers.try_next().await? {
+        for msg in container.into_messages().into_iter().flatten() {
+            cells.add_message(&msg);
+            if let Ok(Some((timestamp, mut gsmtap_msg))) = gsmtap_parser::parse(msg) {
+                if redact_identities {
+                    redact_for_export(&mut gsmtap_msg);
+                }
+                pcap_writer.write_gsmtap_message(gsmtap_msg, timestamp).await?;
+            }
+        }
+    }
+    Ok(cells)
+}
+
+// Writes the bundle's files one after another, so only the summary (which
+// comes last, once the warnings and cells are known) is held in memory
+async fn write_bundle<W, A, Q>(
+    writer: W,
+    entry: &ManifestEntry,
+    analysis: Option<A>,
+    qmdl_reader: Option<QmdlReader<Q>>,
+    redact_identities: bool,
+) -> Result<W, GsmtapPcapError>
+    where W: AsyncWrite + Unpin + Send, A: AsyncRead + Unpin, Q: AsyncRead + Unpin
+{
+    let mut zip = ZipWriter::new(writer);
+    zip.add_file("manifest_entry.json", serde_json::to_string_pretty(entry).unwrap().as_bytes()).await?;
+
+    let mut warnings = Warnings::default();
+    zip.start_file(&format!("{}.ndjson", entry.name)).await?;
+    if let Some(analysis) = analysis {
+        let mut analysis = BufReader::new(analysis);
+        let mut line = Vec::new();
+        while analysis.read_until(b'\n', &mut line).await? > 0 {
+            zip.write_all(&line).await?;
+            warnings.add_line(&line);
+            line.clear();
+        }
+    }
+    zip.finish_file().await?;
+
+    let cells = match qmdl_reader {
+        Some(mut qmdl_reader) => {
+            zip.start_file(&format!("{}.pcapng", entry.name)).await?;
+            let cells = write_pcap(&mut zip, &mut qmdl_reader, redact_identities).await?;
+            zip.finish_file().await?;
+            Some(cells)
+        },
+        None => None,
+    };
+
+    zip.add_file("summary.md", summary_report(entry, &warnings.warnings, cells.as_ref()).as_bytes()).await?;
+    Ok(zip.finish().await?)
+}
+
+// Opens the complete rows of an entry's analysis file, if it has one
+async fn open_analysis(qmdl_store: &RecordingStore, entry: &ManifestEntry) -> Result<Option<Take<File>>, ApiError> {
+    let mut analysis_file = match qmdl_store.open_entry_analysis(entry).await {
+        Ok(file) => file,
+        Err(RecordingStoreError::ReadFileError(err)) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("error opening analysis file: {}", e)).into()),
+    };
+    let analysis_len = complete_lines_len(&mut analysis_file).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error reading analysis file: {}", e)))?;
+    Ok(Some(analysis_file.take(analysis_len.min(entry.analysis_size_bytes as u64))))
+}
+
+// Bundles everything about a recording into one zip file for reporting an
+// incident: its pcap, analysis, manifest entry, and a summary of its warnings.
+// Like get_pcap, the zip is streamed to the client as it's written, so if
+// something goes wrong partway through the download ends without the zip's
+// central directory and won't open.
+pub async fn get_evidence_bundle(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Response, ApiError> {
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let entry = qmdl_store.entry_for_name(&qmdl_name)
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
+    let analysis = open_analysis(&qmdl_store, &entry).await?;
+    let qmdl_reader = if entry.analysis_only {
+        None
+    } else {
+        let qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)))?;
+        Some(QmdlReader::new(qmdl_file, Some(entry.qmdl_size_bytes)))
+    };
+    drop(qmdl_store);
+
+    let headers = [
+        (CONTENT_TYPE, "application/zip".to_string()),
+        (CONTENT_DISPOSITION, format!("attachment; filename=\"rayhunter-{}-evidence.zip\"", entry.name)),
+    ];
+    let (reader, writer) = duplex(1024);
+    let redact_identities = state.redact_identities;
+    tokio::spawn(async move {
+        if let Err(e) = write_bundle(writer, &entry, analysis, qmdl_reader, redact_identities).await {
+            error!("error writing evidence bundle for {}: {}", entry.name, e);
+        }
+    });
+
+    let body = Body::from_stream(ReaderStream::new(reader));
+    Ok((headers, body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use rayhunter::diag::{HdlcEncapsulatedMessage, MessagesContainer, CRC_CCITT};
+    use rayhunter::hdlc::hdlc_encapsulate;
+    use rayhunter::qmdl::QmdlWriter;
+    use crate::diag::tests::lte_rrc_log;
+    use crate::zip::tests::read_zip;
+
+    fn entry(analysis_only: bool) -> ManifestEntry {
+        ManifestEntry {
+            name: "1700000000".to_string(),
+            start_time: Local::now(),
+            last_message_time: None,
+            qmdl_size_bytes: if analysis_only { 0 } else { 1234 },
+            analysis_size_bytes: 0,
+            notes: Some("parked outside the stadium".to_string()),
+            first_message_time: None,
+            analysis_only,
+            protected: false,
+            migrated_from: None,
+        }
+    }
+
+    const ANALYSIS: &str = concat!(
+        "{\"analyzers\":[{\"name\":\"IMSI Requested\",\"description\":\"\"},{\"name\":\"Null Cipher\",\"description\":\"\"}]}\n",
+        "{\"timestamp\":\"2024-01-01T00:00:10+00:00\",\"skipped_message_reasons\":[],\"analysis\":[",
+        "{\"timestamp\":\"2024-01-01T00:00:10+00:00\",\"message_type\":null,\"events\":[",
+        "{\"event_type\":{\"type\":\"QualitativeWarning\",\"severity\":\"High\"},\"message\":\"IMSI requested\"},",
+        "{\"event_type\":{\"type\":\"Informational\"},\"message\":\"just so you know\"}]}]}\n",
+    );
+
+    // an RRCConnectionRelease with a redirect
+    const RRC_RELEASE: [u8; 5] = [0x28, 0x22, 0x00, 0x6a, 0x40];
+
+    // diag timestamps count 1/800ths of a second in their upper 48 bits
+    fn diag_timestamp(secs_after_2024: u64) -> u64 {
+        ((1_388_102_400 + secs_after_2024) * 800) << 16
+    }
+
+    // a QMDL file with an LTE RRC message from PCI 123 at midnight on
+    // 2024-01-01, then from PCI 456 twenty seconds later
+    async fn qmdl() -> Vec<u8> {
+        let mut qmdl = Vec::new();
+        let mut qmdl_writer = QmdlWriter::new(&mut qmdl);
+        for (pci, secs) in [(123, 0), (123, 5), (456, 20)] {
+            let data = hdlc_encapsulate(&lte_rrc_log(pci, 5230, diag_timestamp(secs), &RRC_RELEASE), &CRC_CCITT);
+            let container = MessagesContainer {
+                data_type: DataType::UserSpace,
+                num_messages: 1,
+                messages: vec![HdlcEncapsulatedMessage { len: data.len() as u32, data }],
+            };
+            qmdl_writer.write_container(&container).await.unwrap();
+        }
+        qmdl
+    }
+
+    #[tokio::test]
+    async fn test_evidence_bundle_members() {
+        let qmdl = qmdl().await;
+        let qmdl_reader = QmdlReader::new(qmdl.as_slice(), None);
+        let bundle = write_bundle(Vec::new(), &entry(false), Some(ANALYSIS.as_bytes()), Some(qmdl_reader), false).await.unwrap();
+        let files = read_zip(&bundle);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["manifest_entry.json", "1700000000.ndjson", "1700000000.pcapng", "summary.md"]);
+
+        let manifest_entry: ManifestEntry = serde_json::from_slice(&files[0].1).unwrap();
+        assert_eq!(manifest_entry.name, "1700000000");
+        assert_eq!(manifest_entry.qmdl_size_bytes, 1234);
+        assert_eq!(files[1].1, ANALYSIS.as_bytes());
+        // a pcapng file starts with a section header block, and each
+        // message's payload ends up in a packet
+        let pcap = &files[2].1;
+        assert_eq!(pcap[..4], [0x0a, 0x0d, 0x0d, 0x0a]);
+        assert_eq!(pcap.windows(RRC_RELEASE.len()).filter(|window| *window == RRC_RELEASE).count(), 3);
+
+        let summary = String::from_utf8(files[3].1.clone()).unwrap();
+        assert!(summary.contains("- Notes: parked outside the stadium"));
+        assert!(summary.contains("| 2024-01-01T00:00:10+00:00 | High | IMSI Requested | PCI 123 EARFCN 5230 | IMSI requested |"));
+        // informational events aren't warnings
+        assert!(!summary.contains("just so you know"));
+    }
+
+    #[tokio::test]
+    async fn test_evidence_bundle_without_qmdl() {
+        let bundle = write_bundle(Vec::new(), &entry(true), None::<&[u8]>, None::<QmdlReader<&[u8]>>, false).await.unwrap();
+        let files = read_zip(&bundle);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["manifest_entry.json", "1700000000.ndjson", "summary.md"]);
+        assert!(files[1].1.is_empty());
+        let summary = String::from_utf8(files[2].1.clone()).unwrap();
+        assert!(summary.contains("discarded after analysis"));
+        assert!(summary.contains("No warnings were recorded."));
+    }
+
+    #[tokio::test]
+    async fn test_cell_timeline_keeps_only_changes() {
+        let qmdl = qmdl().await;
+        let mut qmdl_reader = QmdlReader::new(qmdl.as_slice(), None);
+        let cells = write_pcap(Vec::new(), &mut qmdl_reader, false).await.unwrap();
+        let pcis: Vec<u16> = cells.changes.iter().map(|&(_, pci, _)| pci).collect();
+        assert_eq!(pcis, [123, 456]);
+        let time = |secs| DateTime::parse_from_rfc3339("2023-12-31T23:59:59+00:00").unwrap() + chrono::Duration::seconds(secs);
+        assert_eq!(cells.cell_at(time(0)), None);
+        assert_eq!(cells.cell_at(time(11)), Some((123, 5230)));
+        assert_eq!(cells.cell_at(time(21)), Some((456, 5230)));
+    }
+}
@@ -1,8 +1,7 @@
 use image::{codecs::gif::GifDecoder, imageops::FilterType, AnimationDecoder, DynamicImage};
+use crate::qr::QrCode;
 use std::{io::Cursor, time::Duration};
 
-const FB_PATH:&str = "/dev/fb0";
-
 #[derive(Copy, Clone)]
 // TODO actually poll for this, maybe w/ fbset?
 struct Dimensions {
@@ -22,20 +21,26 @@ pub enum Color565 {
     Pink =   0b1111010010011111,
 }
 
-#[derive(Copy, Clone)]
-pub struct Framebuffer<'a> {
+pub struct Framebuffer {
     dimensions: Dimensions,
-    path: &'a str,
+    path: String,
+    brightness: u8,
 }
 
-impl Framebuffer<'_>{
-    pub const fn new() -> Self {
+impl Framebuffer {
+    pub fn new(path: String, width: u32, height: u32) -> Self {
         Framebuffer{
-            dimensions: Dimensions{height: 128, width: 128},
-            path: FB_PATH,
+            dimensions: Dimensions{height, width},
+            path,
+            brightness: 100,
         }
     }
 
+    // Sets the percentage (0-100) that every pixel's intensity is scaled by
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(100);
+    }
+
     fn write(&mut self, img: DynamicImage) {
         let mut width = img.width();
         let mut height = img.height();
@@ -56,10 +61,10 @@ impl Framebuffer<'_>{
                 let mut rgb565: u16 = (px[0] as u16 & 0b11111000) << 8;
                 rgb565 |= (px[1] as u16 & 0b11111100) << 3;
                 rgb565 |= (px[2] as u16) >> 3;
-                buf.extend(rgb565.to_le_bytes());
+                buf.extend(scale_rgb565(rgb565, self.brightness).to_le_bytes());
             }
         }
-        std::fs::write(self.path, &buf).unwrap();
+        std::fs::write(&self.path, &buf).unwrap();
     }
 
     pub fn draw_gif(&mut self, img_buffer: &[u8]) {
@@ -82,11 +87,96 @@ impl Framebuffer<'_>{
 
     pub fn draw_line(&mut self, color: Color565, height: u32){
         let px_num= height * self.dimensions.width;
-        let color: u16 = color as u16;
+        let color: u16 = scale_rgb565(color as u16, self.brightness);
         let mut buffer: Vec<u8> = Vec::new();
         for _ in 0..px_num {
             buffer.extend(color.to_le_bytes());
         }
-        std::fs::write(self.path, &buffer).unwrap();
+        std::fs::write(&self.path, &buffer).unwrap();
+    }
+
+    // Draws the code black on white, centred, with each module scaled to a
+    // whole number of pixels so it stays sharp enough to scan
+    pub fn draw_qr(&mut self, qr: &QrCode) {
+        let buffer: Vec<u8> = qr_pixels(qr, self.dimensions.width, self.dimensions.height)
+            .into_iter()
+            .flat_map(|dark| {
+                let color = if dark { Color565::Black } else { Color565::White };
+                scale_rgb565(color as u16, self.brightness).to_le_bytes()
+            })
+            .collect();
+        std::fs::write(&self.path, &buffer).unwrap();
+    }
+}
+
+// the number of light modules the standard asks for around the code
+const QR_QUIET_ZONE: u32 = 4;
+
+// lays out a QR code to fit the screen, returning whether each pixel is dark
+fn qr_pixels(qr: &QrCode, width: u32, height: u32) -> Vec<bool> {
+    let modules = qr.size() as u32 + 2 * QR_QUIET_ZONE;
+    let scale = (width.min(height) / modules).max(1);
+    let x_offset = (width as i64 - (modules * scale) as i64) / 2;
+    let y_offset = (height as i64 - (modules * scale) as i64) / 2;
+    let module_at = |pixel: u32, offset: i64| {
+        let module = (pixel as i64 - offset).div_euclid(scale as i64) - QR_QUIET_ZONE as i64;
+        usize::try_from(module).ok().filter(|module| *module < qr.size())
+    };
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let dark = match (module_at(x, x_offset), module_at(y, y_offset)) {
+                (Some(x), Some(y)) => qr.get(x, y),
+                _ => false,
+            };
+            pixels.push(dark);
+        }
     }
-}
\ No newline at end of file
+    pixels
+}
+
+// scales each channel of an RGB565 pixel by the given brightness percentage
+fn scale_rgb565(color: u16, brightness: u8) -> u16 {
+    if brightness >= 100 {
+        return color;
+    }
+    let scale = |channel: u16| channel * brightness as u16 / 100;
+    let r = scale(color >> 11);
+    let g = scale((color >> 5) & 0b111111);
+    let b = scale(color & 0b11111);
+    (r << 11) | (g << 5) | b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_rgb565() {
+        assert_eq!(scale_rgb565(Color565::White as u16, 100), Color565::White as u16);
+        assert_eq!(scale_rgb565(Color565::White as u16, 0), Color565::Black as u16);
+        assert_eq!(scale_rgb565(Color565::Green as u16, 50), 31 << 5);
+        assert_eq!(scale_rgb565(Color565::Red as u16, 50), 15 << 11);
+    }
+
+    #[test]
+    fn test_qr_pixels() {
+        // a version 2 code is 25 modules, 33 with the quiet zone, so on a
+        // 128x128 screen each module is 3 pixels and the code starts 14
+        // pixels in, plus 12 for the quiet zone
+        let qr = QrCode::encode(b"http://192.168.1.1:8080/").unwrap();
+        assert_eq!(qr.size(), 25);
+        let pixels = qr_pixels(&qr, 128, 128);
+        assert_eq!(pixels.len(), 128 * 128);
+        let pixel = |x: usize, y: usize| pixels[y * 128 + x];
+        for y in 0..qr.size() {
+            for x in 0..qr.size() {
+                assert_eq!(pixel(26 + x * 3, 26 + y * 3), qr.get(x, y));
+                assert_eq!(pixel(26 + x * 3 + 2, 26 + y * 3 + 2), qr.get(x, y));
+            }
+        }
+        assert!(!pixel(25, 26));
+        assert!(pixel(26, 26));
+        assert!(!pixel(26 + 25 * 3, 26));
+    }
+}
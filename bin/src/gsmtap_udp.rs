@@ -0,0 +1,97 @@
+//! Streams GSMTAP packets over UDP as they're read from the diag device, so
+//! Wireshark on another machine can follow along live instead of waiting for
+//! a recording's pcap.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use log::debug;
+use rayhunter::diag::MessagesContainer;
+use rayhunter::gsmtap_parser;
+use tokio::net::UdpSocket;
+
+pub struct GsmtapUdpSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl GsmtapUdpSender {
+    pub async fn new(target: SocketAddr) -> Result<Self, std::io::Error> {
+        let bind_ip = match target {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0)).await?;
+        Ok(GsmtapUdpSender { socket, target })
+    }
+
+    // Sends a datagram for each message in the container that has a GSMTAP
+    // equivalent
+    pub async fn send_container(&self, container: &MessagesContainer) {
+        let gsmtap_msgs = container.clone().into_messages().into_iter()
+            .flatten()
+            .filter_map(|msg| gsmtap_parser::parse(msg).ok().flatten());
+        for (_, gsmtap_msg) in gsmtap_msgs {
+            let datagram = match gsmtap_msg.to_datagram() {
+                Ok(datagram) => datagram,
+                Err(err) => {
+                    debug!("couldn't serialize GSMTAP message: {}", err);
+                    continue;
+                },
+            };
+            // nobody listening (or no route to them) is expected whenever the
+            // researcher's laptop isn't around, so don't fill the logs with it
+            if let Err(err) = self.socket.send_to(&datagram, self.target).await {
+                debug!("couldn't send GSMTAP datagram to {}: {}", self.target, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayhunter::diag::{DataType, HdlcEncapsulatedMessage, CRC_CCITT};
+    use rayhunter::hdlc::hdlc_encapsulate;
+
+    #[tokio::test]
+    async fn test_sends_gsmtap_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = GsmtapUdpSender::new(receiver.local_addr().unwrap()).await.unwrap();
+
+        // an LTE RRC OTA log (ext header version 20) of a DL-DCCH message
+        // on PCI 160, EARFCN 2050
+        let payload = [0x28, 0x22, 0x00, 0x6a, 0x40];
+        let mut log = vec![16, 0];
+        log.extend(36u16.to_le_bytes()); // outer_length
+        log.extend(36u16.to_le_bytes()); // inner_length
+        log.extend(0xb0c0u16.to_le_bytes());
+        log.extend(72659535985485082u64.to_le_bytes());
+        log.extend([20, 14, 48, 0]); // ext header version, rrc rel, bearer id
+        log.extend(160u16.to_le_bytes()); // pci
+        log.extend(2050u32.to_le_bytes()); // earfcn
+        log.extend(4057u16.to_le_bytes()); // sfn_subfn
+        log.push(7); // pdu_num
+        log.extend(0u32.to_le_bytes()); // sib_mask
+        log.extend((payload.len() as u16).to_le_bytes());
+        log.extend(payload);
+        let data = hdlc_encapsulate(&log, &CRC_CCITT);
+        sender.send_container(&MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![HdlcEncapsulatedMessage { len: data.len() as u32, data }],
+        }).await;
+
+        let mut buf = [0; 128];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let mut expected = vec![
+            2, 4, // version, header length
+            0x0d, 0, // LTE RRC, timeslot
+            0x08, 0x02, // ARFCN 2050
+            0, 0, // signal, SNR
+            0, 0, 0, 253, // frame number
+            1, 0, 9, 0, // DL-DCCH, antenna, subframe, reserved
+        ];
+        expected.extend(payload);
+        assert_eq!(&buf[..len], &expected);
+    }
+}
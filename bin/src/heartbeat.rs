@@ -0,0 +1,61 @@
+//! A file the diag read loop rewrites as messages come in, so an external
+//! watchdog can tell when capture has stalled (e.g. the diag device hung) and
+//! reboot the device. Unlike a check that the daemon's process is alive, it
+//! goes stale whenever messages stop flowing.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+pub struct Heartbeat {
+    path: PathBuf,
+    // the least time between writes, to spare the flash
+    interval: Duration,
+    last_beat: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new<P>(path: P, interval: Duration) -> Self where P: Into<PathBuf> {
+        Heartbeat {
+            path: path.into(),
+            interval,
+            last_beat: None,
+        }
+    }
+
+    // Writes the current unix time to the heartbeat file, unless it was
+    // written less than `interval` ago. Failures are only logged, since
+    // a watchdog noticing the file go stale is the right outcome.
+    pub async fn beat(&mut self) {
+        if self.last_beat.is_some_and(|last_beat| last_beat.elapsed() < self.interval) {
+            return;
+        }
+        self.last_beat = Some(Instant::now());
+        let now = chrono::Utc::now().timestamp();
+        if let Err(err) = tokio::fs::write(&self.path, format!("{}\n", now)).await {
+            warn!("couldn't write heartbeat file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_beat_interval() {
+        let dir = TempDir::new("heartbeat_test").unwrap();
+        let path = dir.path().join("heartbeat");
+        let mut heartbeat = Heartbeat::new(&path, Duration::from_secs(60));
+        heartbeat.beat().await;
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(contents.trim().parse::<i64>().unwrap() > 0);
+
+        // too soon for another write
+        tokio::fs::remove_file(&path).await.unwrap();
+        heartbeat.beat().await;
+        assert!(!path.exists());
+    }
+}
@@ -1,7 +1,11 @@
 use crate::ServerState;
+use crate::server::ApiError;
+use crate::qmdl_store::RecordingStoreError;
 
 use rayhunter::diag::DataType;
+use rayhunter::gsmtap::{GsmtapMessage, GsmtapType};
 use rayhunter::gsmtap_parser;
+use rayhunter::nas;
 use rayhunter::pcap::GsmtapPcapWriter;
 use rayhunter::qmdl::QmdlReader;
 use axum::body::Body;
@@ -16,27 +20,40 @@ use std::sync::Arc;
 use log::error;
 use futures::TryStreamExt;
 
+// Removes subscriber identities from a message before it's exported. Only
+// the exported copy is redacted, the QMDL file on the device is left
+// untouched.
+pub fn redact_for_export(gsmtap_msg: &mut GsmtapMessage) {
+    if matches!(gsmtap_msg.header.gsmtap_type, GsmtapType::LteNas(_)) {
+        nas::redact_identities(&mut gsmtap_msg.payload);
+    }
+}
+
 // Streams a pcap file chunk-by-chunk to the client by reading the QMDL data
 // written so far. This is done by spawning a thread which streams chunks of
 // pcap data to a channel that's piped to the client.
-pub async fn get_pcap(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Response, (StatusCode, String)> {
+pub async fn get_pcap(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Response, ApiError> {
     let qmdl_store = state.qmdl_store_lock.read().await;
     let entry = qmdl_store.entry_for_name(&qmdl_name)
-        .ok_or((StatusCode::NOT_FOUND, format!("couldn't find qmdl file with name {}", qmdl_name)))?;
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
     if entry.qmdl_size_bytes == 0 {
         return Err((
             StatusCode::SERVICE_UNAVAILABLE,
             "QMDL file is empty, try again in a bit!".to_string()
-        ));
+        ).into());
     }
 
     let qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)))?;
+        .map_err(|e| match e {
+            RecordingStoreError::QmdlDiscarded(_) => ApiError::qmdl_discarded(e),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)).into(),
+        })?;
     // the QMDL reader should stop at the last successfully written data chunk
     // (entry.size_bytes)
     let (reader, writer) = duplex(1024);
     let mut pcap_writer = GsmtapPcapWriter::new(writer).await.unwrap();
     pcap_writer.write_iface_header().await.unwrap();
+    let redact_identities = state.redact_identities;
 
     tokio::spawn(async move {
         let mut reader = QmdlReader::new(qmdl_file, Some(entry.qmdl_size_bytes));
@@ -49,7 +66,10 @@ pub async fn get_pcap(State(state): State<Arc<ServerState>>, Path(qmdl_name): Pa
                     Ok(msg) => {
                         let maybe_gsmtap_msg = gsmtap_parser::parse(msg)
                             .expect("error parsing gsmtap message");
-                        if let Some((timestamp, gsmtap_msg)) = maybe_gsmtap_msg {
+                        if let Some((timestamp, mut gsmtap_msg)) = maybe_gsmtap_msg {
+                            if redact_identities {
+                                redact_for_export(&mut gsmtap_msg);
+                            }
                             pcap_writer.write_gsmtap_message(gsmtap_msg, timestamp).await
                                 .expect("error writing pcap packet");
                         }
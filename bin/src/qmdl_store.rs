@@ -1,6 +1,7 @@
 use std::path::{PathBuf, Path};
+use std::time::Duration;
 use thiserror::Error;
-use tokio::{fs::{self, File, try_exists}, io::AsyncWriteExt};
+use tokio::{fs::{self, File, try_exists}, io::AsyncWriteExt, sync::RwLock, time::Instant};
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Local};
 
@@ -19,13 +20,50 @@ pub enum RecordingStoreError {
     #[error("Couldn't write manifest file: {0}")]
     WriteManifestError(tokio::io::Error),
     #[error("Couldn't parse QMDL store manifest file: {0}")]
-    ParseManifestError(toml::de::Error)
+    ParseManifestError(toml::de::Error),
+    #[error("No entry with name {0}")]
+    NoSuchEntry(String),
+    #[error("Store is already in use")]
+    Locked,
+    #[error("Couldn't lock store: {0}")]
+    LockError(tokio::io::Error),
+    #[error("Couldn't move recording files: {0}")]
+    MigrateFileError(tokio::io::Error),
+    #[error("Can't migrate a store into itself")]
+    MigrateIntoSelf,
+    #[error("Can't migrate a store while recording")]
+    MigrateWhileRecording,
+    #[error("Recording name {0} isn't a plain file name")]
+    InvalidEntryName(String),
+    #[error("QMDL file for entry {0} was discarded after analysis")]
+    QmdlDiscarded(String),
+    #[error("Couldn't delete QMDL file: {0}")]
+    DiscardQmdlError(tokio::io::Error),
 }
 
+// Exclusively flock()ed by whichever RecordingStore has the store open, and
+// containing its process's PID for anyone investigating. The kernel releases
+// the lock when the holder's file is closed, including when its process dies,
+// so a lock can't be left behind or mistaken for one held by a reused PID.
+const LOCK_FILENAME: &str = "rayhunter.lock";
+
+// The recording in progress has its sizes updated for nearly every container,
+// but they're only written out this often, since each write's synced to disk.
+// Closing the entry writes out whatever's left.
+const LIVE_MANIFEST_WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct RecordingStore {
     pub path: PathBuf,
     pub manifest: Manifest,
     pub current_entry: Option<usize>, // index into manifest
+    // whether to delete each recording's QMDL file once it's closed, keeping
+    // just the analysis, for when storage is tight
+    pub discard_qmdl_after_analysis: bool,
+    // when the manifest was last written out, and whether it's changed since
+    manifest_written_at: Instant,
+    manifest_dirty: bool,
+    // holds the store's lock until the RecordingStore is dropped
+    _lock_file: std::fs::File,
 }
 
 #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
@@ -40,6 +78,27 @@ pub struct ManifestEntry {
     pub last_message_time: Option<DateTime<Local>>,
     pub qmdl_size_bytes: usize,
     pub analysis_size_bytes: usize,
+    // free-text notes about the recording. Manifests written before notes
+    // existed don't have this field
+    #[serde(default)]
+    pub notes: Option<String>,
+    // when the first message was logged according to the modem, which unlike
+    // start_time doesn't depend on the device's clock being set
+    #[serde(default)]
+    pub first_message_time: Option<DateTime<Local>>,
+    // set once the QMDL file's been deleted to save space, leaving only the
+    // analysis
+    #[serde(default)]
+    pub analysis_only: bool,
+    // marks an important recording, such as one of an incident, which is
+    // kept whole even when discard_qmdl_after_analysis is set
+    #[serde(default)]
+    pub protected: bool,
+    // the entry's name in the store it was migrated from, if it was, which
+    // along with start_time identifies it even if it's been renamed or
+    // edited since
+    #[serde(default)]
+    pub migrated_from: Option<String>,
 }
 
 impl ManifestEntry {
@@ -51,6 +110,11 @@ impl ManifestEntry {
             last_message_time: None,
             qmdl_size_bytes: 0,
             analysis_size_bytes: 0,
+            notes: None,
+            first_message_time: None,
+            analysis_only: false,
+            protected: false,
+            migrated_from: None,
         }
     }
 
@@ -67,6 +131,52 @@ impl ManifestEntry {
     }
 }
 
+// Writes the manifest to a temporary file and renames it over the old one, so
+// that a crash or power cut mid-write leaves either the old or new manifest
+// intact. Both the file and the directory entry are synced before returning,
+// otherwise the rename can hit the disk before the file's contents do.
+async fn write_manifest_atomically(dir: &Path, manifest: &Manifest) -> Result<(), RecordingStoreError> {
+    let manifest_path = dir.join("manifest.toml");
+    let tmp_manifest_path = dir.join("manifest.toml.new");
+    let manifest_contents = toml::to_string_pretty(manifest)
+        .expect("failed to serialize manifest");
+    let mut tmp_manifest_file = File::create(&tmp_manifest_path).await
+        .map_err(RecordingStoreError::WriteManifestError)?;
+    tmp_manifest_file.write_all(manifest_contents.as_bytes()).await
+        .map_err(RecordingStoreError::WriteManifestError)?;
+    tmp_manifest_file.sync_all().await
+        .map_err(RecordingStoreError::WriteManifestError)?;
+    drop(tmp_manifest_file);
+    fs::rename(&tmp_manifest_path, &manifest_path).await
+        .map_err(RecordingStoreError::WriteManifestError)?;
+    sync_dir(dir).await
+}
+
+// Copies a file, across filesystems if need be, and makes sure the copy's
+// on disk before returning
+async fn copy_and_sync(from: &Path, to: &Path) -> Result<(), RecordingStoreError> {
+    fs::copy(from, to).await
+        .map_err(RecordingStoreError::MigrateFileError)?;
+    File::open(to).await
+        .map_err(RecordingStoreError::MigrateFileError)?
+        .sync_all().await
+        .map_err(RecordingStoreError::MigrateFileError)
+}
+
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> Result<(), RecordingStoreError> {
+    File::open(dir).await
+        .map_err(RecordingStoreError::OpenDirError)?
+        .sync_all().await
+        .map_err(RecordingStoreError::WriteManifestError)
+}
+
+// directories can't be opened (and thus synced) as files on other platforms
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> Result<(), RecordingStoreError> {
+    Ok(())
+}
+
 impl RecordingStore {
     // Returns whether a directory with a "manifest.toml" exists at the given
     // path (though doesn't check if that manifest is valid)
@@ -79,32 +189,45 @@ impl RecordingStore {
 
     // Loads an existing RecordingStore at the given path. Errors if no store exists,
     // or if it's malformed.
+    // Errors if another RecordingStore (in this process or another) has it
+    // open.
     pub async fn load<P>(path: P) -> Result<Self, RecordingStoreError> where P: AsRef<Path> {
         let path: PathBuf = path.as_ref().to_path_buf();
         let manifest = RecordingStore::read_manifest(&path).await?;
+        let lock_file = acquire_lock(&path)?;
         Ok(RecordingStore {
             path,
             manifest,
             current_entry: None,
+            discard_qmdl_after_analysis: false,
+            manifest_written_at: Instant::now(),
+            manifest_dirty: false,
+            _lock_file: lock_file,
         })
     }
 
     // Creates a new RecordingStore at the given path. This involves creating a dir
     // and writing an empty manifest.
     pub async fn create<P>(path: P) -> Result<Self, RecordingStoreError> where P: AsRef<Path> {
-        let manifest_path = path.as_ref().join("manifest.toml");
         fs::create_dir_all(&path).await
             .map_err(RecordingStoreError::OpenDirError)?;
-        let mut manifest_file = File::create(&manifest_path).await
-            .map_err(RecordingStoreError::WriteManifestError)?;
         let empty_manifest = Manifest { entries: Vec::new() };
-        let empty_manifest_contents = toml::to_string_pretty(&empty_manifest)
-            .expect("failed to serialize manifest");
-        manifest_file.write_all(empty_manifest_contents.as_bytes()).await
-            .map_err(RecordingStoreError::WriteManifestError)?;
+        write_manifest_atomically(path.as_ref(), &empty_manifest).await?;
         RecordingStore::load(path).await
     }
 
+    // Returns whether a RecordingStore (in this process or another) currently
+    // has the store at the given path open
+    pub async fn is_locked<P>(path: P) -> Result<bool, RecordingStoreError> where P: AsRef<Path> {
+        match acquire_lock(path.as_ref()) {
+            // dropping the file straight away releases the lock again
+            Ok(_) => Ok(false),
+            Err(RecordingStoreError::Locked) => Ok(true),
+            Err(RecordingStoreError::LockError(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     async fn read_manifest<P>(path: P) -> Result<Manifest, RecordingStoreError> where P: AsRef<Path> {
         let manifest_path = path.as_ref().join("manifest.toml");
         let file_contents = fs::read_to_string(&manifest_path).await
@@ -121,7 +244,10 @@ impl RecordingStore {
         if self.current_entry.is_some() {
             self.close_current_entry().await?;
         }
-        let new_entry = ManifestEntry::new();
+        let mut new_entry = ManifestEntry::new();
+        // a rotation can start a new entry within the same second as the
+        // last one, which would otherwise write over its files
+        new_entry.name = self.unique_entry_name(&new_entry.name);
         let qmdl_filepath = new_entry.get_qmdl_filepath(&self.path);
         let qmdl_file = File::options()
             .create(true)
@@ -140,8 +266,35 @@ impl RecordingStore {
         Ok((qmdl_file, analysis_file))
     }
 
+    // Adds a new, already closed entry holding QMDL data captured elsewhere
+    // (e.g. by QCSuper or SCAT). Returns the entry's index and its newly
+    // created analysis file.
+    pub async fn import_entry(&mut self, start_time: DateTime<Local>, qmdl_data: &[u8]) -> Result<(usize, File), RecordingStoreError> {
+        let mut new_entry = ManifestEntry::new();
+        // imports can happen within the same second as a recording starting
+        // or another import, so make sure the name's unique
+        new_entry.name = self.unique_entry_name(&format!("{}-import", new_entry.name));
+        new_entry.start_time = start_time;
+        new_entry.qmdl_size_bytes = qmdl_data.len();
+
+        let mut qmdl_file = File::create(new_entry.get_qmdl_filepath(&self.path)).await
+            .map_err(RecordingStoreError::CreateFileError)?;
+        qmdl_file.write_all(qmdl_data).await
+            .map_err(RecordingStoreError::CreateFileError)?;
+        qmdl_file.flush().await
+            .map_err(RecordingStoreError::CreateFileError)?;
+        let analysis_file = File::create(new_entry.get_analysis_filepath(&self.path)).await
+            .map_err(RecordingStoreError::CreateFileError)?;
+        self.manifest.entries.push(new_entry);
+        self.write_manifest().await?;
+        Ok((self.manifest.entries.len() - 1, analysis_file))
+    }
+
     // Returns the corresponding QMDL file for a given entry
     pub async fn open_entry_qmdl(&self, entry: &ManifestEntry) -> Result<File, RecordingStoreError> {
+        if entry.analysis_only {
+            return Err(RecordingStoreError::QmdlDiscarded(entry.name.clone()));
+        }
         File::open(entry.get_qmdl_filepath(&self.path)).await
             .map_err(RecordingStoreError::ReadFileError)
     }
@@ -152,42 +305,207 @@ impl RecordingStore {
             .map_err(RecordingStoreError::ReadFileError)
     }
 
-    // Unsets the current entry
-    pub async fn close_current_entry(&mut self) -> Result<(), RecordingStoreError> {
+    // Empties the analysis file of the entry with the given name so it can be
+    // re-analyzed, returning the entry's index and the file
+    pub async fn clear_and_open_entry_analysis(&mut self, name: &str) -> Result<(usize, File), RecordingStoreError> {
+        let entry_index = self.manifest.entries.iter()
+            .position(|entry| entry.name == name)
+            .ok_or_else(|| RecordingStoreError::NoSuchEntry(name.to_string()))?;
+        let entry = &mut self.manifest.entries[entry_index];
+        let analysis_file = File::create(entry.get_analysis_filepath(&self.path)).await
+            .map_err(RecordingStoreError::CreateFileError)?;
+        entry.analysis_size_bytes = 0;
+        self.write_manifest().await?;
+        Ok((entry_index, analysis_file))
+    }
+
+    // Unsets the current entry, returning its index. Its QMDL file's kept
+    // either way, since whoever's writing it may not have finished yet.
+    pub async fn close_current_entry(&mut self) -> Result<usize, RecordingStoreError> {
         match self.current_entry {
-            Some(_) => {
+            Some(entry_index) => {
                 self.current_entry = None;
-                Ok(())
+                if self.manifest_dirty {
+                    self.write_manifest().await?;
+                }
+                Ok(entry_index)
             },
             None => Err(RecordingStoreError::NoCurrentEntry)
         }
     }
 
+    // Called once a closed entry's writers are too. Recordings are analyzed
+    // as they're made, so this is when their analysis is complete and, if
+    // discard_qmdl_after_analysis is set and it isn't protected, their QMDL
+    // file can go.
+    pub async fn finish_entry(&mut self, entry_index: usize) -> Result<(), RecordingStoreError> {
+        if self.discard_qmdl_after_analysis && !self.manifest.entries[entry_index].protected {
+            self.discard_entry_qmdl(entry_index).await?;
+        }
+        Ok(())
+    }
+
+    // Deletes the given entry's QMDL file, keeping its analysis and manifest
+    // entry. The entry's marked first, so a power cut in between leaves a
+    // stray file rather than an entry pointing at a missing one.
+    async fn discard_entry_qmdl(&mut self, entry_index: usize) -> Result<(), RecordingStoreError> {
+        self.manifest.entries[entry_index].analysis_only = true;
+        self.write_manifest().await?;
+        let qmdl_filepath = self.manifest.entries[entry_index].get_qmdl_filepath(&self.path);
+        match fs::remove_file(&qmdl_filepath).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(RecordingStoreError::DiscardQmdlError(err)),
+            _ => Ok(()),
+        }
+    }
+
     // Sets the given entry's size and updates the last_message_time to now, updating the manifest
     pub async fn update_entry_qmdl_size(&mut self, entry_index: usize, size_bytes: usize) -> Result<(), RecordingStoreError> {
         self.manifest.entries[entry_index].qmdl_size_bytes = size_bytes;
         self.manifest.entries[entry_index].last_message_time = Some(Local::now());
+        self.write_manifest_after_size_update(entry_index).await
+    }
+
+    // Records when the given entry's first message was logged, unless that's
+    // already known
+    pub async fn set_entry_first_message_time(&mut self, entry_index: usize, time: DateTime<Local>) -> Result<(), RecordingStoreError> {
+        let entry = &mut self.manifest.entries[entry_index];
+        if entry.first_message_time.is_some() {
+            return Ok(());
+        }
+        entry.first_message_time = Some(time);
         self.write_manifest().await
     }
 
     // Sets the given entry's analysis file size
     pub async fn update_entry_analysis_size(&mut self, entry_index: usize, size_bytes: usize) -> Result<(), RecordingStoreError> {
         self.manifest.entries[entry_index].analysis_size_bytes = size_bytes;
+        self.write_manifest_after_size_update(entry_index).await
+    }
+
+    // Sets (or with None, clears) the notes on the entry with the given name
+    pub async fn set_entry_notes(&mut self, name: &str, notes: Option<String>) -> Result<(), RecordingStoreError> {
+        let entry = self.manifest.entries.iter_mut()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| RecordingStoreError::NoSuchEntry(name.to_string()))?;
+        entry.notes = notes;
+        self.write_manifest().await
+    }
+
+    // Protects the entry with the given name from having its QMDL file
+    // discarded
+    pub async fn set_entry_protected(&mut self, name: &str) -> Result<(), RecordingStoreError> {
+        let entry = self.manifest.entries.iter_mut()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| RecordingStoreError::NoSuchEntry(name.to_string()))?;
+        entry.protected = true;
         self.write_manifest().await
     }
 
+    // Moves every recording from the store at old_path into the one behind
+    // store_lock, e.g. after qmdl_store_path is pointed at a newly added SD
+    // card. Returns how many recordings were moved. This is refused while a
+    // recording's being made, so the copying doesn't compete with it for the
+    // disk.
+    //
+    // Recordings are moved one at a time: the files are copied under
+    // temporary names and synced, then renamed into place and the entry's
+    // added to this manifest, then removed from the old manifest, and only
+    // then are the old files deleted. A power cut at any point leaves every
+    // recording listed in at least one manifest, and running the migration
+    // again picks up where it left off. The store's write-locked throughout,
+    // so a recording can't be started partway through.
+    pub async fn migrate_from<P>(store_lock: &RwLock<RecordingStore>, old_path: P) -> Result<usize, RecordingStoreError> where P: AsRef<Path> {
+        let mut store = store_lock.write().await;
+        if store.current_entry.is_some() {
+            return Err(RecordingStoreError::MigrateWhileRecording);
+        }
+        let path = store.path.clone();
+        let canonical_old_path = fs::canonicalize(&old_path).await
+            .map_err(RecordingStoreError::OpenDirError)?;
+        let canonical_path = fs::canonicalize(&path).await
+            .map_err(RecordingStoreError::OpenDirError)?;
+        if canonical_old_path == canonical_path {
+            return Err(RecordingStoreError::MigrateIntoSelf);
+        }
+        let mut old_store = RecordingStore::load(old_path).await?;
+        let mut migrated = 0;
+        while let Some(old_entry) = old_store.manifest.entries.first().cloned() {
+            // the old store can be anywhere, so don't let its manifest point
+            // us at files outside it
+            if old_entry.name.is_empty() || old_entry.name.starts_with('.') || old_entry.name.contains('/') {
+                return Err(RecordingStoreError::InvalidEntryName(old_entry.name));
+            }
+            // finding it here means we were interrupted after adding it but
+            // before removing it from the old store
+            let already_migrated = store.manifest.entries.iter().any(|entry| {
+                entry.migrated_from.as_ref() == Some(&old_entry.name) && entry.start_time == old_entry.start_time
+            });
+            if !already_migrated {
+                // hidden until it's added, and overwritten if a previous
+                // attempt left it behind
+                let mut new_entry = old_entry.clone();
+                new_entry.name = format!(".migrating-{}", old_entry.name);
+                new_entry.migrated_from = Some(old_entry.name.clone());
+                let temp_entry = new_entry.clone();
+                if !old_entry.analysis_only {
+                    copy_and_sync(&old_entry.get_qmdl_filepath(&old_store.path), &temp_entry.get_qmdl_filepath(&path)).await?;
+                }
+                copy_and_sync(&old_entry.get_analysis_filepath(&old_store.path), &temp_entry.get_analysis_filepath(&path)).await?;
+
+                new_entry.name = store.unique_entry_name(&old_entry.name);
+                if !old_entry.analysis_only {
+                    fs::rename(temp_entry.get_qmdl_filepath(&path), new_entry.get_qmdl_filepath(&path)).await
+                        .map_err(RecordingStoreError::MigrateFileError)?;
+                }
+                fs::rename(temp_entry.get_analysis_filepath(&path), new_entry.get_analysis_filepath(&path)).await
+                    .map_err(RecordingStoreError::MigrateFileError)?;
+                sync_dir(&path).await?;
+                store.manifest.entries.push(new_entry);
+                store.write_manifest().await?;
+            }
+            old_store.manifest.entries.remove(0);
+            old_store.write_manifest().await?;
+            for old_file in [old_entry.get_qmdl_filepath(&old_store.path), old_entry.get_analysis_filepath(&old_store.path)] {
+                if let Err(err) = fs::remove_file(&old_file).await {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        return Err(RecordingStoreError::MigrateFileError(err));
+                    }
+                }
+            }
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    // Returns base_name, or if an entry already has that name, base_name
+    // with the first numeric suffix that's free
+    fn unique_entry_name(&self, base_name: &str) -> String {
+        let mut name = base_name.to_string();
+        let mut suffix = 1;
+        while self.entry_for_name(&name).is_some() {
+            name = format!("{}-{}", base_name, suffix);
+            suffix += 1;
+        }
+        name
+    }
+
     async fn write_manifest(&mut self) -> Result<(), RecordingStoreError> {
-        let mut manifest_file = File::options()
-            .write(true)
-            .open(self.path.join("manifest.toml")).await
-            .map_err(RecordingStoreError::WriteManifestError)?;
-        let manifest_contents = toml::to_string_pretty(&self.manifest)
-            .expect("failed to serialize manifest");
-        manifest_file.write_all(manifest_contents.as_bytes()).await
-            .map_err(RecordingStoreError::WriteManifestError)?;
+        write_manifest_atomically(&self.path, &self.manifest).await?;
+        self.manifest_written_at = Instant::now();
+        self.manifest_dirty = false;
         Ok(())
     }
 
+    // Size updates to the recording in progress are only written out every
+    // LIVE_MANIFEST_WRITE_INTERVAL, any others straight away
+    async fn write_manifest_after_size_update(&mut self, entry_index: usize) -> Result<(), RecordingStoreError> {
+        if self.current_entry == Some(entry_index) && self.manifest_written_at.elapsed() < LIVE_MANIFEST_WRITE_INTERVAL {
+            self.manifest_dirty = true;
+            return Ok(());
+        }
+        self.write_manifest().await
+    }
+
     // Finds an entry by filename
     pub fn entry_for_name(&self, name: &str) -> Option<ManifestEntry> {
         self.manifest.entries.iter()
@@ -201,6 +519,49 @@ impl RecordingStore {
     }
 }
 
+// flock() locks belong to the open file rather than the process, so this
+// also fails if another RecordingStore in this process holds the lock
+#[cfg(unix)]
+fn lock_exclusive(lock_file: &std::fs::File) -> Result<(), RecordingStoreError> {
+    use std::os::fd::AsRawFd;
+
+    if unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+            return Err(RecordingStoreError::Locked);
+        }
+        return Err(RecordingStoreError::LockError(err));
+    }
+    Ok(())
+}
+
+// there's no flock() on other platforms, so the store goes unlocked there
+#[cfg(not(unix))]
+fn lock_exclusive(_lock_file: &std::fs::File) -> Result<(), RecordingStoreError> {
+    Ok(())
+}
+
+// Takes the store's lock, returning the file it's held through
+fn acquire_lock(path: &Path) -> Result<std::fs::File, RecordingStoreError> {
+    use std::io::{Seek, Write};
+
+    // never truncated or removed, since another process may have it open
+    let mut lock_file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.join(LOCK_FILENAME))
+        .map_err(RecordingStoreError::LockError)?;
+    lock_exclusive(&lock_file)?;
+    // now that it's ours, record our PID in place of the last holder's
+    lock_file.set_len(0)
+        .and_then(|_| lock_file.rewind())
+        .and_then(|_| write!(lock_file, "{}", std::process::id()))
+        .map_err(RecordingStoreError::LockError)?;
+    Ok(lock_file)
+}
+
 #[cfg(test)]
 mod tests {
     use tempdir::TempDir;
@@ -210,12 +571,44 @@ mod tests {
     async fn test_load_from_empty_dir() {
         let dir = TempDir::new("qmdl_store_test").unwrap();
         assert!(!RecordingStore::exists(dir.path()).await.unwrap());
-        let _created_store = RecordingStore::create(dir.path()).await.unwrap();
+        let created_store = RecordingStore::create(dir.path()).await.unwrap();
         assert!(RecordingStore::exists(dir.path()).await.unwrap());
+        drop(created_store);
         let loaded_store = RecordingStore::load(dir.path()).await.unwrap();
         assert_eq!(loaded_store.manifest.entries.len(), 0);
     }
 
+    // the store's only locked on unix
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_store_lock() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let store = RecordingStore::create(dir.path()).await.unwrap();
+        assert!(RecordingStore::is_locked(dir.path()).await.unwrap());
+        assert!(matches!(RecordingStore::load(dir.path()).await, Err(RecordingStoreError::Locked)));
+        // checking the lock doesn't take it away from its holder
+        assert!(RecordingStore::is_locked(dir.path()).await.unwrap());
+        let lock_contents = fs::read_to_string(dir.path().join(LOCK_FILENAME)).await.unwrap();
+        assert_eq!(lock_contents, std::process::id().to_string());
+
+        drop(store);
+        assert!(!RecordingStore::is_locked(dir.path()).await.unwrap());
+        let _loaded_store = RecordingStore::load(dir.path()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_leftover_store_lock() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        drop(RecordingStore::create(dir.path()).await.unwrap());
+        // a lock file from a daemon that died, whose PID may since have been
+        // reused, isn't held by anyone
+        fs::write(dir.path().join(LOCK_FILENAME), "1").await.unwrap();
+        assert!(!RecordingStore::is_locked(dir.path()).await.unwrap());
+        let _loaded_store = RecordingStore::load(dir.path()).await.unwrap();
+        let lock_contents = fs::read_to_string(dir.path().join(LOCK_FILENAME)).await.unwrap();
+        assert_eq!(lock_contents, std::process::id().to_string());
+    }
+
     #[tokio::test]
     async fn test_creating_updating_and_closing_entries() {
         let dir = TempDir::new("qmdl_store_test").unwrap();
@@ -229,12 +622,40 @@ mod tests {
         let entry = store.entry_for_name(&store.manifest.entries[entry_index].name).unwrap();
         assert!(entry.last_message_time.is_some());
         assert_eq!(store.manifest.entries[entry_index].qmdl_size_bytes, 1000);
-        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
 
         store.close_current_entry().await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
         assert!(matches!(store.close_current_entry().await, Err(RecordingStoreError::NoCurrentEntry)));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_live_size_updates_are_throttled() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+
+        // the entry was only just written out when it was created
+        store.update_entry_qmdl_size(entry_index, 1000).await.unwrap();
+        store.update_entry_analysis_size(entry_index, 10).await.unwrap();
+        let written = RecordingStore::read_manifest(dir.path()).await.unwrap();
+        assert_eq!(written.entries[entry_index].qmdl_size_bytes, 0);
+        assert_eq!(written.entries[entry_index].analysis_size_bytes, 0);
+
+        tokio::time::advance(LIVE_MANIFEST_WRITE_INTERVAL).await;
+        store.update_entry_qmdl_size(entry_index, 2000).await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+        store.update_entry_qmdl_size(entry_index, 3000).await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap().entries[entry_index].qmdl_size_bytes, 2000);
+
+        // closing it writes out the rest
+        store.close_current_entry().await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+        // and closed entries' sizes are written straight away
+        store.update_entry_analysis_size(entry_index, 20).await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+    }
+
     #[tokio::test]
     async fn test_repeated_new_entries() {
         let dir = TempDir::new("qmdl_store_test").unwrap();
@@ -246,4 +667,256 @@ mod tests {
         assert_ne!(entry_index, new_entry_index);
         assert_eq!(store.manifest.entries.len(), 2);
     }
+
+    #[tokio::test]
+    async fn test_import_entry() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let start_time = Local::now() - chrono::Duration::days(1);
+        let (entry_index, _) = store.import_entry(start_time, &[1, 2, 3]).await.unwrap();
+        let (second_index, _) = store.import_entry(start_time, &[4, 5]).await.unwrap();
+        assert!(store.current_entry.is_none());
+
+        let entry = &store.manifest.entries[entry_index];
+        assert_eq!(entry.start_time, start_time);
+        assert_eq!(entry.qmdl_size_bytes, 3);
+        assert_ne!(entry.name, store.manifest.entries[second_index].name);
+        assert_eq!(fs::read(entry.get_qmdl_filepath(dir.path())).await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+    }
+
+    #[tokio::test]
+    async fn test_entry_notes() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let name = store.get_current_entry().unwrap().name.clone();
+        store.set_entry_notes(&name, Some("parked outside the embassy".to_string())).await.unwrap();
+        assert!(matches!(store.set_entry_notes("nope", None).await, Err(RecordingStoreError::NoSuchEntry(_))));
+        drop(store);
+
+        let loaded_store = RecordingStore::load(dir.path()).await.unwrap();
+        let entry = loaded_store.entry_for_name(&name).unwrap();
+        assert_eq!(entry.notes.as_deref(), Some("parked outside the embassy"));
+    }
+
+    #[tokio::test]
+    async fn test_first_message_time() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        let first = Local::now() - chrono::Duration::days(365);
+        store.set_entry_first_message_time(entry_index, first).await.unwrap();
+        // later messages don't move it
+        store.set_entry_first_message_time(entry_index, Local::now()).await.unwrap();
+        drop(store);
+
+        let loaded_store = RecordingStore::load(dir.path()).await.unwrap();
+        assert_eq!(loaded_store.manifest.entries[entry_index].first_message_time, Some(first));
+    }
+
+    #[tokio::test]
+    async fn test_clear_and_open_entry_analysis() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let (_, mut analysis_file) = store.new_entry().await.unwrap();
+        analysis_file.write_all(b"old analysis\n").await.unwrap();
+        analysis_file.flush().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        store.update_entry_analysis_size(entry_index, 13).await.unwrap();
+        let name = store.manifest.entries[entry_index].name.clone();
+
+        let (cleared_index, _) = store.clear_and_open_entry_analysis(&name).await.unwrap();
+        assert_eq!(cleared_index, entry_index);
+        assert_eq!(store.manifest.entries[entry_index].analysis_size_bytes, 0);
+        let analysis_path = store.manifest.entries[entry_index].get_analysis_filepath(dir.path());
+        assert!(fs::read(analysis_path).await.unwrap().is_empty());
+        assert!(matches!(store.clear_and_open_entry_analysis("nope").await, Err(RecordingStoreError::NoSuchEntry(_))));
+    }
+
+    #[test]
+    fn test_parse_manifest_without_notes() {
+        let manifest: Manifest = toml::from_str(r#"
+            [[entries]]
+            name = "1710000000"
+            start_time = "2024-03-09T16:00:00-08:00"
+            qmdl_size_bytes = 100
+            analysis_size_bytes = 10
+        "#).unwrap();
+        assert_eq!(manifest.entries[0].notes, None);
+        assert_eq!(manifest.entries[0].last_message_time, None);
+        assert_eq!(manifest.entries[0].first_message_time, None);
+        assert!(!manifest.entries[0].analysis_only);
+        assert!(!manifest.entries[0].protected);
+    }
+
+    #[tokio::test]
+    async fn test_discard_qmdl_after_analysis() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        store.discard_qmdl_after_analysis = true;
+        let (mut qmdl_file, mut analysis_file) = store.new_entry().await.unwrap();
+        qmdl_file.write_all(&[1, 2, 3]).await.unwrap();
+        analysis_file.write_all(b"{}\n").await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        store.update_entry_qmdl_size(entry_index, 3).await.unwrap();
+        store.update_entry_analysis_size(entry_index, 3).await.unwrap();
+        let entry = store.manifest.entries[entry_index].clone();
+        // the recording in progress keeps its QMDL, as does one that's just
+        // been closed, since its writers may still be open
+        assert!(try_exists(entry.get_qmdl_filepath(dir.path())).await.unwrap());
+        assert_eq!(store.close_current_entry().await.unwrap(), entry_index);
+        assert!(try_exists(entry.get_qmdl_filepath(dir.path())).await.unwrap());
+        assert!(!store.entry_for_name(&entry.name).unwrap().analysis_only);
+
+        store.finish_entry(entry_index).await.unwrap();
+        assert!(!try_exists(entry.get_qmdl_filepath(dir.path())).await.unwrap());
+        assert_eq!(fs::read(entry.get_analysis_filepath(dir.path())).await.unwrap(), b"{}\n");
+        let entry = store.entry_for_name(&entry.name).unwrap();
+        assert!(entry.analysis_only);
+        assert_eq!(entry.analysis_size_bytes, 3);
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+        assert!(matches!(store.open_entry_qmdl(&entry).await, Err(RecordingStoreError::QmdlDiscarded(_))));
+        assert!(store.open_entry_analysis(&entry).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_protected_entry_keeps_qmdl() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        store.discard_qmdl_after_analysis = true;
+        let (mut qmdl_file, _) = store.new_entry().await.unwrap();
+        qmdl_file.write_all(&[1, 2, 3]).await.unwrap();
+        let name = store.get_current_entry().unwrap().name.clone();
+        store.set_entry_protected(&name).await.unwrap();
+        assert!(matches!(store.set_entry_protected("nope").await, Err(RecordingStoreError::NoSuchEntry(_))));
+
+        let entry_index = store.close_current_entry().await.unwrap();
+        store.finish_entry(entry_index).await.unwrap();
+        let entry = store.entry_for_name(&name).unwrap();
+        assert!(entry.protected);
+        assert!(!entry.analysis_only);
+        assert!(try_exists(entry.get_qmdl_filepath(dir.path())).await.unwrap());
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_writes_replace_old_contents() {
+        let dir = TempDir::new("qmdl_store_test").unwrap();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        for _ in 0..3 {
+            let _ = store.new_entry().await.unwrap();
+        }
+        // shrinking the manifest mustn't leave any of the old one behind
+        store.manifest.entries.truncate(1);
+        store.write_manifest().await.unwrap();
+        assert_eq!(RecordingStore::read_manifest(dir.path()).await.unwrap(), store.manifest);
+        assert!(!try_exists(dir.path().join("manifest.toml.new")).await.unwrap());
+    }
+
+    // creates a store with two closed recordings, returning their entries
+    async fn populated_store(path: &Path) -> Vec<ManifestEntry> {
+        let mut store = RecordingStore::create(path).await.unwrap();
+        let start_time = Local::now() - chrono::Duration::days(1);
+        store.import_entry(start_time, &[1, 2, 3]).await.unwrap();
+        store.import_entry(start_time, &[4, 5]).await.unwrap();
+        let name = store.manifest.entries[0].name.clone();
+        store.set_entry_notes(&name, Some("parked car".to_string())).await.unwrap();
+        store.manifest.entries.clone()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_store() {
+        let old_dir = TempDir::new("qmdl_store_test").unwrap();
+        let new_dir = TempDir::new("qmdl_store_test").unwrap();
+        let old_entries = populated_store(old_dir.path()).await;
+        let mut store = RecordingStore::create(new_dir.path()).await.unwrap();
+        store.import_entry(Local::now(), &[6]).await.unwrap();
+        let store_lock = RwLock::new(store);
+
+        assert_eq!(RecordingStore::migrate_from(&store_lock, old_dir.path()).await.unwrap(), 2);
+        let store = store_lock.read().await;
+        assert_eq!(store.manifest.entries.len(), 3);
+        assert_eq!(RecordingStore::read_manifest(new_dir.path()).await.unwrap(), store.manifest);
+        let mut contents = Vec::new();
+        for entry in &store.manifest.entries {
+            contents.push(fs::read(entry.get_qmdl_filepath(new_dir.path())).await.unwrap());
+            assert!(try_exists(entry.get_analysis_filepath(new_dir.path())).await.unwrap());
+        }
+        assert_eq!(contents, vec![vec![6], vec![1, 2, 3], vec![4, 5]]);
+        assert_eq!(store.manifest.entries[1].notes.as_deref(), Some("parked car"));
+        // names stay unique even if the stores' recordings started in the same
+        // second
+        assert_ne!(store.manifest.entries[0].name, store.manifest.entries[1].name);
+        assert_ne!(store.manifest.entries[0].name, store.manifest.entries[2].name);
+        // and the files copied under temporary names have all been renamed
+        let mut dir_entries = fs::read_dir(new_dir.path()).await.unwrap();
+        while let Some(dir_entry) = dir_entries.next_entry().await.unwrap() {
+            assert!(!dir_entry.file_name().to_string_lossy().starts_with(".migrating-"));
+        }
+        drop(store);
+
+        // the old store's left empty and unlocked
+        assert!(RecordingStore::read_manifest(old_dir.path()).await.unwrap().entries.is_empty());
+        assert!(!RecordingStore::is_locked(old_dir.path()).await.unwrap());
+        for entry in &old_entries {
+            assert!(!try_exists(entry.get_qmdl_filepath(old_dir.path())).await.unwrap());
+            assert!(!try_exists(entry.get_analysis_filepath(old_dir.path())).await.unwrap());
+        }
+
+        assert!(matches!(RecordingStore::migrate_from(&store_lock, new_dir.path()).await, Err(RecordingStoreError::MigrateIntoSelf)));
+    }
+
+    #[tokio::test]
+    async fn test_resume_interrupted_migration() {
+        let old_dir = TempDir::new("qmdl_store_test").unwrap();
+        let new_dir = TempDir::new("qmdl_store_test").unwrap();
+        let old_entries = populated_store(old_dir.path()).await;
+        let mut store = RecordingStore::create(new_dir.path()).await.unwrap();
+        // as if the power went out after the first recording was added to the
+        // new store, but before it was removed from the old one
+        fs::copy(old_entries[0].get_qmdl_filepath(old_dir.path()), old_entries[0].get_qmdl_filepath(new_dir.path())).await.unwrap();
+        fs::copy(old_entries[0].get_analysis_filepath(old_dir.path()), old_entries[0].get_analysis_filepath(new_dir.path())).await.unwrap();
+        let mut migrated_entry = old_entries[0].clone();
+        migrated_entry.migrated_from = Some(old_entries[0].name.clone());
+        // and it's been edited since
+        migrated_entry.notes = Some("edited after migrating".to_string());
+        store.manifest.entries.push(migrated_entry);
+        store.write_manifest().await.unwrap();
+        // and a previous attempt got partway through copying the second
+        fs::write(new_dir.path().join(format!(".migrating-{}.qmdl", old_entries[1].name)), [9]).await.unwrap();
+        let store_lock = RwLock::new(store);
+
+        assert_eq!(RecordingStore::migrate_from(&store_lock, old_dir.path()).await.unwrap(), 2);
+        let store = store_lock.read().await;
+        let names: Vec<_> = store.manifest.entries.iter().map(|entry| entry.name.clone()).collect();
+        let old_names: Vec<_> = old_entries.iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, old_names);
+        assert_eq!(store.manifest.entries[0].notes.as_deref(), Some("edited after migrating"));
+        assert_eq!(store.manifest.entries[1].migrated_from.as_ref(), Some(&old_entries[1].name));
+        assert_eq!(fs::read(old_entries[1].get_qmdl_filepath(new_dir.path())).await.unwrap(), [4, 5]);
+        assert!(RecordingStore::read_manifest(old_dir.path()).await.unwrap().entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_store_refusals() {
+        let old_dir = TempDir::new("qmdl_store_test").unwrap();
+        let new_dir = TempDir::new("qmdl_store_test").unwrap();
+        let old_entries = populated_store(old_dir.path()).await;
+        let mut store = RecordingStore::create(new_dir.path()).await.unwrap();
+        store.new_entry().await.unwrap();
+        let store_lock = RwLock::new(store);
+        assert!(matches!(RecordingStore::migrate_from(&store_lock, old_dir.path()).await, Err(RecordingStoreError::MigrateWhileRecording)));
+        store_lock.write().await.close_current_entry().await.unwrap();
+
+        // a manifest naming files outside its store isn't followed
+        let mut old_store = RecordingStore::load(old_dir.path()).await.unwrap();
+        old_store.manifest.entries[0].name = "../outside".to_string();
+        old_store.write_manifest().await.unwrap();
+        drop(old_store);
+        assert!(matches!(RecordingStore::migrate_from(&store_lock, old_dir.path()).await, Err(RecordingStoreError::InvalidEntryName(_))));
+        assert_eq!(RecordingStore::read_manifest(old_dir.path()).await.unwrap().entries.len(), old_entries.len());
+        assert_eq!(store_lock.read().await.manifest.entries.len(), 1);
+    }
 }
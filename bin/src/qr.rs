@@ -0,0 +1,641 @@
+// A minimal QR code encoder, just enough to put the web UI's URL on the
+// device's screen: byte mode, error correction level M, versions 1 to 10
+// (up to 213 bytes). The layout follows ISO/IEC 18004.
+
+// (total codewords, EC codewords per block, number of blocks) for level M
+const VERSIONS: [(usize, usize, usize); 10] = [
+    (26, 10, 1),
+    (44, 16, 1),
+    (70, 26, 1),
+    (100, 18, 2),
+    (134, 24, 2),
+    (172, 16, 4),
+    (196, 18, 4),
+    (242, 22, 4),
+    (292, 22, 5),
+    (346, 26, 5),
+];
+
+const ALIGNMENT_POSITIONS: [&[usize]; 10] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+// level M's two format bits are 00
+const FORMAT_EC_LEVEL_M: u32 = 0;
+
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,
+    // finder, timing, alignment, format and version modules, which hold no
+    // data and aren't masked
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes the data in the smallest version that fits, or returns None if
+    /// it's too long
+    pub fn encode(data: &[u8]) -> Option<QrCode> {
+        let version = (1..=VERSIONS.len()).find(|&version| {
+            let capacity_bits = data_codewords(version) * 8;
+            4 + char_count_bits(version) + data.len() * 8 <= capacity_bits
+        })?;
+        let mut qr = QrCode {
+            version,
+            size: version * 4 + 17,
+            modules: vec![false; (version * 4 + 17).pow(2)],
+            is_function: vec![false; (version * 4 + 17).pow(2)],
+        };
+        qr.draw_function_patterns();
+        let codewords = add_error_correction(version, &encode_data(version, data));
+        qr.draw_codewords(&codewords);
+
+        let best_mask = (0..8).min_by_key(|&mask| {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+            let penalty = qr.penalty();
+            qr.apply_mask(mask);
+            penalty
+        }).unwrap();
+        qr.apply_mask(best_mask);
+        qr.draw_format_bits(best_mask);
+        Some(qr)
+    }
+
+    /// The width and height in modules, not including the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column x, row y is dark
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        for i in 0..self.size {
+            self.set_function(6, i, i.is_multiple_of(2));
+            self.set_function(i, 6, i.is_multiple_of(2));
+        }
+
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size - 4, 3);
+        self.draw_finder_pattern(3, self.size - 4);
+
+        // alignment patterns go everywhere on the grid except where they'd
+        // overlap the finder patterns
+        let positions = ALIGNMENT_POSITIONS[self.version - 1];
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function((x as i32 + dx) as usize, (y as i32 + dy) as usize, dark);
+                    }
+                }
+            }
+        }
+
+        // reserve the format areas for now, they're filled in once the mask
+        // is chosen
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    // draws a finder pattern centred on (x, y) along with its separator
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if xx < 0 || yy < 0 || xx >= self.size as i32 || yy >= self.size as i32 {
+                    continue;
+                }
+                let distance = dx.abs().max(dy.abs());
+                self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        // the copy around the top left finder
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        // the copy split between the other two finders
+        for i in 0..8 {
+            self.set_function(self.size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, self.size - 15 + i, bit(i));
+        }
+        self.set_function(8, self.size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let mut remainder = self.version as u32;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+        }
+        let bits = (self.version as u32) << 12 | remainder;
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    // fills in the data modules in the standard zigzag, two columns at a time
+    // from the bottom right, skipping the vertical timing pattern
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let mut i = 0;
+        for (x, y) in data_module_order(self.size) {
+            if self.is_function[y * self.size + x] {
+                continue;
+            }
+            if i < codewords.len() * 8 {
+                self.modules[y * self.size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                i += 1;
+            }
+        }
+    }
+
+    // applying the same mask twice undoes it
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if !self.is_function[y * self.size + x] && mask_bit(mask, x, y) {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // scores how hard the symbol would be to scan, per the standard's mask
+    // evaluation rules
+    fn penalty(&self) -> usize {
+        let mut penalty = 0;
+        let lines = (0..self.size).map(|y| (0..self.size).map(|x| self.get(x, y)).collect::<Vec<_>>())
+            .chain((0..self.size).map(|x| (0..self.size).map(|y| self.get(x, y)).collect()));
+        for line in lines {
+            // runs of five or more of the same colour
+            let mut run = 1;
+            for i in 1..=line.len() {
+                if i < line.len() && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+                run = 1;
+            }
+            // anything that looks like a finder pattern
+            const FINDER_LIKE: [bool; 11] = [true, false, true, true, true, false, true, false, false, false, false];
+            for window in line.windows(11) {
+                if window == FINDER_LIKE || window.iter().rev().eq(FINDER_LIKE.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        // 2x2 blocks of the same colour
+        for y in 0..self.size - 1 {
+            for x in 0..self.size - 1 {
+                let dark = self.get(x, y);
+                if dark == self.get(x + 1, y) && dark == self.get(x, y + 1) && dark == self.get(x + 1, y + 1) {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // an imbalance of dark and light
+        let dark = self.modules.iter().filter(|dark| **dark).count();
+        let percent = dark * 100 / self.modules.len();
+        penalty += percent.abs_diff(50) / 5 * 10;
+        penalty
+    }
+}
+
+fn data_codewords(version: usize) -> usize {
+    let (total, ec_per_block, blocks) = VERSIONS[version - 1];
+    total - ec_per_block * blocks
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version < 10 { 8 } else { 16 }
+}
+
+fn format_bits(mask: u32) -> u32 {
+    let data = FORMAT_EC_LEVEL_M << 3 | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+fn mask_bit(mask: u32, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y).is_multiple_of(2),
+        1 => y.is_multiple_of(2),
+        2 => x.is_multiple_of(3),
+        3 => (x + y).is_multiple_of(3),
+        4 => (x / 3 + y / 2).is_multiple_of(2),
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+        7 => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+        _ => unreachable!(),
+    }
+}
+
+// every module position in the order data bits are placed, function
+// patterns included
+fn data_module_order(size: usize) -> Vec<(usize, usize)> {
+    let mut order = Vec::with_capacity(size * size);
+    let mut right = size as i32 - 1;
+    while right >= 1 {
+        if right == 6 {
+            right = 5;
+        }
+        let upward = (right + 1) & 2 == 0;
+        for vert in 0..size {
+            let y = if upward { size - 1 - vert } else { vert };
+            for dx in 0..2 {
+                order.push(((right - dx) as usize, y));
+            }
+        }
+        right -= 2;
+    }
+    order
+}
+
+// builds the byte mode segment, then pads it out to the version's capacity
+fn encode_data(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity_bits = data_codewords(version) * 8;
+    let mut bits: Vec<bool> = Vec::with_capacity(capacity_bits);
+    let mut push = |value: usize, len: usize| {
+        for i in (0..len).rev() {
+            bits.push((value >> i) & 1 != 0);
+        }
+    };
+    push(0b0100, 4);
+    push(data.len(), char_count_bits(version));
+    for byte in data {
+        push(*byte as usize, 8);
+    }
+    let terminator = (capacity_bits - bits.len()).min(4);
+    bits.resize(bits.len() + terminator, false);
+    while !bits.len().is_multiple_of(8) {
+        bits.push(false);
+    }
+
+    let mut codewords: Vec<u8> = bits.chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, bit| acc << 1 | *bit as u8))
+        .collect();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() >= data_codewords(version) {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+// splits the data into blocks, appends each block's error correction, and
+// interleaves the result
+fn add_error_correction(version: usize, data: &[u8]) -> Vec<u8> {
+    let (total, ec_per_block, num_blocks) = VERSIONS[version - 1];
+    let num_long_blocks = total % num_blocks;
+    let short_data_len = total / num_blocks - ec_per_block;
+    let divisor = reed_solomon_divisor(ec_per_block);
+
+    let mut blocks: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    let mut rest = data;
+    for i in 0..num_blocks {
+        let data_len = short_data_len + (i >= num_blocks - num_long_blocks) as usize;
+        let (block, remaining) = rest.split_at(data_len);
+        blocks.push((block, reed_solomon_remainder(block, &divisor)));
+        rest = remaining;
+    }
+
+    let mut result = Vec::with_capacity(total);
+    for i in 0..=short_data_len {
+        for (block, _) in &blocks {
+            if let Some(byte) = block.get(i) {
+                result.push(*byte);
+            }
+        }
+    }
+    for i in 0..ec_per_block {
+        for (_, ec) in &blocks {
+            result.push(ec[i]);
+        }
+    }
+    result
+}
+
+// multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+    z as u8
+}
+
+// the generator polynomial (x - a^0)(x - a^1)...(x - a^(degree-1)), highest
+// power first with its implicit leading 1 dropped
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 2);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_multiply(*y, factor);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // evaluates a codeword polynomial (highest power first) at x
+    fn evaluate(codewords: &[u8], x: u8) -> u8 {
+        codewords.iter().fold(0, |acc, byte| gf_multiply(acc, x) ^ byte)
+    }
+
+    // reads a symbol back the way a scanner would, returning the payload
+    fn decode(qr: &QrCode) -> Vec<u8> {
+        let size = qr.size();
+        let version = (size - 17) / 4;
+
+        // format info, checked against both copies
+        let read_bits = |positions: &[(usize, usize)]| {
+            positions.iter().enumerate().fold(0, |acc, (i, &(x, y))| acc | (qr.get(x, y) as u32) << i)
+        };
+        let mut first_copy: Vec<(usize, usize)> = (0..=5).map(|i| (8, i)).collect();
+        first_copy.extend([(8, 7), (8, 8), (7, 8)]);
+        first_copy.extend((9..15).map(|i| (14 - i, 8)));
+        let mut second_copy: Vec<(usize, usize)> = (0..8).map(|i| (size - 1 - i, 8)).collect();
+        second_copy.extend((8..15).map(|i| (8, size - 15 + i)));
+        let format = read_bits(&first_copy);
+        assert_eq!(format, read_bits(&second_copy));
+        let mask = (0..8).find(|&mask| format_bits(mask) == format).expect("invalid format bits");
+        assert!(qr.get(8, size - 8), "missing dark module");
+
+        // finder patterns
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            assert!(qr.get(cx, cy));
+            assert!(!qr.get(cx - 2, cy));
+            assert!(qr.get(cx - 3, cy - 3));
+        }
+
+        // unmasked codewords in placement order
+        let mut bits = Vec::new();
+        for (x, y) in data_module_order(size) {
+            if !qr.is_function[y * size + x] {
+                bits.push(qr.get(x, y) ^ mask_bit(mask, x, y));
+            }
+        }
+        let codewords: Vec<u8> = bits.chunks_exact(8)
+            .map(|byte| byte.iter().fold(0, |acc, bit| acc << 1 | *bit as u8))
+            .collect();
+
+        // deinterleave, and check each block is a valid Reed-Solomon codeword
+        let (total, ec_per_block, num_blocks) = VERSIONS[version - 1];
+        let num_long_blocks = total % num_blocks;
+        let short_data_len = total / num_blocks - ec_per_block;
+        let mut blocks = vec![Vec::new(); num_blocks];
+        let mut iter = codewords.iter();
+        for i in 0..=short_data_len {
+            for (j, block) in blocks.iter_mut().enumerate() {
+                if i < short_data_len || j >= num_blocks - num_long_blocks {
+                    block.push(*iter.next().unwrap());
+                }
+            }
+        }
+        for _ in 0..ec_per_block {
+            for block in blocks.iter_mut() {
+                block.push(*iter.next().unwrap());
+            }
+        }
+        let mut root = 1;
+        for _ in 0..ec_per_block {
+            for block in &blocks {
+                assert_eq!(evaluate(block, root), 0);
+            }
+            root = gf_multiply(root, 2);
+        }
+
+        // the byte mode segment
+        let data: Vec<bool> = blocks.iter()
+            .flat_map(|block| block[..block.len() - ec_per_block].to_vec())
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 != 0))
+            .collect();
+        let read = |start: usize, len: usize| data[start..start + len].iter().fold(0, |acc, bit| acc << 1 | *bit as usize);
+        assert_eq!(read(0, 4), 0b0100);
+        let count_bits = char_count_bits(version);
+        let len = read(4, count_bits);
+        (0..len).map(|i| read(4 + count_bits + i * 8, 8) as u8).collect()
+    }
+
+    #[test]
+    fn test_reed_solomon() {
+        // "HELLO WORLD" as version 1-M, from the well-known worked example
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        let ec = reed_solomon_remainder(&data, &reed_solomon_divisor(10));
+        assert_eq!(ec, vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn test_format_bits() {
+        // level M with mask 0, from the standard's table
+        assert_eq!(format_bits(0), 0b101010000010010);
+    }
+
+    #[test]
+    fn test_decodable() {
+        let url = b"http://192.168.1.1:8080/#token=0123456789abcdef";
+        let qr = QrCode::encode(url).unwrap();
+        assert_eq!(qr.size(), 33);
+        assert_eq!(decode(&qr), url);
+
+        // long enough to need multiple blocks and version info
+        let long_url = format!("http://192.168.1.1:8080/#token={}", "a".repeat(150));
+        let qr = QrCode::encode(long_url.as_bytes()).unwrap();
+        assert!(qr.size() >= 45);
+        assert_eq!(decode(&qr), long_url.as_bytes());
+    }
+
+    // rows of modules ('#' is dark), without the quiet zone
+    fn rows(qr: &QrCode) -> Vec<String> {
+        (0..qr.size())
+            .map(|y| (0..qr.size()).map(|x| if qr.get(x, y) { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    // these symbols come from Kazuhiko Arase's qrcode-generator at level M,
+    // told to use the masks we pick (4 and 1), since encoders don't all score
+    // masks the same way
+    const TOKEN_URL_SYMBOL: &str = "\
+        #######.#...#####...#.###.#######
+        #.....#....#.##.#.###.#.#.#.....#
+        #.###.#..#..#.##.##..##.#.#.###.#
+        #.###.#.####...###.....#..#.###.#
+        #.###.#.###.#...##.#.#.#..#.###.#
+        #.....#.#.#.#..#..#.#.....#.....#
+        #######.#.#.#.#.#.#.#.#.#.#######
+        ........###.#....#.####..........
+        #...#.###.#...##.##..#...#####..#
+        ..##.#.#####...###..#..##....##..
+        #...###.#..#.###..#..#.###...#...
+        .#.##......#.#..#....#.#.##....#.
+        #..#.###.#...#.##....##.######.##
+        #......##.##.##...####.#......#..
+        ##..#.#.#..##..#.#..#..#...#.###.
+        #.##.....#..######...##..#..##...
+        #####.##......#..###.#...##.##.#.
+        ....#..##.#..#.##.#.#.###....##..
+        ..##..#.....##.#.#.....#..#..#...
+        ###.#..##.#......###.##.#........
+        ..#...#.##.##.##..#.#..#######.##
+        #..#.#..####.#..##.#...#......##.
+        ..######..###...#.##.####.#.#.##.
+        ..#.#..##.#......#####.#.#..##.##
+        ##.#.##.##.#..##.##..#..######...
+        ........#.###.#####.##..#...#.#..
+        #######.##..#.###.#.#...#.#.##...
+        #.....#....##..#......###...##.#.
+        #.###.#.#.##.##.#..#..#.######...
+        #.###.#..###.#....####...##.####.
+        #.###.#..#.##..#....#..#.#####...
+        #.....#..#.##.#####..####.##.#...
+        #######.#.###...##.#.#.##.#.##..#";
+
+    const LONG_URL_SYMBOL: &str = "\
+        #######.#..#..##.....#..#.#.#.#.#.#.###.#.#.####..#######
+        #.....#..##...#...###..#..#...###.#..#....#..#.#..#.....#
+        #.###.#.#.#.####..###.######.#######.#.#.###.###..#.###.#
+        #.###.#....#..##.....#..##.#.#.###.#...#.#...#.#..#.###.#
+        #.###.#..#.#..##.##.##..########..#.###.#.###..#..#.###.#
+        #.....#.###.#.#..#..####.##...###.#..##...#.#.#...#.....#
+        #######.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#######
+        .........#..#...###.#.#..##...#.#..##...#..#..#..........
+        #.#...##.###.###...##..#.######.#.#.#.#.#.###.##...#..#.#
+        .#.##..##.#.##.##.##...#...#.#.#...###.#.#.#...#.#.#....#
+        ..#...#.#...##.##.##.##.#.####.##.####.###.##...##.##.#.#
+        ####...###..#...###.##...#......##......###.#.......##.#.
+        ##.#.##.####....#..####.##....#.###...#.##..#.##..#.##.##
+        ##.#.#.##.#.#.#.#.##.#......##.#.#..##.#.###.#.#.#.#....#
+        ....###.##..#.#...##....#.#..#.###...#.#######..##.##.#.#
+        ###.##.##.#.####.##.###.....#...#...#..####.#.....#.##.#.
+        ##.#..#.#.##..###..##..#..#.#.#.#.#.#.####..#.##.#..##..#
+        ##.##..##...#####.#.#..#...#.#.#.#.#.#..#..#.#.#...#....#
+        ...#..#.###.#.#...#.###.#.####.###.###.#...###..##.##.#.#
+        ##..##.####.###..##...#..#..#..##...###.....#......###.#.
+        ##.#.##..##.#.#######..#.#..#.###.#.##....#.#.#...####.##
+        ##.#.#...##.#####..#####...#....##.#.##.##.#.#...#....#.#
+        #..#.##.#.#.#.#..#.#.##.#.###.#..#.####..#.###..##.####.#
+        ....##.#.######...#.#.#..#..#..#....#...#..##.......##.#.
+        ##.#.##.##.##.###.#....#.#..#.###.#.###.#.###.##..#.#..##
+        #..#.#...###.###########...#.#.#.#.#...#.#..#..#.#.#....#
+        ##.######.#.#......####.##########.##..###..###.#####.#.#
+        ##..#...####..#..###..#..##...#.###.#...#.......#...##.#.
+        .####.#.##.#.#....#....#.##.#.#.##.##.#.#.#..#.##.#.##.##
+        ###.#...###...##.#.#####.##...##.#####.#.#..#...#...#...#
+        .##.#####.#....##..####.#.#########..#.###...#.######.#.#
+        .#.##..#.####..#.###.#........#.####....#...#..##.#..#.#.
+        #.#...##.#.#...#..#..#######.##.#..##.#.#.#.#.##.###.#.##
+        ###.#..#.##..#...#.##.###.##.#.#.#.#.#.#...#.#...#.#...##
+        .#..#.#.#.#..###...##..#....#.####.###.##.####.##.#.#.##.
+        .##.#......##.#.####.#.###....#.#...#..##...#..##.#..#...
+        #.##..##...#.#..#.####.#....###.#.#.#.###.#.#.##.###.#.##
+        ######.#..#...##.#..####.#...#.#.#.#.#.#...#.#...#.#....#
+        .##...#.#....##.#..##...##..#.####.###.###.###.##.#.#.#.#
+        .#.......####.###..#.##.......#.#...#..#....#..##.#..#.#.
+        #.###.##.#####..#...####.###.##.#.#.#.###.#.#.##...#.#.##
+        ######.#...##.##.##....#..##.#.#.#.#...#.#.#.#...###.#..#
+        #.#.###.##...##.##......#...#.#.##.##.####.###.#.##.###.#
+        ##..##.....#..###..####.......#.....#.#.#..#####..#..#.#.
+        ..#####.##...#..##.#####.###......#.###.#.####.#.##....##
+        ####.#...#..#.##..##...#..##..#.##.#...#.#.#.#####.#....#
+        #.#..##.##.#.##.#.#.#...#...#....#.##..###.####.#.###.#.#
+        #####..##..##..##..#.##.........#...#...#..#.####.##.#.#.
+        ......#.##....###..#####.######.#.###.#.#.####.#######.##
+        ........##....#...##...#..#...##.....#.#.#.#...##...#...#
+        #######.##..#.....#.#.#.#.#.#.###.####.###.##..##.#.#.#.#
+        #.....#....##.#.#..#..#...#...#.#.#.....###.#...#...##.#.
+        #.###.#..#....##...####.#.#####.#.....#.##..#.########.##
+        #.###.#..#...#.#..##......#.#..#.#..##.#.###.#..#...#....
+        #.###.#.#.#.#.....#.###.###.#.####...#.#######..#.#.#.###
+        #.....#....###.....#.....#.#.#..#...#..####.#..#.#.#.#...
+        #######.#.#..###......##..####..#.#.#.####..#.####.###..#";
+
+    #[test]
+    fn test_matches_reference() {
+        let qr = QrCode::encode(b"http://192.168.1.1:8080/#token=0123456789abcdef").unwrap();
+        assert_eq!(rows(&qr), TOKEN_URL_SYMBOL.split_whitespace().collect::<Vec<_>>());
+
+        let long_url = format!("http://192.168.1.1:8080/#token={}", "a".repeat(150));
+        let qr = QrCode::encode(long_url.as_bytes()).unwrap();
+        assert_eq!(rows(&qr), LONG_URL_SYMBOL.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_too_long() {
+        assert!(QrCode::encode(&[0; 213]).is_some());
+        assert!(QrCode::encode(&[0; 214]).is_none());
+    }
+}
@@ -0,0 +1,71 @@
+//! Feeds a recorded QMDL file through the daemon in place of the diag device,
+//! for reproducing bugs without the hardware.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use rayhunter::diag::MessagesContainer;
+use rayhunter::diag_device::DiagDeviceError;
+use rayhunter::qmdl::QmdlReader;
+use tokio::fs::File;
+
+pub type ReplayStream = BoxStream<'static, Result<MessagesContainer, DiagDeviceError>>;
+
+// Spaces replayed containers out by the time between their messages'
+// timestamps, so the replay runs at the same pace it was recorded at
+#[derive(Default)]
+struct Pacer {
+    start: Option<(DateTime<FixedOffset>, Instant)>,
+}
+
+impl Pacer {
+    fn delay_for(&mut self, timestamp: DateTime<FixedOffset>, now: Instant) -> Duration {
+        let (first_timestamp, started) = *self.start.get_or_insert((timestamp, now));
+        // timestamps going backwards just means no delay
+        let offset = (timestamp - first_timestamp).to_std().unwrap_or_default();
+        (started + offset).saturating_duration_since(now)
+    }
+}
+
+/// Opens the QMDL file at `path` as a stream of containers. If `realtime` is
+/// set, each container is held back until the same amount of time has passed
+/// since the first one as did when it was recorded.
+pub async fn replay_stream(path: &str, realtime: bool) -> Result<ReplayStream, std::io::Error> {
+    let file = File::open(path).await?;
+    let containers = QmdlReader::new(file, None).into_stream()
+        .map_err(DiagDeviceError::DeviceReadFailed);
+    if !realtime {
+        return Ok(containers.boxed());
+    }
+    let mut pacer = Pacer::default();
+    Ok(containers.and_then(move |container| {
+        let delay = container.first_log_timestamp()
+            .map_or(Duration::ZERO, |timestamp| pacer.delay_for(timestamp, Instant::now()));
+        async move {
+            tokio::time::sleep(delay).await;
+            Ok(container)
+        }
+    }).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_pacer() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let first = utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let now = Instant::now();
+        let mut pacer = Pacer::default();
+        assert_eq!(pacer.delay_for(first, now), Duration::ZERO);
+        assert_eq!(pacer.delay_for(first + chrono::Duration::seconds(5), now), Duration::from_secs(5));
+        // if the replay's fallen behind, don't wait at all
+        let later = now + Duration::from_secs(10);
+        assert_eq!(pacer.delay_for(first + chrono::Duration::seconds(5), later), Duration::ZERO);
+        assert_eq!(pacer.delay_for(first - chrono::Duration::seconds(5), now), Duration::ZERO);
+    }
+}
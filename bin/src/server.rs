@@ -1,39 +1,313 @@
 use axum::body::Body;
 use axum::http::header::{CONTENT_TYPE, self};
 use axum::extract::State;
-use axum::http::{StatusCode, HeaderValue};
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode, HeaderValue};
 use axum::response::{Response, IntoResponse};
 use axum::extract::Path;
-use tokio::io::AsyncReadExt;
 use tokio::sync::mpsc::Sender;
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::AtomicBool;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use futures::TryStreamExt;
+use std::io::SeekFrom;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
+use tokio_util::task::TaskTracker;
+use rayhunter::cellular_data::CellularData;
+use rayhunter::qmdl::{QmdlCompression, QmdlReader, ZSTD_QMDL_MAGIC};
 use include_dir::{include_dir, Dir};
+use rayhunter::analysis::analyzer::AnalyzerConfig;
+use serde::{Deserialize, Serialize};
 
 use crate::DiagDeviceCtrlMessage;
-use crate::qmdl_store::RecordingStore;
+use crate::config::validate_config;
+use crate::qmdl_store::{RecordingStore, RecordingStoreError};
+use crate::stats::{DiagCounters, EventCounts};
 
 pub struct ServerState {
     pub qmdl_store_lock: Arc<RwLock<RecordingStore>>,
     pub diag_device_ctrl_sender: Sender<DiagDeviceCtrlMessage>,
-    pub readonly_mode: bool
+    pub readonly_mode: bool,
+    pub redact_identities: bool,
+    pub auto_resume_after_secs: u64,
+    pub auto_resume_task: Mutex<Option<JoinHandle<()>>>,
+    // set at boot when autostart is off, until a recording is started or
+    // stopped
+    pub armed: AtomicBool,
+    pub analyzer_event_counts: Arc<RwLock<Vec<EventCounts>>>,
+    pub analyzer_config: AnalyzerConfig,
+    pub stats_stream_interval_secs: u64,
+    pub allow_debug_endpoints: bool,
+    pub store_compression: QmdlCompression,
+    pub current_cell: Arc<RwLock<CellularData>>,
+    pub diag_counters: Arc<DiagCounters>,
+    pub config_path: String,
+    // the daemon's tracker, for background work like analyzing an imported
+    // recording, so that shutdown waits for it to finish
+    pub task_tracker: TaskTracker,
+    // names of the entries currently being re-analyzed
+    pub reanalyzing: Mutex<HashSet<String>>,
 }
 
-pub async fn get_qmdl(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Response, (StatusCode, String)> {
+/// A stable, machine-readable code for each kind of API error, so clients
+/// don't have to match on messages
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApiErrorCode {
+    NoSuchEntry,
+    RecordingActive,
+    NotRecording,
+    ReadonlyMode,
+    QmdlDiscarded,
+    Unauthorized,
+    Forbidden,
+    InvalidRequest,
+    NotFound,
+    Conflict,
+    Unavailable,
+    InternalError,
+}
+
+/// An API error, sent as a JSON body of the form
+/// `{"code": "NO_SUCH_ENTRY", "message": "..."}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: ApiErrorCode,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    code: ApiErrorCode,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: ApiErrorCode, message: impl Into<String>) -> Self {
+        ApiError { status, code, message: message.into() }
+    }
+
+    pub fn no_such_entry(name: &str) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, ApiErrorCode::NoSuchEntry, format!("couldn't find qmdl file with name {}", name))
+    }
+
+    pub fn readonly_mode() -> Self {
+        ApiError::new(StatusCode::FORBIDDEN, ApiErrorCode::ReadonlyMode, "server is in readonly mode")
+    }
+
+    pub fn qmdl_discarded(err: RecordingStoreError) -> Self {
+        ApiError::new(StatusCode::GONE, ApiErrorCode::QmdlDiscarded, err.to_string())
+    }
+}
+
+// Errors without a more specific code get a generic one for their status
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        let code = match status {
+            StatusCode::UNAUTHORIZED => ApiErrorCode::Unauthorized,
+            // readonly mode has its own code, from ApiError::readonly_mode
+            StatusCode::FORBIDDEN => ApiErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ApiErrorCode::NotFound,
+            StatusCode::CONFLICT => ApiErrorCode::Conflict,
+            StatusCode::SERVICE_UNAVAILABLE => ApiErrorCode::Unavailable,
+            status if status.is_client_error() => ApiErrorCode::InvalidRequest,
+            _ => ApiErrorCode::InternalError,
+        };
+        ApiError::new(status, code, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiErrorBody { code: self.code, message: &self.message };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+pub async fn get_qmdl(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>, headers: HeaderMap) -> Result<Response, ApiError> {
     let qmdl_store = state.qmdl_store_lock.read().await;
     let entry = qmdl_store.entry_for_name(&qmdl_name)
-        .ok_or((StatusCode::NOT_FOUND, format!("couldn't find qmdl file with name {}", qmdl_name)))?;
-    let qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error opening QMDL file: {}", e)))?;
-    let limited_qmdl_file = qmdl_file.take(entry.qmdl_size_bytes as u64);
-    let qmdl_stream = ReaderStream::new(limited_qmdl_file);
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
+    let mut qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
+        .map_err(|e| match e {
+            RecordingStoreError::QmdlDiscarded(_) => ApiError::qmdl_discarded(e),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("error opening QMDL file: {}", e)).into(),
+        })?;
+    drop(qmdl_store);
+
+    // plain QMDL files can be served as-is, which lets interrupted downloads
+    // resume with range requests
+    if !is_compressed_qmdl(&mut qmdl_file).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error reading QMDL file: {}", e)))? {
+        return ranged_file_response(qmdl_file, entry.qmdl_size_bytes as u64, headers.get(header::RANGE)).await;
+    }
+
+    // going through QmdlReader decompresses entries that are stored
+    // compressed, so downloads are always plain QMDL
+    let qmdl_reader = QmdlReader::new(qmdl_file, Some(entry.qmdl_size_bytes));
+    let qmdl_stream = qmdl_reader.into_stream()
+        .map_ok(|container| container.messages.into_iter()
+            .flat_map(|msg| msg.data)
+            .collect::<Vec<u8>>());
 
     let headers = [(CONTENT_TYPE, "application/octet-stream")];
     let body = Body::from_stream(qmdl_stream);
     Ok((headers, body).into_response())
 }
 
+// Checks for the compressed QMDL magic, leaving the file at its start
+async fn is_compressed_qmdl(file: &mut File) -> std::io::Result<bool> {
+    let mut magic = Vec::with_capacity(ZSTD_QMDL_MAGIC.len());
+    (&mut *file).take(ZSTD_QMDL_MAGIC.len() as u64).read_to_end(&mut magic).await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(magic == ZSTD_QMDL_MAGIC)
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    // start and end are both inclusive, like in the header
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    // Parses a Range header against a body of `len` bytes. Only single
+    // ranges are supported, anything else gets the whole body.
+    fn parse(range: &str, len: u64) -> ByteRange {
+        let Some((start, end)) = range.strip_prefix("bytes=").and_then(|spec| spec.trim().split_once('-')) else {
+            return ByteRange::Full;
+        };
+        let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+            // "bytes=-500" is the last 500 bytes
+            (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            (Err(_), Ok(0)) if start.is_empty() => return ByteRange::Unsatisfiable,
+            _ => return ByteRange::Full,
+        };
+        if start >= len {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Partial { start, end }
+    }
+}
+
+// Serves the first `len` bytes of a file, or just the part of it asked for
+// in a Range header
+async fn ranged_file_response(mut file: File, len: u64, range: Option<&HeaderValue>) -> Result<Response, ApiError> {
+    let range = range.and_then(|range| range.to_str().ok())
+        .map_or(ByteRange::Full, |range| ByteRange::parse(range, len));
+    let (status, start, body_len) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, len),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            let headers = [(header::CONTENT_RANGE, format!("bytes */{}", len))];
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        },
+    };
+    file.seek(SeekFrom::Start(start)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error seeking in file: {}", e)))?;
+    let body = Body::from_stream(ReaderStream::new(file.take(body_len)));
+    let mut response = (status, body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_len));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", start, start + body_len - 1, len);
+        headers.insert(header::CONTENT_RANGE, HeaderValue::from_str(&content_range).unwrap());
+    }
+    Ok(response)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordingNotes {
+    pub notes: Option<String>,
+}
+
+pub async fn get_recording_notes(State(state): State<Arc<ServerState>>, Path(qmdl_name): Path<String>) -> Result<Json<RecordingNotes>, ApiError> {
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let entry = qmdl_store.entry_for_name(&qmdl_name)
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
+    Ok(Json(RecordingNotes { notes: entry.notes }))
+}
+
+// Replaces a recording's notes. Empty notes clear them.
+pub async fn set_recording_notes(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    Json(body): Json<RecordingNotes>,
+) -> Result<(StatusCode, String), ApiError> {
+    if state.readonly_mode {
+        return Err(ApiError::readonly_mode());
+    }
+    let notes = body.notes.filter(|notes| !notes.trim().is_empty());
+    let mut qmdl_store = state.qmdl_store_lock.write().await;
+    qmdl_store.set_entry_notes(&qmdl_name, notes).await
+        .map_err(|e| match e {
+            RecordingStoreError::NoSuchEntry(_) => ApiError::no_such_entry(&qmdl_name),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't update notes: {}", e)).into(),
+        })?;
+    Ok((StatusCode::ACCEPTED, "ok".to_string()))
+}
+
+// Marks a recording as protected, so its QMDL file is kept even if
+// discard_qmdl_after_analysis is set
+pub async fn protect_recording(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+) -> Result<(StatusCode, String), ApiError> {
+    if state.readonly_mode {
+        return Err(ApiError::readonly_mode());
+    }
+    let mut qmdl_store = state.qmdl_store_lock.write().await;
+    qmdl_store.set_entry_protected(&qmdl_name).await
+        .map_err(|e| match e {
+            RecordingStoreError::NoSuchEntry(_) => ApiError::no_such_entry(&qmdl_name),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("couldn't protect recording: {}", e)).into(),
+        })?;
+    Ok((StatusCode::ACCEPTED, "ok".to_string()))
+}
+
+#[derive(Serialize)]
+pub struct VersionInfo {
+    pub rayhunter_version: &'static str,
+    pub git_commit: Option<&'static str>,
+    pub os: &'static str,
+    pub arch: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct ConfigValidation {
+    pub valid: bool,
+    pub errors: Vec<String>,
+}
+
+// Checks the config file the daemon was started with, which may have been
+// edited since
+pub async fn get_config_validation(State(state): State<Arc<ServerState>>) -> Json<ConfigValidation> {
+    let errors: Vec<String> = validate_config(&state.config_path).iter()
+        .map(|error| error.to_string())
+        .collect();
+    Json(ConfigValidation { valid: errors.is_empty(), errors })
+}
+
+pub async fn get_version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        rayhunter_version: env!("CARGO_PKG_VERSION"),
+        // set by build.rs when built from a git checkout
+        git_commit: option_env!("RAYHUNTER_GIT_COMMIT"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+    })
+}
+
 // Bundles the server's static files (html/css/js) into the binary for easy distribution
 static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/static");
 
@@ -56,3 +330,139 @@ pub async fn serve_static(Path(path): Path<String>) -> impl IntoResponse {
             .unwrap(),
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// A ServerState for testing handlers against a new store at `path`, along
+    /// with the other end of its diag device control channel. Tweak its
+    /// settings before wrapping it in an Arc.
+    pub async fn test_state(path: &std::path::Path) -> (ServerState, tokio::sync::mpsc::Receiver<DiagDeviceCtrlMessage>) {
+        let store = RecordingStore::create(path).await.unwrap();
+        let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::channel(10);
+        let state = ServerState {
+            qmdl_store_lock: Arc::new(RwLock::new(store)),
+            diag_device_ctrl_sender: ctrl_tx,
+            readonly_mode: false,
+            redact_identities: false,
+            auto_resume_after_secs: 0,
+            auto_resume_task: Mutex::new(None),
+            armed: AtomicBool::new(false),
+            analyzer_event_counts: Arc::new(RwLock::new(Vec::new())),
+            analyzer_config: AnalyzerConfig::default(),
+            stats_stream_interval_secs: 1,
+            allow_debug_endpoints: false,
+            store_compression: QmdlCompression::None,
+            current_cell: Arc::new(RwLock::new(CellularData::default())),
+            diag_counters: Arc::new(DiagCounters::default()),
+            config_path: String::new(),
+            task_tracker: TaskTracker::new(),
+            reanalyzing: Mutex::new(HashSet::new()),
+        };
+        (state, ctrl_rx)
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(ByteRange::parse("bytes=0-9", 100), ByteRange::Partial { start: 0, end: 9 });
+        assert_eq!(ByteRange::parse("bytes=90-", 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(ByteRange::parse("bytes=-10", 100), ByteRange::Partial { start: 90, end: 99 });
+        // ends past the end of the body are clamped
+        assert_eq!(ByteRange::parse("bytes=50-500", 100), ByteRange::Partial { start: 50, end: 99 });
+        assert_eq!(ByteRange::parse("bytes=100-", 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-0", 100), ByteRange::Unsatisfiable);
+        // multiple ranges and other units aren't supported
+        assert_eq!(ByteRange::parse("bytes=0-9,20-29", 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse("items=0-9", 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse("bytes=9-0", 100), ByteRange::Full);
+    }
+
+    async fn get(file: &std::path::Path, len: u64, range: Option<&str>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let range = range.map(|range| HeaderValue::from_str(range).unwrap());
+        let response = ranged_file_response(File::open(file).await.unwrap(), len, range.as_ref()).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_ranged_request() {
+        let dir = TempDir::new("server_test").unwrap();
+        let path = dir.path().join("test.qmdl");
+        // the last few bytes are past the recording's size, as if they were
+        // still being written
+        let data: Vec<u8> = (0..110).collect();
+        tokio::fs::write(&path, &data).await.unwrap();
+
+        let (status, headers, body) = get(&path, 100, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(body, &data[..100]);
+
+        let (status, headers, body) = get(&path, 100, Some("bytes=40-")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 40-99/100");
+        assert_eq!(headers[header::CONTENT_LENGTH], "60");
+        assert_eq!(body, &data[40..100]);
+
+        let (status, headers, body) = get(&path, 100, Some("bytes=200-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */100");
+        assert!(body.is_empty());
+    }
+
+    async fn error_body(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_api_error_response() {
+        let (status, body) = error_body(ApiError::no_such_entry("1234")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "NO_SUCH_ENTRY");
+        assert_eq!(body["message"], "couldn't find qmdl file with name 1234");
+
+        let (status, body) = error_body(ApiError::readonly_mode()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "READONLY_MODE");
+
+        // plain errors get a generic code for their status
+        let error: ApiError = (StatusCode::BAD_REQUEST, "bad".to_string()).into();
+        let (status, body) = error_body(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_REQUEST");
+        assert_eq!(body["message"], "bad");
+
+        // and only readonly mode's errors say so
+        let error: ApiError = (StatusCode::FORBIDDEN, "not allowed".to_string()).into();
+        let (status, body) = error_body(error).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "FORBIDDEN");
+        assert_eq!(body["message"], "not allowed");
+    }
+
+    #[tokio::test]
+    async fn test_get_version() {
+        let Json(version) = get_version().await;
+        let version = serde_json::to_value(version).unwrap();
+        assert_eq!(version["rayhunter_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["os"], std::env::consts::OS);
+        assert_eq!(version["arch"], std::env::consts::ARCH);
+        // the commit's only known when built from a git checkout, and is
+        // then a short hash
+        match &version["git_commit"] {
+            serde_json::Value::Null => {},
+            serde_json::Value::String(commit) => {
+                assert!(!commit.is_empty());
+                assert!(commit.chars().all(|c| c.is_ascii_hexdigit()));
+            },
+            other => panic!("unexpected git_commit {}", other),
+        }
+    }
+}
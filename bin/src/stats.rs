@@ -1,30 +1,83 @@
+use std::future;
+use std::pin::pin;
+use std::fmt::Write;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use crate::qmdl_store::ManifestEntry;
-use crate::server::ServerState;
+use crate::qmdl_store::{ManifestEntry, RecordingStoreError};
+use crate::server::{ApiError, ServerState};
 
 use axum::Json;
-use axum::extract::State;
+use rayhunter::analysis::analyzer::{AnalysisRow, Event, EventType, Harness, Severity};
+use axum::extract::{Path, Query, State};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::{IntoResponse, Response};
 use axum::http::StatusCode;
-use log::error;
-use serde::Serialize;
+use axum::http::header::CONTENT_TYPE;
+use futures::TryStreamExt;
+use log::{debug, error};
+use rayhunter::cell_summary::{CellSummary, CellSummaryDiff, ObservedCell};
+use rayhunter::diag::DataType;
+use rayhunter::qmdl::QmdlReader;
+use rayhunter::signal_series::{SignalSample, SignalSeries};
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
+use tokio::sync::RwLock;
+use tokio::time::interval;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingState {
+    Recording,
+    // stopped, but an auto-resume or delayed autostart is scheduled
+    Paused,
+    // autostart is off and nothing's been recorded since boot
+    Armed,
+    Stopped,
+}
 
 #[derive(Debug, Serialize)]
 pub struct SystemStats {
     pub disk_stats: DiskStats,
     pub memory_stats: MemoryStats,
+    pub recording_state: RecordingState,
+    pub current_entry_name: Option<String>,
+    // how many times the diag device has gone quiet since startup
+    pub diag_stalls: u64,
 }
 
 impl SystemStats {
-    pub async fn new(qmdl_path: &str) -> Result<Self, String> {
+    pub async fn new(state: &ServerState) -> Result<Self, String> {
+        let (recording_state, current_entry_name) = get_recording_state(state).await;
+        let qmdl_path = state.qmdl_store_lock.read().await.path.to_str().unwrap().to_string();
         Ok(Self {
-            disk_stats: DiskStats::new(qmdl_path).await?,
+            disk_stats: DiskStats::new(&qmdl_path).await?,
             memory_stats: MemoryStats::new().await?,
+            recording_state,
+            current_entry_name,
+            diag_stalls: state.diag_counters.stalls.load(Ordering::Relaxed),
         })
     }
 }
 
+// returns the recording state, and the current entry's name if recording
+async fn get_recording_state(state: &ServerState) -> (RecordingState, Option<String>) {
+    // the auto-resume lock is taken before the store's everywhere else, so
+    // don't hold onto it here
+    let auto_resume_pending = state.auto_resume_task.lock().await.is_some();
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let current_entry_name = qmdl_store.get_current_entry().map(|entry| entry.name.clone());
+    let armed = state.armed.load(Ordering::Relaxed);
+    let recording_state = match (&current_entry_name, auto_resume_pending, armed) {
+        (Some(_), _, _) => RecordingState::Recording,
+        (None, true, _) => RecordingState::Paused,
+        (None, false, true) => RecordingState::Armed,
+        (None, false, false) => RecordingState::Stopped,
+    };
+    (recording_state, current_entry_name)
+}
+
 #[derive(Debug, Serialize)]
 pub struct DiskStats {
     partition: String,
@@ -97,16 +150,15 @@ fn humanize_kb(kb: usize) -> String {
     format!("{:.1}M", kb as f64 / 1024.0)
 }
 
-pub async fn get_system_stats(State(state): State<Arc<ServerState>>) -> Result<Json<SystemStats>, (StatusCode, String)> {
-    let qmdl_store = state.qmdl_store_lock.read().await;
-    match SystemStats::new(qmdl_store.path.to_str().unwrap()).await {
+pub async fn get_system_stats(State(state): State<Arc<ServerState>>) -> Result<Json<SystemStats>, ApiError> {
+    match SystemStats::new(&state).await {
         Ok(stats) => Ok(Json(stats)),
         Err(err) => {
             error!("error getting system stats: {}", err);
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "error getting system stats".to_string()
-            ))
+            ).into())
         },
     }
 }
@@ -117,7 +169,7 @@ pub struct ManifestStats {
     pub current_entry: Option<ManifestEntry>,
 }
 
-pub async fn get_qmdl_manifest(State(state): State<Arc<ServerState>>) -> Result<Json<ManifestStats>, (StatusCode, String)> {
+pub async fn get_qmdl_manifest(State(state): State<Arc<ServerState>>) -> Result<Json<ManifestStats>, ApiError> {
     let qmdl_store = state.qmdl_store_lock.read().await;
     let mut entries = qmdl_store.manifest.entries.clone();
     let current_entry = qmdl_store.current_entry.map(|index| entries.remove(index));
@@ -126,3 +178,508 @@ pub async fn get_qmdl_manifest(State(state): State<Arc<ServerState>>) -> Result<
         current_entry,
     }))
 }
+
+#[derive(Serialize)]
+pub struct LiveStats {
+    pub system_stats: SystemStats,
+    pub recording: bool,
+    pub current_entry: Option<ManifestEntry>,
+}
+
+impl LiveStats {
+    async fn new(state: &ServerState) -> Result<Self, String> {
+        let system_stats = SystemStats::new(state).await?;
+        let current_entry = state.qmdl_store_lock.read().await.get_current_entry().cloned();
+        Ok(Self {
+            system_stats,
+            recording: current_entry.is_some(),
+            current_entry,
+        })
+    }
+}
+
+// Upgrades to a WebSocket which pushes a LiveStats every
+// stats_stream_interval_secs, for live displays that would otherwise have to
+// poll /api/system-stats
+pub async fn get_stats_stream(State(state): State<Arc<ServerState>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| stream_stats(socket, state))
+}
+
+async fn stream_stats(mut socket: WebSocket, state: Arc<ServerState>) {
+    let mut ticker = interval(Duration::from_secs(state.stats_stream_interval_secs));
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let stats = match LiveStats::new(&state).await {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        error!("error getting system stats: {}", err);
+                        continue;
+                    },
+                };
+                let json = serde_json::to_string(&stats).expect("failed to serialize stats");
+                if socket.send(Message::Text(json)).await.is_err() {
+                    debug!("stats stream client went away");
+                    return;
+                }
+            },
+            // we don't expect anything from the client, but have to poll the
+            // socket to notice it closing
+            maybe_msg = socket.recv() => match maybe_msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {},
+            },
+        }
+    }
+    debug!("stats stream client disconnected");
+    let _ = socket.close().await;
+}
+
+// Returns the system information most recently broadcast by the serving cell,
+// or 204 No Content if none has been decoded yet
+pub async fn get_current_cell_sibs(State(state): State<Arc<ServerState>>) -> Response {
+    let current_cell = state.current_cell.read().await;
+    if current_cell.is_empty() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    Json(current_cell.clone()).into_response()
+}
+
+const DEFAULT_CELL_SUMMARY_LIMIT: usize = 100;
+const MAX_CELL_SUMMARY_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct CellSummaryQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct CellSummaryPage {
+    pub total: usize,
+    pub offset: usize,
+    pub cells: Vec<ObservedCell>,
+}
+
+// Runs every message in a recording through `f`
+async fn for_each_recording_message<F>(state: &ServerState, qmdl_name: &str, mut f: F) -> Result<(), ApiError>
+    where F: FnMut(rayhunter::diag::Message)
+{
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let entry = qmdl_store.entry_for_name(qmdl_name)
+        .ok_or_else(|| ApiError::no_such_entry(&qmdl_name))?;
+    let qmdl_file = qmdl_store.open_entry_qmdl(&entry).await
+        .map_err(|e| match e {
+            RecordingStoreError::QmdlDiscarded(_) => ApiError::qmdl_discarded(e),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)).into(),
+        })?;
+    drop(qmdl_store);
+
+    let mut qmdl_reader = QmdlReader::new(qmdl_file, Some(entry.qmdl_size_bytes));
+    let mut qmdl_stream = pin!(qmdl_reader.as_stream()
+        .try_filter(|container| future::ready(container.data_type == DataType::UserSpace)));
+    while let Some(container) = qmdl_stream.try_next().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("error reading QMDL file: {}", e)))? {
+        for msg in container.into_messages().into_iter().flatten() {
+            f(msg);
+        }
+    }
+    Ok(())
+}
+
+async fn summarize_recording(state: &ServerState, qmdl_name: &str) -> Result<CellSummary, ApiError> {
+    let mut summary = CellSummary::new();
+    for_each_recording_message(state, qmdl_name, |msg| summary.add_message(msg)).await?;
+    Ok(summary)
+}
+
+// Lists the cells seen over the course of a recording, ordered by when they
+// were first seen. Long recordings can see a lot of cells, so results are
+// paginated with the "offset" and "limit" query parameters.
+pub async fn get_cell_summary(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    Query(query): Query<CellSummaryQuery>,
+) -> Result<Json<CellSummaryPage>, ApiError> {
+    let cells = summarize_recording(&state, &qmdl_name).await?.cells();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_CELL_SUMMARY_LIMIT).min(MAX_CELL_SUMMARY_LIMIT);
+    Ok(Json(CellSummaryPage {
+        total: cells.len(),
+        offset,
+        cells: cells.into_iter().skip(offset).take(limit).collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SignalSeriesQuery {
+    max_points: Option<usize>,
+}
+
+// Returns the serving cell's signal strength over the course of a recording.
+// Drives can produce a lot of samples, so "max_points" thins them out evenly.
+pub async fn get_signal_series(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    Query(query): Query<SignalSeriesQuery>,
+) -> Result<Json<Vec<SignalSample>>, ApiError> {
+    let mut series = SignalSeries::new();
+    for_each_recording_message(&state, &qmdl_name, |msg| series.add_message(msg)).await?;
+    Ok(Json(series.samples(query.max_points)))
+}
+
+#[derive(Deserialize)]
+pub struct RecordingDiffQuery {
+    a: String,
+    b: String,
+}
+
+// Compares the cells seen in two recordings, e.g. to spot a tower that's
+// appeared since yesterday, or a known one whose system information changed
+pub async fn get_recording_diff(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<RecordingDiffQuery>,
+) -> Result<Json<CellSummaryDiff>, ApiError> {
+    let a = summarize_recording(&state, &query.a).await?;
+    let b = summarize_recording(&state, &query.b).await?;
+    Ok(Json(CellSummaryDiff::new(&a, &b)))
+}
+
+/// Running count of the events an analyzer has emitted since the daemon
+/// started, broken down by severity
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct EventCounts {
+    pub informational: usize,
+    pub low: usize,
+    pub medium: usize,
+    pub high: usize,
+}
+
+impl EventCounts {
+    fn record(&mut self, event: &Event) {
+        match &event.event_type {
+            EventType::Informational => self.informational += 1,
+            EventType::QualitativeWarning { severity: Severity::Low } => self.low += 1,
+            EventType::QualitativeWarning { severity: Severity::Medium } => self.medium += 1,
+            EventType::QualitativeWarning { severity: Severity::High } => self.high += 1,
+        }
+    }
+}
+
+// Adds the events in an analysis row to the per-analyzer counts, which are
+// indexed the same way as the harness's analyzers
+pub async fn record_event_counts(event_counts_lock: &RwLock<Vec<EventCounts>>, row: &AnalysisRow) {
+    if row.analysis.is_empty() {
+        return;
+    }
+    let mut event_counts = event_counts_lock.write().await;
+    for packet_analysis in &row.analysis {
+        for (i, maybe_event) in packet_analysis.events.iter().enumerate() {
+            if let Some(event) = maybe_event {
+                if event_counts.len() <= i {
+                    event_counts.resize(i + 1, EventCounts::default());
+                }
+                event_counts[i].record(event);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AnalyzerStats {
+    pub name: String,
+    pub description: String,
+    pub event_counts: EventCounts,
+}
+
+pub async fn get_analyzers(State(state): State<Arc<ServerState>>) -> Json<Vec<AnalyzerStats>> {
+    let metadata = Harness::new_with_config(&state.analyzer_config).get_metadata();
+    let event_counts = state.analyzer_event_counts.read().await;
+    let analyzers = metadata.analyzers.into_iter()
+        .enumerate()
+        .map(|(i, analyzer)| AnalyzerStats {
+            name: analyzer.name,
+            description: analyzer.description,
+            event_counts: event_counts.get(i).cloned().unwrap_or_default(),
+        })
+        .collect();
+    Json(analyzers)
+}
+
+/// Counters for the diag read loop, which only go up while the daemon's running
+#[derive(Debug, Default)]
+pub struct DiagCounters {
+    pub messages: AtomicU64,
+    pub parse_errors: AtomicU64,
+    pub reopens: AtomicU64,
+    pub stalls: AtomicU64,
+}
+
+// A snapshot of everything served at /metrics
+struct Metrics {
+    messages: u64,
+    parse_errors: u64,
+    reopens: u64,
+    stalls: u64,
+    analyzers: Vec<(String, EventCounts)>,
+    recording_state: RecordingState,
+    disk_available_bytes: Option<u64>,
+}
+
+impl Metrics {
+    // renders the metrics in the prometheus text exposition format
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: u64| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+        counter("rayhunter_diag_messages_total", "Diag messages read from the modem.", self.messages);
+        counter("rayhunter_parse_errors_total", "Messages the analyzers couldn't parse.", self.parse_errors);
+        counter("rayhunter_diag_reopens_total", "Times the diag device was re-opened after a modem reset.", self.reopens);
+        counter("rayhunter_diag_stalls_total", "Times the diag device went quiet for stall_timeout_secs.", self.stalls);
+
+        writeln!(out, "# HELP rayhunter_warnings_total Warnings emitted by each analyzer, by severity.").unwrap();
+        writeln!(out, "# TYPE rayhunter_warnings_total counter").unwrap();
+        for (name, counts) in &self.analyzers {
+            let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+            for (severity, count) in [("low", counts.low), ("medium", counts.medium), ("high", counts.high)] {
+                writeln!(out, "rayhunter_warnings_total{{analyzer=\"{}\",severity=\"{}\"}} {}", name, severity, count).unwrap();
+            }
+        }
+
+        writeln!(out, "# HELP rayhunter_recording_state Whether the daemon is in each recording state.").unwrap();
+        writeln!(out, "# TYPE rayhunter_recording_state gauge").unwrap();
+        for (label, state) in [("recording", RecordingState::Recording), ("paused", RecordingState::Paused), ("armed", RecordingState::Armed), ("stopped", RecordingState::Stopped)] {
+            writeln!(out, "rayhunter_recording_state{{state=\"{}\"}} {}", label, (self.recording_state == state) as u8).unwrap();
+        }
+
+        if let Some(bytes) = self.disk_available_bytes {
+            writeln!(out, "# HELP rayhunter_disk_available_bytes Free space on the partition holding recordings.").unwrap();
+            writeln!(out, "# TYPE rayhunter_disk_available_bytes gauge").unwrap();
+            writeln!(out, "rayhunter_disk_available_bytes {}", bytes).unwrap();
+        }
+        out
+    }
+}
+
+// runs "df -Pk <qmdl_path>" to get the free space in bytes, since DiskStats
+// only has df's human-readable sizes
+async fn get_disk_available_bytes(qmdl_path: &str) -> Result<u64, String> {
+    let mut df_cmd = Command::new("df");
+    df_cmd.arg("-Pk");
+    df_cmd.arg(qmdl_path);
+    let stdout = get_cmd_output(df_cmd).await?;
+    let available_kb: u64 = stdout.lines().nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|available| available.parse().ok())
+        .ok_or("error parsing df output")?;
+    Ok(available_kb * 1024)
+}
+
+pub async fn get_metrics(State(state): State<Arc<ServerState>>) -> Response {
+    let (recording_state, _) = get_recording_state(&state).await;
+    let qmdl_path = state.qmdl_store_lock.read().await.path.to_str().unwrap().to_string();
+    // a missing disk gauge is better than no metrics at all
+    let disk_available_bytes = get_disk_available_bytes(&qmdl_path).await
+        .inspect_err(|e| error!("couldn't get free disk space: {}", e))
+        .ok();
+    let metadata = Harness::new_with_config(&state.analyzer_config).get_metadata();
+    let event_counts = state.analyzer_event_counts.read().await;
+    let analyzers = metadata.analyzers.into_iter()
+        .enumerate()
+        .map(|(i, analyzer)| (analyzer.name, event_counts.get(i).cloned().unwrap_or_default()))
+        .collect();
+    let metrics = Metrics {
+        messages: state.diag_counters.messages.load(Ordering::Relaxed),
+        parse_errors: state.diag_counters.parse_errors.load(Ordering::Relaxed),
+        reopens: state.diag_counters.reopens.load(Ordering::Relaxed),
+        stalls: state.diag_counters.stalls.load(Ordering::Relaxed),
+        analyzers,
+        recording_state,
+        disk_available_bytes,
+    };
+    let headers = [(CONTENT_TYPE, "text/plain; version=0.0.4")];
+    (headers, metrics.render()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use rayhunter::analysis::analyzer::PacketAnalysis;
+    use tempdir::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::server::tests::test_state;
+
+    fn warning(severity: Severity) -> Option<Event> {
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity },
+            message: "test".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_record_event_counts() {
+        let timestamp = Local::now().fixed_offset();
+        let row = AnalysisRow {
+            timestamp,
+            skipped_message_reasons: Vec::new(),
+            analysis: vec![
+                PacketAnalysis { timestamp, message_type: None, events: vec![None, warning(Severity::High)] },
+                PacketAnalysis { timestamp, message_type: None, events: vec![warning(Severity::Low), warning(Severity::High)] },
+            ],
+        };
+        let event_counts_lock = RwLock::new(Vec::new());
+        record_event_counts(&event_counts_lock, &row).await;
+        let event_counts = event_counts_lock.read().await;
+        assert_eq!(event_counts.len(), 2);
+        assert_eq!(event_counts[0].low, 1);
+        assert_eq!(event_counts[0].high, 0);
+        assert_eq!(event_counts[1].high, 2);
+        assert_eq!(event_counts[1].informational, 0);
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics {
+            messages: 42,
+            parse_errors: 3,
+            reopens: 1,
+            stalls: 2,
+            analyzers: vec![
+                ("IMSI Requested".to_string(), EventCounts { informational: 5, low: 0, medium: 2, high: 1 }),
+            ],
+            recording_state: RecordingState::Paused,
+            disk_available_bytes: Some(1024),
+        };
+        let rendered = metrics.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+        for expected in [
+            "# TYPE rayhunter_diag_messages_total counter",
+            "rayhunter_diag_messages_total 42",
+            "rayhunter_parse_errors_total 3",
+            "rayhunter_diag_reopens_total 1",
+            "rayhunter_diag_stalls_total 2",
+            "rayhunter_warnings_total{analyzer=\"IMSI Requested\",severity=\"medium\"} 2",
+            "rayhunter_warnings_total{analyzer=\"IMSI Requested\",severity=\"high\"} 1",
+            "rayhunter_recording_state{state=\"recording\"} 0",
+            "rayhunter_recording_state{state=\"paused\"} 1",
+            "rayhunter_disk_available_bytes 1024",
+        ] {
+            assert!(lines.contains(&expected), "missing {:?} in:\n{}", expected, rendered);
+        }
+        // informational events aren't warnings
+        assert!(!rendered.contains("severity=\"informational\""));
+        // every sample line is "<name>[{labels}] <value>"
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<u64>().is_ok(), "bad sample line {:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_render_metrics_without_disk() {
+        let metrics = Metrics {
+            messages: 0,
+            parse_errors: 0,
+            reopens: 0,
+            stalls: 0,
+            analyzers: Vec::new(),
+            recording_state: RecordingState::Stopped,
+            disk_available_bytes: None,
+        };
+        assert!(!metrics.render().contains("rayhunter_disk_available_bytes"));
+    }
+
+    // Reads a single unfragmented frame sent by the server, which never masks
+    // them, returning its opcode and payload
+    async fn read_ws_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = match header[1] & 0x7f {
+            126 => stream.read_u16().await.unwrap() as usize,
+            127 => stream.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        (header[0] & 0x0f, payload)
+    }
+
+    #[tokio::test]
+    async fn test_stats_stream() {
+        let dir = TempDir::new("stats_test").unwrap();
+        let (state, _ctrl_rx) = test_state(dir.path()).await;
+        let state = Arc::new(state);
+        state.qmdl_store_lock.write().await.new_entry().await.unwrap();
+        let entry_name = state.qmdl_store_lock.read().await.get_current_entry().unwrap().name.clone();
+        let app = axum::Router::new()
+            .route("/api/ws/stats", axum::routing::get(get_stats_stream))
+            .with_state(state);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // the key from RFC 6455's example handshake
+        stream.write_all(format!(
+            "GET /api/ws/stats HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            addr,
+        ).as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        let response = String::from_utf8(response).unwrap().to_lowercase();
+        assert!(response.starts_with("http/1.1 101"));
+        assert!(response.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="));
+
+        // the first update comes right away
+        let (opcode, payload) = tokio::time::timeout(Duration::from_secs(5), read_ws_frame(&mut stream)).await
+            .expect("no stats were sent");
+        assert_eq!(opcode, 0x1); // text
+        let stats: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(stats["recording"], true);
+        assert_eq!(stats["current_entry"]["name"], entry_name.as_str());
+        assert_eq!(stats["system_stats"]["recording_state"], "recording");
+
+        // closing from our end (with an empty, masked close frame) gets a
+        // close back, maybe after one more update
+        stream.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let (opcode, _) = read_ws_frame(&mut stream).await;
+                if opcode == 0x8 {
+                    break;
+                }
+            }
+        }).await;
+        assert!(closed.is_ok(), "the server didn't close the stream");
+    }
+
+    #[tokio::test]
+    async fn test_get_recording_state() {
+        let dir = TempDir::new("stats_test").unwrap();
+        let (mut state, _ctrl_rx) = test_state(dir.path()).await;
+        state.armed = std::sync::atomic::AtomicBool::new(true);
+        // long enough that the auto-resume never fires during the test
+        state.auto_resume_after_secs = 600;
+        let state = Arc::new(state);
+        assert_eq!(get_recording_state(&state).await, (RecordingState::Armed, None));
+
+        crate::diag::start_recording(State(state.clone())).await.unwrap();
+        let entry_name = state.qmdl_store_lock.read().await.get_current_entry().unwrap().name.clone();
+        assert_eq!(get_recording_state(&state).await, (RecordingState::Recording, Some(entry_name)));
+
+        crate::diag::stop_recording(State(state.clone())).await.unwrap();
+        assert_eq!(get_recording_state(&state).await, (RecordingState::Paused, None));
+
+        state.auto_resume_task.lock().await.take().unwrap().abort();
+        assert_eq!(get_recording_state(&state).await, (RecordingState::Stopped, None));
+    }
+}
@@ -0,0 +1,289 @@
+//! A minimal streaming ZIP archive writer, just enough to bundle a few files
+//! into a single download without holding them in memory. Files are stored
+//! uncompressed, and archives are limited to 4GiB since there's no ZIP64
+//! support.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+static CRC_32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+// 2.0, the first version that could read directories and stored files
+const VERSION: u16 = 20;
+// the CRC and sizes follow the file's data rather than being in its header
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+// file names are UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+// MS-DOS timestamps start at 1980-01-01 00:00, which is what every file gets
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+struct CentralDirectoryEntry {
+    name: String,
+    flags: u16,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+// A file added with start_file, whose CRC and size are worked out as its
+// contents are written
+struct StreamedFile {
+    entry: CentralDirectoryEntry,
+    digest: Digest<'static, u32>,
+    size: u64,
+}
+
+pub struct ZipWriter<W> {
+    writer: W,
+    // how much has been written so far, i.e. the offset of whatever's next
+    offset: u64,
+    entries: Vec<CentralDirectoryEntry>,
+    current: Option<StreamedFile>,
+}
+
+fn too_big() -> io::Error {
+    io::Error::other("zip archive is too big without ZIP64")
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend(value.to_le_bytes());
+}
+
+impl<W> ZipWriter<W> where W: AsyncWrite + Unpin {
+    pub fn new(writer: W) -> Self {
+        ZipWriter {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Adds a file whose contents are already in memory
+    pub async fn add_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let entry = CentralDirectoryEntry {
+            name: name.to_string(),
+            flags: FLAG_UTF8,
+            crc: CRC_32.checksum(contents),
+            size: u32::try_from(contents.len()).map_err(|_| too_big())?,
+            offset: self.current_offset()?,
+        };
+        self.write_local_header(&entry).await?;
+        self.write_raw(contents).await?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Starts a file whose contents are then written to the ZipWriter itself,
+    /// through its [AsyncWrite] implementation. The file's CRC and size go in
+    /// a data descriptor after its contents once [Self::finish_file] is called.
+    pub async fn start_file(&mut self, name: &str) -> io::Result<()> {
+        assert!(self.current.is_none(), "the previous file wasn't finished");
+        let entry = CentralDirectoryEntry {
+            name: name.to_string(),
+            flags: FLAG_UTF8 | FLAG_DATA_DESCRIPTOR,
+            crc: 0,
+            size: 0,
+            offset: self.current_offset()?,
+        };
+        self.write_local_header(&entry).await?;
+        self.current = Some(StreamedFile {
+            entry,
+            digest: CRC_32.digest(),
+            size: 0,
+        });
+        Ok(())
+    }
+
+    pub async fn finish_file(&mut self) -> io::Result<()> {
+        let StreamedFile { mut entry, digest, size } = self.current.take()
+            .expect("no file was started");
+        entry.crc = digest.finalize();
+        entry.size = u32::try_from(size).map_err(|_| too_big())?;
+        let mut descriptor = Vec::new();
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut descriptor, entry.crc);
+        // compressed and uncompressed sizes are the same when stored
+        put_u32(&mut descriptor, entry.size);
+        put_u32(&mut descriptor, entry.size);
+        self.write_raw(&descriptor).await?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory, returning the underlying writer
+    pub async fn finish(mut self) -> io::Result<W> {
+        assert!(self.current.is_none(), "the last file wasn't finished");
+        let central_directory_offset = self.current_offset()?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut directory, CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+            put_u16(&mut directory, VERSION); // version made by
+            put_u16(&mut directory, VERSION); // version needed to extract
+            put_u16(&mut directory, entry.flags);
+            put_u16(&mut directory, METHOD_STORED);
+            put_u16(&mut directory, DOS_TIME);
+            put_u16(&mut directory, DOS_DATE);
+            put_u32(&mut directory, entry.crc);
+            put_u32(&mut directory, entry.size);
+            put_u32(&mut directory, entry.size);
+            put_u16(&mut directory, entry.name.len() as u16);
+            put_u16(&mut directory, 0); // extra field length
+            put_u16(&mut directory, 0); // comment length
+            put_u16(&mut directory, 0); // disk number
+            put_u16(&mut directory, 0); // internal attributes
+            put_u32(&mut directory, 0); // external attributes
+            put_u32(&mut directory, entry.offset);
+            directory.extend(entry.name.as_bytes());
+        }
+        let central_directory_size = u32::try_from(directory.len()).map_err(|_| too_big())?;
+        put_u32(&mut directory, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut directory, 0); // this disk's number
+        put_u16(&mut directory, 0); // the central directory's disk
+        put_u16(&mut directory, self.entries.len() as u16); // entries on this disk
+        put_u16(&mut directory, self.entries.len() as u16); // total entries
+        put_u32(&mut directory, central_directory_size);
+        put_u32(&mut directory, central_directory_offset);
+        put_u16(&mut directory, 0); // comment length
+        self.write_raw(&directory).await?;
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+
+    fn current_offset(&self) -> io::Result<u32> {
+        u32::try_from(self.offset).map_err(|_| too_big())
+    }
+
+    async fn write_local_header(&mut self, entry: &CentralDirectoryEntry) -> io::Result<()> {
+        let mut header = Vec::new();
+        put_u32(&mut header, LOCAL_FILE_HEADER_SIGNATURE);
+        put_u16(&mut header, VERSION);
+        put_u16(&mut header, entry.flags);
+        put_u16(&mut header, METHOD_STORED);
+        put_u16(&mut header, DOS_TIME);
+        put_u16(&mut header, DOS_DATE);
+        put_u32(&mut header, entry.crc);
+        put_u32(&mut header, entry.size);
+        put_u32(&mut header, entry.size);
+        put_u16(&mut header, entry.name.len() as u16);
+        put_u16(&mut header, 0); // extra field length
+        header.extend(entry.name.as_bytes());
+        self.write_raw(&header).await
+    }
+
+    async fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data).await?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+// Writes the contents of the file started with start_file
+impl<W> AsyncWrite for ZipWriter<W> where W: AsyncWrite + Unpin {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(file) = this.current.as_mut() else {
+            return Poll::Ready(Err(io::Error::other("no file was started")));
+        };
+        let written = ready!(Pin::new(&mut this.writer).poll_write(cx, buf))?;
+        file.digest.update(&buf[..written]);
+        file.size += written as u64;
+        this.offset += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    // the archive isn't done until finish, so this only flushes
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Reads back the (name, contents) of each file in an archive written by
+    /// [ZipWriter], by way of its central directory like a real unzip would
+    pub fn read_zip(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = data.len() - 22;
+        assert_eq!(u32_at(data, end), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        let count = u16_at(data, end + 10) as usize;
+        let mut offset = u32_at(data, end + 16) as usize;
+        let mut files = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(data, offset), CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+            let flags = u16_at(data, offset + 8);
+            let crc = u32_at(data, offset + 16);
+            let size = u32_at(data, offset + 24) as usize;
+            let name_len = u16_at(data, offset + 28) as usize;
+            let local_offset = u32_at(data, offset + 42) as usize;
+            let name = String::from_utf8(data[offset + 46..offset + 46 + name_len].to_vec()).unwrap();
+
+            assert_eq!(u32_at(data, local_offset), LOCAL_FILE_HEADER_SIGNATURE);
+            assert_eq!(u16_at(data, local_offset + 6), flags);
+            let contents_start = local_offset + 30 + u16_at(data, local_offset + 26) as usize;
+            let contents = data[contents_start..contents_start + size].to_vec();
+            assert_eq!(CRC_32.checksum(&contents), crc);
+            if flags & FLAG_DATA_DESCRIPTOR != 0 {
+                let descriptor = contents_start + size;
+                assert_eq!(u32_at(data, descriptor), DATA_DESCRIPTOR_SIGNATURE);
+                assert_eq!(u32_at(data, descriptor + 4), crc);
+                assert_eq!(u32_at(data, descriptor + 8) as usize, size);
+            }
+            files.push((name, contents));
+            offset += 46 + name_len;
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_zip_round_trip() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add_file("a.txt", b"hello").await.unwrap();
+        zip.start_file("dir/b.bin").await.unwrap();
+        zip.write_all(&[0, 1]).await.unwrap();
+        zip.write_all(&[2, 255]).await.unwrap();
+        zip.finish_file().await.unwrap();
+        zip.add_file("empty", b"").await.unwrap();
+        let data = zip.finish().await.unwrap();
+        assert_eq!(read_zip(&data), [
+            ("a.txt".to_string(), b"hello".to_vec()),
+            ("dir/b.bin".to_string(), vec![0, 1, 2, 255]),
+            ("empty".to_string(), Vec::new()),
+        ]);
+        // the well-known CRC-32 of "hello"
+        assert_eq!(u32_at(&data, 14), 0x3610a686);
+    }
+
+    #[tokio::test]
+    async fn test_write_without_started_file() {
+        let mut zip = ZipWriter::new(Vec::new());
+        assert!(zip.write_all(b"stray").await.is_err());
+    }
+}
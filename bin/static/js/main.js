@@ -29,6 +29,10 @@ function updateQmdlManifestTable(manifest) {
 
 function createEntryRow(entry) {
     const row = document.createElement('tr');
+    if (entry.protected) {
+        row.classList.add('protected');
+        row.title = 'Protected: the QMDL file is kept';
+    }
     const name = document.createElement('th');
     name.scope = 'row';
     name.innerText = entry.name;
@@ -38,21 +42,35 @@ function createEntryRow(entry) {
         td.innerText = entry[key];
         row.appendChild(td);
     }
-    const pcap_td = document.createElement('td');
-    const pcap_link = document.createElement('a');
-    pcap_link.href = `/api/pcap/${entry.name}`;
-    pcap_link.innerText = 'pcap';
-    pcap_td.appendChild(pcap_link);
-    row.appendChild(pcap_td);
-    const qmdl_td = document.createElement('td');
-    const qmdl_link = document.createElement('a');
-    qmdl_link.href = `/api/qmdl/${entry.name}`;
-    qmdl_link.innerText = 'qmdl';
-    qmdl_td.appendChild(qmdl_link);
-    row.appendChild(qmdl_td);
+    row.appendChild(createDownloadCell(entry, 'pcap'));
+    row.appendChild(createDownloadCell(entry, 'qmdl'));
+    // the evidence bundle still has the analysis when the QMDL's gone
+    const evidence = document.createElement('td');
+    const link = document.createElement('a');
+    link.href = `/api/evidence/${entry.name}`;
+    link.innerText = 'zip';
+    evidence.appendChild(link);
+    row.appendChild(evidence);
     return row;
 }
 
+// recordings whose QMDL was discarded after analysis have nothing left to
+// download
+function createDownloadCell(entry, kind) {
+    const td = document.createElement('td');
+    if (entry.analysis_only) {
+        td.innerText = 'discarded';
+        td.classList.add('discarded');
+        td.title = 'The QMDL file was deleted after analysis';
+        return td;
+    }
+    const link = document.createElement('a');
+    link.href = `/api/${kind}/${entry.name}`;
+    link.innerText = kind;
+    td.appendChild(link);
+    return td;
+}
+
 async function getAnalysisReport() {
     const rows = await req('GET', '/api/analysis-report');
     return rows.split('\n')
@@ -64,10 +82,16 @@ async function getSystemStats() {
     return JSON.parse(await req('GET', '/api/system-stats'));
 }
 
+// the first message's timestamp comes from the modem, so it's right even when
+// the device's clock isn't
+function entryStartTime(entry) {
+    return new Date(entry.first_message_time ?? entry.start_time);
+}
+
 async function getQmdlManifest() {
     const manifest = JSON.parse(await req('GET', '/api/qmdl-manifest'));
     if (manifest.current_entry) {
-        manifest.current_entry.start_time = new Date(manifest.current_entry.start_time);
+        manifest.current_entry.start_time = entryStartTime(manifest.current_entry);
         if (manifest.current_entry.last_message_time === undefined) {
             manifest.current_entry.last_message_time = "N/A";
         } else {
@@ -75,7 +99,7 @@ async function getQmdlManifest() {
         }
     }
     for (entry of manifest.entries) {
-        entry.start_time = new Date(entry.start_time);
+        entry.start_time = entryStartTime(entry);
         entry.last_message_time = new Date(entry.last_message_time);
     }
     // sort them in reverse chronological order
@@ -94,13 +118,45 @@ async function stopRecording() {
 }
 
 async function req(method, url) {
-    const response = await fetch(url, {
+    let response = await fetch(url, {
         method: method,
+        headers: authHeaders(),
     });
+    if (response.status === 401) {
+        // the daemon has an api_token configured, ask for it and try again
+        const token = prompt("This rayhunter requires an API token:");
+        if (token) {
+            localStorage.setItem("apiToken", token);
+            response = await fetch(url, {
+                method: method,
+                headers: authHeaders(),
+            });
+        }
+    }
     const body = await response.text();
     if (response.status >= 200 && response.status < 300) {
         return body;
-    } else {
-        throw new Error(body);
     }
+    // API errors are JSON of the form {"code": ..., "message": ...}
+    let message = body;
+    try {
+        message = JSON.parse(body).message || body;
+    } catch (e) {}
+    throw new Error(message);
+}
+
+// the QR code on the device's screen carries the API token as #token=...,
+// save it like a prompted one and take it out of the address bar
+function saveTokenFromUrl() {
+    const params = new URLSearchParams(window.location.hash.slice(1));
+    const token = params.get("token");
+    if (token) {
+        localStorage.setItem("apiToken", token);
+        history.replaceState(null, "", window.location.pathname + window.location.search);
+    }
+}
+
+function authHeaders() {
+    const token = localStorage.getItem("apiToken");
+    return token ? { "Authorization": `Bearer ${token}` } : {};
 }
@@ -59,3 +59,181 @@ setup_rayhunter() {
     adb shell '/bin/rootshell -c "chmod 755 /etc/init.d/misc-daemon"'
     adb shell '/bin/rootshell -c "/etc/init.d/rayhunter_daemon start"'
 }
+
+# Looks for a supported device over USB, then either says how to install onto
+# it or, given --yes, installs right away
+autodetect() {
+    if [[ -z "${SERIAL_PATH}" ]]; then
+        echo "SERIAL_PATH not set, did you run this from install-linux.sh or install-mac.sh?"
+        exit 1
+    fi
+    if ! "${SERIAL_PATH}" --detect; then
+        echo "make sure the device is plugged in and turned on, and if it is, file a bug with the output of lsusb attached"
+        exit 1
+    fi
+    if [[ "$1" == "--yes" ]]; then
+        install
+    else
+        echo "run $0 without arguments to install rayhunter onto it, or $0 autodetect --yes to do it now"
+    fi
+}
+
+# Replaces the daemon binary of an existing install and restarts it, leaving
+# the config and recordings alone
+update() {
+    check_adb
+    if [[ "$(adb shell "test -x /data/rayhunter/rayhunter-daemon && echo PASS" 2> /dev/null)" != *PASS* ]]; then
+        echo "rayhunter doesn't seem to be installed, run this script without arguments to install it"
+        exit 1
+    fi
+    _adb_push rayhunter-daemon /tmp/rayhunter-daemon
+    adb shell '/bin/rootshell -c "/etc/init.d/rayhunter_daemon stop"'
+    adb shell '/bin/rootshell -c "mv /tmp/rayhunter-daemon /data/rayhunter/rayhunter-daemon"'
+    adb shell '/bin/rootshell -c "chmod 755 /data/rayhunter/rayhunter-daemon"'
+    adb shell '/bin/rootshell -c "/etc/init.d/rayhunter_daemon start"'
+    _wait_for_version
+}
+
+_wait_for_version() {
+    local port="${RAYHUNTER_PORT:-8080}"
+    if ! command -v curl &> /dev/null; then
+        echo "curl not found, run this script with selftest to check the daemon came back up"
+        return
+    fi
+    adb forward "tcp:${port}" "tcp:${port}" > /dev/null
+    echo -n "waiting for the new daemon to start"
+    for _ in $(seq 30); do
+        if version="$(_api "http://localhost:${port}/api/version")"; then
+            echo
+            echo "updated! now running ${version}"
+            return
+        fi
+        echo -n .
+        sleep 1
+    done
+    echo
+    echo "the daemon didn't come back up, see /data/rayhunter/rayhunter.log on the device for details"
+    exit 1
+}
+
+selftest() {
+    check_adb
+    SELFTEST_FAILED=0
+    _selftest_check "device is reachable over adb" "true"
+    _selftest_check "rootshell is installed" "test -u /bin/rootshell"
+    _selftest_check "/dev/diag exists" "test -c /dev/diag"
+    _selftest_check "/dev/diag can be opened" "/bin/rootshell -c 'exec 3<> /dev/diag'"
+    _selftest_check "rayhunter is installed" "test -x /data/rayhunter/rayhunter-daemon"
+    _selftest_check "rayhunter is running" "test -f /tmp/rayhunter.pid && kill -0 \$(cat /tmp/rayhunter.pid)"
+    _selftest_port
+    if [[ "${SELFTEST_FAILED}" != 0 ]]; then
+        echo "some checks failed, see /data/rayhunter/rayhunter.log on the device for details"
+        exit 1
+    fi
+    echo "all checks passed!"
+}
+
+# adb shell doesn't reliably forward exit codes, so we echo a marker on success
+_selftest_check() {
+    if [[ "$(adb shell "$2 && echo PASS" 2> /dev/null)" == *PASS* ]]; then
+        echo "[PASS] $1"
+    else
+        echo "[FAIL] $1"
+        SELFTEST_FAILED=1
+    fi
+}
+
+_selftest_port() {
+    local port="${RAYHUNTER_PORT:-8080}"
+    if ! command -v curl &> /dev/null; then
+        echo "[SKIP] web UI is reachable on port ${port} (curl not found)"
+        return
+    fi
+    if adb forward "tcp:${port}" "tcp:${port}" > /dev/null && curl -sf "http://localhost:${port}/index.html" > /dev/null; then
+        echo "[PASS] web UI is reachable on port ${port}"
+    else
+        echo "[FAIL] web UI is reachable on port ${port}"
+        SELFTEST_FAILED=1
+    fi
+}
+
+# Records for a while through the API, then reports what was captured, to
+# check everything from the modem to the analyzers works on this device
+test_capture() {
+    local secs=30
+    if [[ "$1" == "--secs" ]]; then
+        secs="$2"
+    fi
+    if ! [[ "${secs}" =~ ^[1-9][0-9]*$ ]]; then
+        echo "--secs takes a whole number of seconds greater than 0"
+        exit 1
+    fi
+    check_adb
+    if ! command -v curl &> /dev/null; then
+        echo "curl not found, please install it to run a test capture"
+        exit 1
+    fi
+    local port="${RAYHUNTER_PORT:-8080}"
+    local api="http://localhost:${port}/api"
+    adb forward "tcp:${port}" "tcp:${port}" > /dev/null
+    if ! _api -X POST "${api}/start-recording" > /dev/null; then
+        echo "couldn't start recording, run this script with selftest to check the daemon's running"
+        exit 1
+    fi
+    local name
+    name="$(_api "${api}/qmdl-manifest" | _manifest_current_name)"
+    echo -n "recording ${name} for ${secs} seconds"
+    for _ in $(seq "${secs}"); do
+        echo -n .
+        sleep 1
+    done
+    echo
+    if ! _api -X POST "${api}/stop-recording" > /dev/null; then
+        echo "couldn't stop recording, stop it from the web UI"
+        exit 1
+    fi
+
+    local qmdl_bytes analysis rows warnings
+    qmdl_bytes="$(_api "${api}/qmdl-manifest" | _manifest_qmdl_bytes "${name}" || true)"
+    analysis="$(_api "${api}/analysis-ndjson/${name}" || true)"
+    # every line after the first (the analyzers' metadata) is a batch of
+    # analyzed messages
+    rows="$(echo "${analysis}" | grep -c '"analysis":' || true)"
+    warnings="$(echo "${analysis}" | grep -o '"type":"QualitativeWarning"' | wc -l | tr -d ' ')"
+    echo "captured ${qmdl_bytes:-0} bytes of QMDL, in ${rows} analyzed batches with ${warnings} warnings"
+    if [[ -z "${name}" || "${qmdl_bytes:-0}" == 0 ]]; then
+        echo "[FAIL] no messages were captured, the modem's diag logging isn't working. Check /data/rayhunter/rayhunter.log on the device for errors"
+        exit 1
+    fi
+    echo "[PASS] rayhunter is capturing! the recording is kept as ${name}"
+}
+
+# Prints the name of the recording in progress from the manifest JSON on
+# stdin. jq's used if it's installed, since the sed fallback relies on the
+# daemon's field order.
+_manifest_current_name() {
+    if command -v jq &> /dev/null; then
+        jq -r '.current_entry.name // empty'
+    else
+        sed -n 's/.*"current_entry":{"name":"\([^"]*\)".*/\1/p'
+    fi
+}
+
+# Prints the QMDL size of the named recording from the manifest JSON on stdin
+_manifest_qmdl_bytes() {
+    if command -v jq &> /dev/null; then
+        jq -r --arg name "$1" '.entries[] | select(.name == $name) | .qmdl_size_bytes'
+    else
+        grep -o "{\"name\":\"$1\"[^}]*" | sed -n 's/.*"qmdl_size_bytes":\([0-9]*\).*/\1/p'
+    fi
+}
+
+# curl for the daemon's API, with the API token from RAYHUNTER_API_TOKEN if
+# it's set
+_api() {
+    local auth=()
+    if [[ -n "${RAYHUNTER_API_TOKEN}" ]]; then
+        auth=(-H "Authorization: Bearer ${RAYHUNTER_API_TOKEN}")
+    fi
+    curl -sf "${auth[@]}" "$@"
+}
@@ -3,4 +3,10 @@
 set -e
 export SERIAL_PATH="./serial-mac-latest/serial"
 . "$(dirname "$0")"/install-common.sh
-install
+case "$1" in
+    selftest) selftest ;;
+    update) update ;;
+    autodetect) autodetect "$2" ;;
+    test-capture) test_capture "$2" "$3" ;;
+    *) install ;;
+esac
@@ -1,10 +1,120 @@
 use std::borrow::Cow;
 use chrono::{DateTime, FixedOffset};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{diag::MessagesContainer, gsmtap_parser};
 
-use super::{information_element::InformationElement, lte_downgrade::LteSib6And7DowngradeAnalyzer};
+use super::{gprs_mac::GprsMacActivityAnalyzer, imsi_harvest::ImsiHarvestAnalyzer, imsi_paging::ImsiPagingAnalyzer, information_element::InformationElement, lte_downgrade::LteSib6And7DowngradeAnalyzer, null_security::{NasNullSecurityAnalyzer, RrcNullSecurityAnalyzer}, redirect_loop::RedirectLoopAnalyzer, reestablishment_reject::ReestablishmentRejectAnalyzer, tac_change::TrackingAreaChangeAnalyzer};
+
+/// Tunable parameters for the analyzers in a [Harness]. Any fields missing
+/// when deserializing fall back to their defaults, while unknown ones are
+/// rejected.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyzerConfig {
+    /// How many redirects within `redirect_loop_window_secs` it takes for
+    /// [RedirectLoopAnalyzer] to warn
+    pub redirect_loop_count: usize,
+    pub redirect_loop_window_secs: u64,
+    /// How many RRC connection reestablishment rejections within
+    /// `reestablishment_reject_window_secs` it takes for
+    /// [ReestablishmentRejectAnalyzer] to warn
+    pub reestablishment_reject_count: usize,
+    pub reestablishment_reject_window_secs: u64,
+    /// How many times the same barred cell's SIB1 has to be seen within
+    /// `barred_cell_window_secs` for [ReestablishmentRejectAnalyzer] to warn
+    pub barred_cell_count: usize,
+    pub barred_cell_window_secs: u64,
+    /// Which of the built-in analyzers to run
+    pub enabled: EnabledAnalyzers,
+    /// Analyzers from outside this crate, added with
+    /// [AnalyzerConfig::register_analyzer]. These can't come from a config
+    /// file.
+    #[serde(skip)]
+    pub registered_analyzers: Vec<AnalyzerConstructor>,
+}
+
+/// An on/off switch for each built-in analyzer, all on by default. Analyzers
+/// added with [AnalyzerConfig::register_analyzer] always run.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnabledAnalyzers {
+    pub lte_sib6_and_7_downgrade: bool,
+    pub redirect_loop: bool,
+    pub nas_null_security: bool,
+    pub rrc_null_security: bool,
+    pub imsi_harvest: bool,
+    pub tracking_area_change: bool,
+    pub reestablishment_reject: bool,
+    pub gprs_mac_activity: bool,
+    pub imsi_paging: bool,
+}
+
+impl Default for EnabledAnalyzers {
+    fn default() -> Self {
+        EnabledAnalyzers {
+            lte_sib6_and_7_downgrade: true,
+            redirect_loop: true,
+            nas_null_security: true,
+            rrc_null_security: true,
+            imsi_harvest: true,
+            tracking_area_change: true,
+            reestablishment_reject: true,
+            gprs_mac_activity: true,
+            imsi_paging: true,
+        }
+    }
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig {
+            redirect_loop_count: 3,
+            redirect_loop_window_secs: 60,
+            reestablishment_reject_count: 3,
+            reestablishment_reject_window_secs: 60,
+            barred_cell_count: 3,
+            barred_cell_window_secs: 60,
+            enabled: EnabledAnalyzers::default(),
+            registered_analyzers: Vec::new(),
+        }
+    }
+}
+
+impl AnalyzerConfig {
+    /// Adds an [Analyzer] from outside this crate to every [Harness] built
+    /// with this config, after the built-in ones. This lets downstream crates
+    /// add their own heuristics without forking rayhunter. Since events are
+    /// matched up with analyzers by their position, build every harness whose
+    /// results are compared from the same config.
+    pub fn register_analyzer(&mut self, constructor: AnalyzerConstructor) {
+        self.registered_analyzers.push(constructor);
+    }
+
+    /// Checks that the settings make sense, returning a description of the
+    /// first problem found
+    pub fn validate(&self) -> Result<(), String> {
+        if self.redirect_loop_count < 2 {
+            return Err("redirect_loop_count must be at least 2".to_string());
+        }
+        if self.redirect_loop_window_secs == 0 {
+            return Err("redirect_loop_window_secs must be at least 1".to_string());
+        }
+        if self.reestablishment_reject_count < 2 {
+            return Err("reestablishment_reject_count must be at least 2".to_string());
+        }
+        if self.reestablishment_reject_window_secs == 0 {
+            return Err("reestablishment_reject_window_secs must be at least 1".to_string());
+        }
+        if self.barred_cell_count < 2 {
+            return Err("barred_cell_count must be at least 2".to_string());
+        }
+        if self.barred_cell_window_secs == 0 {
+            return Err("barred_cell_window_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
 
 /// Qualitative measure of how severe a Warning event type is.
 /// The levels should break down like this:
@@ -56,23 +166,41 @@ pub trait Analyzer {
     /// [Analyzer] updates per message, since it may be run over hundreds or
     /// thousands of them alongside many other [Analyzers](Analyzer).
     fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event>;
+
+    /// Called with the PCI and EARFCN of the cell each LTE RRC message was
+    /// exchanged with, just before that message is analyzed, since the
+    /// [InformationElement] itself doesn't say. Does nothing by default.
+    ///
+    /// This and [Analyzer::set_message_time] are how the harness passes on
+    /// what's known about a message besides its contents, without changing
+    /// the signature of [Analyzer::analyze_information_element]. Analyzers
+    /// that need either keep it in a field for the call that follows.
+    fn set_lte_rrc_cell(&mut self, _pci: u16, _earfcn: u32) {}
+
+    /// Called with the time the device logged each message, just before that
+    /// message is analyzed, for heuristics that look at how often something
+    /// happens. Does nothing by default.
+    fn set_message_time(&mut self, _timestamp: DateTime<FixedOffset>) {}
 }
 
 #[derive(Serialize, Debug)]
 pub struct AnalyzerMetadata {
-    name: String,
-    description: String,
+    pub name: String,
+    pub description: String,
 }
 
 #[derive(Serialize, Debug)]
 pub struct ReportMetadata {
-    analyzers: Vec<AnalyzerMetadata>,
+    pub analyzers: Vec<AnalyzerMetadata>,
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub struct PacketAnalysis {
-    timestamp: DateTime<FixedOffset>,
-    events: Vec<Option<Event>>,
+    pub timestamp: DateTime<FixedOffset>,
+    /// What kind of message triggered the events, e.g. "Identity Request
+    /// (IMSI)", if we know how to describe it
+    pub message_type: Option<String>,
+    pub events: Vec<Option<Event>>,
 }
 
 #[derive(Serialize, Debug)]
@@ -88,6 +216,10 @@ impl AnalysisRow {
     }
 }
 
+/// Builds an [Analyzer] from the harness's config, for registering with
+/// [AnalyzerConfig::register_analyzer]
+pub type AnalyzerConstructor = fn(&AnalyzerConfig) -> Box<dyn Analyzer + Send>;
+
 pub struct Harness {
     analyzers: Vec<Box<dyn Analyzer + Send>>,
 }
@@ -98,8 +230,42 @@ impl Harness {
     }
 
     pub fn new_with_all_analyzers() -> Self {
+        Harness::new_with_config(&AnalyzerConfig::default())
+    }
+
+    pub fn new_with_config(config: &AnalyzerConfig) -> Self {
         let mut harness = Harness::new();
-        harness.add_analyzer(Box::new(LteSib6And7DowngradeAnalyzer{}));
+        let enabled = &config.enabled;
+        if enabled.lte_sib6_and_7_downgrade {
+            harness.add_analyzer(Box::new(LteSib6And7DowngradeAnalyzer{}));
+        }
+        if enabled.redirect_loop {
+            harness.add_analyzer(Box::new(RedirectLoopAnalyzer::new(config)));
+        }
+        if enabled.nas_null_security {
+            harness.add_analyzer(Box::new(NasNullSecurityAnalyzer{}));
+        }
+        if enabled.rrc_null_security {
+            harness.add_analyzer(Box::new(RrcNullSecurityAnalyzer{}));
+        }
+        if enabled.imsi_harvest {
+            harness.add_analyzer(Box::new(ImsiHarvestAnalyzer::new()));
+        }
+        if enabled.tracking_area_change {
+            harness.add_analyzer(Box::new(TrackingAreaChangeAnalyzer::new()));
+        }
+        if enabled.reestablishment_reject {
+            harness.add_analyzer(Box::new(ReestablishmentRejectAnalyzer::new(config)));
+        }
+        if enabled.gprs_mac_activity {
+            harness.add_analyzer(Box::new(GprsMacActivityAnalyzer::new()));
+        }
+        if enabled.imsi_paging {
+            harness.add_analyzer(Box::new(ImsiPagingAnalyzer{}));
+        }
+        for constructor in &config.registered_analyzers {
+            harness.add_analyzer(constructor(config));
+        }
         harness
     }
 
@@ -122,6 +288,10 @@ impl Harness {
                 }
             };
 
+            if let Some((pci, earfcn)) = qmdl_message.lte_rrc_cell() {
+                self.set_lte_rrc_cell(pci, earfcn);
+            }
+
             let gsmtap_message = match gsmtap_parser::parse(qmdl_message) {
                 Ok(msg) => msg,
                 Err(err) => {
@@ -142,10 +312,12 @@ impl Harness {
                 }
             };
 
-            let analysis_result = self.analyze_information_element(&element);
+            let timestamp = timestamp.to_datetime();
+            let analysis_result = self.analyze_information_element(&element, timestamp);
             if analysis_result.iter().any(Option::is_some) {
                 row.analysis.push(PacketAnalysis {
-                    timestamp: timestamp.to_datetime(),
+                    timestamp,
+                    message_type: element.describe(),
                     events: analysis_result,
                 });
             }
@@ -153,9 +325,23 @@ impl Harness {
         row
     }
 
-    fn analyze_information_element(&mut self, ie: &InformationElement) -> Vec<Option<Event>> {
+    /// Tells every analyzer which cell the LTE RRC messages that follow are
+    /// from, for callers running messages through
+    /// [Harness::analyze_information_element] themselves
+    pub fn set_lte_rrc_cell(&mut self, pci: u16, earfcn: u32) {
+        for analyzer in self.analyzers.iter_mut() {
+            analyzer.set_lte_rrc_cell(pci, earfcn);
+        }
+    }
+
+    /// Runs a single [InformationElement] through every analyzer, returning
+    /// their results in the same order as [Harness::get_names]
+    pub fn analyze_information_element(&mut self, ie: &InformationElement, timestamp: DateTime<FixedOffset>) -> Vec<Option<Event>> {
         self.analyzers.iter_mut()
-            .map(|analyzer| analyzer.analyze_information_element(ie))
+            .map(|analyzer| {
+                analyzer.set_message_time(timestamp);
+                analyzer.analyze_information_element(ie)
+            })
             .collect()
     }
 
@@ -173,7 +359,7 @@ impl Harness {
 
     pub fn get_metadata(&self) -> ReportMetadata {
         let names = self.get_names();
-        let descriptions = self.get_names();
+        let descriptions = self.get_descriptions();
         let mut analyzers = Vec::new();
         for (name, description) in names.iter().zip(descriptions.iter()) {
             analyzers.push(AnalyzerMetadata {
@@ -187,3 +373,80 @@ impl Harness {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // warns about every message, mentioning a config value so we can tell the
+    // config was passed through
+    struct EverythingAnalyzer {
+        redirect_loop_count: usize,
+    }
+
+    impl Analyzer for EverythingAnalyzer {
+        fn get_name(&self) -> Cow<str> {
+            Cow::from("Everything")
+        }
+
+        fn get_description(&self) -> Cow<str> {
+            Cow::from("Warns about every message")
+        }
+
+        fn analyze_information_element(&mut self, _ie: &InformationElement) -> Option<Event> {
+            Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::Low },
+                message: format!("count is {}", self.redirect_loop_count),
+            })
+        }
+    }
+
+    #[test]
+    fn test_enabled_analyzers() {
+        let has_redirect_loop = |harness: &Harness| harness.get_names().iter()
+            .any(|name| name == "Connection Redirect Loop");
+        assert!(has_redirect_loop(&Harness::new_with_all_analyzers()));
+        let mut config = AnalyzerConfig::default();
+        config.enabled.redirect_loop = false;
+        assert!(!has_redirect_loop(&Harness::new_with_config(&config)));
+
+        // every built-in analyzer has to have a switch, so with all of them
+        // off only registered analyzers are left
+        let mut config = AnalyzerConfig {
+            enabled: EnabledAnalyzers {
+                lte_sib6_and_7_downgrade: false,
+                redirect_loop: false,
+                nas_null_security: false,
+                rrc_null_security: false,
+                imsi_harvest: false,
+                tracking_area_change: false,
+                reestablishment_reject: false,
+                gprs_mac_activity: false,
+                imsi_paging: false,
+            },
+            ..Default::default()
+        };
+        assert!(Harness::new_with_config(&config).get_names().is_empty());
+        config.register_analyzer(|config| Box::new(EverythingAnalyzer { redirect_loop_count: config.redirect_loop_count }));
+        assert_eq!(Harness::new_with_config(&config).get_names(), ["Everything"]);
+    }
+
+    #[test]
+    fn test_registered_analyzer() {
+        let builtin_count = Harness::new_with_all_analyzers().get_names().len();
+        let mut config = AnalyzerConfig { redirect_loop_count: 7, ..Default::default() };
+        config.register_analyzer(|config| Box::new(EverythingAnalyzer { redirect_loop_count: config.redirect_loop_count }));
+
+        let mut harness = Harness::new_with_config(&config);
+        let names = harness.get_names();
+        assert_eq!(names.len(), builtin_count + 1);
+        assert_eq!(names[builtin_count], "Everything");
+
+        let events = harness.analyze_information_element(&InformationElement::GSM, chrono::Local::now().fixed_offset());
+        let event = events[builtin_count].as_ref().expect("registered analyzer didn't run");
+        assert_eq!(event.message, "count is 7");
+
+        // other configs, and so other harnesses, don't get it
+        assert_eq!(Harness::new_with_all_analyzers().get_names().len(), builtin_count);
+    }
+}
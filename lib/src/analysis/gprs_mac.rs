@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Duration, FixedOffset};
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::information_element::InformationElement;
+use crate::gprs_mac::{GprsMacMessage, PACKET_ACCESS_REJECT, PACKET_CELL_CHANGE_ORDER, PACKET_PAGING_REQUEST};
+
+// how long GPRS signalling has to pause before the next message counts as new
+// activity
+const ACTIVITY_GAP_SECS: i64 = 60;
+
+/// Reports GPRS/EDGE packet signalling, so that time spent on a 2.5G network
+/// shows up in the analysis. A phone falling back to GPRS while LTE is
+/// available can be a sign of a downgrade attack, but on its own this is only
+/// informational.
+pub struct GprsMacActivityAnalyzer {
+    last_activity: Option<DateTime<FixedOffset>>,
+    // when the current message was logged
+    message_time: DateTime<FixedOffset>,
+}
+
+impl GprsMacActivityAnalyzer {
+    pub fn new() -> Self {
+        GprsMacActivityAnalyzer {
+            last_activity: None,
+            message_time: chrono::Local::now().fixed_offset(),
+        }
+    }
+}
+
+impl Default for GprsMacActivityAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// messages worth reporting every time, rather than only at the start of a
+// burst of activity
+fn is_notable(msg: &GprsMacMessage) -> bool {
+    !msg.uplink && matches!(msg.message_type, PACKET_PAGING_REQUEST | PACKET_CELL_CHANGE_ORDER | PACKET_ACCESS_REJECT)
+}
+
+impl Analyzer for GprsMacActivityAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("GPRS MAC Activity")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Reports GPRS/EDGE RLC/MAC signalling, such as the packet resource assignments around a GPRS attach or routing area update, plus packet paging, cell change orders and access rejections. These are informational only, to show when the phone was on a 2G packet data network.")
+    }
+
+    fn set_message_time(&mut self, timestamp: DateTime<FixedOffset>) {
+        self.message_time = timestamp;
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let InformationElement::GprsMac(msg) = ie else {
+            return None;
+        };
+        let timestamp = self.message_time;
+        let new_activity = !self.last_activity
+            .is_some_and(|last| timestamp - last < Duration::seconds(ACTIVITY_GAP_SECS));
+        self.last_activity = Some(timestamp);
+        if is_notable(msg) {
+            Some(Event {
+                event_type: EventType::Informational,
+                message: format!("GPRS {}", msg),
+            })
+        } else if new_activity {
+            Some(Event {
+                event_type: EventType::Informational,
+                message: format!("GPRS/EDGE packet signalling started with a {}", msg),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gprs_mac::{PACKET_RESOURCE_REQUEST, PACKET_UPLINK_ASSIGNMENT};
+
+    fn mac(uplink: bool, message_type: u8) -> InformationElement {
+        InformationElement::GprsMac(GprsMacMessage { uplink, message_type })
+    }
+
+    #[test]
+    fn test_gprs_activity() {
+        let mut analyzer = GprsMacActivityAnalyzer::new();
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap();
+        let mut analyze_at = |ie: &InformationElement, secs| {
+            analyzer.set_message_time(start + Duration::seconds(secs));
+            analyzer.analyze_information_element(ie)
+        };
+
+        let event = analyze_at(&mac(true, PACKET_RESOURCE_REQUEST), 0).unwrap();
+        assert!(matches!(event.event_type, EventType::Informational));
+        assert_eq!(event.message, "GPRS/EDGE packet signalling started with a Packet Resource Request");
+        // the rest of the burst is quiet...
+        assert!(analyze_at(&mac(false, PACKET_UPLINK_ASSIGNMENT), 1).is_none());
+        assert!(analyze_at(&mac(false, PACKET_UPLINK_ASSIGNMENT), 50).is_none());
+        // ...except for notable messages
+        let event = analyze_at(&mac(false, PACKET_PAGING_REQUEST), 55).unwrap();
+        assert_eq!(event.message, "GPRS Packet Paging Request");
+        // and a pause starts a new burst
+        assert!(analyze_at(&mac(false, PACKET_UPLINK_ASSIGNMENT), 200).is_some());
+
+        // other messages are ignored
+        assert!(analyze_at(&InformationElement::GSM, 500).is_none());
+    }
+}
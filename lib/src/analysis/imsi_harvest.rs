@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, FixedOffset};
+
+use super::analyzer::{Analyzer, Event, EventType, Severity};
+use super::information_element::InformationElement;
+use crate::nas::{MobileIdentityType, NasMessageType, ATTACH_REQUEST, DETACH_REQUEST, IDENTITY_REQUEST, IDENTITY_RESPONSE, SECURITY_MODE_COMMAND};
+
+const TRACKING_AREA_UPDATE_REQUEST: u8 = 0x48;
+
+/// Detects an Identity Request for the IMSI being answered with the IMSI
+/// before a Security Mode Command has been seen, i.e. before the NAS
+/// connection is protected. A real network occasionally needs the IMSI (e.g.
+/// when it doesn't recognize the UE's GUTI), but an IMSI catcher harvests
+/// IMSIs by asking every phone that attaches to it.
+pub struct ImsiHarvestAnalyzer {
+    // whether a Security Mode Command has been seen since the current EMM
+    // procedure started
+    security_established: bool,
+    // when the network asked for the IMSI, if it's still waiting for it
+    imsi_requested_at: Option<DateTime<FixedOffset>>,
+    // when the current message was logged
+    message_time: DateTime<FixedOffset>,
+}
+
+impl ImsiHarvestAnalyzer {
+    pub fn new() -> Self {
+        ImsiHarvestAnalyzer {
+            security_established: false,
+            imsi_requested_at: None,
+            message_time: chrono::Local::now().fixed_offset(),
+        }
+    }
+}
+
+impl Default for ImsiHarvestAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analyzer for ImsiHarvestAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("Unprotected IMSI Request")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Tests for an Identity Request for the IMSI which the phone answers before NAS security has been set up with a Security Mode Command, exposing its IMSI in cleartext. Networks occasionally need to do this when they don't recognize the phone, e.g. the first time it attaches.")
+    }
+
+    fn set_message_time(&mut self, timestamp: DateTime<FixedOffset>) {
+        self.message_time = timestamp;
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let InformationElement::LteNas(nas_msg) = ie else {
+            return None;
+        };
+        let NasMessageType::Emm(message_type) = nas_msg.message_type else {
+            return None;
+        };
+        match message_type {
+            // each of these start a new procedure without NAS security
+            ATTACH_REQUEST | DETACH_REQUEST | TRACKING_AREA_UPDATE_REQUEST => {
+                self.security_established = false;
+                self.imsi_requested_at = None;
+            },
+            SECURITY_MODE_COMMAND => {
+                self.security_established = true;
+                self.imsi_requested_at = None;
+            },
+            IDENTITY_REQUEST if nas_msg.requested_identity == Some(MobileIdentityType::Imsi) && !self.security_established => {
+                self.imsi_requested_at = Some(self.message_time);
+            },
+            IDENTITY_RESPONSE if nas_msg.provided_identity == Some(MobileIdentityType::Imsi) => {
+                let requested_at = self.imsi_requested_at.take()?;
+                if self.security_established {
+                    return None;
+                }
+                return Some(Event {
+                    event_type: EventType::QualitativeWarning { severity: Severity::High },
+                    message: format!(
+                        "IMSI requested at {} and sent in cleartext at {}, before any Security Mode Command",
+                        requested_at, self.message_time,
+                    ),
+                });
+            },
+            _ => {},
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::nas;
+
+    const IDENTITY_REQUEST_IMSI: [u8; 3] = [0x07, 0x55, 0x01];
+    const IDENTITY_RESPONSE_IMSI: [u8; 11] = [0x07, 0x56, 0x08, 0x09, 0x10, 0x10, 0x10, 0x32, 0x54, 0x76, 0x98];
+    const SECURITY_MODE_COMMAND_EEA2: [u8; 7] = [0x07, 0x5d, 0x22, 0x01, 0x02, 0xe0, 0xe0];
+
+    fn run(analyzer: &mut ImsiHarvestAnalyzer, messages: &[&[u8]]) -> Vec<Event> {
+        let start = chrono::Local::now().fixed_offset();
+        messages.iter()
+            .enumerate()
+            .filter_map(|(i, msg)| {
+                let ie = InformationElement::LteNas(nas::classify(msg).unwrap());
+                analyzer.set_message_time(start + Duration::seconds(i as i64));
+                analyzer.analyze_information_element(&ie)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cleartext_imsi_after_request() {
+        let mut analyzer = ImsiHarvestAnalyzer::new();
+        let events = run(&mut analyzer, &[&IDENTITY_REQUEST_IMSI, &IDENTITY_RESPONSE_IMSI]);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].event_type, EventType::QualitativeWarning { severity: Severity::High }));
+        assert!(events[0].message.contains("cleartext"));
+    }
+
+    #[test]
+    fn test_imsi_after_security_mode_command() {
+        let mut analyzer = ImsiHarvestAnalyzer::new();
+        let events = run(&mut analyzer, &[&SECURITY_MODE_COMMAND_EEA2, &IDENTITY_REQUEST_IMSI, &IDENTITY_RESPONSE_IMSI]);
+        assert!(events.is_empty());
+
+        // an unsolicited identity response isn't a harvest either
+        let mut analyzer = ImsiHarvestAnalyzer::new();
+        assert!(run(&mut analyzer, &[&IDENTITY_RESPONSE_IMSI]).is_empty());
+    }
+}
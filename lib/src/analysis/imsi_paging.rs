@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+
+use telcom_parser::lte_rrc::{PCCH_MessageType, PCCH_MessageType_c1, PagingRecordCn_Domain, PagingUE_Identity};
+
+use super::analyzer::{Analyzer, Event, EventType, Severity};
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// Detects LTE paging records which identify the UE by its IMSI rather than
+/// its S-TMSI. Networks only fall back to this in rare error recovery, e.g.
+/// after an MME loses its context for the UE, while an IMSI catcher can page
+/// by IMSI to find out whether a particular subscriber is nearby.
+///
+/// The modem only logs the paging messages sent on its own paging occasions,
+/// but other UEs can share those, so a record isn't necessarily for the
+/// monitored device.
+pub struct ImsiPagingAnalyzer {}
+
+impl Analyzer for ImsiPagingAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("IMSI Paging")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Tests for LTE paging messages which page a phone by its IMSI instead of its temporary S-TMSI, exposing the permanent identity in cleartext. Real networks very rarely do this, only to recover after losing track of a phone.")
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let InformationElement::LTE(LteInformationElement::PCCH(pcch_message)) = ie else {
+            return None;
+        };
+        let PCCH_MessageType::C1(PCCH_MessageType_c1::Paging(paging)) = &pcch_message.message else {
+            return None;
+        };
+        let records = &paging.paging_record_list.as_ref()?.0;
+        let mut domains: Vec<&str> = records.iter()
+            .filter(|record| matches!(record.ue_identity, PagingUE_Identity::Imsi(_)))
+            .map(|record| match record.cn_domain.0 {
+                PagingRecordCn_Domain::CS => "CS",
+                _ => "PS",
+            })
+            .collect();
+        let imsi_records = domains.len();
+        if imsi_records == 0 {
+            return None;
+        }
+        domains.sort();
+        domains.dedup();
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity: Severity::High },
+            message: format!(
+                "Paging by IMSI instead of S-TMSI ({} of {} paging records, {} domain)",
+                imsi_records,
+                records.len(),
+                domains.join("/"),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telcom_parser::decode;
+
+    // a Paging with an S-TMSI record for MMEC 0x12 and M-TMSI 0x34567890, then
+    // an IMSI record for 001010123456789, both for the PS domain
+    const PAGING_S_TMSI_AND_IMSI: [u8; 16] = [
+        0x40, 0x81, 0x23, 0x45, 0x67, 0x89, 0x01, 0x90,
+        0x01, 0x01, 0x01, 0x23, 0x45, 0x67, 0x89, 0x00,
+    ];
+    // just the S-TMSI record
+    const PAGING_S_TMSI: [u8; 7] = [0x40, 0x01, 0x23, 0x45, 0x67, 0x89, 0x00];
+
+    fn pcch(data: &[u8]) -> InformationElement {
+        InformationElement::LTE(LteInformationElement::PCCH(decode(data).unwrap()))
+    }
+
+    #[test]
+    fn test_imsi_paging() {
+        let mut analyzer = ImsiPagingAnalyzer {};
+        let event = analyzer.analyze_information_element(&pcch(&PAGING_S_TMSI_AND_IMSI)).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::High }));
+        assert_eq!(event.message, "Paging by IMSI instead of S-TMSI (1 of 2 paging records, PS domain)");
+
+        assert!(analyzer.analyze_information_element(&pcch(&PAGING_S_TMSI)).is_none());
+    }
+}
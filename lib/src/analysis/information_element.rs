@@ -5,7 +5,9 @@
 
 use telcom_parser::{decode, lte_rrc};
 use thiserror::Error;
-use crate::gsmtap::{GsmtapType, LteRrcSubtype, GsmtapMessage};
+use crate::gprs_mac::GprsMacMessage;
+use crate::gsmtap::{GsmtapType, LteRrcSubtype, GsmtapMessage, UmSubtype, GSMTAP_ARFCN_F_UPLINK};
+use crate::nas::{self, NasMessage};
 
 #[derive(Error, Debug)]
 pub enum InformationElementError {
@@ -13,16 +15,36 @@ pub enum InformationElementError {
     DecodingError(#[from] telcom_parser::ParsingError),
     #[error("Unsupported LTE RRC subtype {0:?}")]
     UnsupportedGsmtapType(GsmtapType),
+    #[error("Unrecognized LTE NAS message")]
+    UnrecognizedNasMessage,
+    #[error("GPRS MAC block isn't a control message")]
+    NotGprsMacControlMessage,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum InformationElement {
     GSM,
+    GprsMac(GprsMacMessage),
+    // a UMTS RRC message, which isn't decoded (see below), so analyzers can
+    // tell one was seen but not what it says
     UMTS,
     LTE(LteInformationElement),
+    LteNas(NasMessage),
     FiveG,
 }
 
+impl InformationElement {
+    /// A short, human-readable description of the message, for the types of
+    /// message we know how to describe
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            InformationElement::LteNas(nas_msg) => Some(nas_msg.to_string()),
+            InformationElement::GprsMac(mac_msg) => Some(mac_msg.to_string()),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum LteInformationElement {
     DlCcch(lte_rrc::DL_CCCH_Message),
@@ -79,6 +101,20 @@ impl TryFrom<&GsmtapMessage> for InformationElement {
                 };
                 Ok(InformationElement::LTE(lte))
             },
+            GsmtapType::Um(UmSubtype::Pacch) => {
+                let uplink = gsmtap_msg.header.arfcn & GSMTAP_ARFCN_F_UPLINK != 0;
+                GprsMacMessage::parse(uplink, &gsmtap_msg.payload)
+                    .map(InformationElement::GprsMac)
+                    .ok_or(InformationElementError::NotGprsMacControlMessage)
+            },
+            // FIXME: telcom-parser doesn't yet include the UMTS RRC (TS 25.331)
+            // ASN.1 spec, so we can recognize these messages but not decode
+            // them. Until it does, no analyzer looks at 3G, e.g. the IMSI
+            // request and null cipher checks only cover LTE.
+            GsmtapType::UmtsRrc(_) => Ok(InformationElement::UMTS),
+            GsmtapType::LteNas(_) => nas::classify(&gsmtap_msg.payload)
+                .map(InformationElement::LteNas)
+                .ok_or(InformationElementError::UnrecognizedNasMessage),
             _ => Err(InformationElementError::UnsupportedGsmtapType(gsmtap_msg.header.gsmtap_type)),
         }
     }
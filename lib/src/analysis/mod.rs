@@ -1,3 +1,10 @@
 pub mod analyzer;
+pub mod gprs_mac;
+pub mod imsi_harvest;
+pub mod imsi_paging;
 pub mod information_element;
 pub mod lte_downgrade;
+pub mod null_security;
+pub mod redirect_loop;
+pub mod reestablishment_reject;
+pub mod tac_change;
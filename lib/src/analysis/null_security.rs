@@ -0,0 +1,230 @@
+use std::borrow::Cow;
+
+use telcom_parser::lte_rrc::{CipheringAlgorithm_r12, DL_DCCH_MessageType, DL_DCCH_MessageType_c1, RRCConnectionReconfigurationCriticalExtensions, RRCConnectionReconfigurationCriticalExtensions_c1, SecurityAlgorithmConfig, SecurityAlgorithmConfigIntegrityProtAlgorithm, SecurityConfigHOHandoverType, SecurityModeCommandCriticalExtensions, SecurityModeCommandCriticalExtensions_c1};
+
+use super::analyzer::{Analyzer, Event, EventType, Severity};
+use super::information_element::{InformationElement, LteInformationElement};
+use crate::nas::{NasMessageType, SECURITY_MODE_COMMAND};
+
+/// Detects a NAS Security Mode Command selecting the null ciphering (EEA0) or
+/// null integrity (EIA0) algorithm. Some networks legitimately run without
+/// ciphering, but a real network should never turn off integrity protection
+/// outside of emergency calls, since it lets anyone inject NAS messages.
+pub struct NasNullSecurityAnalyzer {
+}
+
+impl Analyzer for NasNullSecurityAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("NAS Null Security")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Tests for NAS Security Mode Commands which select null integrity protection (EIA0) or null ciphering (EEA0). Null ciphering alone is a medium severity warning, since some networks use it legitimately, while null integrity is high severity. Emergency calls without a SIM may also trigger this.")
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let InformationElement::LteNas(nas_msg) = ie else {
+            return None;
+        };
+        if nas_msg.message_type != NasMessageType::Emm(SECURITY_MODE_COMMAND) {
+            return None;
+        }
+        let algorithms = nas_msg.selected_algorithms?;
+        match (algorithms.ciphering, algorithms.integrity) {
+            (0, 0) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::High },
+                message: "Security Mode Command selected null integrity protection (EIA0) and null ciphering (EEA0)".to_string(),
+            }),
+            (_, 0) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::High },
+                message: format!("Security Mode Command selected null integrity protection (EIA0) with ciphering EEA{}", algorithms.ciphering),
+            }),
+            (0, _) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::Medium },
+                message: format!("Security Mode Command selected null ciphering (EEA0) with integrity EIA{}", algorithms.integrity),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Detects RRC messages setting up access stratum security with the null
+/// ciphering (EEA0) or null integrity (EIA0) algorithm: the RRC Security Mode
+/// Command, or the security config of a handover in an RRC Connection
+/// Reconfiguration. Unlike the NAS case, this is the ciphering of the radio
+/// bearers themselves, so EEA0 here leaves the user's traffic (and RRC
+/// signalling) readable over the air.
+pub struct RrcNullSecurityAnalyzer {
+}
+
+impl RrcNullSecurityAnalyzer {
+    // The algorithms an RRC message configures, and which message it was
+    fn unpack_security_algorithm_config(ie: &InformationElement) -> Option<(&SecurityAlgorithmConfig, &'static str)> {
+        let InformationElement::LTE(LteInformationElement::DlDcch(dl_dcch_message)) = ie else {
+            return None;
+        };
+        let DL_DCCH_MessageType::C1(c1) = &dl_dcch_message.message else {
+            return None;
+        };
+        match c1 {
+            DL_DCCH_MessageType_c1::SecurityModeCommand(smc) => {
+                let SecurityModeCommandCriticalExtensions::C1(SecurityModeCommandCriticalExtensions_c1::SecurityModeCommand_r8(smc)) = &smc.critical_extensions else {
+                    return None;
+                };
+                Some((&smc.security_config_smc.security_algorithm_config, "RRC Security Mode Command"))
+            },
+            DL_DCCH_MessageType_c1::RrcConnectionReconfiguration(reconfiguration) => {
+                let RRCConnectionReconfigurationCriticalExtensions::C1(RRCConnectionReconfigurationCriticalExtensions_c1::RrcConnectionReconfiguration_r8(reconfiguration)) = &reconfiguration.critical_extensions else {
+                    return None;
+                };
+                // intra-LTE handovers only include the algorithms if they change
+                let config = match &reconfiguration.security_config_ho.as_ref()?.handover_type {
+                    SecurityConfigHOHandoverType::IntraLTE(intra_lte) => intra_lte.security_algorithm_config.as_ref()?,
+                    SecurityConfigHOHandoverType::InterRAT(inter_rat) => &inter_rat.security_algorithm_config,
+                };
+                Some((config, "RRC Connection Reconfiguration handover"))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Analyzer for RrcNullSecurityAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("RRC Null Security")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Tests for RRC Security Mode Commands and handovers which select null integrity protection (EIA0) or null ciphering (EEA0) for the radio bearers, which leaves user-plane traffic unencrypted over the air. This is separate from NAS Null Security, which covers the control-plane NAS messages. Null ciphering alone is a medium severity warning, while null integrity is high severity. Emergency calls without a SIM may also trigger this.")
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let (config, source) = Self::unpack_security_algorithm_config(ie)?;
+        let ciphering = config.ciphering_algorithm.0;
+        let integrity = config.integrity_prot_algorithm.0;
+        let null_ciphering = ciphering == CipheringAlgorithm_r12::EEA0;
+        let null_integrity = integrity == SecurityAlgorithmConfigIntegrityProtAlgorithm::EIA0_V920;
+        match (null_ciphering, null_integrity) {
+            (true, true) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::High },
+                message: format!("{} selected null integrity protection (EIA0) and null user-plane ciphering (EEA0)", source),
+            }),
+            (false, true) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::High },
+                message: format!("{} selected null integrity protection (EIA0) with ciphering EEA{}", source, ciphering),
+            }),
+            (true, false) => Some(Event {
+                event_type: EventType::QualitativeWarning { severity: Severity::Medium },
+                message: format!("{} selected null user-plane ciphering (EEA0) with integrity EIA{}", source, integrity),
+            }),
+            (false, false) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nas;
+    use telcom_parser::lte_rrc::{DL_DCCH_Message, NextHopChainingCount, RRCConnectionReconfiguration, RRCConnectionReconfiguration_r8_IEs, RRC_TransactionIdentifier, SecurityConfigHO, SecurityConfigHOHandoverType_intraLTE, SecurityConfigHOHandoverType_intraLTEKeyChangeIndicator, SecurityConfigSMC, SecurityModeCommand, SecurityModeCommand_r8_IEs};
+
+    fn analyze(nas_bytes: &[u8]) -> Option<Event> {
+        let ie = InformationElement::LteNas(nas::classify(nas_bytes).unwrap());
+        NasNullSecurityAnalyzer {}.analyze_information_element(&ie)
+    }
+
+    #[test]
+    fn test_null_integrity() {
+        // Security Mode Command selecting EEA0 and EIA0, NAS KSI 1 and UE
+        // security capabilities EEA0-2/EIA0-2
+        let event = analyze(&[0x07, 0x5d, 0x00, 0x01, 0x02, 0xe0, 0xe0]).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::High }));
+        assert!(event.message.contains("EIA0"));
+
+        // EEA2 with EIA0, integrity protected with the new security context
+        let event = analyze(&[0x37, 0x11, 0x22, 0x33, 0x44, 0x00, 0x07, 0x5d, 0x20, 0x01, 0x02, 0xe0, 0xe0]).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::High }));
+    }
+
+    #[test]
+    fn test_null_ciphering() {
+        let event = analyze(&[0x07, 0x5d, 0x02, 0x01, 0x02, 0xe0, 0xe0]).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Medium }));
+    }
+
+    #[test]
+    fn test_secure_algorithms() {
+        assert!(analyze(&[0x07, 0x5d, 0x22, 0x01, 0x02, 0xe0, 0xe0]).is_none());
+        // other messages are ignored
+        assert!(analyze(&[0x07, 0x55, 0x01]).is_none());
+    }
+
+    fn algorithms(ciphering: u8, integrity: u8) -> SecurityAlgorithmConfig {
+        SecurityAlgorithmConfig {
+            ciphering_algorithm: CipheringAlgorithm_r12(ciphering),
+            integrity_prot_algorithm: SecurityAlgorithmConfigIntegrityProtAlgorithm(integrity),
+        }
+    }
+
+    fn analyze_rrc(message: DL_DCCH_MessageType_c1) -> Option<Event> {
+        let ie = InformationElement::LTE(LteInformationElement::DlDcch(DL_DCCH_Message {
+            message: DL_DCCH_MessageType::C1(message),
+        }));
+        RrcNullSecurityAnalyzer {}.analyze_information_element(&ie)
+    }
+
+    fn security_mode_command(ciphering: u8, integrity: u8) -> DL_DCCH_MessageType_c1 {
+        DL_DCCH_MessageType_c1::SecurityModeCommand(SecurityModeCommand {
+            rrc_transaction_identifier: RRC_TransactionIdentifier(0),
+            critical_extensions: SecurityModeCommandCriticalExtensions::C1(SecurityModeCommandCriticalExtensions_c1::SecurityModeCommand_r8(SecurityModeCommand_r8_IEs {
+                security_config_smc: SecurityConfigSMC { security_algorithm_config: algorithms(ciphering, integrity) },
+                non_critical_extension: None,
+            })),
+        })
+    }
+
+    fn handover(security_algorithm_config: Option<SecurityAlgorithmConfig>) -> DL_DCCH_MessageType_c1 {
+        DL_DCCH_MessageType_c1::RrcConnectionReconfiguration(RRCConnectionReconfiguration {
+            rrc_transaction_identifier: RRC_TransactionIdentifier(1),
+            critical_extensions: RRCConnectionReconfigurationCriticalExtensions::C1(RRCConnectionReconfigurationCriticalExtensions_c1::RrcConnectionReconfiguration_r8(RRCConnectionReconfiguration_r8_IEs {
+                meas_config: None,
+                mobility_control_info: None,
+                dedicated_info_nas_list: None,
+                radio_resource_config_dedicated: None,
+                security_config_ho: Some(SecurityConfigHO {
+                    handover_type: SecurityConfigHOHandoverType::IntraLTE(SecurityConfigHOHandoverType_intraLTE {
+                        security_algorithm_config,
+                        key_change_indicator: SecurityConfigHOHandoverType_intraLTEKeyChangeIndicator(false),
+                        next_hop_chaining_count: NextHopChainingCount(2),
+                    }),
+                }),
+                non_critical_extension: None,
+            })),
+        })
+    }
+
+    #[test]
+    fn test_rrc_null_ciphering() {
+        let event = analyze_rrc(security_mode_command(CipheringAlgorithm_r12::EEA0, SecurityAlgorithmConfigIntegrityProtAlgorithm::EIA2)).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Medium }));
+        assert!(event.message.contains("RRC Security Mode Command selected null user-plane ciphering"));
+
+        let event = analyze_rrc(handover(Some(algorithms(CipheringAlgorithm_r12::EEA0, SecurityAlgorithmConfigIntegrityProtAlgorithm::EIA1)))).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Medium }));
+        assert!(event.message.contains("handover"));
+    }
+
+    #[test]
+    fn test_rrc_null_integrity() {
+        let event = analyze_rrc(security_mode_command(CipheringAlgorithm_r12::EEA2, SecurityAlgorithmConfigIntegrityProtAlgorithm::EIA0_V920)).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::High }));
+        assert!(event.message.contains("EIA0"));
+    }
+
+    #[test]
+    fn test_rrc_secure_algorithms() {
+        assert!(analyze_rrc(security_mode_command(CipheringAlgorithm_r12::EEA2, SecurityAlgorithmConfigIntegrityProtAlgorithm::EIA2)).is_none());
+        // a handover keeping the current algorithms doesn't say what they are
+        assert!(analyze_rrc(handover(None)).is_none());
+    }
+}
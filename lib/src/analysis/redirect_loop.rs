@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, FixedOffset};
+use telcom_parser::lte_rrc::{DL_DCCH_MessageType, DL_DCCH_MessageType_c1, RRCConnectionReleaseCriticalExtensions, RRCConnectionReleaseCriticalExtensions_c1, RedirectedCarrierInfo};
+
+use super::analyzer::{Analyzer, AnalyzerConfig, Event, EventType, Severity};
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// Detects a cell repeatedly releasing the connection and redirecting the UE
+/// elsewhere within a short window, e.g. ping-ponging it between two
+/// frequencies. A single redirect is routine (load balancing, CS fallback),
+/// but an IMSI catcher may bounce a phone around until it lands on a network
+/// it controls.
+pub struct RedirectLoopAnalyzer {
+    max_redirects: usize,
+    window: Duration,
+    recent_redirects: VecDeque<(DateTime<FixedOffset>, String)>,
+    // when the current message was logged
+    message_time: DateTime<FixedOffset>,
+}
+
+impl RedirectLoopAnalyzer {
+    pub fn new(config: &AnalyzerConfig) -> Self {
+        RedirectLoopAnalyzer {
+            // a "loop" of a single redirect would fire on every redirect
+            max_redirects: config.redirect_loop_count.max(2),
+            window: Duration::seconds(config.redirect_loop_window_secs as i64),
+            recent_redirects: VecDeque::new(),
+            message_time: chrono::Local::now().fixed_offset(),
+        }
+    }
+
+    // Returns a description of where an RRC connection release redirected the
+    // UE to, if it's a release with redirection
+    fn unpack_redirect_target(ie: &InformationElement) -> Option<String> {
+        let InformationElement::LTE(LteInformationElement::DlDcch(dl_dcch_message)) = ie else {
+            return None;
+        };
+        let DL_DCCH_MessageType::C1(DL_DCCH_MessageType_c1::RrcConnectionRelease(release)) = &dl_dcch_message.message else {
+            return None;
+        };
+        let RRCConnectionReleaseCriticalExtensions::C1(RRCConnectionReleaseCriticalExtensions_c1::RrcConnectionRelease_r8(release_r8)) = &release.critical_extensions else {
+            return None;
+        };
+        let target = match release_r8.redirected_carrier_info.as_ref()? {
+            RedirectedCarrierInfo::Eutra(arfcn) => format!("LTE EARFCN {}", arfcn.0),
+            RedirectedCarrierInfo::Geran(freqs) => format!("GSM ARFCN {}", freqs.starting_arfcn.0),
+            RedirectedCarrierInfo::Utra_FDD(arfcn) => format!("UMTS FDD UARFCN {}", arfcn.0),
+            RedirectedCarrierInfo::Utra_TDD(arfcn) => format!("UMTS TDD UARFCN {}", arfcn.0),
+            _ => "another RAT".to_string(),
+        };
+        Some(target)
+    }
+}
+
+impl Analyzer for RedirectLoopAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("Connection Redirect Loop")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from(format!(
+            "Tests for {} or more RRC connection releases with redirection within {} seconds, which can indicate a cell bouncing the phone between frequencies or onto 2G/3G. Poor coverage at the edge of a network can occasionally cause this too.",
+            self.max_redirects,
+            self.window.num_seconds(),
+        ))
+    }
+
+    fn set_message_time(&mut self, timestamp: DateTime<FixedOffset>) {
+        self.message_time = timestamp;
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let target = Self::unpack_redirect_target(ie)?;
+        let timestamp = self.message_time;
+        self.recent_redirects.push_back((timestamp, target));
+        while self.recent_redirects.len() > self.max_redirects {
+            self.recent_redirects.pop_front();
+        }
+        while let Some((oldest, _)) = self.recent_redirects.front() {
+            if timestamp - *oldest <= self.window {
+                break;
+            }
+            self.recent_redirects.pop_front();
+        }
+        if self.recent_redirects.len() < self.max_redirects {
+            return None;
+        }
+
+        let targets: Vec<String> = self.recent_redirects.drain(..)
+            .map(|(_, target)| target)
+            .collect();
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity: Severity::High },
+            message: format!(
+                "{} connection redirects within {} seconds (to {})",
+                targets.len(),
+                self.window.num_seconds(),
+                targets.join(", "),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telcom_parser::lte_rrc::{ARFCN_ValueEUTRA, DL_DCCH_Message, RRCConnectionRelease, RRCConnectionRelease_r8_IEs, RRC_TransactionIdentifier, ReleaseCause};
+
+    fn release(redirected_carrier_info: Option<RedirectedCarrierInfo>) -> InformationElement {
+        InformationElement::LTE(LteInformationElement::DlDcch(DL_DCCH_Message {
+            message: DL_DCCH_MessageType::C1(DL_DCCH_MessageType_c1::RrcConnectionRelease(RRCConnectionRelease {
+                rrc_transaction_identifier: RRC_TransactionIdentifier(0),
+                critical_extensions: RRCConnectionReleaseCriticalExtensions::C1(
+                    RRCConnectionReleaseCriticalExtensions_c1::RrcConnectionRelease_r8(RRCConnectionRelease_r8_IEs {
+                        release_cause: ReleaseCause(ReleaseCause::OTHER),
+                        redirected_carrier_info,
+                        idle_mode_mobility_control_info: None,
+                        non_critical_extension: None,
+                    }),
+                ),
+            })),
+        }))
+    }
+
+    fn redirect_to(earfcn: u16) -> InformationElement {
+        release(Some(RedirectedCarrierInfo::Eutra(ARFCN_ValueEUTRA(earfcn))))
+    }
+
+    fn at(secs: i64) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap() + Duration::seconds(secs)
+    }
+
+    fn analyze_at(analyzer: &mut RedirectLoopAnalyzer, ie: &InformationElement, secs: i64) -> Option<Event> {
+        analyzer.set_message_time(at(secs));
+        analyzer.analyze_information_element(ie)
+    }
+
+    fn analyzer() -> RedirectLoopAnalyzer {
+        RedirectLoopAnalyzer::new(&AnalyzerConfig {
+            redirect_loop_count: 3,
+            redirect_loop_window_secs: 30,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_ping_pong_redirects() {
+        let mut analyzer = analyzer();
+        assert!(analyze_at(&mut analyzer, &redirect_to(850), 0).is_none());
+        assert!(analyze_at(&mut analyzer, &redirect_to(5230), 5).is_none());
+        let event = analyze_at(&mut analyzer, &redirect_to(850), 10).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::High }));
+        assert!(event.message.contains("LTE EARFCN 850, LTE EARFCN 5230, LTE EARFCN 850"));
+
+        // the window starts over after a warning
+        assert!(analyze_at(&mut analyzer, &redirect_to(5230), 15).is_none());
+    }
+
+    #[test]
+    fn test_spread_out_redirects() {
+        let mut analyzer = analyzer();
+        for i in 0..10 {
+            assert!(analyze_at(&mut analyzer, &redirect_to(850), i * 20).is_none());
+        }
+    }
+
+    #[test]
+    fn test_releases_without_redirects() {
+        let mut analyzer = analyzer();
+        for i in 0..10 {
+            assert!(analyze_at(&mut analyzer, &release(None), i).is_none());
+        }
+    }
+
+    #[test]
+    fn test_releases_between_redirects() {
+        let mut analyzer = analyzer();
+        // plain releases, e.g. going idle between redirects, neither count
+        // towards a loop nor start it over
+        assert!(analyze_at(&mut analyzer, &release(None), 0).is_none());
+        assert!(analyze_at(&mut analyzer, &redirect_to(850), 2).is_none());
+        assert!(analyze_at(&mut analyzer, &release(None), 4).is_none());
+        assert!(analyze_at(&mut analyzer, &redirect_to(1000), 6).is_none());
+        assert!(analyze_at(&mut analyzer, &release(None), 8).is_none());
+        let event = analyze_at(&mut analyzer, &redirect_to(5230), 10).unwrap();
+        assert_eq!(event.message, "3 connection redirects within 30 seconds (to LTE EARFCN 850, LTE EARFCN 1000, LTE EARFCN 5230)");
+    }
+}
@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, FixedOffset};
+use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1, DL_CCCH_MessageType, DL_CCCH_MessageType_c1, SystemInformationBlockType1CellAccessRelatedInfoCellBarred};
+
+use super::analyzer::{Analyzer, AnalyzerConfig, Event, EventType, Severity};
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// Detects the UE failing to get back onto the network: a burst of RRC
+/// connection reestablishment rejections, or SIB1s from the same cell
+/// repeatedly saying it's barred. Either happens occasionally on a congested
+/// or misbehaving network, but over and over it can mean the phone is being
+/// jammed, or kept camped on a fake cell that won't serve it.
+pub struct ReestablishmentRejectAnalyzer {
+    max_rejects: usize,
+    reject_window: Duration,
+    recent_rejects: VecDeque<DateTime<FixedOffset>>,
+    max_barred: usize,
+    barred_window: Duration,
+    // when each barred SIB1 was seen, and the (PCI, EARFCN) it came from
+    recent_barred: VecDeque<(DateTime<FixedOffset>, (u16, u32))>,
+    // the cell the current message came from, and when it was logged
+    current_cell: Option<(u16, u32)>,
+    message_time: DateTime<FixedOffset>,
+}
+
+impl ReestablishmentRejectAnalyzer {
+    pub fn new(config: &AnalyzerConfig) -> Self {
+        ReestablishmentRejectAnalyzer {
+            max_rejects: config.reestablishment_reject_count.max(2),
+            reject_window: Duration::seconds(config.reestablishment_reject_window_secs as i64),
+            recent_rejects: VecDeque::new(),
+            max_barred: config.barred_cell_count.max(2),
+            barred_window: Duration::seconds(config.barred_cell_window_secs as i64),
+            recent_barred: VecDeque::new(),
+            current_cell: None,
+            message_time: chrono::Local::now().fixed_offset(),
+        }
+    }
+
+    fn is_reestablishment_reject(ie: &InformationElement) -> bool {
+        matches!(ie, InformationElement::LTE(LteInformationElement::DlCcch(dl_ccch_message))
+            if matches!(dl_ccch_message.message, DL_CCCH_MessageType::C1(DL_CCCH_MessageType_c1::RrcConnectionReestablishmentReject(_))))
+    }
+
+    fn is_barred_sib1(ie: &InformationElement) -> bool {
+        let InformationElement::LTE(LteInformationElement::BcchDlSch(bcch_dl_sch_message)) = ie else {
+            return false;
+        };
+        let BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1)) = &bcch_dl_sch_message.message else {
+            return false;
+        };
+        sib1.cell_access_related_info.cell_barred.0 == SystemInformationBlockType1CellAccessRelatedInfoCellBarred::BARRED
+    }
+
+    fn on_reject(&mut self, timestamp: DateTime<FixedOffset>) -> Option<Event> {
+        self.recent_rejects.push_back(timestamp);
+        while let Some(oldest) = self.recent_rejects.front() {
+            if timestamp - *oldest <= self.reject_window {
+                break;
+            }
+            self.recent_rejects.pop_front();
+        }
+        if self.recent_rejects.len() < self.max_rejects {
+            return None;
+        }
+
+        let count = self.recent_rejects.len();
+        self.recent_rejects.clear();
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity: Severity::Medium },
+            message: format!(
+                "{} RRC connection reestablishments rejected within {} seconds",
+                count,
+                self.reject_window.num_seconds(),
+            ),
+        })
+    }
+
+    fn on_barred_sib1(&mut self, timestamp: DateTime<FixedOffset>) -> Option<Event> {
+        let cell = self.current_cell?;
+        self.recent_barred.push_back((timestamp, cell));
+        while let Some((oldest, _)) = self.recent_barred.front() {
+            if timestamp - *oldest <= self.barred_window {
+                break;
+            }
+            self.recent_barred.pop_front();
+        }
+        let count = self.recent_barred.iter()
+            .filter(|(_, barred_cell)| *barred_cell == cell)
+            .count();
+        if count < self.max_barred {
+            return None;
+        }
+
+        self.recent_barred.retain(|(_, barred_cell)| *barred_cell != cell);
+        let (pci, earfcn) = cell;
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity: Severity::Low },
+            message: format!(
+                "Offered barred cell PCI {} EARFCN {} {} times within {} seconds",
+                pci,
+                earfcn,
+                count,
+                self.barred_window.num_seconds(),
+            ),
+        })
+    }
+}
+
+impl Analyzer for ReestablishmentRejectAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("Reestablishment Reject or Barred Cell Loop")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from(format!(
+            "Tests for {} or more RRC connection reestablishment rejections within {} seconds, or the same barred cell's SIB1 being read {} or more times within {} seconds, which can indicate jamming or a fake cell that won't let the phone connect. Congested cells and cells barred for maintenance can occasionally cause this too.",
+            self.max_rejects,
+            self.reject_window.num_seconds(),
+            self.max_barred,
+            self.barred_window.num_seconds(),
+        ))
+    }
+
+    fn set_lte_rrc_cell(&mut self, pci: u16, earfcn: u32) {
+        self.current_cell = Some((pci, earfcn));
+    }
+
+    fn set_message_time(&mut self, timestamp: DateTime<FixedOffset>) {
+        self.message_time = timestamp;
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        if Self::is_reestablishment_reject(ie) {
+            self.on_reject(self.message_time)
+        } else if Self::is_barred_sib1(ie) {
+            self.on_barred_sib1(self.message_time)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telcom_parser::decode;
+    use telcom_parser::lte_rrc::{BCCH_DL_SCH_Message, DL_CCCH_Message, RRCConnectionReestablishmentReject, RRCConnectionReestablishmentRejectCriticalExtensions, RRCConnectionReestablishmentReject_r8_IEs};
+
+    // a SIB1 for MCC 310 MNC 26, TAC 1, which isn't barred
+    const SIB1: [u8; 15] = [0x40, 0x4c, 0x40, 0x4d, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, 0x18, 0xc0, 0x10, 0x50, 0x00];
+
+    fn reject() -> InformationElement {
+        InformationElement::LTE(LteInformationElement::DlCcch(DL_CCCH_Message {
+            message: DL_CCCH_MessageType::C1(DL_CCCH_MessageType_c1::RrcConnectionReestablishmentReject(RRCConnectionReestablishmentReject {
+                critical_extensions: RRCConnectionReestablishmentRejectCriticalExtensions::RrcConnectionReestablishmentReject_r8(
+                    RRCConnectionReestablishmentReject_r8_IEs { non_critical_extension: None },
+                ),
+            })),
+        }))
+    }
+
+    fn sib1(barred: bool) -> InformationElement {
+        let mut message: BCCH_DL_SCH_Message = decode(&SIB1).unwrap();
+        let BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1)) = &mut message.message else {
+            panic!("not a SIB1");
+        };
+        assert_eq!(sib1.cell_access_related_info.cell_barred.0, SystemInformationBlockType1CellAccessRelatedInfoCellBarred::NOT_BARRED);
+        if barred {
+            sib1.cell_access_related_info.cell_barred.0 = SystemInformationBlockType1CellAccessRelatedInfoCellBarred::BARRED;
+        }
+        InformationElement::LTE(LteInformationElement::BcchDlSch(message))
+    }
+
+    fn at(secs: i64) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap() + Duration::seconds(secs)
+    }
+
+    fn analyzer() -> ReestablishmentRejectAnalyzer {
+        ReestablishmentRejectAnalyzer::new(&AnalyzerConfig {
+            reestablishment_reject_count: 3,
+            reestablishment_reject_window_secs: 30,
+            barred_cell_count: 3,
+            barred_cell_window_secs: 60,
+            ..Default::default()
+        })
+    }
+
+    fn analyze_at(analyzer: &mut ReestablishmentRejectAnalyzer, ie: &InformationElement, secs: i64) -> Option<Event> {
+        analyzer.set_message_time(at(secs));
+        analyzer.analyze_information_element(ie)
+    }
+
+    fn analyze_sib1(analyzer: &mut ReestablishmentRejectAnalyzer, pci: u16, barred: bool, secs: i64) -> Option<Event> {
+        analyzer.set_lte_rrc_cell(pci, 5230);
+        analyze_at(analyzer, &sib1(barred), secs)
+    }
+
+    #[test]
+    fn test_reject_rate() {
+        let mut analyzer = analyzer();
+        assert!(analyze_at(&mut analyzer, &reject(), 0).is_none());
+        assert!(analyze_at(&mut analyzer, &reject(), 10).is_none());
+        let event = analyze_at(&mut analyzer, &reject(), 20).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Medium }));
+        assert!(event.message.contains("3 RRC connection reestablishments rejected within 30 seconds"));
+
+        // the window starts over after a warning
+        assert!(analyze_at(&mut analyzer, &reject(), 25).is_none());
+    }
+
+    #[test]
+    fn test_spread_out_rejects() {
+        let mut analyzer = analyzer();
+        for i in 0..10 {
+            assert!(analyze_at(&mut analyzer, &reject(), i * 20).is_none());
+        }
+    }
+
+    #[test]
+    fn test_repeatedly_barred_cell() {
+        let mut analyzer = analyzer();
+        assert!(analyze_sib1(&mut analyzer, 160, true, 0).is_none());
+        // barred SIB1s from other cells don't count towards this one
+        assert!(analyze_sib1(&mut analyzer, 161, true, 5).is_none());
+        assert!(analyze_sib1(&mut analyzer, 160, true, 10).is_none());
+        let event = analyze_sib1(&mut analyzer, 160, true, 20).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Low }));
+        assert!(event.message.contains("PCI 160 EARFCN 5230 3 times"));
+
+        assert!(analyze_sib1(&mut analyzer, 161, true, 25).is_none());
+        assert!(analyze_sib1(&mut analyzer, 160, true, 30).is_none());
+    }
+
+    #[test]
+    fn test_unbarred_and_spread_out_sib1s() {
+        let mut analyzer = analyzer();
+        for i in 0..10 {
+            assert!(analyze_sib1(&mut analyzer, 160, false, i).is_none());
+        }
+        for i in 0..10 {
+            assert!(analyze_sib1(&mut analyzer, 160, true, 100 + i * 40).is_none());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::borrow::Cow;
+
+use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1};
+
+use super::analyzer::{Analyzer, Event, EventType, Severity};
+use super::information_element::{InformationElement, LteInformationElement};
+use crate::cellular_data::bits_to_u32;
+
+/// Detects the tracking area code broadcast in SIB1 changing while the PCI and
+/// EARFCN stay the same. A real cell's TAC is essentially fixed, but a fake
+/// base station imitating it may advertise a new TAC to force phones into a
+/// Tracking Area Update, during which it can ask for their identities.
+pub struct TrackingAreaChangeAnalyzer {
+    // the cell the current message came from
+    current_cell: Option<(u16, u32)>,
+    // the (PCI, EARFCN, TAC) of the last SIB1 seen
+    last_sib1: Option<(u16, u32, u32)>,
+}
+
+impl TrackingAreaChangeAnalyzer {
+    pub fn new() -> Self {
+        TrackingAreaChangeAnalyzer {
+            current_cell: None,
+            last_sib1: None,
+        }
+    }
+}
+
+impl Default for TrackingAreaChangeAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analyzer for TrackingAreaChangeAnalyzer {
+    fn get_name(&self) -> Cow<str> {
+        Cow::from("Tracking Area Change")
+    }
+
+    fn get_description(&self) -> Cow<str> {
+        Cow::from("Tests for a cell's SIB1 advertising a different tracking area code than the last SIB1 seen on the same PCI and EARFCN, which can mean another base station is imitating the cell to force phones to re-register. Operators very occasionally re-plan their tracking areas, which would trigger this once.")
+    }
+
+    fn set_lte_rrc_cell(&mut self, pci: u16, earfcn: u32) {
+        self.current_cell = Some((pci, earfcn));
+    }
+
+    fn analyze_information_element(&mut self, ie: &InformationElement) -> Option<Event> {
+        let InformationElement::LTE(LteInformationElement::BcchDlSch(bcch_dl_sch_message)) = ie else {
+            return None;
+        };
+        let BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1)) = &bcch_dl_sch_message.message else {
+            return None;
+        };
+        let (pci, earfcn) = self.current_cell?;
+        let tac = bits_to_u32(sib1.cell_access_related_info.tracking_area_code.0.iter().by_vals());
+        let (last_pci, last_earfcn, last_tac) = self.last_sib1.replace((pci, earfcn, tac))?;
+        if (last_pci, last_earfcn) != (pci, earfcn) || last_tac == tac {
+            return None;
+        }
+        Some(Event {
+            event_type: EventType::QualitativeWarning { severity: Severity::Medium },
+            message: format!(
+                "TAC changed from {} to {} on PCI {} EARFCN {} without a change of cell",
+                last_tac, tac, pci, earfcn,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telcom_parser::{decode, lte_rrc::BCCH_DL_SCH_Message};
+
+    // SIB1s for MCC 310 MNC 26, cell identity 0x1234567, band 13, differing
+    // only in their TAC
+    const SIB1_TAC_1: [u8; 15] = [0x40, 0x4c, 0x40, 0x4d, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, 0x18, 0xc0, 0x10, 0x50, 0x00];
+    const SIB1_TAC_2: [u8; 15] = [0x40, 0x4c, 0x40, 0x4d, 0x00, 0x02, 0x12, 0x34, 0x56, 0x78, 0x18, 0xc0, 0x10, 0x50, 0x00];
+
+    fn sib1(data: &[u8]) -> InformationElement {
+        let message: BCCH_DL_SCH_Message = decode(data).unwrap();
+        InformationElement::LTE(LteInformationElement::BcchDlSch(message))
+    }
+
+    fn analyze(analyzer: &mut TrackingAreaChangeAnalyzer, pci: u16, earfcn: u32, data: &[u8]) -> Option<Event> {
+        analyzer.set_lte_rrc_cell(pci, earfcn);
+        analyzer.analyze_information_element(&sib1(data))
+    }
+
+    #[test]
+    fn test_tac_change_on_same_cell() {
+        let mut analyzer = TrackingAreaChangeAnalyzer::new();
+        assert!(analyze(&mut analyzer, 160, 5230, &SIB1_TAC_1).is_none());
+        assert!(analyze(&mut analyzer, 160, 5230, &SIB1_TAC_1).is_none());
+        let event = analyze(&mut analyzer, 160, 5230, &SIB1_TAC_2).unwrap();
+        assert!(matches!(event.event_type, EventType::QualitativeWarning { severity: Severity::Medium }));
+        assert!(event.message.contains("from 1 to 2"));
+    }
+
+    #[test]
+    fn test_tac_change_with_cell_change() {
+        let mut analyzer = TrackingAreaChangeAnalyzer::new();
+        assert!(analyze(&mut analyzer, 160, 5230, &SIB1_TAC_1).is_none());
+        assert!(analyze(&mut analyzer, 161, 5230, &SIB1_TAC_2).is_none());
+        assert!(analyze(&mut analyzer, 161, 850, &SIB1_TAC_1).is_none());
+    }
+}
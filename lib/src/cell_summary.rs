@@ -0,0 +1,287 @@
+//! Aggregates the LTE cells seen over the course of a recording, giving a
+//! quick overview of which towers the device heard from and how strongly.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use telcom_parser::lte_rrc::{MeasResultsMeasResultNeighCells, MeasurementReportCriticalExtensions, MeasurementReportCriticalExtensions_c1, RSRP_Range, UL_DCCH_MessageType, UL_DCCH_MessageType_c1};
+
+use crate::analysis::information_element::{InformationElement, LteInformationElement};
+use crate::cellular_data::CellularData;
+use crate::diag::{LogBody, Message};
+use crate::gsmtap_parser;
+use crate::lte_bands::band_for_earfcn;
+
+/// A cell seen at least once in a recording. Cells are identified by their
+/// PCI and EARFCN, though neighbor cells reported in measurement reports have
+/// no EARFCN since the report doesn't say which frequency they were measured
+/// on.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ObservedCell {
+    pub pci: u16,
+    pub earfcn: Option<u32>,
+    pub band: Option<u8>,
+    /// Whether any RRC messages were exchanged with this cell, as opposed to
+    /// it only being reported as a neighbor
+    pub serving: bool,
+    pub first_seen: DateTime<FixedOffset>,
+    pub last_seen: DateTime<FixedOffset>,
+    pub times_seen: usize,
+    pub min_rsrp_dbm: Option<i16>,
+    pub max_rsrp_dbm: Option<i16>,
+    /// System information broadcast by this cell, if it was the serving cell
+    /// while its SIBs went by
+    #[serde(skip_serializing_if = "CellularData::is_empty")]
+    pub sibs: CellularData,
+}
+
+impl ObservedCell {
+    fn new(pci: u16, earfcn: Option<u32>, timestamp: DateTime<FixedOffset>) -> Self {
+        ObservedCell {
+            pci,
+            earfcn,
+            band: earfcn.and_then(band_for_earfcn),
+            serving: false,
+            first_seen: timestamp,
+            last_seen: timestamp,
+            times_seen: 0,
+            min_rsrp_dbm: None,
+            max_rsrp_dbm: None,
+            sibs: CellularData::default(),
+        }
+    }
+
+    fn add_rsrp(&mut self, rsrp: &RSRP_Range) {
+        let dbm = rsrp_to_dbm(rsrp);
+        self.min_rsrp_dbm = Some(self.min_rsrp_dbm.map_or(dbm, |min| min.min(dbm)));
+        self.max_rsrp_dbm = Some(self.max_rsrp_dbm.map_or(dbm, |max| max.max(dbm)));
+    }
+}
+
+// RSRP is reported in 1dB steps starting at -140dBm (TS 36.133 section 9.1.4)
+pub(crate) fn rsrp_to_dbm(rsrp: &RSRP_Range) -> i16 {
+    rsrp.0 as i16 - 140
+}
+
+#[derive(Default, Debug)]
+pub struct CellSummary {
+    cells: HashMap<(u16, Option<u32>), ObservedCell>,
+}
+
+impl CellSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn observe(&mut self, pci: u16, earfcn: Option<u32>, timestamp: DateTime<FixedOffset>) -> &mut ObservedCell {
+        let cell = self.cells.entry((pci, earfcn))
+            .or_insert_with(|| ObservedCell::new(pci, earfcn, timestamp));
+        cell.first_seen = cell.first_seen.min(timestamp);
+        cell.last_seen = cell.last_seen.max(timestamp);
+        cell.times_seen += 1;
+        cell
+    }
+
+    /// Adds any cells referenced by the given message to the summary. Only
+    /// LTE RRC messages are considered.
+    pub fn add_message(&mut self, msg: Message) {
+        let Message::Log { timestamp, body: LogBody::LteRrcOtaMessage { packet, .. }, .. } = &msg else {
+            return;
+        };
+        let timestamp = timestamp.to_datetime();
+        let pci = packet.get_phy_cell_id();
+        let earfcn = Some(packet.get_earfcn());
+        self.observe(pci, earfcn, timestamp).serving = true;
+
+        let Ok(Some((_, gsmtap_msg))) = gsmtap_parser::parse(msg) else {
+            return;
+        };
+        if let Some(cell) = self.cells.get_mut(&(pci, earfcn)) {
+            cell.sibs.update_from_gsmtap(&gsmtap_msg);
+        }
+        let Ok(InformationElement::LTE(LteInformationElement::UlDcch(ul_dcch_message))) = InformationElement::try_from(&gsmtap_msg) else {
+            return;
+        };
+        let UL_DCCH_MessageType::C1(UL_DCCH_MessageType_c1::MeasurementReport(report)) = ul_dcch_message.message else {
+            return;
+        };
+        let MeasurementReportCriticalExtensions::C1(MeasurementReportCriticalExtensions_c1::MeasurementReport_r8(report)) = report.critical_extensions else {
+            return;
+        };
+        let meas_results = report.meas_results;
+        if let Some(cell) = self.cells.get_mut(&(pci, earfcn)) {
+            cell.add_rsrp(&meas_results.meas_result_p_cell.rsrp_result);
+        }
+        if let Some(MeasResultsMeasResultNeighCells::MeasResultListEUTRA(neighbors)) = meas_results.meas_result_neigh_cells {
+            for neighbor in neighbors.0 {
+                let cell = self.observe(neighbor.phys_cell_id.0, None, timestamp);
+                if let Some(rsrp) = &neighbor.meas_result.rsrp_result {
+                    cell.add_rsrp(rsrp);
+                }
+            }
+        }
+    }
+
+    /// Returns every observed cell, ordered by when it was first seen
+    pub fn cells(&self) -> Vec<ObservedCell> {
+        let mut cells: Vec<ObservedCell> = self.cells.values().cloned().collect();
+        cells.sort_by_key(|cell| (cell.first_seen, cell.pci, cell.earfcn));
+        cells
+    }
+}
+
+/// A system information parameter with different values in two recordings
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChangedParameter {
+    pub parameter: &'static str,
+    pub a: String,
+    pub b: String,
+}
+
+/// A cell seen in both recordings whose system information differs
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChangedCell {
+    pub pci: u16,
+    pub earfcn: Option<u32>,
+    pub changes: Vec<ChangedParameter>,
+}
+
+/// The differences between the cells seen in two recordings, "a" and "b"
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CellSummaryDiff {
+    /// Cells seen in b but not a
+    pub added: Vec<ObservedCell>,
+    /// Cells seen in a but not b
+    pub removed: Vec<ObservedCell>,
+    pub changed: Vec<ChangedCell>,
+}
+
+impl CellSummaryDiff {
+    /// Compares two recordings' cells. Only parameters known in both
+    /// recordings are compared, since a SIB missing from one of them usually
+    /// just means it wasn't broadcast while recording.
+    pub fn new(a: &CellSummary, b: &CellSummary) -> Self {
+        let added = b.cells().into_iter()
+            .filter(|cell| !a.cells.contains_key(&(cell.pci, cell.earfcn)))
+            .collect();
+        let mut removed = Vec::new();
+        let mut changed = Vec::new();
+        for a_cell in a.cells() {
+            let Some(b_cell) = b.cells.get(&(a_cell.pci, a_cell.earfcn)) else {
+                removed.push(a_cell);
+                continue;
+            };
+            let b_params = b_cell.sibs.to_scat_format();
+            let changes: Vec<ChangedParameter> = a_cell.sibs.to_scat_format().into_iter()
+                .filter_map(|(parameter, a_value)| {
+                    let (_, b_value) = b_params.iter().find(|(key, _)| *key == parameter)?;
+                    (a_value != *b_value).then(|| ChangedParameter { parameter, a: a_value, b: b_value.clone() })
+                })
+                .collect();
+            if !changes.is_empty() {
+                changed.push(ChangedCell { pci: a_cell.pci, earfcn: a_cell.earfcn, changes });
+            }
+        }
+        CellSummaryDiff { added, removed, changed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diag::{LteRrcOtaPacket, Timestamp};
+
+    // an undecodable BCCH-DL-SCH message, which only tells us the cell's PCI
+    // and EARFCN
+    fn rrc_message(ts: u64, phy_cell_id: u16, earfcn: u32) -> Message {
+        Message::Log {
+            pending_msgs: 0,
+            outer_length: 0,
+            inner_length: 0,
+            log_type: 0xb0c0,
+            timestamp: Timestamp { ts },
+            body: LogBody::LteRrcOtaMessage {
+                ext_header_version: 20,
+                packet: LteRrcOtaPacket::V8 {
+                    rrc_rel_maj: 14,
+                    rrc_rel_min: 48,
+                    bearer_id: 0,
+                    phy_cell_id,
+                    earfcn,
+                    sfn_subfn: 0,
+                    pdu_num: 2,
+                    sib_mask: 0,
+                    len: 1,
+                    packet: vec![0xff],
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_deduplicates_cells() {
+        let mut summary = CellSummary::new();
+        summary.add_message(rrc_message(2 << 16, 160, 2050));
+        summary.add_message(rrc_message(1 << 16, 160, 2050));
+        summary.add_message(rrc_message(3 << 16, 161, 2050));
+        summary.add_message(rrc_message(4 << 16, 160, 5230));
+        summary.add_message(rrc_message(5 << 16, 160, 2050));
+
+        let cells = summary.cells();
+        assert_eq!(cells.len(), 3);
+        assert_eq!((cells[0].pci, cells[0].earfcn), (160, Some(2050)));
+        assert_eq!(cells[0].times_seen, 3);
+        assert_eq!(cells[0].first_seen, Timestamp { ts: 1 << 16 }.to_datetime());
+        assert_eq!(cells[0].last_seen, Timestamp { ts: 5 << 16 }.to_datetime());
+        assert!(cells[0].serving);
+        assert_eq!((cells[1].pci, cells[1].earfcn), (161, Some(2050)));
+        assert_eq!((cells[2].pci, cells[2].earfcn), (160, Some(5230)));
+        assert_eq!(cells[2].band, Some(13));
+    }
+
+    #[test]
+    fn test_rsrp_range() {
+        let mut cell = ObservedCell::new(1, None, Timestamp { ts: 0 }.to_datetime());
+        cell.add_rsrp(&RSRP_Range(50));
+        cell.add_rsrp(&RSRP_Range(0));
+        cell.add_rsrp(&RSRP_Range(97));
+        assert_eq!(cell.min_rsrp_dbm, Some(-140));
+        assert_eq!(cell.max_rsrp_dbm, Some(-43));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut a = CellSummary::new();
+        a.add_message(rrc_message(1 << 16, 160, 2050));
+        a.add_message(rrc_message(1 << 16, 161, 2050));
+        a.add_message(rrc_message(1 << 16, 162, 2050));
+        let mut b = CellSummary::new();
+        b.add_message(rrc_message(2 << 16, 160, 2050));
+        b.add_message(rrc_message(2 << 16, 161, 2050));
+        b.add_message(rrc_message(2 << 16, 300, 5230));
+
+        a.cells.get_mut(&(160, Some(2050))).unwrap().sibs.tracking_area_code = Some(1);
+        b.cells.get_mut(&(160, Some(2050))).unwrap().sibs.tracking_area_code = Some(2);
+        // only known to one of them, so not a change
+        a.cells.get_mut(&(161, Some(2050))).unwrap().sibs.q_hyst_db = Some(4);
+
+        let diff = CellSummaryDiff::new(&a, &b);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!((diff.added[0].pci, diff.added[0].earfcn), (300, Some(5230)));
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!((diff.removed[0].pci, diff.removed[0].earfcn), (162, Some(2050)));
+        assert_eq!(diff.changed, vec![ChangedCell {
+            pci: 160,
+            earfcn: Some(2050),
+            changes: vec![ChangedParameter {
+                parameter: "lte_rrc.sib1.tracking_area_code",
+                a: "1".to_string(),
+                b: "2".to_string(),
+            }],
+        }]);
+
+        let same = CellSummaryDiff::new(&a, &a);
+        assert!(same.added.is_empty() && same.removed.is_empty() && same.changed.is_empty());
+    }
+}
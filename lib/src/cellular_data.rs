@@ -0,0 +1,248 @@
+//! Cell configuration gathered from the system information a cell
+//! broadcasts. Some of these parameters (e.g. unusually aggressive
+//! reselection thresholds) are telltale signs of an IMSI catcher trying to
+//! attract phones, so they're kept around for analyzers to inspect.
+//!
+//! This only covers LTE so far. Nothing here comes from NR (5G) cells, whose
+//! measurement logs aren't decoded yet (see
+//! [crate::diag::LogBody::NrMl1SearcherMeasDbUpdate]).
+
+use serde::Serialize;
+use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1, PLMN_Identity, ReselectionThreshold, SystemInformationBlockType1, SystemInformationBlockType1CellAccessRelatedInfoCellBarred, SystemInformationBlockType3, SystemInformationBlockType4, SystemInformationBlockType5, SystemInformationCriticalExtensions, SystemInformation_r8_IEsSib_TypeAndInfo_Entry};
+
+use crate::analysis::information_element::{InformationElement, LteInformationElement};
+use crate::gsmtap::{GsmtapMessage, GsmtapType, LteRrcSubtype};
+
+// q-Hyst is an enumeration of dB values, TS 36.331 section 6.3.1
+const Q_HYST_DB: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 14, 16, 18, 20, 22, 24];
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct CellularData {
+    // SIB1 cell access info. PLMNs are formatted as "MCC-MNC".
+    pub plmns: Vec<String>,
+    pub tracking_area_code: Option<u32>,
+    pub cell_identity: Option<u32>,
+    pub cell_barred: Option<bool>,
+
+    // SIB3 cell reselection parameters, all in dB(m) rather than their raw
+    // encoded values
+    pub q_hyst_db: Option<u8>,
+    pub s_non_intra_search_db: Option<u8>,
+    pub thresh_serving_low_db: Option<u8>,
+    pub cell_reselection_priority: Option<u8>,
+    pub q_rx_lev_min_dbm: Option<i16>,
+    pub p_max_dbm: Option<i8>,
+    pub s_intra_search_db: Option<u8>,
+    pub t_reselection_eutra_secs: Option<u8>,
+
+    // SIB4 and SIB5 neighbor lists
+    pub intra_freq_neighbor_pcis: Vec<u16>,
+    pub inter_freq_earfcns: Vec<u16>,
+}
+
+// reselection thresholds are encoded in 2dB steps
+fn reselection_threshold_db(threshold: &ReselectionThreshold) -> u8 {
+    threshold.0 * 2
+}
+
+// interprets a bit string (e.g. a cell identity) as a big-endian integer
+pub(crate) fn bits_to_u32(bits: impl IntoIterator<Item = bool>) -> u32 {
+    bits.into_iter().fold(0, |acc, bit| (acc << 1) | bit as u32)
+}
+
+// the MCC is optional for all but the first PLMN in a list, in which case it's
+// the same as the previous entry's, TS 36.331 section 6.3.4
+fn format_plmn(plmn: &PLMN_Identity, previous_mcc: &mut String) -> String {
+    if let Some(mcc) = &plmn.mcc {
+        *previous_mcc = mcc.0.iter().map(|digit| digit.0.to_string()).collect();
+    }
+    let mnc: String = plmn.mnc.0.iter().map(|digit| digit.0.to_string()).collect();
+    format!("{}-{}", previous_mcc, mnc)
+}
+
+impl CellularData {
+    /// Updates the cell's data from any system information blocks contained
+    /// in the given message.
+    pub fn update(&mut self, ie: &InformationElement) {
+        let InformationElement::LTE(LteInformationElement::BcchDlSch(bcch_dl_sch_message)) = ie else {
+            return;
+        };
+        let system_information = match &bcch_dl_sch_message.message {
+            BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1)) => {
+                self.extract_from_sib1(sib1);
+                return;
+            },
+            BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformation(system_information)) => system_information,
+            _ => return,
+        };
+        let SystemInformationCriticalExtensions::SystemInformation_r8(sib) = &system_information.critical_extensions else {
+            return;
+        };
+        for sib in &sib.sib_type_and_info.0 {
+            match sib {
+                SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib3(sib3) => self.extract_from_sib3(sib3),
+                SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib4(sib4) => self.extract_from_sib4(sib4),
+                SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib5(sib5) => self.extract_from_sib5(sib5),
+                _ => {},
+            }
+        }
+    }
+
+    /// Like [CellularData::update], but takes a GSMTAP message, only bothering
+    /// to decode it if it's carrying system information
+    pub fn update_from_gsmtap(&mut self, gsmtap_msg: &GsmtapMessage) {
+        if gsmtap_msg.header.gsmtap_type != GsmtapType::LteRrc(LteRrcSubtype::BcchDlSch) {
+            return;
+        }
+        if let Ok(ie) = InformationElement::try_from(gsmtap_msg) {
+            self.update(&ie);
+        }
+    }
+
+    /// Whether any system information has been seen yet
+    pub fn is_empty(&self) -> bool {
+        *self == CellularData::default()
+    }
+
+    /// Returns the known parameters as SCAT-style key/value pairs, so they can
+    /// be compared line by line with other tools' output
+    pub fn to_scat_format(&self) -> Vec<(&'static str, String)> {
+        let join = |values: &[u16]| (!values.is_empty()).then(|| values.iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(","));
+        let fields = [
+            ("lte_rrc.sib1.plmns", (!self.plmns.is_empty()).then(|| self.plmns.join(","))),
+            ("lte_rrc.sib1.tracking_area_code", self.tracking_area_code.map(|v| v.to_string())),
+            ("lte_rrc.sib1.cell_identity", self.cell_identity.map(|v| v.to_string())),
+            ("lte_rrc.sib1.cell_barred", self.cell_barred.map(|v| v.to_string())),
+            ("lte_rrc.sib3.q_hyst_db", self.q_hyst_db.map(|v| v.to_string())),
+            ("lte_rrc.sib3.s_non_intra_search_db", self.s_non_intra_search_db.map(|v| v.to_string())),
+            ("lte_rrc.sib3.thresh_serving_low_db", self.thresh_serving_low_db.map(|v| v.to_string())),
+            ("lte_rrc.sib3.cell_reselection_priority", self.cell_reselection_priority.map(|v| v.to_string())),
+            ("lte_rrc.sib3.q_rx_lev_min_dbm", self.q_rx_lev_min_dbm.map(|v| v.to_string())),
+            ("lte_rrc.sib3.p_max_dbm", self.p_max_dbm.map(|v| v.to_string())),
+            ("lte_rrc.sib3.s_intra_search_db", self.s_intra_search_db.map(|v| v.to_string())),
+            ("lte_rrc.sib3.t_reselection_eutra_secs", self.t_reselection_eutra_secs.map(|v| v.to_string())),
+            ("lte_rrc.sib4.intra_freq_neighbor_pcis", join(&self.intra_freq_neighbor_pcis)),
+            ("lte_rrc.sib5.inter_freq_earfcns", join(&self.inter_freq_earfcns)),
+        ];
+        fields.into_iter()
+            .filter_map(|(key, maybe_value)| maybe_value.map(|value| (key, value)))
+            .collect()
+    }
+
+    pub fn extract_from_sib1(&mut self, sib1: &SystemInformationBlockType1) {
+        let info = &sib1.cell_access_related_info;
+        let cell_identity = bits_to_u32(info.cell_identity.0.iter().by_vals());
+        // a SIB1 from a different cell means everything we know is stale
+        if self.cell_identity.is_some_and(|known| known != cell_identity) {
+            *self = CellularData::default();
+        }
+        let mut previous_mcc = String::new();
+        self.plmns = info.plmn_identity_list.0.iter()
+            .map(|info| format_plmn(&info.plmn_identity, &mut previous_mcc))
+            .collect();
+        self.tracking_area_code = Some(bits_to_u32(info.tracking_area_code.0.iter().by_vals()));
+        self.cell_identity = Some(cell_identity);
+        self.cell_barred = Some(info.cell_barred.0 == SystemInformationBlockType1CellAccessRelatedInfoCellBarred::BARRED);
+    }
+
+    pub fn extract_from_sib3(&mut self, sib3: &SystemInformationBlockType3) {
+        let common = &sib3.cell_reselection_info_common;
+        self.q_hyst_db = Q_HYST_DB.get(common.q_hyst.0 as usize).copied();
+
+        let serving_freq = &sib3.cell_reselection_serving_freq_info;
+        self.s_non_intra_search_db = serving_freq.s_non_intra_search.as_ref().map(reselection_threshold_db);
+        self.thresh_serving_low_db = Some(reselection_threshold_db(&serving_freq.thresh_serving_low));
+        self.cell_reselection_priority = Some(serving_freq.cell_reselection_priority.0);
+
+        let intra_freq = &sib3.intra_freq_cell_reselection_info;
+        // q-RxLevMin is also in 2dB steps
+        self.q_rx_lev_min_dbm = Some(intra_freq.q_rx_lev_min.0 as i16 * 2);
+        self.p_max_dbm = intra_freq.p_max.as_ref().map(|p_max| p_max.0);
+        self.s_intra_search_db = intra_freq.s_intra_search.as_ref().map(reselection_threshold_db);
+        self.t_reselection_eutra_secs = Some(intra_freq.t_reselection_eutra.0);
+    }
+
+    pub fn extract_from_sib4(&mut self, sib4: &SystemInformationBlockType4) {
+        self.intra_freq_neighbor_pcis = sib4.intra_freq_neigh_cell_list.as_ref()
+            .map(|list| list.0.iter().map(|cell| cell.phys_cell_id.0).collect())
+            .unwrap_or_default();
+    }
+
+    pub fn extract_from_sib5(&mut self, sib5: &SystemInformationBlockType5) {
+        self.inter_freq_earfcns = sib5.inter_freq_carrier_freq_list.0.iter()
+            .map(|freq| freq.dl_carrier_freq.0)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telcom_parser::{decode, lte_rrc::BCCH_DL_SCH_Message};
+
+    #[test]
+    fn test_extract_from_sib3() {
+        // SystemInformation carrying a single SIB3 with q-Hyst dB4,
+        // s-NonIntraSearch 8, threshServingLow 6, cellReselectionPriority 5,
+        // q-RxLevMin -64, p-Max 23, s-IntraSearch 31, presenceAntennaPort1
+        // true, neighCellConfig 01 and t-ReselectionEUTRA 1
+        let data = [0x00, 0x04, 0x4a, 0x0d, 0x70, 0x6d, 0x7f, 0x48];
+        let message: BCCH_DL_SCH_Message = decode(&data).unwrap();
+        let ie = InformationElement::LTE(LteInformationElement::BcchDlSch(message));
+        let mut cellular_data = CellularData::default();
+        cellular_data.update(&ie);
+        assert_eq!(cellular_data, CellularData {
+            plmns: Vec::new(),
+            tracking_area_code: None,
+            cell_identity: None,
+            cell_barred: None,
+            q_hyst_db: Some(4),
+            s_non_intra_search_db: Some(16),
+            thresh_serving_low_db: Some(12),
+            cell_reselection_priority: Some(5),
+            q_rx_lev_min_dbm: Some(-128),
+            p_max_dbm: Some(23),
+            s_intra_search_db: Some(62),
+            t_reselection_eutra_secs: Some(1),
+            intra_freq_neighbor_pcis: Vec::new(),
+            inter_freq_earfcns: Vec::new(),
+        });
+    }
+
+    #[test]
+    fn test_format_plmn() {
+        use telcom_parser::lte_rrc::{MCC, MCC_MNC_Digit, MNC};
+        let digits = |ds: &[u8]| ds.iter().map(|d| MCC_MNC_Digit(*d)).collect();
+        let mut previous_mcc = String::new();
+        let first = PLMN_Identity { mcc: Some(MCC(digits(&[3, 1, 0]))), mnc: MNC(digits(&[4, 1, 0])) };
+        assert_eq!(format_plmn(&first, &mut previous_mcc), "310-410");
+        // the second PLMN inherits the first's MCC
+        let second = PLMN_Identity { mcc: None, mnc: MNC(digits(&[2, 6, 0])) };
+        assert_eq!(format_plmn(&second, &mut previous_mcc), "310-260");
+    }
+
+    #[test]
+    fn test_bits_to_u32() {
+        assert_eq!(bits_to_u32([]), 0);
+        assert_eq!(bits_to_u32([true, false, true, true]), 0b1011);
+    }
+
+    #[test]
+    fn test_to_scat_format() {
+        assert!(CellularData::default().to_scat_format().is_empty());
+        assert!(CellularData::default().is_empty());
+
+        let data = [0x00, 0x04, 0x4a, 0x0d, 0x70, 0x6d, 0x7f, 0x48];
+        let message: BCCH_DL_SCH_Message = decode(&data).unwrap();
+        let mut cellular_data = CellularData::default();
+        cellular_data.update(&InformationElement::LTE(LteInformationElement::BcchDlSch(message)));
+        let fields = cellular_data.to_scat_format();
+        assert_eq!(fields.len(), 8);
+        assert!(fields.iter().all(|(key, _)| key.starts_with("lte_rrc.")));
+        assert!(fields.contains(&("lte_rrc.sib3.q_hyst_db", "4".to_string())));
+        assert!(fields.contains(&("lte_rrc.sib3.q_rx_lev_min_dbm", "-128".to_string())));
+    }
+}
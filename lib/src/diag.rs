@@ -4,7 +4,8 @@ use chrono::{DateTime, FixedOffset};
 use crc::{Algorithm, Crc};
 use deku::prelude::*;
 
-use crate::hdlc::{self, hdlc_decapsulate};
+use crate::hdlc::{self, hdlc_decapsulate, HdlcDecoder};
+use crate::log_codes;
 use log::{warn, error};
 use thiserror::Error;
 
@@ -14,6 +15,13 @@ pub const MESSAGE_ESCAPE_CHAR: u8 = 0x7d;
 pub const ESCAPED_MESSAGE_TERMINATOR: u8 = 0x5e;
 pub const ESCAPED_MESSAGE_ESCAPE_CHAR: u8 = 0x5d;
 
+// outer_length is a u16, so no well-formed message is longer than this
+pub const MAX_MESSAGE_LEN: usize = u16::MAX as usize + 4;
+
+// sizes of the fields preceding a log's inner_length and body, respectively
+const LOG_OUTER_HEADER_LEN: usize = 4;
+const LOG_INNER_HEADER_LEN: usize = 12;
+
 #[derive(Debug, Clone, DekuWrite)]
 pub struct RequestContainer {
     pub data_type: DataType,
@@ -60,6 +68,8 @@ pub enum DiagParsingError {
     MessageParsingError(deku::DekuError, Vec<u8>),
     #[error("HDLC decapsulation of message failed: {0}, data: {1:?}")]
     HdlcDecapsulationError(hdlc::HdlcError, Vec<u8>),
+    #[error("Message's {0} of {1} is out of bounds (limit {2})")]
+    LengthOutOfBounds(&'static str, usize, usize),
 }
 
 // this is sorta based on the params qcsuper uses, plus what seems to be used in
@@ -88,23 +98,56 @@ impl MessagesContainer {
     pub fn into_messages(self) -> Vec<Result<Message, DiagParsingError>> {
         let mut result = Vec::new();
         for msg in self.messages {
+            let mut decoder = HdlcDecoder::new(&CRC_CCITT);
             for sub_msg in msg.data.split_inclusive(|&b| b == MESSAGE_TERMINATOR) {
-                match hdlc_decapsulate(sub_msg, &CRC_CCITT) {
-                    Ok(data) => match Message::from_bytes((&data, 0)) {
-                        Ok(((leftover_bytes, _), res)) => {
-                            if !leftover_bytes.is_empty() {
-                                warn!("warning: {} leftover bytes when parsing Message", leftover_bytes.len());
-                            }
-                            result.push(Ok(res));
-                        },
-                        Err(e) => result.push(Err(DiagParsingError::MessageParsingError(e, data))),
-                    },
+                decoder.push_bytes(sub_msg);
+                // only the last chunk of a container can be missing its
+                // terminator, and hdlc_decapsulate reports why
+                let decoded = decoder.pop_frame()
+                    .unwrap_or_else(|| hdlc_decapsulate(sub_msg, &CRC_CCITT));
+                match decoded {
+                    Ok(data) => result.push(Message::try_parse_limited(&data, MAX_MESSAGE_LEN)),
                     Err(err) => result.push(Err(DiagParsingError::HdlcDecapsulationError(err, sub_msg.to_vec()))),
                 }
             }
         }
         result
     }
+
+    /// Returns the timestamp of the first log message in the container, if
+    /// it has any that parse with a valid timestamp
+    pub fn first_log_timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        self.clone().into_messages().into_iter()
+            .flatten()
+            .find_map(|msg| match msg {
+                Message::Log { timestamp, .. } => timestamp.try_to_datetime(),
+                _ => None,
+            })
+    }
+
+    /// Drops every message for which `keep` returns false, e.g. to avoid
+    /// writing them to a QMDL file. Messages that fail to parse are always
+    /// kept.
+    pub fn retain_messages<F>(&mut self, mut keep: F) where F: FnMut(&Message) -> bool {
+        for msg in self.messages.iter_mut() {
+            let mut kept_data = Vec::with_capacity(msg.data.len());
+            for sub_msg in msg.data.split_inclusive(|&b| b == MESSAGE_TERMINATOR) {
+                let parsed = hdlc_decapsulate(sub_msg, &CRC_CCITT).ok()
+                    .and_then(|data| Message::try_parse_limited(&data, MAX_MESSAGE_LEN).ok());
+                let kept = match parsed {
+                    Some(res) => keep(&res),
+                    None => true,
+                };
+                if kept {
+                    kept_data.extend_from_slice(sub_msg);
+                }
+            }
+            msg.len = kept_data.len() as u32;
+            msg.data = kept_data;
+        }
+        self.messages.retain(|msg| !msg.data.is_empty());
+        self.num_messages = self.messages.len() as u32;
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, DekuRead, DekuWrite)]
@@ -125,7 +168,7 @@ pub enum Message {
         log_type: u16,
         timestamp: Timestamp,
         // pass the log type and log length (inner_length - (sizeof(log_type) + sizeof(timestamp)))
-        #[deku(ctx = "*log_type, *inner_length - 12")]
+        #[deku(ctx = "*log_type, inner_length.saturating_sub(12)")]
         body: LogBody,
     },
 
@@ -193,8 +236,10 @@ pub enum LogBody {
     },
     #[deku(id = "0x11eb")]
     IpTraffic {
-        // is this right?? based on https://github.com/P1sec/QCSuper/blob/81dbaeee15ec7747e899daa8e3495e27cdcc1264/src/modules/pcap_dump.py#L378
-        #[deku(count = "hdr_len - 8")]
+        // based on https://github.com/P1sec/QCSuper/blob/81dbaeee15ec7747e899daa8e3495e27cdcc1264/src/modules/pcap_dump.py#L378,
+        // the IP packet follows an 8 byte header we don't decode yet
+        header: [u8; 8],
+        #[deku(count = "hdr_len.saturating_sub(8)")]
         msg: Vec<u8>,
     },
     #[deku(id = "0x713a")]
@@ -208,6 +253,115 @@ pub enum LogBody {
     NrRrcOtaMessage {
         #[deku(count = "hdr_len")]
         msg: Vec<u8>,
+    },
+    // NR5G ML1 searcher measurement database updates carry SS-RSRP/RSRQ/SINR
+    // for the serving and neighbor cells, but their layout changes between
+    // log versions and we don't have captures to check one against. So only
+    // the version is decoded: the measurements stay in the raw body, and
+    // nothing reports 5G signal quality. They aren't in our capture mask
+    // either, but QMDL files made by other tools can contain them.
+    #[deku(id = "0xb97f")]
+    NrMl1SearcherMeasDbUpdate {
+        minor_version: u16,
+        major_version: u16,
+        #[deku(count = "hdr_len.saturating_sub(4)")]
+        data: Vec<u8>,
+    },
+}
+
+// reads a little-endian u16/u32 at the given offset, if the data's long enough
+fn read_u16_le(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?) as usize)
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize)
+}
+
+// Checks the length fields deku sizes its buffers by against how much data
+// there actually is. Anything too short to hold a field is left for deku to
+// reject.
+fn check_declared_lengths(data: &[u8]) -> Result<(), DiagParsingError> {
+    // only logs carry length fields
+    if data.first() != Some(&16) {
+        return Ok(());
+    }
+    // outer_length isn't checked, since nothing is sized by it
+    let available = data.len().saturating_sub(LOG_OUTER_HEADER_LEN);
+    let Some(inner_length) = read_u16_le(data, 4) else {
+        return Ok(());
+    };
+    if inner_length > available {
+        return Err(DiagParsingError::LengthOutOfBounds("inner_length", inner_length, available));
+    }
+    if inner_length < LOG_INNER_HEADER_LEN {
+        return Err(DiagParsingError::LengthOutOfBounds("inner_length", inner_length, LOG_INNER_HEADER_LEN));
+    }
+    // UMTS NAS messages are the only ones with a 32 bit length, which could
+    // otherwise have deku try allocating up to 4GB
+    let body_offset = LOG_OUTER_HEADER_LEN + LOG_INNER_HEADER_LEN;
+    if read_u16_le(data, 6) == Some(0x713a) {
+        let body_available = inner_length - LOG_INNER_HEADER_LEN;
+        if let Some(length) = read_u32_le(data, body_offset + 1) {
+            if length > body_available {
+                return Err(DiagParsingError::LengthOutOfBounds("length", length, body_available));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Message {
+    /// Parses a Message, first rejecting it if it's longer than `max_len` or
+    /// any of its length fields claim more data than there is. Malformed
+    /// input could otherwise have deku allocate huge buffers for it.
+    pub fn try_parse_limited(data: &[u8], max_len: usize) -> Result<Message, DiagParsingError> {
+        if data.len() > max_len {
+            return Err(DiagParsingError::LengthOutOfBounds("data length", data.len(), max_len));
+        }
+        check_declared_lengths(data)?;
+        let ((leftover_bytes, _), msg) = Message::from_bytes((data, 0))
+            .map_err(|e| DiagParsingError::MessageParsingError(e, data.to_vec()))?;
+        if !leftover_bytes.is_empty() {
+            warn!("warning: {} leftover bytes when parsing Message", leftover_bytes.len());
+        }
+        Ok(msg)
+    }
+
+    /// Returns the PCI and EARFCN of the cell an LTE RRC message was
+    /// exchanged with, or None for any other kind of message
+    pub fn lte_rrc_cell(&self) -> Option<(u16, u32)> {
+        match self {
+            Message::Log { body: LogBody::LteRrcOtaMessage { packet, .. }, .. } => Some((packet.get_phy_cell_id(), packet.get_earfcn())),
+            _ => None,
+        }
+    }
+
+    /// Returns the EARFCN an LTE RRC message was received on, or None for
+    /// any other kind of message
+    pub fn lte_rrc_earfcn(&self) -> Option<u32> {
+        match self {
+            Message::Log { body: LogBody::LteRrcOtaMessage { packet, .. }, .. } => Some(packet.get_earfcn()),
+            _ => None,
+        }
+    }
+}
+
+impl LogBody {
+    /// Returns the name of this log's variant, e.g. for tallying the kinds of
+    /// logs in a QMDL file
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogBody::WcdmaSignallingMessage { .. } => "WcdmaSignallingMessage",
+            LogBody::GsmRrSignallingMessage { .. } => "GsmRrSignallingMessage",
+            LogBody::GprsMacSignallingMessage { .. } => "GprsMacSignallingMessage",
+            LogBody::LteRrcOtaMessage { .. } => "LteRrcOtaMessage",
+            LogBody::Nas4GMessage { .. } => "Nas4GMessage",
+            LogBody::IpTraffic { .. } => "IpTraffic",
+            LogBody::UmtsNasOtaMessage { .. } => "UmtsNasOtaMessage",
+            LogBody::NrRrcOtaMessage { .. } => "NrRrcOtaMessage",
+            LogBody::NrMl1SearcherMeasDbUpdate { .. } => "NrMl1SearcherMeasDbUpdate",
+        }
     }
 }
 
@@ -308,6 +462,15 @@ impl LteRrcOtaPacket {
         }
     }
 
+    pub fn get_phy_cell_id(&self) -> u16 {
+        match self {
+            LteRrcOtaPacket::V0 { phy_cell_id, .. } => *phy_cell_id,
+            LteRrcOtaPacket::V5 { phy_cell_id, .. } => *phy_cell_id,
+            LteRrcOtaPacket::V8 { phy_cell_id, .. } => *phy_cell_id,
+            LteRrcOtaPacket::V25 { phy_cell_id, .. } => *phy_cell_id,
+        }
+    }
+
     pub fn take_payload(self) -> Vec<u8> {
         match self {
             LteRrcOtaPacket::V0 { packet, .. } => packet,
@@ -324,17 +487,38 @@ pub struct Timestamp {
     pub ts: u64,
 }
 
+// diag timestamps count from the GPS epoch
+const GPS_EPOCH: &str = "1980-01-06T00:00:00-00:00";
+// anything past this is a garbage timestamp rather than a real one
+const LATEST_VALID_TIMESTAMP: &str = "2100-01-01T00:00:00-00:00";
+
 impl Timestamp {
     pub fn to_datetime(&self) -> DateTime<FixedOffset> {
+        self.epoch() + self.since_epoch()
+    }
+
+    /// Like `to_datetime`, but returns None if the timestamp can't be right,
+    /// i.e. it's at the epoch (the modem hasn't got a time yet) or absurdly
+    /// far in the future, so callers can fall back to the wall clock.
+    pub fn try_to_datetime(&self) -> Option<DateTime<FixedOffset>> {
+        let epoch = self.epoch();
+        let latest = chrono::DateTime::parse_from_rfc3339(LATEST_VALID_TIMESTAMP).unwrap();
+        epoch.checked_add_signed(self.since_epoch())
+            .filter(|datetime| *datetime > epoch && *datetime < latest)
+    }
+
+    fn epoch(&self) -> DateTime<FixedOffset> {
+        chrono::DateTime::parse_from_rfc3339(GPS_EPOCH).unwrap()
+    }
+
+    fn since_epoch(&self) -> chrono::Duration {
         // Upper 48 bits: epoch at 1980-01-06 00:00:00, incremented by 1 for 1/800s
         // Lower 16 bits: time since last 1/800s tick in 1/32 chip units
         let ts_upper = self.ts >> 16;
         let ts_lower = self.ts & 0xffff;
-        let epoch = chrono::DateTime::parse_from_rfc3339("1980-01-06T00:00:00-00:00").unwrap();
         let mut delta_seconds = ts_upper as f64 * 1.25;
         delta_seconds += ts_lower as f64 / 40960.0;
-        let ts_delta = chrono::Duration::milliseconds(delta_seconds as i64);
-        epoch + ts_delta
+        chrono::Duration::milliseconds(delta_seconds as i64)
     }
 }
 
@@ -382,6 +566,58 @@ pub fn build_log_mask_request(log_type: u32, log_mask_bitsize: u32, accepted_log
     })
 }
 
+/// Returns the accepted log codes of the given log type which don't fit in a
+/// mask of the given size, and so can't be enabled on this device
+pub fn log_codes_outside_mask(log_type: u32, log_mask_bitsize: u32, accepted_log_codes: &[u32]) -> Vec<u32> {
+    accepted_log_codes.iter()
+        .filter(|&&log_code| log_code >> 12 == log_type && log_code & 0xfff >= log_mask_bitsize)
+        .copied()
+        .collect()
+}
+
+/// Returns the log codes enabled by a log mask for the given log type
+pub fn log_mask_enabled_codes(log_type: u32, log_mask: &[u8]) -> Vec<u32> {
+    let mut enabled_codes = Vec::new();
+    for (byte_index, byte) in log_mask.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (1 << bit) != 0 {
+                enabled_codes.push((log_type << 12) | (byte_index as u32 * 8 + bit));
+            }
+        }
+    }
+    enabled_codes
+}
+
+/// Builds the log mask for every log type used by the given log codes and
+/// returns a human-readable listing of the codes each mask enables. The real
+/// mask sizes are only known by the device, so each mask is sized to fit the
+/// highest code of its log type.
+pub fn describe_log_masks(accepted_log_codes: &[u32]) -> String {
+    let mut log_types: Vec<u32> = accepted_log_codes.iter()
+        .map(|log_code| log_code >> 12)
+        .collect();
+    log_types.sort();
+    log_types.dedup();
+
+    let mut result = String::new();
+    for log_type in log_types {
+        let log_mask_bitsize = accepted_log_codes.iter()
+            .filter(|&log_code| log_code >> 12 == log_type)
+            .map(|log_code| (log_code & 0xfff) + 1)
+            .max()
+            .unwrap_or(0);
+        let Request::LogConfig(LogConfigRequest::SetMask { log_mask, .. }) = build_log_mask_request(log_type, log_mask_bitsize, accepted_log_codes) else {
+            unreachable!("build_log_mask_request always builds a SetMask request");
+        };
+        result.push_str(&format!("log type {} ({} bit mask):\n", log_type, log_mask_bitsize));
+        for log_code in log_mask_enabled_codes(log_type, &log_mask) {
+            let name = log_codes::log_code_name(log_code).unwrap_or("unknown");
+            result.push_str(&format!("  {:#06x} {}\n", log_code, name));
+        }
+    }
+    result
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -425,6 +661,60 @@ mod test {
         }));
     }
 
+    #[test]
+    fn test_log_mask_enabled_codes() {
+        let accepted_log_codes = [0xb0c0, 0xb0e2, 0xb821];
+        for log_mask_bitsize in [0x822, 0x900] {
+            let Request::LogConfig(LogConfigRequest::SetMask { log_mask, .. }) = build_log_mask_request(11, log_mask_bitsize, &accepted_log_codes) else {
+                panic!("expected SetMask request");
+            };
+            assert_eq!(log_mask_enabled_codes(11, &log_mask), accepted_log_codes);
+        }
+    }
+
+    #[test]
+    fn test_log_mask_sized_to_negotiated_range() {
+        let accepted_log_codes = [0xb0c0, 0xb0e2, 0xb821];
+        for (log_mask_bitsize, expected_len) in [(0xc1, 25), (0xc8, 25), (0x822, 261), (0x900, 288)] {
+            let Request::LogConfig(LogConfigRequest::SetMask { log_mask, .. }) = build_log_mask_request(11, log_mask_bitsize, &accepted_log_codes) else {
+                panic!("expected SetMask request");
+            };
+            assert_eq!(log_mask.len(), expected_len);
+        }
+
+        // a smaller range than we'd like drops the codes past its end
+        let Request::LogConfig(LogConfigRequest::SetMask { log_mask, .. }) = build_log_mask_request(11, 0xc1, &accepted_log_codes) else {
+            panic!("expected SetMask request");
+        };
+        assert_eq!(log_mask_enabled_codes(11, &log_mask), [0xb0c0]);
+        assert_eq!(log_codes_outside_mask(11, 0xc1, &accepted_log_codes), [0xb0e2, 0xb821]);
+        assert!(log_codes_outside_mask(11, 0x822, &accepted_log_codes).is_empty());
+        assert!(log_codes_outside_mask(1, 0, &accepted_log_codes).is_empty());
+    }
+
+    #[test]
+    fn test_describe_log_masks() {
+        let description = describe_log_masks(&crate::diag_device::LOG_CODES_FOR_RAW_PACKET_LOGGING);
+        assert_eq!(description, "\
+log type 1 (492 bit mask):
+  0x11eb LOG_DATA_PROTOCOL_LOGGING_C
+log type 4 (304 bit mask):
+  0x412f WCDMA_SIGNALLING_MESSAGE
+log type 5 (551 bit mask):
+  0x512f LOG_GSM_RR_SIGNALING_MESSAGE_C
+  0x5226 LOG_GPRS_MAC_SIGNALLING_MESSAGE_C
+log type 7 (315 bit mask):
+  0x713a LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C
+log type 11 (2082 bit mask):
+  0xb0c0 LOG_LTE_RRC_OTA_MSG_LOG_C
+  0xb0e2 LOG_LTE_NAS_ESM_OTA_IN_MSG_LOG_C
+  0xb0e3 LOG_LTE_NAS_ESM_OTA_OUT_MSG_LOG_C
+  0xb0ec LOG_LTE_NAS_EMM_OTA_IN_MSG_LOG_C
+  0xb0ed LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C
+  0xb821 LOG_NR_RRC_OTA_MSG_LOG_C
+");
+    }
+
     #[test]
     fn test_request_container() {
         let req = RequestContainer {
@@ -482,6 +772,47 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_nr_ml1_searcher_meas_db_update() {
+        let data = vec![
+            16, 0, 20, 0, 20, 0, 0x7f, 0xb9, 0, 0, 0, 0, 0, 0, 0, 0,
+            7, 0, 2, 0, 0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        assert_eq!(msg, Message::Log {
+            pending_msgs: 0,
+            outer_length: 20,
+            inner_length: 20,
+            log_type: 0xb97f,
+            timestamp: Timestamp { ts: 0 },
+            body: LogBody::NrMl1SearcherMeasDbUpdate {
+                minor_version: 7,
+                major_version: 2,
+                data: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            },
+        });
+    }
+
+    #[test]
+    fn test_ip_traffic() {
+        let data = vec![
+            16, 0, 24, 0, 24, 0, 0xeb, 0x11, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 2, 3, 4, 5, 6, 7, 8, 0x45, 0x00, 0x00, 0x14,
+        ];
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        assert_eq!(msg, Message::Log {
+            pending_msgs: 0,
+            outer_length: 24,
+            inner_length: 24,
+            log_type: 0x11eb,
+            timestamp: Timestamp { ts: 0 },
+            body: LogBody::IpTraffic {
+                header: [1, 2, 3, 4, 5, 6, 7, 8],
+                msg: vec![0x45, 0x00, 0x00, 0x14],
+            },
+        });
+    }
+
     fn make_container(data_type: DataType, message: HdlcEncapsulatedMessage) -> MessagesContainer {
         MessagesContainer {
             data_type,
@@ -494,13 +825,21 @@ mod test {
     // serialize or deserialize, that's probably a problem with this mock, not
     // the DiagReader implementation
     fn get_test_message(payload: &[u8]) -> (HdlcEncapsulatedMessage, Message) {
+        get_test_message_with_earfcn(payload, 2050)
+    }
+
+    fn get_test_message_with_earfcn(payload: &[u8], earfcn: u32) -> (HdlcEncapsulatedMessage, Message) {
+        get_test_message_with_timestamp(payload, earfcn, 72659535985485082)
+    }
+
+    fn get_test_message_with_timestamp(payload: &[u8], earfcn: u32, ts: u64) -> (HdlcEncapsulatedMessage, Message) {
         let length_with_payload = 31 + payload.len() as u16;
         let message = Message::Log {
             pending_msgs: 0,
             outer_length: length_with_payload,
             inner_length: length_with_payload,
             log_type: 0xb0c0,
-            timestamp: Timestamp { ts: 72659535985485082 },
+            timestamp: Timestamp { ts },
             body: LogBody::LteRrcOtaMessage {
                 ext_header_version: 20,
                 packet: LteRrcOtaPacket::V8 {
@@ -508,7 +847,7 @@ mod test {
                     rrc_rel_min: 48,
                     bearer_id: 0,
                     phy_cell_id: 160,
-                    earfcn: 2050,
+                    earfcn,
                     sfn_subfn: 4057,
                     pdu_num: 5,
                     sib_mask: 0,
@@ -546,6 +885,117 @@ mod test {
         assert_eq!(container.into_messages(), vec![Ok(message1), Ok(message2)]);
     }
 
+    // 2024-01-01T00:00:00Z, in 1/800s ticks since the GPS epoch
+    const JAN_1_2024: u64 = (1_388_102_400 * 800) << 16;
+
+    #[test]
+    fn test_try_to_datetime() {
+        let expected = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00-00:00").unwrap();
+        assert_eq!(Timestamp { ts: JAN_1_2024 }.try_to_datetime(), Some(expected));
+        assert_eq!(Timestamp { ts: JAN_1_2024 }.to_datetime(), expected);
+    }
+
+    #[test]
+    fn test_try_to_datetime_zero() {
+        assert_eq!(Timestamp { ts: 0 }.try_to_datetime(), None);
+    }
+
+    #[test]
+    fn test_try_to_datetime_overflow() {
+        assert_eq!(Timestamp { ts: u64::MAX }.try_to_datetime(), None);
+        assert_eq!(Timestamp { ts: 72659535985485082 }.try_to_datetime(), None);
+    }
+
+    #[test]
+    fn test_first_log_timestamp() {
+        let (encapsulated, message) = get_test_message_with_timestamp(&[1], 2050, JAN_1_2024);
+        let Message::Log { timestamp, .. } = message else { unreachable!() };
+        let container = make_container(DataType::UserSpace, encapsulated);
+        assert_eq!(container.first_log_timestamp(), Some(timestamp.to_datetime()));
+
+        // messages with garbage timestamps are skipped
+        let (encapsulated, _) = get_test_message(&[1]);
+        assert_eq!(make_container(DataType::UserSpace, encapsulated).first_log_timestamp(), None);
+
+        let bad_encapsulation = HdlcEncapsulatedMessage { len: 2, data: vec![0x01, MESSAGE_TERMINATOR] };
+        assert_eq!(make_container(DataType::UserSpace, bad_encapsulation).first_log_timestamp(), None);
+    }
+
+    #[test]
+    fn test_retain_messages_by_earfcn() {
+        let (mut encapsulated1, message1) = get_test_message_with_earfcn(&[1], 2050);
+        let (encapsulated2, message2) = get_test_message_with_earfcn(&[2], 100);
+        let (encapsulated3, _) = get_test_message_with_earfcn(&[3], 2050);
+        encapsulated1.data.extend(encapsulated2.data);
+        encapsulated1.len += encapsulated2.len;
+        let mut container = make_container(DataType::UserSpace, encapsulated1);
+        container.messages.push(encapsulated3);
+        container.num_messages += 1;
+        // garbage data should be left alone
+        container.messages.push(HdlcEncapsulatedMessage { len: 2, data: vec![0x01, MESSAGE_TERMINATOR] });
+        container.num_messages += 1;
+
+        container.retain_messages(|msg| !msg.lte_rrc_earfcn().is_some_and(|earfcn| earfcn >= 1000));
+        assert_eq!(container.num_messages, 2);
+        let messages = container.into_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], Ok(message2));
+        assert!(messages[1].is_err());
+        assert_eq!(message1.lte_rrc_earfcn(), Some(2050));
+    }
+
+    #[test]
+    fn test_parse_limited_accepts_valid_messages() {
+        let data = vec![
+            16, 0, 24, 0, 24, 0, 0xeb, 0x11, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 2, 3, 4, 5, 6, 7, 8, 0x45, 0x00, 0x00, 0x14,
+        ];
+        let (_, expected) = Message::from_bytes((&data, 0)).unwrap();
+        assert_eq!(Message::try_parse_limited(&data, MAX_MESSAGE_LEN), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_limited_ignores_mismatched_outer_length() {
+        // outer_length claims far more than the data holds, but since deku
+        // doesn't size anything by it, the message still parses
+        let data = vec![
+            16, 0, 0xff, 0xff, 24, 0, 0xeb, 0x11, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 2, 3, 4, 5, 6, 7, 8, 0x45, 0x00, 0x00, 0x14,
+        ];
+        let (_, expected) = Message::from_bytes((&data, 0)).unwrap();
+        assert_eq!(Message::try_parse_limited(&data, MAX_MESSAGE_LEN), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_limited_rejects_oversized_lengths() {
+        // longer than the cap
+        let data = vec![16; 64];
+        assert_eq!(Message::try_parse_limited(&data, 32), Err(DiagParsingError::LengthOutOfBounds("data length", 64, 32)));
+
+        // inner_length claims far more than the data holds
+        let data = vec![16, 0, 0xff, 0xff, 0xff, 0xff, 0xeb, 0x11, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(Message::try_parse_limited(&data, MAX_MESSAGE_LEN),
+            Err(DiagParsingError::LengthOutOfBounds("inner_length", 0xffff, 12))));
+
+        // inner_length too short to hold the log's header, which used to
+        // underflow
+        let data = vec![16, 0, 4, 0, 4, 0, 0xeb, 0x11];
+        assert!(matches!(Message::try_parse_limited(&data, MAX_MESSAGE_LEN),
+            Err(DiagParsingError::LengthOutOfBounds("inner_length", 4, _))));
+
+        // UMTS NAS message claiming a 4GB payload
+        let data = vec![
+            16, 0, 20, 0, 20, 0, 0x3a, 0x71, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0xff, 0xff, 0xff, 0xff, 0, 0, 0,
+        ];
+        assert_eq!(Message::try_parse_limited(&data, MAX_MESSAGE_LEN),
+            Err(DiagParsingError::LengthOutOfBounds("length", 0xffffffff, 8)));
+
+        // truncated headers are left for deku to reject
+        assert!(matches!(Message::try_parse_limited(&[16, 0, 1], MAX_MESSAGE_LEN),
+            Err(DiagParsingError::MessageParsingError(_, _))));
+    }
+
     #[test]
     fn test_handles_parsing_errors() {
         let (encapsulated1, message1) = get_test_message(&[1]);
@@ -576,4 +1026,25 @@ mod test {
         assert_eq!(result[0], Ok(message1));
         assert!(matches!(result[1], Err(DiagParsingError::HdlcDecapsulationError(_, _))));
     }
+
+    #[test]
+    fn test_bad_frame_within_a_message() {
+        let (encapsulated1, message1) = get_test_message(&[1]);
+        let (encapsulated2, message2) = get_test_message(&[2]);
+        let mut bad_frame = hdlc::hdlc_encapsulate(&[0x01, 0x02, 0x03, 0x04], &CRC_CCITT);
+        bad_frame[0] = 0xff;
+        let mut data = encapsulated1.data;
+        data.extend_from_slice(&bad_frame);
+        data.extend_from_slice(&encapsulated2.data);
+        let container = make_container(DataType::UserSpace, HdlcEncapsulatedMessage {
+            len: data.len() as u32,
+            data,
+        });
+        let result = container.into_messages();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0], Ok(message1));
+        assert!(matches!(&result[1], Err(DiagParsingError::HdlcDecapsulationError(hdlc::HdlcError::InvalidChecksum(_, _), raw))
+            if raw == &bad_frame));
+        assert_eq!(result[2], Ok(message2));
+    }
 }
@@ -1,13 +1,17 @@
 use crate::hdlc::hdlc_encapsulate;
-use crate::diag::{build_log_mask_request, DataType, DiagParsingError, LogConfigRequest, LogConfigResponse, Message, MessagesContainer, Request, RequestContainer, ResponsePayload, CRC_CCITT};
+use crate::diag::{build_log_mask_request, log_codes_outside_mask, DataType, DiagParsingError, LogConfigRequest, LogConfigResponse, Message, MessagesContainer, Request, RequestContainer, ResponsePayload, CRC_CCITT};
 use crate::log_codes;
 
 use std::io::ErrorKind;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::os::fd::AsRawFd;
 use futures_core::TryStream;
 use thiserror::Error;
 use log::{info, warn, error};
 use deku::prelude::*;
+use serde::Deserialize;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -19,6 +23,8 @@ pub enum DiagDeviceError {
     InitializationFailed(String),
     #[error("Failed to read diag device: {0}")]
     DeviceReadFailed(std::io::Error),
+    #[error("No diag messages received for {0:?}")]
+    Stalled(Duration),
     #[error("Failed to write diag device: {0}")]
     DeviceWriteFailed(std::io::Error),
     #[error("Nonzero status code {0} for diag request: {1:?}")]
@@ -53,9 +59,81 @@ pub const LOG_CODES_FOR_RAW_PACKET_LOGGING: [u32; 11] = [
     log_codes::LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C, // 0xb0ed
 
     // User IP traffic:
-    log_codes::LOG_DATA_PROTOCOL_LOGGING_C // 0x11eb
+    log_codes::LOG_DATA_PROTOCOL_LOGGING_C, // 0x11eb
 ];
 
+// NAS messages only, which is enough to see what the network asks the phone
+// for while keeping recordings small
+const LOG_CODES_MINIMAL: [u32; 5] = [
+    log_codes::LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C, // 0x713a
+    log_codes::LOG_LTE_NAS_ESM_OTA_IN_MSG_LOG_C, // 0xb0e2
+    log_codes::LOG_LTE_NAS_ESM_OTA_OUT_MSG_LOG_C, // 0xb0e3
+    log_codes::LOG_LTE_NAS_EMM_OTA_IN_MSG_LOG_C, // 0xb0ec
+    log_codes::LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C, // 0xb0ed
+];
+
+// Layer 2 and 3 signalling plus NAS, leaving out user IP traffic and
+// measurements
+const LOG_CODES_SIGNALING: [u32; 10] = [
+    log_codes::LOG_GPRS_MAC_SIGNALLING_MESSAGE_C, // 0x5226
+    log_codes::LOG_GSM_RR_SIGNALING_MESSAGE_C, // 0x512f
+    log_codes::WCDMA_SIGNALLING_MESSAGE, // 0x412f
+    log_codes::LOG_LTE_RRC_OTA_MSG_LOG_C, // 0xb0c0
+    log_codes::LOG_NR_RRC_OTA_MSG_LOG_C, // 0xb821
+    log_codes::LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C, // 0x713a
+    log_codes::LOG_LTE_NAS_ESM_OTA_IN_MSG_LOG_C, // 0xb0e2
+    log_codes::LOG_LTE_NAS_ESM_OTA_OUT_MSG_LOG_C, // 0xb0e3
+    log_codes::LOG_LTE_NAS_EMM_OTA_IN_MSG_LOG_C, // 0xb0ec
+    log_codes::LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C, // 0xb0ed
+];
+
+/// Which diag log codes to capture
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureProfile {
+    /// NAS messages only
+    Minimal,
+    /// RRC and NAS signalling, without user IP traffic
+    Signaling,
+    /// Everything in [LOG_CODES_FOR_RAW_PACKET_LOGGING]
+    #[default]
+    Full,
+    /// An explicit list of log codes
+    Custom,
+}
+
+impl CaptureProfile {
+    /// Returns the log codes captured under this profile. `custom_log_codes`
+    /// is only used by, and must only be given for, the custom profile.
+    pub fn log_codes(self, custom_log_codes: &[u32]) -> Result<Vec<u32>, String> {
+        if self != CaptureProfile::Custom && !custom_log_codes.is_empty() {
+            return Err(format!("custom log codes were given, but the capture profile is {:?}", self));
+        }
+        let log_codes = match self {
+            CaptureProfile::Minimal => LOG_CODES_MINIMAL.to_vec(),
+            CaptureProfile::Signaling => LOG_CODES_SIGNALING.to_vec(),
+            CaptureProfile::Full => LOG_CODES_FOR_RAW_PACKET_LOGGING.to_vec(),
+            CaptureProfile::Custom => {
+                if custom_log_codes.is_empty() {
+                    return Err("the custom capture profile needs at least one log code".to_string());
+                }
+                // log codes are a 4 bit log type followed by a 12 bit index
+                if let Some(log_code) = custom_log_codes.iter().find(|&&log_code| log_code > 0xffff) {
+                    return Err(format!("{:#x} isn't a valid log code", log_code));
+                }
+                let mut log_codes = custom_log_codes.to_vec();
+                log_codes.sort();
+                log_codes.dedup();
+                log_codes
+            },
+        };
+        Ok(log_codes)
+    }
+}
+
+/// Where Qualcomm devices expose the diag interface
+pub const DEFAULT_DIAG_DEVICE_PATH: &str = "/dev/diag";
+
 const BUFFER_LEN: usize = 1024 * 1024 * 10;
 const MEMORY_DEVICE_MODE: i32 = 2;
 
@@ -70,17 +148,19 @@ const DIAG_IOCTL_SWITCH_LOGGING: u32 = 7;
 const DIAG_IOCTL_SWITCH_LOGGING: u64 = 7;
 
 pub struct DiagDevice {
+    path: PathBuf,
     file: File,
     read_buf: Vec<u8>,
     use_mdm: i32,
+    log_codes: Vec<u32>,
 }
 
 impl DiagDevice {
-    pub async fn new() -> DiagResult<Self> {
+    pub async fn new<P: AsRef<Path>>(path: P) -> DiagResult<Self> {
         let diag_file = File::options()
             .read(true)
             .write(true)
-            .open("/dev/diag")
+            .open(path.as_ref())
             .await
             .map_err(DiagDeviceError::OpenDiagDeviceError)?;
         let fd = diag_file.as_raw_fd();
@@ -89,12 +169,36 @@ impl DiagDevice {
         let use_mdm = determine_use_mdm(fd)?;
 
         Ok(DiagDevice {
+            path: path.as_ref().to_path_buf(),
             read_buf: vec![0; BUFFER_LEN],
             file: diag_file,
             use_mdm,
+            log_codes: LOG_CODES_FOR_RAW_PACKET_LOGGING.to_vec(),
         })
     }
 
+    /// Sets the log codes [Self::config_logs] enables, which default to
+    /// [LOG_CODES_FOR_RAW_PACKET_LOGGING]. Kept across [Self::reopen].
+    pub fn set_log_codes(&mut self, log_codes: Vec<u32>) {
+        self.log_codes = log_codes;
+    }
+
+    /// Re-opens and reconfigures the diag device, e.g. after the modem has
+    /// reset out from under us. Gives up after `attempts` failed tries,
+    /// waiting `delay` between each.
+    pub async fn reopen(&mut self, attempts: usize, delay: Duration) -> DiagResult<()> {
+        let path = self.path.clone();
+        let log_codes = self.log_codes.clone();
+        let dev = retry_with_delay(attempts, delay, || async {
+            let mut dev = DiagDevice::new(&path).await?;
+            dev.set_log_codes(log_codes.clone());
+            dev.config_logs().await?;
+            Ok(dev)
+        }).await?;
+        *self = dev;
+        Ok(())
+    }
+
     pub fn as_stream(&mut self) -> impl TryStream<Ok = MessagesContainer, Error = DiagDeviceError> + '_ {
         futures::stream::try_unfold(self, |dev| async {
             let container = dev.get_next_messages_container().await?;
@@ -174,8 +278,13 @@ impl DiagDevice {
         Err(DiagDeviceError::NoResponse(req))
     }
 
+    // log_mask_bitsize is the size the device reported for this log type in
+    // its RetrieveIdRanges response, which the mask has to match exactly
     async fn set_log_mask(&mut self, log_type: u32, log_mask_bitsize: u32) -> DiagResult<()> {
-        let req = build_log_mask_request(log_type, log_mask_bitsize, &LOG_CODES_FOR_RAW_PACKET_LOGGING);
+        for log_code in log_codes_outside_mask(log_type, log_mask_bitsize, &self.log_codes) {
+            warn!("log code {:#x} is outside this device's {} bit mask for log type {}, it won't be logged", log_code, log_mask_bitsize, log_type);
+        }
+        let req = build_log_mask_request(log_type, log_mask_bitsize, &self.log_codes);
         self.write_request(&req).await?;
 
         for msg in self.read_response().await? {
@@ -211,6 +320,25 @@ impl DiagDevice {
     }
 }
 
+// Calls `f` until it succeeds, up to `attempts` times, returning the last
+// error if none of them do
+async fn retry_with_delay<T, F, Fut>(attempts: usize, delay: Duration, mut f: F) -> DiagResult<T>
+    where F: FnMut() -> Fut, Fut: Future<Output = DiagResult<T>>
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(result) => return Ok(result),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(err) => {
+                warn!("attempt {}/{} failed: {}", attempt, attempts, err);
+                attempt += 1;
+                tokio::time::sleep(delay).await;
+            },
+        }
+    }
+}
+
 // Triggers the diag device's debug logging mode
 fn enable_frame_readwrite(fd: i32, mode: i32) -> DiagResult<()> {
     unsafe {
@@ -242,3 +370,87 @@ fn determine_use_mdm(fd: i32) -> DiagResult<i32> {
     }
     Ok(use_mdm)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found() -> DiagDeviceError {
+        DiagDeviceError::OpenDiagDeviceError(std::io::Error::from(ErrorKind::NotFound))
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let mut calls = 0;
+        let result = retry_with_delay(5, Duration::ZERO, || {
+            calls += 1;
+            let result = if calls < 3 { Err(not_found()) } else { Ok(calls) };
+            async move { result }
+        }).await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        let mut calls = 0;
+        let result: DiagResult<()> = retry_with_delay(4, Duration::ZERO, || {
+            calls += 1;
+            async { Err(not_found()) }
+        }).await;
+        assert!(matches!(result, Err(DiagDeviceError::OpenDiagDeviceError(_))));
+        assert_eq!(calls, 4);
+    }
+
+    #[tokio::test]
+    async fn test_reopen_gives_up_when_device_is_gone() {
+        let mut dev = DiagDevice {
+            path: PathBuf::from("/nonexistent/rayhunter/diag"),
+            file: File::from_std(std::fs::File::open("/dev/null").unwrap()),
+            read_buf: Vec::new(),
+            use_mdm: 0,
+            log_codes: Vec::new(),
+        };
+        assert!(matches!(dev.reopen(2, Duration::ZERO).await, Err(DiagDeviceError::OpenDiagDeviceError(_))));
+    }
+
+    #[test]
+    fn test_capture_profiles() {
+        let lte_mask = |profile: CaptureProfile, custom_log_codes: &[u32]| {
+            let log_codes = profile.log_codes(custom_log_codes).unwrap();
+            let Request::LogConfig(LogConfigRequest::SetMask { log_mask, .. }) = build_log_mask_request(11, 0x980, &log_codes) else {
+                unreachable!();
+            };
+            crate::diag::log_mask_enabled_codes(11, &log_mask)
+        };
+        assert_eq!(lte_mask(CaptureProfile::Minimal, &[]), [0xb0e2, 0xb0e3, 0xb0ec, 0xb0ed]);
+        assert_eq!(lte_mask(CaptureProfile::Signaling, &[]), [0xb0c0, 0xb0e2, 0xb0e3, 0xb0ec, 0xb0ed, 0xb821]);
+        assert_eq!(lte_mask(CaptureProfile::Full, &[]), [0xb0c0, 0xb0e2, 0xb0e3, 0xb0ec, 0xb0ed, 0xb821]);
+        assert_eq!(lte_mask(CaptureProfile::Custom, &[0xb0c0, 0x11eb, 0xb0c0]), [0xb0c0]);
+        assert_eq!(CaptureProfile::default(), CaptureProfile::Full);
+
+        // GPRS/EDGE MAC signalling is captured unless only NAS is wanted
+        let has_gprs_mac = |profile: CaptureProfile| profile.log_codes(&[]).unwrap()
+            .contains(&log_codes::LOG_GPRS_MAC_SIGNALLING_MESSAGE_C);
+        assert!(has_gprs_mac(CaptureProfile::Full));
+        assert!(has_gprs_mac(CaptureProfile::Signaling));
+        assert!(!has_gprs_mac(CaptureProfile::Minimal));
+    }
+
+    #[test]
+    fn test_capture_profile_validation() {
+        assert!(CaptureProfile::Custom.log_codes(&[]).is_err());
+        assert!(CaptureProfile::Custom.log_codes(&[0x1b0c0]).is_err());
+        assert!(CaptureProfile::Minimal.log_codes(&[0xb0c0]).is_err());
+        assert_eq!(CaptureProfile::Custom.log_codes(&[0xb0c0, 0x11eb]).unwrap(), [0x11eb, 0xb0c0]);
+    }
+
+    #[tokio::test]
+    async fn test_opens_given_path() {
+        let result = DiagDevice::new("/nonexistent/rayhunter/diag").await;
+        match result {
+            Err(DiagDeviceError::OpenDiagDeviceError(err)) => assert_eq!(err.kind(), ErrorKind::NotFound),
+            _ => panic!("expected OpenDiagDeviceError"),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Minimal parsing of GPRS/EDGE RLC/MAC control blocks (3GPP TS 44.060).
+//! Rather than fully decoding them, we only locate the message type.
+
+use std::fmt;
+
+// downlink message types, TS 44.060 table 11.2.0.1
+pub const PACKET_CELL_CHANGE_ORDER: u8 = 0x01;
+pub const PACKET_DOWNLINK_ASSIGNMENT: u8 = 0x02;
+pub const PACKET_MEASUREMENT_ORDER: u8 = 0x03;
+pub const PACKET_POLLING_REQUEST: u8 = 0x04;
+pub const PACKET_POWER_CONTROL_TIMING_ADVANCE: u8 = 0x05;
+pub const PACKET_QUEUEING_NOTIFICATION: u8 = 0x06;
+pub const PACKET_TIMESLOT_RECONFIGURE: u8 = 0x07;
+pub const PACKET_TBF_RELEASE: u8 = 0x08;
+pub const PACKET_UPLINK_ASSIGNMENT: u8 = 0x09;
+pub const PACKET_ACCESS_REJECT: u8 = 0x21;
+pub const PACKET_PAGING_REQUEST: u8 = 0x22;
+pub const PACKET_PDCH_RELEASE: u8 = 0x23;
+pub const PACKET_PRACH_PARAMETERS: u8 = 0x24;
+pub const PACKET_DOWNLINK_DUMMY_CONTROL_BLOCK: u8 = 0x25;
+
+// uplink message types, TS 44.060 table 11.2.0.2
+pub const PACKET_CELL_CHANGE_FAILURE: u8 = 0x00;
+pub const PACKET_CONTROL_ACKNOWLEDGEMENT: u8 = 0x01;
+pub const PACKET_DOWNLINK_ACK_NACK: u8 = 0x02;
+pub const PACKET_UPLINK_DUMMY_CONTROL_BLOCK: u8 = 0x03;
+pub const PACKET_MEASUREMENT_REPORT: u8 = 0x04;
+pub const PACKET_RESOURCE_REQUEST: u8 = 0x05;
+pub const PACKET_MOBILE_TBF_STATUS: u8 = 0x06;
+pub const PACKET_PSI_STATUS: u8 = 0x07;
+
+// the payload type in the top two bits of the MAC header
+const PAYLOAD_TYPE_CONTROL_BLOCK: u8 = 0b01;
+const PAYLOAD_TYPE_CONTROL_BLOCK_OPTIONAL_OCTETS: u8 = 0b10;
+
+/// An RLC/MAC control message, sent on the PACCH
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GprsMacMessage {
+    pub uplink: bool,
+    pub message_type: u8,
+}
+
+impl GprsMacMessage {
+    /// Reads the message type out of an RLC/MAC control block, starting at
+    /// its MAC header. Returns None for RLC data blocks and truncated blocks.
+    pub fn parse(uplink: bool, block: &[u8]) -> Option<Self> {
+        let payload_type = block.first()? >> 6;
+        let offset = match (uplink, payload_type) {
+            (_, PAYLOAD_TYPE_CONTROL_BLOCK) => 1,
+            // downlink control blocks may carry one or two optional octets
+            // after the MAC header, the second one present if the first's AC
+            // bit is set
+            (false, PAYLOAD_TYPE_CONTROL_BLOCK_OPTIONAL_OCTETS) => {
+                if block.get(1)? & 0x01 == 1 { 3 } else { 2 }
+            },
+            _ => return None,
+        };
+        Some(GprsMacMessage {
+            uplink,
+            message_type: block.get(offset)? >> 2,
+        })
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        let name = if self.uplink {
+            match self.message_type {
+                PACKET_CELL_CHANGE_FAILURE => "Packet Cell Change Failure",
+                PACKET_CONTROL_ACKNOWLEDGEMENT => "Packet Control Acknowledgement",
+                PACKET_DOWNLINK_ACK_NACK => "Packet Downlink Ack/Nack",
+                PACKET_UPLINK_DUMMY_CONTROL_BLOCK => "Packet Uplink Dummy Control Block",
+                PACKET_MEASUREMENT_REPORT => "Packet Measurement Report",
+                PACKET_RESOURCE_REQUEST => "Packet Resource Request",
+                PACKET_MOBILE_TBF_STATUS => "Packet Mobile TBF Status",
+                PACKET_PSI_STATUS => "Packet PSI Status",
+                _ => return None,
+            }
+        } else {
+            match self.message_type {
+                PACKET_CELL_CHANGE_ORDER => "Packet Cell Change Order",
+                PACKET_DOWNLINK_ASSIGNMENT => "Packet Downlink Assignment",
+                PACKET_MEASUREMENT_ORDER => "Packet Measurement Order",
+                PACKET_POLLING_REQUEST => "Packet Polling Request",
+                PACKET_POWER_CONTROL_TIMING_ADVANCE => "Packet Power Control/Timing Advance",
+                PACKET_QUEUEING_NOTIFICATION => "Packet Queueing Notification",
+                PACKET_TIMESLOT_RECONFIGURE => "Packet Timeslot Reconfigure",
+                PACKET_TBF_RELEASE => "Packet TBF Release",
+                PACKET_UPLINK_ASSIGNMENT => "Packet Uplink Assignment",
+                PACKET_ACCESS_REJECT => "Packet Access Reject",
+                PACKET_PAGING_REQUEST => "Packet Paging Request",
+                PACKET_PDCH_RELEASE => "Packet PDCH Release",
+                PACKET_PRACH_PARAMETERS => "Packet PRACH Parameters",
+                PACKET_DOWNLINK_DUMMY_CONTROL_BLOCK => "Packet Downlink Dummy Control Block",
+                0x30..=0x37 => "Packet System Information",
+                _ => return None,
+            }
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for GprsMacMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{} RLC/MAC message type {:#04x}", if self.uplink { "uplink" } else { "downlink" }, self.message_type),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_downlink() {
+        // Packet Uplink Assignment: MAC header with payload type 01, then the
+        // message type and page mode
+        let msg = GprsMacMessage::parse(false, &[0x40, 0x24, 0x00, 0x10]).unwrap();
+        assert_eq!(msg.message_type, PACKET_UPLINK_ASSIGNMENT);
+        assert_eq!(msg.to_string(), "Packet Uplink Assignment");
+
+        // Packet Paging Request with one optional octet
+        let msg = GprsMacMessage::parse(false, &[0x80, 0x00, 0x88, 0x00]).unwrap();
+        assert_eq!(msg.message_type, PACKET_PAGING_REQUEST);
+        // ...and with two
+        let msg = GprsMacMessage::parse(false, &[0x80, 0x01, 0x00, 0x84]).unwrap();
+        assert_eq!(msg.message_type, PACKET_ACCESS_REJECT);
+
+        // RLC data blocks aren't control messages
+        assert_eq!(GprsMacMessage::parse(false, &[0x00, 0x24]), None);
+        assert_eq!(GprsMacMessage::parse(false, &[0x40]), None);
+    }
+
+    #[test]
+    fn test_parse_uplink() {
+        let msg = GprsMacMessage::parse(true, &[0x40, 0x14, 0x00]).unwrap();
+        assert_eq!(msg.message_type, PACKET_RESOURCE_REQUEST);
+        assert_eq!(msg.to_string(), "Packet Resource Request");
+        // the same message type means something else downlink
+        let msg = GprsMacMessage::parse(false, &[0x40, 0x14, 0x00]).unwrap();
+        assert_eq!(msg.to_string(), "Packet Power Control/Timing Advance");
+
+        // uplink control blocks don't have optional octets
+        assert_eq!(GprsMacMessage::parse(true, &[0x80, 0x14]), None);
+        let msg = GprsMacMessage::parse(true, &[0x40, 0xfc]).unwrap();
+        assert_eq!(msg.to_string(), "uplink RLC/MAC message type 0x3f");
+    }
+}
@@ -2,6 +2,9 @@
 
 use deku::prelude::*;
 
+/// Set in a GSM header's ARFCN for uplink messages
+pub const GSMTAP_ARFCN_F_UPLINK: u16 = 0x4000;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum GsmtapType {
     Um(UmSubtype),
@@ -239,3 +242,11 @@ pub struct GsmtapMessage {
     pub header: GsmtapHeader,
     pub payload: Vec<u8>,
 }
+
+impl GsmtapMessage {
+    /// Serializes the message as it's sent in a GSMTAP UDP datagram: the
+    /// header followed by the payload
+    pub fn to_datagram(&self) -> Result<Vec<u8>, DekuError> {
+        self.to_bytes()
+    }
+}
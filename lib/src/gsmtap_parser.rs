@@ -1,5 +1,6 @@
 use crate::diag::*;
 use crate::gsmtap::*;
+use crate::log_codes;
 
 use log::error;
 use thiserror::Error;
@@ -10,6 +11,12 @@ pub enum GsmtapParserError {
     InvalidLteRrcOtaExtHeaderVersion(u8),
     #[error("Invalid LteRrcOtaMessage header/PDU number combination: {0}/{1}")]
     InvalidLteRrcOtaHeaderPduNum(u8, u8),
+    #[error("Invalid WcdmaSignallingMessage channel type {0}")]
+    InvalidWcdmaChannelType(u8),
+    #[error("Invalid WcdmaSignallingMessage SIB type {0}")]
+    InvalidWcdmaSibType(u8),
+    #[error("WcdmaSignallingMessage extension SIB is missing its SIB type")]
+    MissingWcdmaSibType,
 }
 
 pub fn parse(msg: Message) -> Result<Option<(Timestamp, GsmtapMessage)>, GsmtapParserError> {
@@ -108,6 +115,34 @@ fn log_to_gsmtap(value: LogBody) -> Result<Option<GsmtapMessage>, GsmtapParserEr
                 payload: packet.take_payload(),
             }))
         },
+        LogBody::WcdmaSignallingMessage { channel_type, msg, .. } => {
+            // based on https://github.com/fgsect/scat/blob/97442580e628de414c9f7c2a185f4e28d0ee7523/src/scat/parsers/qualcomm/diagwcdmalogparser.py
+            let (subtype, payload) = match channel_type as u32 {
+                log_codes::RRCLOG_SIG_UL_CCCH => (UmtsRrcSubtype::UlCcch, msg),
+                log_codes::RRCLOG_SIG_UL_DCCH => (UmtsRrcSubtype::UlDcch, msg),
+                log_codes::RRCLOG_SIG_DL_CCCH => (UmtsRrcSubtype::DlCcch, msg),
+                log_codes::RRCLOG_SIG_DL_DCCH => (UmtsRrcSubtype::DlDcch, msg),
+                log_codes::RRCLOG_SIG_DL_BCCH_BCH => (UmtsRrcSubtype::BcchBch, msg),
+                log_codes::RRCLOG_SIG_DL_BCCH_FACH => (UmtsRrcSubtype::BcchFach, msg),
+                log_codes::RRCLOG_SIG_DL_PCCH => (UmtsRrcSubtype::Pcch, msg),
+                log_codes::RRCLOG_SIG_DL_MCCH => (UmtsRrcSubtype::Mcch, msg),
+                log_codes::RRCLOG_SIG_DL_MSCH => (UmtsRrcSubtype::Msch, msg),
+                log_codes::RRCLOG_SIB_CONTAINER => (UmtsRrcSubtype::SystemInformationContainer, msg),
+                // extension SIBs are prefixed with a single byte indicating
+                // which SIB type follows
+                log_codes::RRCLOG_EXTENSION_SIB => {
+                    let (&sib_type, sib) = msg.split_first()
+                        .ok_or(GsmtapParserError::MissingWcdmaSibType)?;
+                    (umts_sib_type_to_subtype(sib_type)?, sib.to_vec())
+                },
+                _ => return Err(GsmtapParserError::InvalidWcdmaChannelType(channel_type)),
+            };
+            let header = GsmtapHeader::new(GsmtapType::UmtsRrc(subtype));
+            Ok(Some(GsmtapMessage {
+                header,
+                payload,
+            }))
+        },
         LogBody::Nas4GMessage { msg, .. } => {
             // currently we only handle "plain" (i.e. non-secure) NAS messages
             let header = GsmtapHeader::new(GsmtapType::LteNas(LteNasSubtype::Plain));
@@ -116,9 +151,150 @@ fn log_to_gsmtap(value: LogBody) -> Result<Option<GsmtapMessage>, GsmtapParserEr
                 payload: msg,
             }))
         },
+        LogBody::GprsMacSignallingMessage { channel_type, msg, .. } => {
+            // based on https://github.com/fgsect/scat/blob/97442580e628de414c9f7c2a185f4e28d0ee7523/src/scat/parsers/qualcomm/diaggsmlogparser.py,
+            // the top bit of the channel type is set for downlink messages.
+            // RLC/MAC control messages are all sent on the PACCH.
+            let mut header = GsmtapHeader::new(GsmtapType::Um(UmSubtype::Pacch));
+            if channel_type & 0x80 == 0 {
+                header.arfcn |= GSMTAP_ARFCN_F_UPLINK;
+            }
+            Ok(Some(GsmtapMessage {
+                header,
+                payload: msg,
+            }))
+        },
+        // measurements aren't over-the-air messages, so they have no GSMTAP
+        // equivalent
+        LogBody::NrMl1SearcherMeasDbUpdate { .. } => Ok(None),
+        // user-plane IP traffic isn't wrapped in GSMTAP, see the ip module
+        LogBody::IpTraffic { .. } => Ok(None),
         _ => {
             error!("gsmtap_sink: ignoring unhandled log type: {:?}", value);
             Ok(None)
         },
     }
 }
+
+fn umts_sib_type_to_subtype(sib_type: u8) -> Result<UmtsRrcSubtype, GsmtapParserError> {
+    Ok(match sib_type {
+        0 => UmtsRrcSubtype::MasterInformationBlock,
+        1 => UmtsRrcSubtype::SysInfoType1,
+        2 => UmtsRrcSubtype::SysInfoType2,
+        3 => UmtsRrcSubtype::SysInfoType3,
+        4 => UmtsRrcSubtype::SysInfoType4,
+        5 => UmtsRrcSubtype::SysInfoType5,
+        6 => UmtsRrcSubtype::SysInfoType6,
+        7 => UmtsRrcSubtype::SysInfoType7,
+        8 => UmtsRrcSubtype::SysInfoType8,
+        9 => UmtsRrcSubtype::SysInfoType9,
+        10 => UmtsRrcSubtype::SysInfoType10,
+        11 => UmtsRrcSubtype::SysInfoType11,
+        12 => UmtsRrcSubtype::SysInfoType12,
+        13 => UmtsRrcSubtype::SysInfoType13,
+        14 => UmtsRrcSubtype::SysInfoType13_1,
+        15 => UmtsRrcSubtype::SysInfoType13_2,
+        16 => UmtsRrcSubtype::SysInfoType13_3,
+        17 => UmtsRrcSubtype::SysInfoType13_4,
+        18 => UmtsRrcSubtype::SysInfoType14,
+        19 => UmtsRrcSubtype::SysInfoType15,
+        20 => UmtsRrcSubtype::SysInfoType15_1,
+        21 => UmtsRrcSubtype::SysInfoType15_2,
+        22 => UmtsRrcSubtype::SysInfoType15_3,
+        23 => UmtsRrcSubtype::SysInfoType16,
+        24 => UmtsRrcSubtype::SysInfoType17,
+        25 => UmtsRrcSubtype::SysInfoType15_4,
+        26 => UmtsRrcSubtype::SysInfoType18,
+        27 => UmtsRrcSubtype::SysInfoTypeSB1,
+        28 => UmtsRrcSubtype::SysInfoTypeSB2,
+        29 => UmtsRrcSubtype::SysInfoType15_5,
+        30 => UmtsRrcSubtype::SysInfoType5bis,
+        31 | 66 => UmtsRrcSubtype::SysInfoType11bis,
+        67 => UmtsRrcSubtype::SysInfoType19,
+        _ => return Err(GsmtapParserError::InvalidWcdmaSibType(sib_type)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deku::prelude::*;
+
+    fn wcdma_log(channel_type: u8, msg: &[u8]) -> Vec<u8> {
+        let inner_length = 12 + 4 + msg.len() as u16;
+        let mut data = vec![16, 0];
+        data.extend(inner_length.to_le_bytes());
+        data.extend(inner_length.to_le_bytes());
+        data.extend(0x412f_u16.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend([channel_type, 0]);
+        data.extend((msg.len() as u16).to_le_bytes());
+        data.extend(msg);
+        data
+    }
+
+    fn gprs_mac_log(channel_type: u8, message_type: u8, msg: &[u8]) -> Vec<u8> {
+        let inner_length = 12 + 3 + msg.len() as u16;
+        let mut data = vec![16, 0];
+        data.extend(inner_length.to_le_bytes());
+        data.extend(inner_length.to_le_bytes());
+        data.extend(0x5226_u16.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend([channel_type, message_type, msg.len() as u8]);
+        data.extend(msg);
+        data
+    }
+
+    #[test]
+    fn test_gprs_mac_signalling() {
+        // a downlink Packet Uplink Assignment on the PACCH
+        let block = [0x40, 0x24, 0x00, 0x10, 0x2b, 0x2b];
+        let data = gprs_mac_log(0x83, 0x09, &block);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        let Message::Log { body: LogBody::GprsMacSignallingMessage { channel_type, message_type, .. }, .. } = &msg else {
+            panic!("expected a GprsMacSignallingMessage, got {:?}", msg);
+        };
+        assert_eq!((*channel_type, *message_type), (0x83, 0x09));
+        let (_, gsmtap_msg) = parse(msg).unwrap().unwrap();
+        assert_eq!(gsmtap_msg.header.gsmtap_type, GsmtapType::Um(UmSubtype::Pacch));
+        assert_eq!(gsmtap_msg.header.packet_type, 0x01);
+        assert_eq!(gsmtap_msg.header.subtype, UmSubtype::Pacch as u8);
+        assert_eq!(gsmtap_msg.header.arfcn & GSMTAP_ARFCN_F_UPLINK, 0);
+        assert_eq!(gsmtap_msg.payload, block);
+
+        // the same block uplink gets flagged as such
+        let data = gprs_mac_log(0x03, 0x09, &block);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        let (_, gsmtap_msg) = parse(msg).unwrap().unwrap();
+        assert_eq!(gsmtap_msg.header.arfcn & GSMTAP_ARFCN_F_UPLINK, GSMTAP_ARFCN_F_UPLINK);
+    }
+
+    #[test]
+    fn test_wcdma_extension_sib() {
+        // SIB3 extension: the first byte of the payload is the SIB type
+        let data = wcdma_log(log_codes::RRCLOG_EXTENSION_SIB as u8, &[3, 0xaa, 0xbb, 0xcc]);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        let (_, gsmtap_msg) = parse(msg).unwrap().unwrap();
+        assert_eq!(gsmtap_msg.header.gsmtap_type, GsmtapType::UmtsRrc(UmtsRrcSubtype::SysInfoType3));
+        assert_eq!(gsmtap_msg.header.packet_type, 0x0c);
+        assert_eq!(gsmtap_msg.header.subtype, UmtsRrcSubtype::SysInfoType3 as u8);
+        assert_eq!(gsmtap_msg.payload, vec![0xaa, 0xbb, 0xcc]);
+    }
+
+    #[test]
+    fn test_wcdma_channel_types() {
+        let data = wcdma_log(log_codes::RRCLOG_SIG_DL_BCCH_BCH as u8, &[0x01, 0x02]);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        let (_, gsmtap_msg) = parse(msg).unwrap().unwrap();
+        assert_eq!(gsmtap_msg.header.gsmtap_type, GsmtapType::UmtsRrc(UmtsRrcSubtype::BcchBch));
+        assert_eq!(gsmtap_msg.payload, vec![0x01, 0x02]);
+
+        let data = wcdma_log(0xfe, &[0x01]);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        assert!(matches!(parse(msg), Err(GsmtapParserError::InvalidWcdmaChannelType(0xfe))));
+
+        let data = wcdma_log(log_codes::RRCLOG_EXTENSION_SIB as u8, &[]);
+        let (_, msg) = Message::from_bytes((&data, 0)).unwrap();
+        assert!(matches!(parse(msg), Err(GsmtapParserError::MissingWcdmaSibType)));
+    }
+}
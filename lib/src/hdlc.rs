@@ -3,6 +3,8 @@
 //! here:
 //! https://github.com/P1sec/QCSuper/blob/master/docs/The%20Diag%20protocol.md#the-diag-protocol-over-usb
 
+use std::collections::VecDeque;
+
 use crc::Crc;
 use bytes::Buf;
 use thiserror::Error;
@@ -83,6 +85,89 @@ pub fn hdlc_decapsulate(data: &[u8], crc: &Crc<u16>) -> Result<Vec<u8>, HdlcErro
     Ok(unescaped)
 }
 
+/// Incrementally decapsulates a stream of HDLC frames. Bytes can be pushed in
+/// arbitrary chunks, and each frame is unescaped and checksummed as soon as
+/// its terminator arrives, so only the frame currently being received is
+/// buffered rather than the whole input.
+pub struct HdlcDecoder<'a> {
+    crc: &'a Crc<u16>,
+    // the current frame, unescaped
+    frame: Vec<u8>,
+    // how many raw bytes the current frame has had so far
+    raw_len: usize,
+    escaping: bool,
+    // the first error in the current frame, reported once it's terminated
+    error: Option<HdlcError>,
+    frames: VecDeque<Result<Vec<u8>, HdlcError>>,
+}
+
+impl<'a> HdlcDecoder<'a> {
+    pub fn new(crc: &'a Crc<u16>) -> Self {
+        HdlcDecoder {
+            crc,
+            frame: Vec::new(),
+            raw_len: 0,
+            escaping: false,
+            error: None,
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        for &b in data {
+            if b == MESSAGE_TERMINATOR {
+                let result = self.finish_frame();
+                self.frames.push_back(result);
+                continue;
+            }
+            self.raw_len += 1;
+            if self.escaping {
+                match b {
+                    ESCAPED_MESSAGE_TERMINATOR => self.frame.push(MESSAGE_TERMINATOR),
+                    ESCAPED_MESSAGE_ESCAPE_CHAR => self.frame.push(MESSAGE_ESCAPE_CHAR),
+                    _ => { self.error.get_or_insert(HdlcError::InvalidEscapeSequence(b)); },
+                }
+                self.escaping = false;
+            } else if b == MESSAGE_ESCAPE_CHAR {
+                self.escaping = true;
+            } else {
+                self.frame.push(b);
+            }
+        }
+    }
+
+    /// Returns the next complete frame (or the error decapsulating it), if
+    /// one's been received
+    pub fn pop_frame(&mut self) -> Option<Result<Vec<u8>, HdlcError>> {
+        self.frames.pop_front()
+    }
+
+    /// Whether part of a frame has been received but not yet terminated
+    pub fn has_partial_frame(&self) -> bool {
+        self.raw_len > 0
+    }
+
+    fn finish_frame(&mut self) -> Result<Vec<u8>, HdlcError> {
+        let mut frame = std::mem::take(&mut self.frame);
+        let raw_len = std::mem::take(&mut self.raw_len);
+        self.escaping = false;
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        // same as hdlc_decapsulate, which counts the terminator
+        if raw_len + 1 < 3 {
+            return Err(HdlcError::TooShort);
+        }
+        let checksum_hi = frame.pop().ok_or(HdlcError::MissingChecksum)?;
+        let checksum_lo = frame.pop().ok_or(HdlcError::MissingChecksum)?;
+        let checksum = [checksum_lo, checksum_hi].as_slice().get_u16_le();
+        if checksum != self.crc.checksum(&frame) {
+            return Err(HdlcError::InvalidChecksum(checksum, self.crc.checksum(&frame)));
+        }
+        Ok(frame)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,4 +181,56 @@ mod tests {
         assert_eq!(&encapsulated, &expected);
         assert_eq!(hdlc_decapsulate(&encapsulated, &crc), Ok(data));
     }
+
+    #[test]
+    fn test_decoder_across_chunk_boundaries() {
+        let crc = Crc::<u16>::new(&crate::diag::CRC_CCITT_ALG);
+        // include bytes that need escaping, so escape sequences get split too
+        let frames = vec![
+            vec![0x01, MESSAGE_TERMINATOR, 0x02],
+            vec![MESSAGE_ESCAPE_CHAR, 0x03, 0x04, 0x05],
+            vec![0x06],
+        ];
+        let stream: Vec<u8> = frames.iter()
+            .flat_map(|frame| hdlc_encapsulate(frame, &crc))
+            .collect();
+        for chunk_size in 1..=stream.len() {
+            let mut decoder = HdlcDecoder::new(&crc);
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(chunk_size) {
+                decoder.push_bytes(chunk);
+                while let Some(frame) = decoder.pop_frame() {
+                    decoded.push(frame.unwrap());
+                }
+            }
+            assert_eq!(decoded, frames, "chunk size {}", chunk_size);
+            assert!(!decoder.has_partial_frame());
+        }
+    }
+
+    #[test]
+    fn test_decoder_errors_match_decapsulate() {
+        let crc = Crc::<u16>::new(&crate::diag::CRC_CCITT_ALG);
+        let mut bad_checksum = hdlc_encapsulate(&[0x01, 0x02, 0x03, 0x04], &crc);
+        bad_checksum[0] = 0xff;
+        let bad_frames = [
+            bad_checksum,
+            vec![0x01, MESSAGE_ESCAPE_CHAR, 0x01, 0x02, MESSAGE_TERMINATOR],
+            vec![0x01, MESSAGE_TERMINATOR],
+        ];
+        let mut decoder = HdlcDecoder::new(&crc);
+        for frame in &bad_frames {
+            decoder.push_bytes(frame);
+            let result = decoder.pop_frame().unwrap();
+            assert!(result.is_err());
+            assert_eq!(result, hdlc_decapsulate(frame, &crc));
+        }
+        // a bad frame doesn't affect the next one
+        decoder.push_bytes(&hdlc_encapsulate(&[0x01], &crc));
+        assert_eq!(decoder.pop_frame(), Some(Ok(vec![0x01])));
+
+        decoder.push_bytes(&[0x01, 0x02]);
+        assert_eq!(decoder.pop_frame(), None);
+        assert!(decoder.has_partial_frame());
+    }
 }
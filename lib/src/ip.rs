@@ -0,0 +1,133 @@
+//! Decoding of the user-plane IP packets carried in
+//! LOG_DATA_PROTOCOL_LOGGING_C (0x11eb) logs.
+
+use thiserror::Error;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_FRAGMENT_HEADER: u8 = 44;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum IpPacketError {
+    #[error("Empty IP packet")]
+    Empty,
+    #[error("Unknown IP version {0}")]
+    UnknownVersion(u8),
+    #[error("Truncated IPv{0} packet: expected {1} bytes, got {2}")]
+    Truncated(u8, usize, usize),
+    #[error("IPv{0} packet is a fragment")]
+    Fragment(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpPacket {
+    pub version: IpVersion,
+    pub data: Vec<u8>,
+}
+
+/// Identifies the IP version of a logged packet and checks that it's
+/// complete. Diag splits large packets across several logs and doesn't
+/// always log every piece, so rather than try to reassemble them, anything
+/// that isn't a whole, unfragmented packet is rejected. Any trailing bytes
+/// past the packet's length are dropped.
+pub fn decode_ip_packet(mut data: Vec<u8>) -> Result<IpPacket, IpPacketError> {
+    let first = *data.first().ok_or(IpPacketError::Empty)?;
+    let (version, expected_len) = match first >> 4 {
+        4 => {
+            if data.len() < IPV4_MIN_HEADER_LEN {
+                return Err(IpPacketError::Truncated(4, IPV4_MIN_HEADER_LEN, data.len()));
+            }
+            let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+            // "more fragments" flag or a non-zero fragment offset
+            let flags_and_offset = u16::from_be_bytes([data[6], data[7]]);
+            if flags_and_offset & 0x3fff != 0 {
+                return Err(IpPacketError::Fragment(4));
+            }
+            (IpVersion::V4, total_len)
+        },
+        6 => {
+            if data.len() < IPV6_HEADER_LEN {
+                return Err(IpPacketError::Truncated(6, IPV6_HEADER_LEN, data.len()));
+            }
+            let payload_len = u16::from_be_bytes([data[4], data[5]]) as usize;
+            if data[6] == IPV6_FRAGMENT_HEADER {
+                return Err(IpPacketError::Fragment(6));
+            }
+            (IpVersion::V6, IPV6_HEADER_LEN + payload_len)
+        },
+        other => return Err(IpPacketError::UnknownVersion(other)),
+    };
+    if data.len() < expected_len {
+        let version_num = match version {
+            IpVersion::V4 => 4,
+            IpVersion::V6 => 6,
+        };
+        return Err(IpPacketError::Truncated(version_num, expected_len, data.len()));
+    }
+    data.truncate(expected_len);
+    Ok(IpPacket { version, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // UDP DNS query from 10.0.0.2 to 8.8.8.8
+    const IPV4_PACKET: [u8; 36] = [
+        0x45, 0x00, 0x00, 0x24, 0x12, 0x34, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+        0x0a, 0x00, 0x00, 0x02, 0x08, 0x08, 0x08, 0x08,
+        0xd4, 0x31, 0x00, 0x35, 0x00, 0x10, 0x00, 0x00,
+        0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00,
+    ];
+
+    // ICMPv6 echo request from 2001:db8::2 to 2001:db8::1
+    const IPV6_PACKET: [u8; 48] = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x08, 0x3a, 0x40,
+        0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x80, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    #[test]
+    fn test_decode_ipv4() {
+        let packet = decode_ip_packet(IPV4_PACKET.to_vec()).unwrap();
+        assert_eq!(packet.version, IpVersion::V4);
+        assert_eq!(packet.data, IPV4_PACKET.to_vec());
+    }
+
+    #[test]
+    fn test_decode_ipv6() {
+        let packet = decode_ip_packet(IPV6_PACKET.to_vec()).unwrap();
+        assert_eq!(packet.version, IpVersion::V6);
+        assert_eq!(packet.data, IPV6_PACKET.to_vec());
+    }
+
+    #[test]
+    fn test_trailing_bytes_are_dropped() {
+        let mut data = IPV6_PACKET.to_vec();
+        data.extend([0, 0, 0, 0]);
+        let packet = decode_ip_packet(data).unwrap();
+        assert_eq!(packet.data, IPV6_PACKET.to_vec());
+    }
+
+    #[test]
+    fn test_incomplete_packets_are_rejected() {
+        assert_eq!(decode_ip_packet(IPV4_PACKET[..30].to_vec()), Err(IpPacketError::Truncated(4, 36, 30)));
+        assert_eq!(decode_ip_packet(IPV6_PACKET[..20].to_vec()), Err(IpPacketError::Truncated(6, 40, 20)));
+        assert_eq!(decode_ip_packet(Vec::new()), Err(IpPacketError::Empty));
+        // the tail end of a segmented packet won't start with an IP header
+        assert_eq!(decode_ip_packet(vec![0xab, 0xcd, 0x01]), Err(IpPacketError::UnknownVersion(0xa)));
+
+        let mut fragment = IPV4_PACKET.to_vec();
+        fragment[6] = 0x20; // more fragments
+        assert_eq!(decode_ip_packet(fragment), Err(IpPacketError::Fragment(4)));
+    }
+}
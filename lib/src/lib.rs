@@ -7,3 +7,11 @@ pub mod gsmtap;
 pub mod gsmtap_parser;
 pub mod pcap;
 pub mod analysis;
+pub mod nas;
+pub mod gprs_mac;
+pub mod ip;
+pub mod cell_summary;
+pub mod signal_series;
+pub mod cellular_data;
+pub mod lte_bands;
+pub mod nr_bands;
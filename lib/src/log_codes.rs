@@ -28,6 +28,7 @@ pub const PACKET_CHANNEL_REQUEST: u32 = 0x20;
 // These are 5G-related log types.
 
 pub const LOG_NR_RRC_OTA_MSG_LOG_C: u32 = 0xb821;
+pub const LOG_NR_ML1_SEARCHER_MEAS_DB_UPDATE_C: u32 = 0xb97f;
 
 // These are 4G-related log types.
 
@@ -106,3 +107,23 @@ pub const WCDMA_SIGNALLING_MESSAGE: u32 = 0x412f;
 pub const LOG_DATA_PROTOCOL_LOGGING_C: u32 = 0x11eb;
 
 pub const LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C: u32 = 0x713a;
+
+/// Returns the name of one of the log codes above, or None if it's not one we
+/// know about.
+pub fn log_code_name(log_code: u32) -> Option<&'static str> {
+    Some(match log_code {
+        LOG_GSM_RR_SIGNALING_MESSAGE_C => "LOG_GSM_RR_SIGNALING_MESSAGE_C",
+        LOG_GPRS_MAC_SIGNALLING_MESSAGE_C => "LOG_GPRS_MAC_SIGNALLING_MESSAGE_C",
+        LOG_NR_RRC_OTA_MSG_LOG_C => "LOG_NR_RRC_OTA_MSG_LOG_C",
+        LOG_NR_ML1_SEARCHER_MEAS_DB_UPDATE_C => "LOG_NR_ML1_SEARCHER_MEAS_DB_UPDATE_C",
+        LOG_LTE_RRC_OTA_MSG_LOG_C => "LOG_LTE_RRC_OTA_MSG_LOG_C",
+        LOG_LTE_NAS_ESM_OTA_IN_MSG_LOG_C => "LOG_LTE_NAS_ESM_OTA_IN_MSG_LOG_C",
+        LOG_LTE_NAS_ESM_OTA_OUT_MSG_LOG_C => "LOG_LTE_NAS_ESM_OTA_OUT_MSG_LOG_C",
+        LOG_LTE_NAS_EMM_OTA_IN_MSG_LOG_C => "LOG_LTE_NAS_EMM_OTA_IN_MSG_LOG_C",
+        LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C => "LOG_LTE_NAS_EMM_OTA_OUT_MSG_LOG_C",
+        WCDMA_SIGNALLING_MESSAGE => "WCDMA_SIGNALLING_MESSAGE",
+        LOG_DATA_PROTOCOL_LOGGING_C => "LOG_DATA_PROTOCOL_LOGGING_C",
+        LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C => "LOG_UMTS_NAS_OTA_MESSAGE_LOG_PACKET_C",
+        _ => return None,
+    })
+}
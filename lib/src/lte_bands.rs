@@ -0,0 +1,146 @@
+//! The E-UTRA operating bands and the downlink EARFCNs belonging to each,
+//! from 3GPP TS 36.101 table 5.7.3-1. Only downlink EARFCNs are covered, since
+//! those are all the diag logs and system information ever refer to.
+
+use std::ops::RangeInclusive;
+
+// (band, first downlink EARFCN, last downlink EARFCN), in EARFCN order
+const BANDS: [(u8, u32, u32); 66] = [
+    (1, 0, 599),
+    (2, 600, 1199),
+    (3, 1200, 1949),
+    (4, 1950, 2399),
+    (5, 2400, 2649),
+    (6, 2650, 2749),
+    (7, 2750, 3449),
+    (8, 3450, 3799),
+    (9, 3800, 4149),
+    (10, 4150, 4749),
+    (11, 4750, 4949),
+    (12, 5010, 5179),
+    (13, 5180, 5279),
+    (14, 5280, 5379),
+    (17, 5730, 5849),
+    (18, 5850, 5999),
+    (19, 6000, 6149),
+    (20, 6150, 6449),
+    (21, 6450, 6599),
+    (22, 6600, 7399),
+    (23, 7500, 7699),
+    (24, 7700, 8039),
+    (25, 8040, 8689),
+    (26, 8690, 9039),
+    (27, 9040, 9209),
+    (28, 9210, 9659),
+    (29, 9660, 9769),
+    (30, 9770, 9869),
+    (31, 9870, 9919),
+    (32, 9920, 10359),
+    (33, 36000, 36199),
+    (34, 36200, 36349),
+    (35, 36350, 36949),
+    (36, 36950, 37549),
+    (37, 37550, 37749),
+    (38, 37750, 38249),
+    (39, 38250, 38649),
+    (40, 38650, 39649),
+    (41, 39650, 41589),
+    (42, 41590, 43589),
+    (43, 43590, 45589),
+    (44, 45590, 46589),
+    (45, 46590, 46789),
+    (46, 46790, 54539),
+    (47, 54540, 55239),
+    (48, 55240, 56739),
+    (49, 56740, 58239),
+    (50, 58240, 59089),
+    (51, 59090, 59139),
+    (52, 59140, 60139),
+    (53, 60140, 60254),
+    (65, 65536, 66435),
+    (66, 66436, 67335),
+    (67, 67336, 67535),
+    (68, 67536, 67835),
+    (69, 67836, 68335),
+    (70, 68336, 68585),
+    (71, 68586, 68935),
+    (72, 68936, 68985),
+    (73, 68986, 69035),
+    (74, 69036, 69465),
+    (75, 69466, 70315),
+    (76, 70316, 70365),
+    (85, 70366, 70545),
+    (87, 70546, 70595),
+    (88, 70596, 70645),
+];
+
+/// Returns the band a downlink EARFCN belongs to, if it's in any of them
+pub fn band_for_earfcn(earfcn: u32) -> Option<u8> {
+    BANDS.iter()
+        .find(|(_, first, last)| (*first..=*last).contains(&earfcn))
+        .map(|(band, _, _)| *band)
+}
+
+/// Returns the downlink EARFCNs of the given band, if it's a known band
+pub fn earfcn_range_for_band(band: u8) -> Option<RangeInclusive<u32>> {
+    BANDS.iter()
+        .find(|(b, _, _)| *b == band)
+        .map(|(_, first, last)| *first..=*last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_boundaries() {
+        let cases = [
+            (0, Some(1)),
+            (599, Some(1)),
+            (600, Some(2)),
+            (1949, Some(3)),
+            (1950, Some(4)),
+            (4949, Some(11)),
+            (4950, None), // gap before band 12
+            (5010, Some(12)),
+            (5379, Some(14)),
+            (5380, None), // bands 15 and 16 are reserved
+            (5730, Some(17)),
+            (7400, None),
+            (10359, Some(32)),
+            (10360, None),
+            (36000, Some(33)),
+            (60254, Some(53)),
+            (60255, None),
+            (65535, None),
+            (65536, Some(65)),
+            (66436, Some(66)),
+            (68586, Some(71)),
+            (70645, Some(88)),
+            (70646, None),
+        ];
+        for (earfcn, band) in cases {
+            assert_eq!(band_for_earfcn(earfcn), band, "EARFCN {}", earfcn);
+        }
+    }
+
+    #[test]
+    fn test_earfcn_range_for_band() {
+        assert_eq!(earfcn_range_for_band(12), Some(5010..=5179));
+        assert_eq!(earfcn_range_for_band(66), Some(66436..=67335));
+        assert_eq!(earfcn_range_for_band(15), None);
+        for (band, first, last) in BANDS {
+            assert_eq!(band_for_earfcn(first), Some(band));
+            assert_eq!(band_for_earfcn(last), Some(band));
+        }
+    }
+
+    #[test]
+    fn test_bands_are_sorted_and_disjoint() {
+        for pair in BANDS.windows(2) {
+            let (_, _, prev_last) = pair[0];
+            let (band, first, last) = pair[1];
+            assert!(first > prev_last && last >= first, "band {}", band);
+        }
+    }
+}
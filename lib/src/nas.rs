@@ -0,0 +1,422 @@
+//! Minimal parsing of LTE NAS (3GPP TS 24.301) messages. Rather than fully
+//! decoding them, we only locate the handful of fields rayhunter cares about.
+
+use std::fmt;
+use std::ops::Range;
+
+pub const EMM_PROTOCOL_DISCRIMINATOR: u8 = 0x07;
+pub const ESM_PROTOCOL_DISCRIMINATOR: u8 = 0x02;
+
+pub const ATTACH_REQUEST: u8 = 0x41;
+pub const DETACH_REQUEST: u8 = 0x45;
+pub const IDENTITY_REQUEST: u8 = 0x55;
+pub const IDENTITY_RESPONSE: u8 = 0x56;
+pub const SECURITY_MODE_COMMAND: u8 = 0x5d;
+pub const SECURITY_MODE_COMPLETE: u8 = 0x5e;
+
+const IMEISV_IEI: u8 = 0x23;
+
+// security protected NAS messages have a 1 byte header, 4 byte MAC and 1 byte
+// sequence number before the NAS message
+const SECURITY_PROTECTED_HEADER_LEN: usize = 6;
+
+/// The type of identity carried in a (EPS) mobile identity IE, as defined in
+/// TS 24.008 section 10.5.1.4 and TS 24.301 section 9.9.3.12
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MobileIdentityType {
+    Imsi,
+    Imei,
+    Imeisv,
+    Tmsi,
+    Guti,
+    Other(u8),
+}
+
+impl MobileIdentityType {
+    fn from_first_octet(octet: u8) -> Self {
+        match octet & 0x07 {
+            1 => MobileIdentityType::Imsi,
+            2 => MobileIdentityType::Imei,
+            3 => MobileIdentityType::Imeisv,
+            4 => MobileIdentityType::Tmsi,
+            6 => MobileIdentityType::Guti,
+            other => MobileIdentityType::Other(other),
+        }
+    }
+
+    /// Whether this identity permanently identifies a subscriber or device
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, MobileIdentityType::Imsi | MobileIdentityType::Imei | MobileIdentityType::Imeisv)
+    }
+}
+
+// Returns the offset of the plain NAS message within the given NAS message,
+// skipping over the security header if there is one
+fn plain_nas_offset(msg: &[u8]) -> Option<usize> {
+    let first = *msg.first()?;
+    match (first & 0x0f, first >> 4) {
+        (ESM_PROTOCOL_DISCRIMINATOR, _) => Some(0),
+        (EMM_PROTOCOL_DISCRIMINATOR, 0) => Some(0),
+        // integrity protected, with or without a new EPS security context
+        (EMM_PROTOCOL_DISCRIMINATOR, 1 | 3) => {
+            msg.get(SECURITY_PROTECTED_HEADER_LEN)?;
+            Some(SECURITY_PROTECTED_HEADER_LEN)
+        },
+        // the ciphered header types (2 and 4) are followed by ciphertext, and
+        // service requests and reserved header types don't carry a message
+        // type
+        _ => None,
+    }
+}
+
+// Returns the offset of the plain EMM message within the given NAS message,
+// skipping over the security header if there is one
+fn plain_emm_offset(msg: &[u8]) -> Option<usize> {
+    let offset = plain_nas_offset(msg)?;
+    if msg[offset] & 0x0f != EMM_PROTOCOL_DISCRIMINATOR {
+        return None;
+    }
+    Some(offset)
+}
+
+// Returns the range of a length-prefixed value starting at the given offset
+fn lv_range(msg: &[u8], offset: usize) -> Option<Range<usize>> {
+    let len = *msg.get(offset)? as usize;
+    let range = offset + 1..offset + 1 + len;
+    if len == 0 || range.end > msg.len() {
+        return None;
+    }
+    Some(range)
+}
+
+/// Returns the EMM message type of a NAS message, or None if it isn't an EMM
+/// message.
+pub fn emm_message_type(msg: &[u8]) -> Option<u8> {
+    let offset = plain_emm_offset(msg)?;
+    msg.get(offset + 1).copied()
+}
+
+/// The message type of a NAS message, as defined in TS 24.301 section 9.8
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NasMessageType {
+    Emm(u8),
+    Esm(u8),
+}
+
+impl NasMessageType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NasMessageType::Emm(message_type) => match *message_type {
+                ATTACH_REQUEST => "Attach Request",
+                0x42 => "Attach Accept",
+                0x43 => "Attach Complete",
+                0x44 => "Attach Reject",
+                DETACH_REQUEST => "Detach Request",
+                0x46 => "Detach Accept",
+                0x48 => "Tracking Area Update Request",
+                0x49 => "Tracking Area Update Accept",
+                0x4a => "Tracking Area Update Complete",
+                0x4b => "Tracking Area Update Reject",
+                0x4c => "Extended Service Request",
+                0x4e => "Service Reject",
+                0x50 => "GUTI Reallocation Command",
+                0x51 => "GUTI Reallocation Complete",
+                0x52 => "Authentication Request",
+                0x53 => "Authentication Response",
+                0x54 => "Authentication Reject",
+                IDENTITY_REQUEST => "Identity Request",
+                IDENTITY_RESPONSE => "Identity Response",
+                0x5c => "Authentication Failure",
+                SECURITY_MODE_COMMAND => "Security Mode Command",
+                SECURITY_MODE_COMPLETE => "Security Mode Complete",
+                0x5f => "Security Mode Reject",
+                0x60 => "EMM Status",
+                0x61 => "EMM Information",
+                0x62 => "Downlink NAS Transport",
+                0x63 => "Uplink NAS Transport",
+                0x64 => "CS Service Notification",
+                _ => "Unknown EMM message",
+            },
+            NasMessageType::Esm(message_type) => match *message_type {
+                0xc1 => "Activate Default EPS Bearer Context Request",
+                0xc2 => "Activate Default EPS Bearer Context Accept",
+                0xc3 => "Activate Default EPS Bearer Context Reject",
+                0xc5 => "Activate Dedicated EPS Bearer Context Request",
+                0xc6 => "Activate Dedicated EPS Bearer Context Accept",
+                0xc7 => "Activate Dedicated EPS Bearer Context Reject",
+                0xc9 => "Modify EPS Bearer Context Request",
+                0xca => "Modify EPS Bearer Context Accept",
+                0xcb => "Modify EPS Bearer Context Reject",
+                0xcd => "Deactivate EPS Bearer Context Request",
+                0xce => "Deactivate EPS Bearer Context Accept",
+                0xd0 => "PDN Connectivity Request",
+                0xd1 => "PDN Connectivity Reject",
+                0xd2 => "PDN Disconnect Request",
+                0xd3 => "PDN Disconnect Reject",
+                0xd4 => "Bearer Resource Allocation Request",
+                0xd5 => "Bearer Resource Allocation Reject",
+                0xd6 => "Bearer Resource Modification Request",
+                0xd7 => "Bearer Resource Modification Reject",
+                0xd9 => "ESM Information Request",
+                0xda => "ESM Information Response",
+                0xdb => "Notification",
+                0xe8 => "ESM Status",
+                _ => "Unknown ESM message",
+            },
+        }
+    }
+}
+
+/// The NAS security algorithms selected by a Security Mode Command, as defined
+/// in TS 24.301 section 9.9.3.23. 0 is the null algorithm (EEA0/EIA0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NasSecurityAlgorithms {
+    pub ciphering: u8,
+    pub integrity: u8,
+}
+
+/// A NAS message classified by its message type. Only enough of the message
+/// is decoded to explain it to a user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NasMessage {
+    pub message_type: NasMessageType,
+    /// For Identity Requests, which identity the network asked for
+    pub requested_identity: Option<MobileIdentityType>,
+    /// For Security Mode Commands, which algorithms the network selected
+    pub selected_algorithms: Option<NasSecurityAlgorithms>,
+    /// For Identity Responses and Attach Requests, which identity the UE
+    /// provided
+    pub provided_identity: Option<MobileIdentityType>,
+}
+
+impl fmt::Display for NasMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message_type.name())?;
+        match self.requested_identity.or(self.provided_identity) {
+            Some(MobileIdentityType::Imsi) => write!(f, " (IMSI)"),
+            Some(MobileIdentityType::Imei) => write!(f, " (IMEI)"),
+            Some(MobileIdentityType::Imeisv) => write!(f, " (IMEISV)"),
+            Some(MobileIdentityType::Tmsi) => write!(f, " (TMSI)"),
+            Some(MobileIdentityType::Guti) => write!(f, " (GUTI)"),
+            Some(MobileIdentityType::Other(other)) => write!(f, " (identity type {})", other),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Classifies a NAS message by its EMM or ESM message type, or returns None
+/// if it's too short or uses a header we don't understand.
+pub fn classify(msg: &[u8]) -> Option<NasMessage> {
+    let offset = plain_nas_offset(msg)?;
+    let first = msg[offset];
+    if first & 0x0f == ESM_PROTOCOL_DISCRIMINATOR {
+        // ESM messages have a procedure transaction identity before the
+        // message type
+        let message_type = *msg.get(offset + 2)?;
+        return Some(NasMessage {
+            message_type: NasMessageType::Esm(message_type),
+            requested_identity: None,
+            selected_algorithms: None,
+            provided_identity: None,
+        });
+    }
+    if first != EMM_PROTOCOL_DISCRIMINATOR {
+        return None;
+    }
+    let message_type = *msg.get(offset + 1)?;
+    let requested_identity = match message_type {
+        // the identity type is in the lower half of the octet after the
+        // message type
+        IDENTITY_REQUEST => Some(MobileIdentityType::from_first_octet(*msg.get(offset + 2)?)),
+        _ => None,
+    };
+    let selected_algorithms = match message_type {
+        SECURITY_MODE_COMMAND => msg.get(offset + 2).map(|&algorithms| NasSecurityAlgorithms {
+            ciphering: (algorithms >> 4) & 0x07,
+            integrity: algorithms & 0x07,
+        }),
+        _ => None,
+    };
+    let provided_identity = match message_type {
+        ATTACH_REQUEST | IDENTITY_RESPONSE => find_mobile_identities(msg).first()
+            .map(|(identity_type, _)| *identity_type),
+        _ => None,
+    };
+    Some(NasMessage {
+        message_type: NasMessageType::Emm(message_type),
+        requested_identity,
+        selected_algorithms,
+        provided_identity,
+    })
+}
+
+/// Returns the type and byte range (excluding the length prefix) of every
+/// mobile identity found in an EMM message. Only the mandatory identities of
+/// Attach Request, Detach Request and Identity Response, and the optional
+/// IMEISV of Security Mode Complete are located.
+pub fn find_mobile_identities(msg: &[u8]) -> Vec<(MobileIdentityType, Range<usize>)> {
+    let Some(offset) = plain_emm_offset(msg) else {
+        return Vec::new();
+    };
+    let Some(&message_type) = msg.get(offset + 1) else {
+        return Vec::new();
+    };
+    let maybe_range = match message_type {
+        // these have a half-octet pair (e.g. NAS key set identifier + attach
+        // type) before the identity
+        ATTACH_REQUEST | DETACH_REQUEST => lv_range(msg, offset + 3),
+        IDENTITY_RESPONSE => lv_range(msg, offset + 2),
+        SECURITY_MODE_COMPLETE => match msg.get(offset + 2) {
+            Some(&IMEISV_IEI) => lv_range(msg, offset + 3),
+            _ => None,
+        },
+        _ => None,
+    };
+    maybe_range
+        .map(|range| (MobileIdentityType::from_first_octet(msg[range.start]), range))
+        .into_iter()
+        .collect()
+}
+
+/// Zeroes out the digits of every IMSI, IMEI and IMEISV in the given NAS
+/// message, leaving the identity type, odd/even indicator and filler intact.
+/// Temporary identities (TMSI/GUTI) are left as-is, and so are ciphered
+/// messages, whose contents can't be located. Returns the number of
+/// identities redacted.
+pub fn redact_identities(msg: &mut [u8]) -> usize {
+    let mut num_redacted = 0;
+    for (identity_type, range) in find_mobile_identities(msg) {
+        if !identity_type.is_permanent() {
+            continue;
+        }
+        let value = &mut msg[range];
+        let last = value.len() - 1;
+        // the first octet holds the first digit in its upper nibble
+        value[0] &= 0x0f;
+        for (i, octet) in value.iter_mut().enumerate().skip(1) {
+            // an even number of digits is padded with a 0xf filler
+            *octet = if i == last && *octet >> 4 == 0x0f { 0xf0 } else { 0x00 };
+        }
+        num_redacted += 1;
+    }
+    num_redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Attach Request with IMSI 001010123456789
+    const ATTACH_REQUEST_WITH_IMSI: [u8; 20] = [
+        0x07, 0x41, 0x71, 0x08, 0x09, 0x10, 0x10, 0x10, 0x32, 0x54, 0x76, 0x98,
+        0x02, 0xe0, 0xe0, 0x00, 0x04, 0x02, 0x01, 0xd0,
+    ];
+
+    #[test]
+    fn test_find_imsi_in_attach_request() {
+        let identities = find_mobile_identities(&ATTACH_REQUEST_WITH_IMSI);
+        assert_eq!(identities, vec![(MobileIdentityType::Imsi, 4..12)]);
+        assert_eq!(emm_message_type(&ATTACH_REQUEST_WITH_IMSI), Some(ATTACH_REQUEST));
+    }
+
+    #[test]
+    fn test_redact_attach_request() {
+        let mut msg = ATTACH_REQUEST_WITH_IMSI;
+        assert_eq!(redact_identities(&mut msg), 1);
+        assert_eq!(msg, [
+            0x07, 0x41, 0x71, 0x08, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x02, 0xe0, 0xe0, 0x00, 0x04, 0x02, 0x01, 0xd0,
+        ]);
+    }
+
+    #[test]
+    fn test_redact_security_protected_identity_response() {
+        // Identity Response carrying an IMEISV (16 digits, so the last octet
+        // is padded with filler), wrapped in an integrity protected header
+        let mut msg = vec![
+            0x17, 0x11, 0x22, 0x33, 0x44, 0x05,
+            0x07, 0x56, 0x09, 0x33, 0x55, 0x77, 0x99, 0x11, 0x33, 0x55, 0x77, 0xf9,
+        ];
+        assert_eq!(redact_identities(&mut msg), 1);
+        assert_eq!(&msg[6..], &[
+            0x07, 0x56, 0x09, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0,
+        ]);
+    }
+
+    #[test]
+    fn test_leaves_ciphered_messages_alone() {
+        // the same Identity Response, but as if it were integrity protected
+        // and ciphered (header types 2 and 4), so its bytes only look like
+        // an identity by chance
+        for header in [0x27, 0x47] {
+            let mut msg = vec![
+                header, 0x11, 0x22, 0x33, 0x44, 0x05,
+                0x07, 0x56, 0x09, 0x33, 0x55, 0x77, 0x99, 0x11, 0x33, 0x55, 0x77, 0xf9,
+            ];
+            let original = msg.clone();
+            assert!(find_mobile_identities(&msg).is_empty());
+            assert_eq!(redact_identities(&mut msg), 0);
+            assert_eq!(msg, original);
+            assert_eq!(classify(&msg), None);
+        }
+    }
+
+    #[test]
+    fn test_leaves_guti_alone() {
+        let mut msg = vec![
+            0x07, 0x41, 0x71, 0x0b, 0xf6, 0x00, 0xf1, 0x10, 0x00, 0x01, 0x01, 0x12,
+            0x34, 0x56, 0x78,
+        ];
+        let original = msg.clone();
+        assert_eq!(redact_identities(&mut msg), 0);
+        assert_eq!(msg, original);
+    }
+
+    #[test]
+    fn test_classify_identity_request() {
+        let msg = classify(&[0x07, 0x55, 0x01]).unwrap();
+        assert_eq!(msg.message_type, NasMessageType::Emm(IDENTITY_REQUEST));
+        assert_eq!(msg.requested_identity, Some(MobileIdentityType::Imsi));
+        assert_eq!(msg.to_string(), "Identity Request (IMSI)");
+    }
+
+    #[test]
+    fn test_classify_identity_response() {
+        // Identity Response with IMSI 001010123456789
+        let msg = classify(&[0x07, 0x56, 0x08, 0x09, 0x10, 0x10, 0x10, 0x32, 0x54, 0x76, 0x98]).unwrap();
+        assert_eq!(msg.provided_identity, Some(MobileIdentityType::Imsi));
+        assert_eq!(msg.to_string(), "Identity Response (IMSI)");
+        let msg = classify(&ATTACH_REQUEST_WITH_IMSI).unwrap();
+        assert_eq!(msg.provided_identity, Some(MobileIdentityType::Imsi));
+    }
+
+    #[test]
+    fn test_classify_security_protected_messages() {
+        // Security Mode Command, integrity protected with a new EPS security
+        // context
+        let msg = classify(&[0x37, 0x11, 0x22, 0x33, 0x44, 0x00, 0x07, 0x5d, 0x02, 0x01, 0x02, 0xe0, 0xe0]).unwrap();
+        assert_eq!(msg.to_string(), "Security Mode Command");
+        assert_eq!(msg.selected_algorithms, Some(NasSecurityAlgorithms { ciphering: 0, integrity: 2 }));
+        // PDN Connectivity Request tucked inside an integrity protected
+        // header
+        let msg = classify(&[0x17, 0x11, 0x22, 0x33, 0x44, 0x01, 0x02, 0x01, 0xd0, 0x11]).unwrap();
+        assert_eq!(msg.message_type, NasMessageType::Esm(0xd0));
+        assert_eq!(msg.to_string(), "PDN Connectivity Request");
+    }
+
+    #[test]
+    fn test_classify_rejects_unknown_headers() {
+        assert_eq!(classify(&[0x07, 0x52]).map(|msg| msg.to_string()), Some("Authentication Request".to_string()));
+        // service request
+        assert_eq!(classify(&[0xc7, 0x01, 0x02, 0x03]), None);
+        assert_eq!(classify(&[0x07]), None);
+        assert_eq!(classify(&[]), None);
+    }
+
+    #[test]
+    fn test_ignores_truncated_messages() {
+        let mut msg = vec![0x07, 0x56, 0x08, 0x09, 0x10];
+        assert!(find_mobile_identities(&msg).is_empty());
+        assert_eq!(redact_identities(&mut msg), 0);
+    }
+}
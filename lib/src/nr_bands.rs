@@ -0,0 +1,129 @@
+//! The NR operating bands, for working out which band a 5G NR-ARFCN is in.
+//! Unlike LTE, NR bands overlap heavily (n77 contains all of n78, and n1,
+//! n65 and n66 share most of their frequencies), so a channel can belong to
+//! several bands at once.
+
+// NR-ARFCNs are spaced differently in each frequency range, TS 38.104 table
+// 5.4.2.1-1: (first NR-ARFCN, last NR-ARFCN, spacing in kHz, frequency of the
+// first NR-ARFCN in kHz)
+const RASTERS: [(u32, u32, u64, u64); 3] = [
+    (0, 599_999, 5, 0),
+    (600_000, 2_016_666, 15, 3_000_000),
+    (2_016_667, 3_279_165, 60, 24_250_080),
+];
+
+// (band, lowest downlink frequency in kHz, highest downlink frequency in kHz),
+// from TS 38.101-1 table 5.2-1 (FR1) and TS 38.101-2 table 5.2-1 (FR2)
+const BANDS: [(u16, u64, u64); 54] = [
+    (1, 2_110_000, 2_170_000),
+    (2, 1_930_000, 1_990_000),
+    (3, 1_805_000, 1_880_000),
+    (5, 869_000, 894_000),
+    (7, 2_620_000, 2_690_000),
+    (8, 925_000, 960_000),
+    (12, 729_000, 746_000),
+    (13, 746_000, 756_000),
+    (14, 758_000, 768_000),
+    (18, 860_000, 875_000),
+    (20, 791_000, 821_000),
+    (24, 1_525_000, 1_559_000),
+    (25, 1_930_000, 1_995_000),
+    (26, 859_000, 894_000),
+    (28, 758_000, 803_000),
+    (29, 717_000, 728_000),
+    (30, 2_350_000, 2_360_000),
+    (34, 2_010_000, 2_025_000),
+    (38, 2_570_000, 2_620_000),
+    (39, 1_880_000, 1_920_000),
+    (40, 2_300_000, 2_400_000),
+    (41, 2_496_000, 2_690_000),
+    (46, 5_150_000, 5_925_000),
+    (48, 3_550_000, 3_700_000),
+    (50, 1_432_000, 1_517_000),
+    (51, 1_427_000, 1_432_000),
+    (53, 2_483_500, 2_495_000),
+    (65, 2_110_000, 2_200_000),
+    (66, 2_110_000, 2_200_000),
+    (67, 738_000, 758_000),
+    (70, 1_995_000, 2_020_000),
+    (71, 617_000, 652_000),
+    (74, 1_475_000, 1_518_000),
+    (75, 1_432_000, 1_517_000),
+    (76, 1_427_000, 1_432_000),
+    (77, 3_300_000, 4_200_000),
+    (78, 3_300_000, 3_800_000),
+    (79, 4_400_000, 5_000_000),
+    (85, 728_000, 746_000),
+    (90, 2_496_000, 2_690_000),
+    (91, 1_427_000, 1_432_000),
+    (92, 1_432_000, 1_517_000),
+    (93, 1_427_000, 1_432_000),
+    (94, 1_432_000, 1_517_000),
+    (96, 5_925_000, 7_125_000),
+    (100, 919_400, 925_000),
+    (101, 1_900_000, 1_910_000),
+    (257, 26_500_000, 29_500_000),
+    (258, 24_250_000, 27_500_000),
+    (259, 39_500_000, 43_500_000),
+    (260, 37_000_000, 40_000_000),
+    (261, 27_500_000, 28_350_000),
+    (262, 47_200_000, 48_200_000),
+    (263, 57_000_000, 71_000_000),
+];
+
+/// Returns the frequency in kHz of the given NR-ARFCN, if it's a valid one
+pub fn nr_arfcn_to_khz(arfcn: u32) -> Option<u64> {
+    RASTERS.iter()
+        .find(|(first, last, _, _)| (*first..=*last).contains(&arfcn))
+        .map(|(first, _, spacing_khz, offset_khz)| offset_khz + spacing_khz * (arfcn - first) as u64)
+}
+
+/// Returns every band whose downlink includes the given NR-ARFCN, in band
+/// order
+pub fn bands_for_nr_arfcn(arfcn: u32) -> Vec<u16> {
+    let Some(khz) = nr_arfcn_to_khz(arfcn) else {
+        return Vec::new();
+    };
+    BANDS.iter()
+        .filter(|(_, low, high)| (*low..=*high).contains(&khz))
+        .map(|(band, _, _)| *band)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nr_arfcn_to_khz() {
+        assert_eq!(nr_arfcn_to_khz(0), Some(0));
+        assert_eq!(nr_arfcn_to_khz(428_000), Some(2_140_000));
+        assert_eq!(nr_arfcn_to_khz(599_999), Some(2_999_995));
+        assert_eq!(nr_arfcn_to_khz(600_000), Some(3_000_000));
+        assert_eq!(nr_arfcn_to_khz(632_628), Some(3_489_420));
+        assert_eq!(nr_arfcn_to_khz(2_016_667), Some(24_250_080));
+        assert_eq!(nr_arfcn_to_khz(3_279_165), Some(99_999_960));
+        assert_eq!(nr_arfcn_to_khz(3_279_166), None);
+    }
+
+    #[test]
+    fn test_bands_for_nr_arfcn() {
+        let cases: [(u32, &[u16]); 7] = [
+            // n1 at 2140MHz, which n65 and n66 also cover
+            (428_000, &[1, 65, 66]),
+            // the middle of n78, inside n77 too
+            (632_628, &[77, 78]),
+            // above n78's top edge at 3800MHz, so only n77
+            (653_334, &[77]),
+            // the very bottom of FR2, only n258
+            (2_016_667, &[258]),
+            // 27.5GHz is the top of n258 and the bottom of n261
+            (2_070_832, &[257, 258]),
+            (2_070_833, &[257, 261]),
+            (3_279_166, &[]),
+        ];
+        for (arfcn, bands) in cases {
+            assert_eq!(bands_for_nr_arfcn(arfcn), bands, "NR-ARFCN {}", arfcn);
+        }
+    }
+}
@@ -2,6 +2,7 @@
 //! Creates a plausible IP header and [GSMtap](https://osmocom.org/projects/baseband/wiki/GSMTAP) header and then puts the rest of the data under that for wireshark to parse. 
 use crate::gsmtap::GsmtapMessage;
 use crate::diag::Timestamp;
+use crate::ip::IpPacket;
 
 use tokio::io::AsyncWrite;
 use std::borrow::Cow;
@@ -30,6 +31,10 @@ pub struct GsmtapPcapWriter<T> where T: AsyncWrite {
     ip_id: u16,
 }
 
+// interface IDs are assigned in the order their headers are written
+const GSMTAP_INTERFACE_ID: u32 = 0;
+const IP_INTERFACE_ID: u32 = 1;
+
 const IP_HEADER_LEN: u16 = 20;
 #[derive(DekuWrite)]
 #[deku(endian = "big")]
@@ -48,7 +53,8 @@ struct IpHeader {
 }
 
 const UDP_HEADER_LEN: u16 = 8;
-const GSMTAP_PORT: u16 = 4729;
+/// The UDP port Wireshark listens for GSMTAP on
+pub const GSMTAP_PORT: u16 = 4729;
 #[derive(DekuWrite)]
 #[deku(endian = "big")]
 struct UdpHeader {
@@ -74,16 +80,20 @@ impl<T> GsmtapPcapWriter<T> where T: AsyncWrite + Unpin + Send {
         Ok(())
     }
 
-    pub async fn write_gsmtap_message(&mut self, msg: GsmtapMessage, timestamp: Timestamp) -> Result<(), GsmtapPcapError> {
-        let duration = timestamp.to_datetime()
-            .signed_duration_since(DateTime::UNIX_EPOCH)
-            .to_std()?;
-
-        // despite the timestamp above being correct, we have reduce it by
-        // orders of magnitude due to a bug in pcap_file:
-        // https://github.com/courvoif/pcap-file/pull/32
-        let duration = std::time::Duration::from_nanos(duration.as_micros() as u64);
+    /// Writes the header for a second interface carrying raw IPv4/IPv6
+    /// packets, for use with [GsmtapPcapWriter::write_ip_packet]. Must be
+    /// called after [GsmtapPcapWriter::write_iface_header].
+    pub async fn write_ip_iface_header(&mut self) -> Result<(), GsmtapPcapError> {
+        let interface = InterfaceDescriptionBlock {
+            linktype: pcap_file_tokio::DataLink::RAW,
+            snaplen: 0xffff,
+            options: vec![],
+        };
+        self.writer.write_pcapng_block(interface).await?;
+        Ok(())
+    }
 
+    pub async fn write_gsmtap_message(&mut self, msg: GsmtapMessage, timestamp: Timestamp) -> Result<(), GsmtapPcapError> {
         let msg_bytes = msg.to_bytes()?;
         let ip_header = IpHeader {
             version_and_ihl: 0x45,
@@ -108,15 +118,34 @@ impl<T> GsmtapPcapWriter<T> where T: AsyncWrite + Unpin + Send {
         data.extend(&ip_header.to_bytes()?);
         data.extend(&udp_header.to_bytes()?);
         data.extend(&msg_bytes);
+        self.write_packet(GSMTAP_INTERFACE_ID, data, timestamp).await?;
+        self.ip_id = self.ip_id.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Writes a user-plane IP packet as-is to the raw IP interface.
+    pub async fn write_ip_packet(&mut self, packet: IpPacket, timestamp: Timestamp) -> Result<(), GsmtapPcapError> {
+        self.write_packet(IP_INTERFACE_ID, packet.data, timestamp).await
+    }
+
+    async fn write_packet(&mut self, interface_id: u32, data: Vec<u8>, timestamp: Timestamp) -> Result<(), GsmtapPcapError> {
+        let duration = timestamp.to_datetime()
+            .signed_duration_since(DateTime::UNIX_EPOCH)
+            .to_std()?;
+
+        // despite the timestamp above being correct, we have reduce it by
+        // orders of magnitude due to a bug in pcap_file:
+        // https://github.com/courvoif/pcap-file/pull/32
+        let duration = std::time::Duration::from_nanos(duration.as_micros() as u64);
+
         let packet = EnhancedPacketBlock {
-            interface_id: 0,
+            interface_id,
             timestamp: duration,
             original_len: data.len() as u32,
             data: Cow::Owned(data),
             options: vec![],
         };
         self.writer.write_pcapng_block(packet).await?;
-        self.ip_id = self.ip_id.wrapping_add(1);
         Ok(())
     }
 }
@@ -2,15 +2,47 @@
 //! a series of of concatenated HDLC encapsulated diag::Message structs.
 //! QmdlReader and QmdlWriter can read and write MessagesContainers to and from
 //! QMDL files.
+//!
+//! To save space, QMDL files can also be stored zstd compressed. These start
+//! with [ZSTD_QMDL_MAGIC], followed by one length-prefixed zstd frame per
+//! written container, so a file that's still being written can be read up to
+//! its last complete frame. QmdlReader detects and decompresses these
+//! transparently.
+
+use std::io::Read;
 
 use crate::diag::{MessagesContainer, MESSAGE_TERMINATOR, HdlcEncapsulatedMessage, DataType};
 
 use futures::TryStream;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, AsyncBufReadExt};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, AsyncBufReadExt};
 use log::error;
 
+/// Marks the start of a zstd compressed QMDL file. Plain QMDL files start with
+/// a diag message, which can't look like this.
+pub const ZSTD_QMDL_MAGIC: &[u8; 8] = b"RAYHZSTD";
+
+const ZSTD_LEVEL: i32 = 3;
+
+// Each zstd frame holds one container, which is at most one read from the diag
+// device (10MiB), so anything claiming to be much bigger than that, compressed
+// or not, is corrupt or malicious rather than worth trying to allocate
+const MAX_ZSTD_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// How QMDL data is stored on disk
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QmdlCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
 pub struct QmdlWriter<T> where T: AsyncWrite + Unpin {
     writer: T,
+    compression: QmdlCompression,
+    /// The number of bytes written to the underlying writer, which for
+    /// compressed files is less than the size of the QMDL data
     pub total_written: usize,
 }
 
@@ -19,17 +51,48 @@ impl<T> QmdlWriter<T> where T: AsyncWrite + Unpin {
         QmdlWriter::new_with_existing_size(writer, 0)
     }
 
+    pub fn new_with_compression(writer: T, compression: QmdlCompression) -> Self {
+        let mut qmdl_writer = QmdlWriter::new(writer);
+        qmdl_writer.compression = compression;
+        qmdl_writer
+    }
+
     pub fn new_with_existing_size(writer: T, existing_size: usize) -> Self {
         QmdlWriter {
             writer,
+            compression: QmdlCompression::None,
             total_written: existing_size,
         }
     }
 
     pub async fn write_container(&mut self, container: &MessagesContainer) -> std::io::Result<()> {
-        for msg in &container.messages {
-            self.writer.write_all(&msg.data).await?;
-            self.total_written += msg.data.len();
+        match self.compression {
+            QmdlCompression::None => {
+                for msg in &container.messages {
+                    self.writer.write_all(&msg.data).await?;
+                    self.total_written += msg.data.len();
+                }
+            },
+            QmdlCompression::Zstd => {
+                if self.total_written == 0 {
+                    self.writer.write_all(ZSTD_QMDL_MAGIC).await?;
+                    self.total_written += ZSTD_QMDL_MAGIC.len();
+                }
+                let data: Vec<u8> = container.messages.iter()
+                    .flat_map(|msg| msg.data.iter().copied())
+                    .collect();
+                if data.is_empty() {
+                    return Ok(());
+                }
+                let frame = zstd::bulk::compress(&data, ZSTD_LEVEL)?;
+                // write the frame in one go, so that readers never see a
+                // length without its frame
+                let mut record = Vec::with_capacity(4 + frame.len());
+                record.extend((frame.len() as u32).to_le_bytes());
+                record.extend(frame);
+                self.writer.write_all(&record).await?;
+                self.total_written += record.len();
+            },
         }
         Ok(())
     }
@@ -39,14 +102,24 @@ pub struct QmdlReader<T> where T: AsyncRead {
     reader: BufReader<T>,
     bytes_read: usize,
     max_bytes: Option<usize>,
+    // detected from the start of the file on the first read
+    compression: Option<QmdlCompression>,
+    // decompressed data from the last zstd frame that hasn't been returned yet
+    decompressed: Vec<u8>,
+    decompressed_pos: usize,
 }
 
 impl<T> QmdlReader<T> where T: AsyncRead + Unpin {
+    /// `max_bytes` limits how much of the underlying reader is read, which
+    /// for compressed files is its compressed size
     pub fn new(reader: T, max_bytes: Option<usize>) -> Self {
         QmdlReader {
             reader: BufReader::new(reader),
             bytes_read: 0,
             max_bytes,
+            compression: None,
+            decompressed: Vec::new(),
+            decompressed_pos: 0,
         }
     }
 
@@ -60,7 +133,83 @@ impl<T> QmdlReader<T> where T: AsyncRead + Unpin {
         })
     }
 
+    /// Like [QmdlReader::as_stream], but takes ownership of the reader so the
+    /// stream can outlive it (e.g. in an HTTP response body)
+    pub fn into_stream(self) -> impl TryStream<Ok = MessagesContainer, Error = std::io::Error> {
+        futures::stream::try_unfold(self, |mut reader| async {
+            let maybe_container = reader.get_next_messages_container().await?;
+            Ok(maybe_container.map(|container| (container, reader)))
+        })
+    }
+
+    async fn detect_compression(&mut self) -> Result<QmdlCompression, std::io::Error> {
+        if let Some(compression) = self.compression {
+            return Ok(compression);
+        }
+        let compression = if self.reader.fill_buf().await?.starts_with(ZSTD_QMDL_MAGIC) {
+            self.reader.consume(ZSTD_QMDL_MAGIC.len());
+            self.bytes_read += ZSTD_QMDL_MAGIC.len();
+            QmdlCompression::Zstd
+        } else {
+            QmdlCompression::None
+        };
+        self.compression = Some(compression);
+        Ok(compression)
+    }
+
+    fn reached_max_bytes(&self) -> bool {
+        let Some(max_bytes) = self.max_bytes else {
+            return false;
+        };
+        if self.bytes_read > max_bytes {
+            error!("warning: {} bytes read, but max_bytes was {}", self.bytes_read, max_bytes);
+        }
+        self.bytes_read >= max_bytes
+    }
+
+    // Reads and decompresses the next zstd frame, returning false if there
+    // are no more
+    async fn read_zstd_frame(&mut self) -> Result<bool, std::io::Error> {
+        if self.reached_max_bytes() {
+            return Ok(false);
+        }
+        let mut len_bytes = [0; 4];
+        match self.reader.read_exact(&mut len_bytes).await {
+            Ok(_) => {},
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        let frame_len = u32::from_le_bytes(len_bytes) as usize;
+        if frame_len > MAX_ZSTD_FRAME_LEN {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("zstd frame length {} is over the maximum of {}", frame_len, MAX_ZSTD_FRAME_LEN)));
+        }
+        let mut frame = vec![0; frame_len];
+        match self.reader.read_exact(&mut frame).await {
+            Ok(_) => {},
+            // the file's still being written, or was cut off mid-frame
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        self.bytes_read += len_bytes.len() + frame.len();
+        let mut decompressed = Vec::new();
+        zstd::stream::read::Decoder::new(frame.as_slice())?
+            .take(MAX_ZSTD_FRAME_LEN as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() > MAX_ZSTD_FRAME_LEN {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("zstd frame decompresses to over the maximum of {} bytes", MAX_ZSTD_FRAME_LEN)));
+        }
+        self.decompressed = decompressed;
+        self.decompressed_pos = 0;
+        Ok(true)
+    }
+
     async fn get_next_messages_container(&mut self) -> Result<Option<MessagesContainer>, std::io::Error> {
+        if self.detect_compression().await? == QmdlCompression::Zstd {
+            return self.get_next_compressed_messages_container().await;
+        }
+
         if let Some(max_bytes) = self.max_bytes {
             if self.bytes_read >= max_bytes {
                 if self.bytes_read > max_bytes {
@@ -73,22 +222,40 @@ impl<T> QmdlReader<T> where T: AsyncRead + Unpin {
         let mut buf = Vec::new();
         let bytes_read = self.reader.read_until(MESSAGE_TERMINATOR, &mut buf).await?;
         self.bytes_read += bytes_read;
+        Ok(Some(single_message_container(buf)))
+    }
+
+    async fn get_next_compressed_messages_container(&mut self) -> Result<Option<MessagesContainer>, std::io::Error> {
+        while self.decompressed_pos >= self.decompressed.len() {
+            if !self.read_zstd_frame().await? {
+                return Ok(None);
+            }
+        }
+        let remaining = &self.decompressed[self.decompressed_pos..];
+        let len = remaining.iter()
+            .position(|&byte| byte == MESSAGE_TERMINATOR)
+            .map_or(remaining.len(), |i| i + 1);
+        let buf = remaining[..len].to_vec();
+        self.decompressed_pos += len;
+        Ok(Some(single_message_container(buf)))
+    }
+}
 
-        // Since QMDL is just a flat list of messages, we can't actually
-        // reproduce the container structure they came from in the original
-        // read. So we'll just pretend that all containers had exactly one
-        // message. As far as I know, the number of messages per container
-        // doesn't actually affect anything, so this should be fine.
-        Ok(Some(MessagesContainer {
-            data_type: DataType::UserSpace,
-            num_messages: 1,
-            messages: vec![
-                HdlcEncapsulatedMessage {
-                    len: bytes_read as u32,
-                    data: buf,
-                },
-            ]
-        }))
+// Since QMDL is just a flat list of messages, we can't actually reproduce the
+// container structure they came from in the original read. So we'll just
+// pretend that all containers had exactly one message. As far as I know, the
+// number of messages per container doesn't actually affect anything, so this
+// should be fine.
+fn single_message_container(data: Vec<u8>) -> MessagesContainer {
+    MessagesContainer {
+        data_type: DataType::UserSpace,
+        num_messages: 1,
+        messages: vec![
+            HdlcEncapsulatedMessage {
+                len: data.len() as u32,
+                data,
+            },
+        ]
     }
 }
 
@@ -206,4 +373,75 @@ mod test {
         }
         assert!(matches!(reader.get_next_messages_container().await, Ok(None)));
     }
+
+    #[tokio::test]
+    async fn test_compressed_writing_and_reading() {
+        let mut buf = Vec::new();
+        let mut writer = QmdlWriter::new_with_compression(&mut buf, QmdlCompression::Zstd);
+        for container in &get_test_containers() {
+            writer.write_container(container).await.unwrap();
+        }
+        assert_eq!(writer.total_written, buf.len());
+        assert!(buf.starts_with(ZSTD_QMDL_MAGIC));
+
+        let limit = Some(buf.len());
+        let mut reader = QmdlReader::new(Cursor::new(&mut buf), limit);
+        for message in get_test_messages() {
+            let expected_container = MessagesContainer {
+                data_type: DataType::UserSpace,
+                num_messages: 1,
+                messages: vec![message],
+            };
+            assert_eq!(expected_container, reader.get_next_messages_container().await.unwrap().unwrap());
+        }
+        assert!(matches!(reader.get_next_messages_container().await, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn test_bounded_compressed_reader() {
+        let mut buf = Vec::new();
+        let mut writer = QmdlWriter::new_with_compression(&mut buf, QmdlCompression::Zstd);
+        let containers = get_test_containers();
+        writer.write_container(&containers[0]).await.unwrap();
+        // a reader bounded to the first frame shouldn't see the second
+        let limit = Some(writer.total_written);
+        writer.write_container(&containers[1]).await.unwrap();
+
+        let mut reader = QmdlReader::new(Cursor::new(&mut buf), limit);
+        let mut num_messages = 0;
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            assert_eq!(container.messages[0], containers[0].messages[num_messages]);
+            num_messages += 1;
+        }
+        assert_eq!(num_messages, containers[0].messages.len());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_compressed_reader() {
+        let mut buf = Vec::new();
+        let mut writer = QmdlWriter::new_with_compression(&mut buf, QmdlCompression::Zstd);
+        let containers = get_test_containers();
+        writer.write_container(&containers[0]).await.unwrap();
+        writer.write_container(&containers[1]).await.unwrap();
+        // cut off partway through the second frame
+        buf.truncate(buf.len() - 5);
+
+        let mut reader = QmdlReader::new(Cursor::new(&mut buf), None);
+        let mut num_messages = 0;
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            assert_eq!(container.messages[0], containers[0].messages[num_messages]);
+            num_messages += 1;
+        }
+        assert_eq!(num_messages, containers[0].messages.len());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_compressed_frame() {
+        let mut buf = ZSTD_QMDL_MAGIC.to_vec();
+        buf.extend(u32::MAX.to_le_bytes());
+        buf.extend([0; 16]);
+        let mut reader = QmdlReader::new(Cursor::new(&mut buf), None);
+        let err = reader.get_next_messages_container().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,145 @@
+//! Serving cell signal strength over the course of a recording, e.g. for
+//! plotting coverage along a drive.
+//!
+//! For now samples come from the serving cell measurements in LTE RRC
+//! measurement reports, which don't include SINR. Once ML1 serving cell
+//! measurements are decoded they can be added here too.
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use telcom_parser::lte_rrc::{MeasurementReportCriticalExtensions, MeasurementReportCriticalExtensions_c1, RSRQ_Range, UL_DCCH_MessageType, UL_DCCH_MessageType_c1};
+
+use crate::analysis::information_element::{InformationElement, LteInformationElement};
+use crate::cell_summary::rsrp_to_dbm;
+use crate::diag::{LogBody, Message};
+use crate::gsmtap_parser;
+
+/// A single serving cell measurement
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SignalSample {
+    pub timestamp: DateTime<FixedOffset>,
+    pub rsrp: Option<i16>,
+    pub rsrq: Option<f32>,
+    pub sinr: Option<f32>,
+    pub earfcn: u32,
+    pub pci: u16,
+}
+
+// RSRQ is reported in 0.5dB steps starting at -20dB (TS 36.133 section 9.1.7)
+fn rsrq_to_db(rsrq: &RSRQ_Range) -> f32 {
+    rsrq.0 as f32 * 0.5 - 20.0
+}
+
+#[derive(Default, Debug)]
+pub struct SignalSeries {
+    samples: Vec<SignalSample>,
+}
+
+impl SignalSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_sample(&mut self, sample: SignalSample) {
+        self.samples.push(sample);
+    }
+
+    /// Adds a sample if the message is a measurement report with a valid
+    /// timestamp
+    pub fn add_message(&mut self, msg: Message) {
+        let Message::Log { timestamp, body: LogBody::LteRrcOtaMessage { packet, .. }, .. } = &msg else {
+            return;
+        };
+        let Some(timestamp) = timestamp.try_to_datetime() else {
+            return;
+        };
+        let pci = packet.get_phy_cell_id();
+        let earfcn = packet.get_earfcn();
+        let Ok(Some((_, gsmtap_msg))) = gsmtap_parser::parse(msg) else {
+            return;
+        };
+        let Ok(InformationElement::LTE(LteInformationElement::UlDcch(ul_dcch_message))) = InformationElement::try_from(&gsmtap_msg) else {
+            return;
+        };
+        let UL_DCCH_MessageType::C1(UL_DCCH_MessageType_c1::MeasurementReport(report)) = ul_dcch_message.message else {
+            return;
+        };
+        let MeasurementReportCriticalExtensions::C1(MeasurementReportCriticalExtensions_c1::MeasurementReport_r8(report)) = report.critical_extensions else {
+            return;
+        };
+        let serving_cell = report.meas_results.meas_result_p_cell;
+        self.add_sample(SignalSample {
+            timestamp,
+            rsrp: Some(rsrp_to_dbm(&serving_cell.rsrp_result)),
+            rsrq: Some(rsrq_to_db(&serving_cell.rsrq_result)),
+            sinr: None,
+            earfcn,
+            pci,
+        });
+    }
+
+    /// Returns the samples in time order, evenly thinned out to at most
+    /// `max_points` if given
+    pub fn samples(&self, max_points: Option<usize>) -> Vec<SignalSample> {
+        let mut samples = self.samples.clone();
+        samples.sort_by_key(|sample| sample.timestamp);
+        let Some(max_points) = max_points.filter(|max_points| *max_points < samples.len()) else {
+            return samples;
+        };
+        (0..max_points)
+            .map(|i| samples[i * samples.len() / max_points].clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diag::Timestamp;
+
+    fn sample(secs: u64, rsrp: i16) -> SignalSample {
+        SignalSample {
+            timestamp: Timestamp { ts: (secs * 800) << 16 }.to_datetime(),
+            rsrp: Some(rsrp),
+            rsrq: Some(-10.5),
+            sinr: None,
+            earfcn: 2050,
+            pci: 160,
+        }
+    }
+
+    fn synthetic_series(len: u64) -> SignalSeries {
+        let mut series = SignalSeries::new();
+        // added out of order, as if from a rotated recording
+        for secs in (0..len).rev() {
+            series.add_sample(sample(secs, -100 - secs as i16));
+        }
+        series
+    }
+
+    #[test]
+    fn test_samples_in_time_order() {
+        let samples = synthetic_series(5).samples(None);
+        let rsrps: Vec<_> = samples.iter().map(|sample| sample.rsrp.unwrap()).collect();
+        assert_eq!(rsrps, vec![-100, -101, -102, -103, -104]);
+    }
+
+    #[test]
+    fn test_downsampling() {
+        let series = synthetic_series(100);
+        let samples = series.samples(Some(10));
+        assert_eq!(samples.len(), 10);
+        assert_eq!(samples[0], sample(0, -100));
+        assert_eq!(samples[1], sample(10, -110));
+        assert_eq!(samples[9], sample(90, -190));
+        // asking for more points than there are is a no-op
+        assert_eq!(series.samples(Some(1000)).len(), 100);
+        assert!(series.samples(Some(0)).is_empty());
+    }
+
+    #[test]
+    fn test_rsrq_range() {
+        assert_eq!(rsrq_to_db(&RSRQ_Range(0)), -20.0);
+        assert_eq!(rsrq_to_db(&RSRQ_Range(34)), -3.0);
+    }
+}
@@ -13,13 +13,14 @@
 //! match rusb::Context::new() {
 //!	    Ok(mut context) => match open_orbic(&mut context) {
 //!	    Some(mut handle) => {
-//!		send_command(&mut handle, &args[1])
+//!		orbic::send_command(&mut handle, &args[1])
 //!	    },
 //!	    None => panic!("No Orbic device found"),
 //!	},
 //!	Err(e) => panic!("Failed to initialize libusb: {0}", e),
 //! ````
-use std::str;
+mod orbic;
+
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -27,57 +28,47 @@ use rusb::{
     Context, DeviceHandle, UsbContext,
 };
 
+use orbic::AtStatus;
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() < 2 {
         println!("usage: {0} <command>", args[0]);
+        println!("       {0} --detect", args[0]);
+        return;
+    }
+
+    if args[1] == "--detect" {
+        match Context::new() {
+            Ok(context) => match detect_device(&context) {
+                Some(device) => println!("found {}", device.description()),
+                None => {
+                    println!("no supported device found");
+                    std::process::exit(1);
+                }
+            },
+            Err(e) => panic!("Failed to initialize libusb: {0}", e),
+        }
         return;
     }
 
     match Context::new() {
 	Ok(mut context) => match open_orbic(&mut context) {
-	    Some(mut handle) => {
-		send_command(&mut handle, &args[1])
+	    Some(mut handle) => match orbic::send_command(&mut handle, &args[1]) {
+		Ok(response) if response.status == AtStatus::Ok => {},
+		// only warned about, since the installer's SYSCMD steps can report
+		// errors for commands that did what was needed
+		Ok(response) => {
+		    println!("Received unexpected response ({:?}): {}", response.status, response.text);
+		},
+		Err(e) => panic!("Failed to send command: {0}", e),
 	    },
 	    None => panic!("No Orbic device found"),
 	},
 	Err(e) => panic!("Failed to initialize libusb: {0}", e),
     }
 }
-/// Sends an AT command to the usb device over the serial port
-/// 
-/// First establish a USB handle and context by calling `open_orbic(<T>)
-fn send_command<T: UsbContext>(
-    handle: &mut DeviceHandle<T>,
-    command: &str,
-) {
-    let mut data = String::new();
-    data.push_str("\r\n");
-    data.push_str(command);
-    data.push_str("\r\n");
-
-    let timeout = Duration::from_secs(1);
-    let mut response = [0; 256];
-
-    // Set up the serial port appropriately
-    handle.write_control(0x21, 0x22, 3, 1, &[], timeout).expect("Failed to send control request");
-
-    // Send the command
-    handle.write_bulk(0x2, data.as_bytes(), timeout).expect("Failed to write command");
-
-    // Consume the echoed command
-    handle.read_bulk(0x82, &mut response, timeout).expect("Failed to read submitted command");
-
-    // Read the actual response
-    handle.read_bulk(0x82, &mut response, timeout).expect("Failed to read response");
-
-    let responsestr = str::from_utf8(&response).expect("Failed to parse response");
-    if !responsestr.starts_with("\r\nOK\r\n") {
-	println!("Received unexpected response{0}", responsestr)
-    }
-}
-
 /// Send a command to switch the device into generic mode, exposing serial
 /// 
 /// If the device reboots while the command is still executing you may get a pipe error here, not sure what to do about this race condition.
@@ -127,6 +118,45 @@ fn open_orbic<T: UsbContext>(
     panic!("No Orbic device detected")
 }
 
+/// The devices (and the states they can be in) that we know how to install
+/// onto, going by their USB IDs
+#[derive(Debug, PartialEq)]
+enum KnownDevice {
+    /// An Orbic in its out-of-the-box state, before switching modes
+    OrbicOutOfBox,
+    /// An Orbic that's been switched into diag mode
+    OrbicDiag,
+    /// An Orbic in diag mode with rndis enabled as well
+    OrbicDiagRndis,
+}
+
+impl KnownDevice {
+    fn from_usb_ids(vid: u16, pid: u16) -> Option<Self> {
+        match (vid, pid) {
+            (0x05c6, 0xf626) => Some(KnownDevice::OrbicOutOfBox),
+            (0x05c6, 0xf601) => Some(KnownDevice::OrbicDiag),
+            (0x05c6, 0xf622) => Some(KnownDevice::OrbicDiagRndis),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            KnownDevice::OrbicOutOfBox => "an Orbic RC400L (not yet in diag mode)",
+            KnownDevice::OrbicDiag => "an Orbic RC400L (in diag mode)",
+            KnownDevice::OrbicDiagRndis => "an Orbic RC400L (in diag mode with rndis)",
+        }
+    }
+}
+
+/// Looks through the connected USB devices for one we know how to install onto
+fn detect_device<T: UsbContext>(context: &T) -> Option<KnownDevice> {
+    let devices = context.devices().ok()?;
+    devices.iter()
+        .filter_map(|device| device.device_descriptor().ok())
+        .find_map(|desc| KnownDevice::from_usb_ids(desc.vendor_id(), desc.product_id()))
+}
+
 /// Generic function to open a USB device
 fn open_device<T: UsbContext>(
     context: &mut T,
@@ -154,3 +184,18 @@ fn open_device<T: UsbContext>(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_usb_ids() {
+        assert_eq!(KnownDevice::from_usb_ids(0x05c6, 0xf626), Some(KnownDevice::OrbicOutOfBox));
+        assert_eq!(KnownDevice::from_usb_ids(0x05c6, 0xf601), Some(KnownDevice::OrbicDiag));
+        assert_eq!(KnownDevice::from_usb_ids(0x05c6, 0xf622), Some(KnownDevice::OrbicDiagRndis));
+        // other Qualcomm devices, and other vendors using the same PIDs
+        assert_eq!(KnownDevice::from_usb_ids(0x05c6, 0x9091), None);
+        assert_eq!(KnownDevice::from_usb_ids(0x1234, 0xf601), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Running AT commands on the Orbic's serial modem interface
+//!
+//! The modem echoes the command back, then eventually answers `OK` or
+//! `ERROR`, but how that arrives varies: the answer can be split across
+//! several bulk reads, and on macOS the parts of the read buffer that weren't
+//! filled are left full of garbage. So rather than trusting a single read, we
+//! keep reading until we see a final result code or run out of time.
+use std::time::{Duration, Instant};
+
+use rusb::{DeviceHandle, UsbContext};
+
+const OK_RESULT: &[u8] = b"\r\nOK\r\n";
+const ERROR_RESULT: &[u8] = b"\r\nERROR\r\n";
+
+/// How long to wait for the modem to finish answering a command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The modem's final result code for a command
+#[derive(Debug, PartialEq)]
+pub enum AtStatus {
+    Ok,
+    Error,
+    /// Neither `OK` nor `ERROR` arrived before the timeout
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub struct AtResponse {
+    pub status: AtStatus,
+    /// Everything the modem sent, including the echoed command, with any
+    /// invalid UTF-8 replaced
+    pub text: String,
+}
+
+/// Sends an AT command to the usb device over the serial port, and waits for
+/// its result
+///
+/// First establish a USB handle and context by calling `open_orbic(<T>)`
+pub fn send_command<T: UsbContext>(
+    handle: &mut DeviceHandle<T>,
+    command: &str,
+) -> Result<AtResponse, rusb::Error> {
+    let timeout = Duration::from_secs(1);
+
+    // Set up the serial port appropriately
+    handle.write_control(0x21, 0x22, 3, 1, &[], timeout)?;
+
+    // Send the command
+    handle.write_bulk(0x2, format!("\r\n{}\r\n", command).as_bytes(), timeout)?;
+
+    read_response(|buf, timeout| handle.read_bulk(0x82, buf, timeout), RESPONSE_TIMEOUT)
+}
+
+/// Calls `read` until the bytes read so far contain a final result code, or
+/// `timeout` has passed. `read` is given a buffer and how long it may block
+/// for, and returns how many bytes it put in the buffer.
+pub fn read_response<F>(mut read: F, timeout: Duration) -> Result<AtResponse, rusb::Error>
+where
+    F: FnMut(&mut [u8], Duration) -> Result<usize, rusb::Error>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut buf = [0; 256];
+    let status = loop {
+        if let Some(status) = parse_status(&received) {
+            break status;
+        }
+        // libusb treats a timeout of 0 as "wait forever", so stop before the
+        // remaining time rounds down to that
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining < Duration::from_millis(1) {
+            break AtStatus::TimedOut;
+        }
+        match read(&mut buf, remaining) {
+            // only the bytes actually read count, whatever's in the rest of
+            // the buffer is garbage
+            Ok(len) => received.extend_from_slice(&buf[..len]),
+            Err(rusb::Error::Timeout) => {},
+            Err(e) => return Err(e),
+        }
+    };
+    Ok(AtResponse {
+        status,
+        text: String::from_utf8_lossy(&received).into_owned(),
+    })
+}
+
+// Returns the first final result code in what's been received, if any
+fn parse_status(received: &[u8]) -> Option<AtStatus> {
+    let find = |result: &[u8]| received.windows(result.len()).position(|window| window == result);
+    match (find(OK_RESULT), find(ERROR_RESULT)) {
+        (Some(ok), Some(error)) if error < ok => Some(AtStatus::Error),
+        (Some(_), _) => Some(AtStatus::Ok),
+        (None, Some(_)) => Some(AtStatus::Error),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // stands in for the device, returning one chunk per read and then timing
+    // out, and filling the rest of the buffer with garbage like macOS does
+    fn mock_reads(chunks: &[&[u8]]) -> impl FnMut(&mut [u8], Duration) -> Result<usize, rusb::Error> {
+        let mut chunks: VecDeque<Vec<u8>> = chunks.iter().map(|chunk| chunk.to_vec()).collect();
+        move |buf, _timeout| {
+            let chunk = chunks.pop_front().ok_or(rusb::Error::Timeout)?;
+            buf.fill(0xff);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_response_split_across_reads() {
+        let read = mock_reads(&[b"\r\nAT+SYSCMD=id\r\n", b"\r\nO", b"K\r", b"\n"]);
+        let response = read_response(read, Duration::from_secs(1)).unwrap();
+        assert_eq!(response.status, AtStatus::Ok);
+        assert_eq!(response.text, "\r\nAT+SYSCMD=id\r\n\r\nOK\r\n");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let read = mock_reads(&[b"\r\nAT+BOGUS\r\n\r\nERR", b"OR\r\n"]);
+        let response = read_response(read, Duration::from_secs(1)).unwrap();
+        assert_eq!(response.status, AtStatus::Error);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let read = mock_reads(&[b"\r\n\xfe\xfe\r\nOK\r\n"]);
+        let response = read_response(read, Duration::from_secs(1)).unwrap();
+        assert_eq!(response.status, AtStatus::Ok);
+        assert!(response.text.ends_with("\r\nOK\r\n"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let read = mock_reads(&[b"\r\nAT\r\n", b"\r\nO"]);
+        let response = read_response(read, Duration::from_millis(20)).unwrap();
+        assert_eq!(response.status, AtStatus::TimedOut);
+        assert_eq!(response.text, "\r\nAT\r\n\r\nO");
+    }
+
+    #[test]
+    fn test_usb_error() {
+        let read = |_: &mut [u8], _| Err(rusb::Error::NoDevice);
+        assert!(matches!(read_response(read, Duration::from_secs(1)), Err(rusb::Error::NoDevice)));
+    }
+}